@@ -0,0 +1,121 @@
+use crate::{core::*, server::ReliableOutbox};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+/// an action the server hasn't answered after this long is taken back, in seconds
+const ACTION_TIMEOUT: f32 = 5.0;
+
+/// lets a client show what an action does right away instead of a round trip later. the feature
+/// sends it with `PendingActions::predict`, tags whatever it shows with the returned id, and takes
+/// that back if an `ActionDeniedEvent` for the id comes in
+pub struct ActionPlugin;
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            resolve_actions_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.init_resource::<PendingActions>();
+        app.add_event::<ActionResultEvent>();
+        app.add_event::<ActionDeniedEvent>();
+    }
+}
+
+/// the server's answer to one of our actions
+#[derive(Event)]
+pub struct ActionResultEvent {
+    pub action_id: u32,
+    pub accepted: bool,
+}
+
+/// the server said no (or never answered), the action's local effects have to be undone
+#[derive(Event)]
+pub struct ActionDeniedEvent {
+    pub action_id: u32,
+    pub action: PredictedAction,
+}
+
+#[derive(Resource, Default)]
+pub struct PendingActions {
+    next_id: u32,
+    pending: HashMap<u32, PendingAction>,
+}
+
+struct PendingAction {
+    action: PredictedAction,
+    sent_at: f32,
+}
+
+impl PendingActions {
+    /// sends the action to the server, returns the id to tag its local effects with
+    pub fn predict(&mut self, client: &mut RenetClient, now: f32, action: PredictedAction) -> u32 {
+        let action_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        if let Ok(message) = bincode::serialize(&ReliableClientMessage::Action(action_id, action)) {
+            client.send_message(DefaultChannel::ReliableOrdered, message);
+        }
+        self.pending.insert(
+            action_id,
+            PendingAction {
+                action,
+                sent_at: now,
+            },
+        );
+        action_id
+    }
+}
+
+/// the server's side: a predicted action (one with an id) gets exactly one answer, sent only to
+/// whoever did it. actions from players on the server's machine weren't predicted
+pub fn answer_action(
+    outbox: &mut ReliableOutbox,
+    server: &mut RenetServer,
+    sender: ClientId,
+    action_id: Option<u32>,
+    accepted: bool,
+) {
+    if let Some(action_id) = action_id {
+        outbox.send(
+            server,
+            sender,
+            &ReliableServerMessage::ActionResult(action_id, accepted),
+        );
+    }
+}
+
+fn resolve_actions_system(
+    time: Res<Time>,
+    mut results: EventReader<ActionResultEvent>,
+    mut pending_actions: ResMut<PendingActions>,
+    mut denials: EventWriter<ActionDeniedEvent>,
+) {
+    for ActionResultEvent {
+        action_id,
+        accepted,
+    } in results.read()
+    {
+        if let Some(pending) = pending_actions.pending.remove(action_id) {
+            if !accepted {
+                denials.send(ActionDeniedEvent {
+                    action_id: *action_id,
+                    action: pending.action,
+                });
+            }
+        }
+    }
+
+    // the answer is never coming, better to undo something that did happen than keep showing
+    // something that didn't
+    let now = time.elapsed_seconds();
+    pending_actions.pending.retain(|action_id, pending| {
+        let expired = now - pending.sent_at > ACTION_TIMEOUT;
+        if expired {
+            denials.send(ActionDeniedEvent {
+                action_id: *action_id,
+                action: pending.action,
+            });
+        }
+        !expired
+    });
+}
@@ -0,0 +1,257 @@
+use crate::core::StatusEffectKind;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use std::{
+    io::BufRead,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+};
+
+/// reads admin commands typed into the server's terminal
+pub struct AdminPlugin;
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_console_system);
+        app.add_systems(Update, read_console_system);
+        app.add_event::<AdminCommandEvent>();
+    }
+}
+
+pub enum AdminCommand {
+    Status,
+    Mute(ClientId),
+    Unmute(ClientId),
+    FilterWord(String),
+    UnfilterWord(String),
+    Teleport(ClientId, Vec3),
+    ToggleGodMode(ClientId),
+    Push(ClientId, Vec3),
+    /// what went into the last snapshot sent to a client and why anything was left out
+    Interest(ClientId),
+    /// puts a status effect on a character for that many seconds
+    StatusEffect(ClientId, StatusEffectKind, f32),
+    /// a default explosion at that point in a room, from nobody
+    Explode(Vec3, u32),
+    /// starts the world over as if the map had changed, every character back at the spawn
+    ResetWorld,
+    /// shown to everyone as a banner, the rest of the line after the command
+    Announce(String),
+    /// switches everyone to another map from `assets/maps`, by name
+    ChangeMap(String),
+    /// what the anti-cheat checks have caught from each client so far
+    Cheats,
+    /// totals of what clients that opted in said about their setup
+    Telemetry,
+    /// starts or stops sending a client the server's collision shapes and checks to draw
+    DebugDraw(ClientId),
+    /// how fast the simulation runs, 1 is normal speed
+    TimeScale(f32),
+    /// stops the simulation or starts it again, connections stay up either way
+    ToggleFreeze,
+    /// runs this many ticks of a frozen simulation
+    Step(u32),
+    /// moves a client and their character to another room
+    Room(ClientId, u32),
+    /// where characters in a room start and respawn from then on
+    RoomSpawn(u32, Vec3),
+}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        let argument = words.next();
+
+        match command {
+            "status" => Ok(Self::Status),
+            "cheats" => Ok(Self::Cheats),
+            "telemetry" => Ok(Self::Telemetry),
+            "debugdraw" => Ok(Self::DebugDraw(parse_client_id(argument)?)),
+            "timescale" => Ok(Self::TimeScale(parse_time_scale(argument)?)),
+            "freeze" => Ok(Self::ToggleFreeze),
+            "step" => Ok(Self::Step(parse_steps(argument)?)),
+            "mute" => Ok(Self::Mute(parse_client_id(argument)?)),
+            "unmute" => Ok(Self::Unmute(parse_client_id(argument)?)),
+            "filter" => Ok(Self::FilterWord(parse_word(argument)?)),
+            "unfilter" => Ok(Self::UnfilterWord(parse_word(argument)?)),
+            "teleport" => Ok(Self::Teleport(
+                parse_client_id(argument)?,
+                Vec3::new(
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                ),
+            )),
+            "god" => Ok(Self::ToggleGodMode(parse_client_id(argument)?)),
+            "push" => Ok(Self::Push(
+                parse_client_id(argument)?,
+                Vec3::new(
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                ),
+            )),
+            "interest" => Ok(Self::Interest(parse_client_id(argument)?)),
+            "effect" => Ok(Self::StatusEffect(
+                parse_client_id(argument)?,
+                parse_status_effect(words.next())?,
+                parse_duration(words.next())?,
+            )),
+            "explode" => Ok(Self::Explode(
+                Vec3::new(
+                    parse_coordinate(argument)?,
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                ),
+                parse_optional_room(words.next())?,
+            )),
+            "reset" => Ok(Self::ResetWorld),
+            "announce" => Ok(Self::Announce(parse_text(line, command)?)),
+            "map" => Ok(Self::ChangeMap(
+                argument.ok_or("missing map name")?.to_string(),
+            )),
+            "room" => Ok(Self::Room(
+                parse_client_id(argument)?,
+                parse_room(words.next())?,
+            )),
+            "roomspawn" => Ok(Self::RoomSpawn(
+                parse_room(argument)?,
+                Vec3::new(
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                    parse_coordinate(words.next())?,
+                ),
+            )),
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+}
+
+fn parse_client_id(argument: Option<&str>) -> Result<ClientId, String> {
+    argument
+        .ok_or("missing client id")?
+        .parse::<u64>()
+        .map(ClientId::from_raw)
+        .map_err(|e| format!("invalid client id: {}", e))
+}
+
+fn parse_word(argument: Option<&str>) -> Result<String, String> {
+    argument
+        .map(|word| word.to_lowercase())
+        .ok_or_else(|| "missing word".into())
+}
+
+/// everything after the command, spaces and all
+fn parse_text(line: &str, command: &str) -> Result<String, String> {
+    let text = line.trim_start()[command.len()..].trim();
+    if text.is_empty() {
+        return Err("missing text".into());
+    }
+    Ok(text.into())
+}
+
+fn parse_status_effect(argument: Option<&str>) -> Result<StatusEffectKind, String> {
+    match argument.ok_or("missing status effect")? {
+        "speed" => Ok(StatusEffectKind::SpeedBoost),
+        "slow" => Ok(StatusEffectKind::Slow),
+        "burn" => Ok(StatusEffectKind::Burn),
+        other => Err(format!("unknown status effect: {}", other)),
+    }
+}
+
+fn parse_duration(argument: Option<&str>) -> Result<f32, String> {
+    argument
+        .ok_or("missing duration")?
+        .parse::<f32>()
+        .ok()
+        .filter(|duration| duration.is_finite() && *duration > 0.0)
+        .ok_or_else(|| "invalid duration".into())
+}
+
+fn parse_time_scale(argument: Option<&str>) -> Result<f32, String> {
+    argument
+        .ok_or("missing time scale")?
+        .parse::<f32>()
+        .ok()
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .ok_or_else(|| "invalid time scale".into())
+}
+
+/// one if not given
+fn parse_steps(argument: Option<&str>) -> Result<u32, String> {
+    match argument {
+        Some(argument) => argument
+            .parse::<u32>()
+            .ok()
+            .filter(|steps| *steps > 0)
+            .ok_or_else(|| "invalid step count".into()),
+        None => Ok(1),
+    }
+}
+
+fn parse_room(argument: Option<&str>) -> Result<u32, String> {
+    argument
+        .ok_or("missing room")?
+        .parse::<u32>()
+        .map_err(|e| format!("invalid room: {}", e))
+}
+
+/// room 0 if not given
+fn parse_optional_room(argument: Option<&str>) -> Result<u32, String> {
+    match argument {
+        Some(argument) => parse_room(Some(argument)),
+        None => Ok(0),
+    }
+}
+
+fn parse_coordinate(argument: Option<&str>) -> Result<f32, String> {
+    argument
+        .ok_or("missing coordinate")?
+        .parse::<f32>()
+        .ok()
+        .filter(|coordinate| coordinate.is_finite())
+        .ok_or_else(|| "invalid coordinate".into())
+}
+
+#[derive(Event)]
+pub struct AdminCommandEvent(pub AdminCommand);
+
+#[derive(Resource)]
+struct AdminConsole {
+    lines: Mutex<Receiver<String>>,
+}
+
+fn start_console_system(mut commands: Commands) {
+    let (sender, receiver) = mpsc::channel();
+
+    // stdin blocks, so it gets its own thread
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    commands.insert_resource(AdminConsole {
+        lines: Mutex::new(receiver),
+    });
+}
+
+fn read_console_system(console: Res<AdminConsole>, mut events: EventWriter<AdminCommandEvent>) {
+    if let Ok(lines) = console.lines.lock() {
+        for line in lines.try_iter() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match AdminCommand::parse(&line) {
+                Ok(command) => {
+                    events.send(AdminCommandEvent(command));
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
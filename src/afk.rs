@@ -0,0 +1,204 @@
+use crate::{
+    chat::ChatInput,
+    core::*,
+    localization::Localization,
+    palette::PlayerColor,
+    room::Rooms,
+    server::{NetSpawner, ReliableOutbox},
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+/// how long before acting on an idle client they're told about it, in seconds
+const AFK_WARNING_LEAD: f32 = 15.0;
+const REJOIN_KEY: KeyCode = KeyCode::Space;
+
+/// what happens to a client that stays idle for the whole `afk_timeout`
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum AfkAction {
+    /// their character is removed, they keep watching and can rejoin
+    Spectate,
+    Kick,
+}
+
+/// the server side runs only while there's a server, spectators on any client can ask to rejoin
+pub struct AfkPlugin;
+impl Plugin for AfkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (afk_system, rejoin_system)
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            request_rejoin_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.init_resource::<AfkTracker>();
+        app.add_event::<ClientActivityEvent>();
+        app.add_event::<IncomingRejoinEvent>();
+    }
+}
+
+/// a client sent input that shows someone is at the keyboard
+#[derive(Event)]
+pub struct ClientActivityEvent(pub ClientId);
+
+/// a spectating client asked for a character again
+#[derive(Event)]
+pub struct IncomingRejoinEvent {
+    pub sender: ClientId,
+}
+
+#[derive(Resource, Default)]
+struct AfkTracker {
+    clients: HashMap<ClientId, AfkState>,
+}
+
+struct AfkState {
+    last_active: f32,
+    warned: bool,
+    /// already moved to spectators, nothing more to do until they come back
+    spectating: bool,
+}
+
+fn afk_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    server_settings: Res<ServerSettings>,
+    localization: Res<Localization>,
+    local_player: Res<LocalPlayer>,
+    mut activity: EventReader<ClientActivityEvent>,
+    mut tracker: ResMut<AfkTracker>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+    characters: Query<(Entity, &Character)>,
+) {
+    let now = time.elapsed_seconds();
+
+    // the host is never idle as far as the server is concerned
+    let connected = server.clients_id();
+    tracker
+        .clients
+        .retain(|client_id, _| connected.contains(client_id));
+    for client_id in connected.iter().filter(|id| **id != local_player.client_id) {
+        tracker.clients.entry(*client_id).or_insert(AfkState {
+            last_active: now,
+            warned: false,
+            spectating: false,
+        });
+    }
+
+    for ClientActivityEvent(client_id) in activity.read() {
+        if let Some(state) = tracker.clients.get_mut(client_id) {
+            state.last_active = now;
+            state.warned = false;
+        }
+    }
+
+    let timeout = match server_settings.afk_timeout {
+        Some(timeout) => timeout,
+        None => return,
+    };
+
+    for (client_id, state) in tracker.clients.iter_mut() {
+        if state.spectating {
+            continue;
+        }
+        let idle = now - state.last_active;
+
+        if idle >= timeout {
+            match server_settings.afk_action {
+                AfkAction::Spectate => {
+                    println!("Moving idle client {} to spectators", client_id);
+                    for (entity, character) in characters.iter() {
+                        if character.owner_client_id == *client_id {
+                            commands.entity(entity).despawn();
+                        }
+                    }
+                    outbox.broadcast(
+                        &mut server,
+                        &ReliableServerMessage::DespawnCharacter(client_id.raw()),
+                    );
+                    outbox.send(
+                        &mut server,
+                        *client_id,
+                        &ReliableServerMessage::Chat(
+                            None,
+                            localization.get("afk.spectating").into(),
+                        ),
+                    );
+                    state.spectating = true;
+                }
+                AfkAction::Kick => {
+                    println!("Kicking idle client {}", client_id);
+                    server.disconnect(*client_id);
+                }
+            }
+        } else if !state.warned && idle >= timeout - AFK_WARNING_LEAD {
+            let notice = localization.format("afk.warning", &[&((timeout - idle).ceil() as u32)]);
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::Chat(None, notice),
+            );
+            state.warned = true;
+        }
+    }
+}
+
+/// spectators come back at their room's spawn with whatever color is free by then
+fn rejoin_system(
+    mut rejoins: EventReader<IncomingRejoinEvent>,
+    mut tracker: ResMut<AfkTracker>,
+    mut spawner: NetSpawner,
+    rooms: Res<Rooms>,
+    time: Res<Time>,
+    characters: Query<(&Character, &PlayerColor)>,
+) {
+    let mut taken_colors: Vec<PlayerColor> = characters.iter().map(|(_, color)| *color).collect();
+
+    for IncomingRejoinEvent { sender } in rejoins.read() {
+        let state = match tracker.clients.get_mut(sender) {
+            Some(state) if state.spectating => state,
+            _ => continue,
+        };
+        if characters
+            .iter()
+            .any(|(character, _)| character.owner_client_id == *sender)
+        {
+            continue;
+        }
+        state.spectating = false;
+        state.warned = false;
+        state.last_active = time.elapsed_seconds();
+
+        let color = PlayerColor::first_free(&taken_colors);
+        taken_colors.push(color);
+        spawner.spawn_character(*sender, color, rooms.spawn_point_of(*sender), Vec3::ZERO);
+    }
+}
+
+/// without a character there are no inputs to send, so rejoining is asked for explicitly
+fn request_rejoin_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    local_player: Res<LocalPlayer>,
+    characters: Query<&Character>,
+    mut client: ResMut<RenetClient>,
+) {
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active);
+    if typing
+        || !keyboard.just_pressed(REJOIN_KEY)
+        || characters
+            .iter()
+            .any(|character| character.owner_client_id == local_player.client_id)
+    {
+        return;
+    }
+
+    if let Ok(message) = bincode::serialize(&ReliableClientMessage::Rejoin) {
+        client.send_message(DefaultChannel::ReliableOrdered, message);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    chat::ChatMessageEvent,
+    core::*,
+    server::ReliableOutbox,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetServer, ServerEvent};
+
+/// how long the banner stays up, in seconds
+const BANNER_DURATION: f32 = 6.0;
+
+/// server-wide notices shown as a banner across the top and kept in the chat log: the message
+/// of the day everyone gets on joining, and whatever an admin announces
+pub struct AnnouncementPlugin;
+impl Plugin for AnnouncementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_banner_system);
+        app.add_systems(
+            Update,
+            (
+                host_motd_system.run_if(resource_added::<RenetServer>),
+                (send_motd_system, admin_announce_system).run_if(resource_exists::<RenetServer>),
+                banner_system,
+            )
+                .chain(),
+        );
+        app.add_event::<AnnouncementEvent>();
+    }
+}
+
+/// something the server wants everyone to read
+#[derive(Event)]
+pub struct AnnouncementEvent(pub String);
+
+/// the host joined their own server the moment it started
+fn host_motd_system(
+    server_settings: Res<ServerSettings>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut announcements: EventWriter<AnnouncementEvent>,
+) {
+    let is_host = local_player.is_some_and(|local_player| local_player.is_authority());
+    if let (Some(motd), true) = (&server_settings.motd, is_host) {
+        announcements.send(AnnouncementEvent(motd.clone()));
+    }
+}
+
+fn send_motd_system(
+    server_settings: Res<ServerSettings>,
+    mut server_events: EventReader<ServerEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    let motd = match &server_settings.motd {
+        Some(motd) => motd,
+        None => return,
+    };
+    for event in server_events.read() {
+        if let ServerEvent::ClientConnected { client_id } = event {
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::Announcement(motd.clone()),
+            );
+        }
+    }
+}
+
+fn admin_announce_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut announcements: EventWriter<AnnouncementEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Announce(text) = command {
+            println!("Announcing \"{}\"", text);
+            outbox.broadcast(
+                &mut server,
+                &ReliableServerMessage::Announcement(text.clone()),
+            );
+            announcements.send(AnnouncementEvent(text.clone()));
+        }
+    }
+}
+
+#[derive(Component)]
+struct Banner {
+    remaining: f32,
+}
+
+fn setup_banner_system(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                Banner { remaining: 0.0 },
+                TextBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 24.0,
+                            color: Color::rgb(1.0, 0.85, 0.3),
+                            ..default()
+                        },
+                    ),
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ));
+        });
+}
+
+/// the newest announcement replaces whatever was up, every one of them goes to the chat log
+fn banner_system(
+    time: Res<Time>,
+    mut announcements: EventReader<AnnouncementEvent>,
+    mut chat_messages: EventWriter<ChatMessageEvent>,
+    mut banners: Query<(&mut Banner, &mut Text, &mut Visibility)>,
+) {
+    let mut latest = None;
+    for AnnouncementEvent(text) in announcements.read() {
+        chat_messages.send(ChatMessageEvent {
+            sender: None,
+            text: text.clone(),
+        });
+        latest = Some(text);
+    }
+
+    for (mut banner, mut text, mut visibility) in banners.iter_mut() {
+        if let Some(latest) = latest {
+            banner.remaining = BANNER_DURATION;
+            text.sections[0].value.clone_from(latest);
+        }
+
+        banner.remaining -= time.delta_seconds();
+        let target = if banner.remaining > 0.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+}
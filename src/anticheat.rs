@@ -0,0 +1,337 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    server::ResetWorldEvent,
+    SPRINT_SPEED_MULTIPLIER,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, RenetServer, ServerEvent};
+use std::f32::consts::{PI, TAU};
+
+/// seconds of input counted together before the rate is checked
+const RATE_WINDOW: f32 = 2.0;
+/// input groups a client may send per fixed tick on average, catching up after a hitch included
+const INPUT_RATE_TOLERANCE: f32 = 1.5;
+/// inputs in one group, one per rendered frame, so about 1000 fps at 64 Hz
+const MAX_INPUTS_PER_GROUP: usize = 16;
+/// radians the view can turn between two frames before it counts as a snap
+const ANGLE_SNAP: f32 = 2.5;
+/// how much further than its speed allows a character may move in a tick
+const SPEED_TOLERANCE: f32 = 1.25;
+/// score lost per second without violations
+const SCORE_DECAY: f32 = 0.2;
+
+/// adds up what the server's checks catch per client. none of it proves anything on its own, a
+/// hitch looks like an input burst and a fast flick like a snap, so each kind of violation only
+/// adds to a decaying score. above `ServerSettings::cheat_kick_score` the client is kicked
+pub struct AntiCheatPlugin;
+impl Plugin for AntiCheatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedPostUpdate,
+            speed_check_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            (
+                input_check_system,
+                report_violations_system,
+                admin_cheats_system,
+            )
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.init_resource::<CheatReports>();
+        app.init_resource::<InputRates>();
+        app.init_resource::<LastMovement>();
+        app.add_event::<InputsReceivedEvent>();
+        app.add_event::<ViolationEvent>();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ViolationKind {
+    /// moved further in a tick than its velocity or top speed allow
+    Speed,
+    AngleSnap,
+    /// more input groups than ticks, or more inputs in a group than frames
+    InputRate,
+    /// sent input with NaN or infinite view angles
+    NonFinite,
+}
+
+impl ViolationKind {
+    const ALL: [Self; 4] = [
+        Self::Speed,
+        Self::AngleSnap,
+        Self::InputRate,
+        Self::NonFinite,
+    ];
+
+    /// a bad value can't happen by accident, a fast flick can
+    fn weight(self) -> f32 {
+        match self {
+            Self::Speed => 1.0,
+            Self::AngleSnap => 0.5,
+            Self::InputRate => 2.0,
+            Self::NonFinite => 5.0,
+        }
+    }
+}
+
+#[derive(Event)]
+pub struct ViolationEvent {
+    pub client_id: ClientId,
+    pub kind: ViolationKind,
+}
+
+/// the shape of an input message a client sent, for the rate and aim checks
+#[derive(Event)]
+pub struct InputsReceivedEvent {
+    pub client_id: ClientId,
+    pub group_count: usize,
+    pub largest_group: usize,
+    /// the most the view turned between two consecutive inputs, in radians
+    pub largest_turn: f32,
+}
+
+/// how far the view turned from one input to the next, the short way around
+pub fn turn_between(previous: &PlayerInput, input: &PlayerInput) -> f32 {
+    let yaw = (input.yaw - previous.yaw + PI).rem_euclid(TAU) - PI;
+    let pitch = input.pitch - previous.pitch;
+    (yaw * yaw + pitch * pitch).sqrt()
+}
+
+#[derive(Default)]
+struct CheatReport {
+    counts: HashMap<ViolationKind, u32>,
+    score: f32,
+}
+
+/// only connected clients are kept, whoever leaves takes their report along
+#[derive(Resource, Default)]
+struct CheatReports {
+    clients: HashMap<ClientId, CheatReport>,
+}
+
+#[derive(Resource, Default)]
+struct InputRates {
+    window_start: f32,
+    groups: HashMap<ClientId, usize>,
+}
+
+/// where each character was at the end of the last tick
+#[derive(Resource, Default)]
+struct LastMovement {
+    characters: HashMap<ClientId, (Vec3, Vec3, u32)>,
+}
+
+fn input_check_system(
+    time: Res<Time<Real>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut received: EventReader<InputsReceivedEvent>,
+    mut rates: ResMut<InputRates>,
+    mut violations: EventWriter<ViolationEvent>,
+) {
+    for event in received.read() {
+        *rates.groups.entry(event.client_id).or_default() += event.group_count;
+        if event.largest_group > MAX_INPUTS_PER_GROUP {
+            violations.send(ViolationEvent {
+                client_id: event.client_id,
+                kind: ViolationKind::InputRate,
+            });
+        }
+        if event.largest_turn > ANGLE_SNAP {
+            violations.send(ViolationEvent {
+                client_id: event.client_id,
+                kind: ViolationKind::AngleSnap,
+            });
+        }
+    }
+
+    let now = time.elapsed_seconds();
+    if now - rates.window_start < RATE_WINDOW {
+        return;
+    }
+    let allowed =
+        (now - rates.window_start) / fixed_time.timestep().as_secs_f32() * INPUT_RATE_TOLERANCE;
+    rates.window_start = now;
+    for (client_id, groups) in rates.groups.drain() {
+        if groups as f32 > allowed {
+            violations.send(ViolationEvent {
+                client_id,
+                kind: ViolationKind::InputRate,
+            });
+        }
+    }
+}
+
+/// the server moves characters itself, but a client that gets more input groups played than
+/// it had ticks moves more than one tick's worth in one
+fn speed_check_system(
+    fixed_time: Res<Time<Fixed>>,
+    server: Res<RenetServer>,
+    mutators: Res<Mutators>,
+    mut resets: EventReader<ResetWorldEvent>,
+    mut last_movement: ResMut<LastMovement>,
+    mut violations: EventWriter<ViolationEvent>,
+    characters: Query<(&Character, &Transform)>,
+) {
+    // everyone was put back at the spawn without a teleport
+    if resets.read().count() > 0 {
+        last_movement.characters.clear();
+    }
+
+    let mut movement = HashMap::default();
+    for (character, transform) in characters.iter() {
+        let client_id = character.owner_client_id;
+        movement.insert(
+            client_id,
+            (
+                transform.translation,
+                character.velocity,
+                character.teleport_count,
+            ),
+        );
+
+        // nobody on this machine is checked
+        if !server.is_connected(client_id) {
+            continue;
+        }
+        let (last_translation, last_velocity, last_teleport_count) =
+            match last_movement.characters.get(&client_id) {
+                Some(last) => *last,
+                None => continue,
+            };
+        if last_teleport_count != character.teleport_count {
+            continue;
+        }
+
+        let top_speed = character.move_speed
+            * SPRINT_SPEED_MULTIPLIER
+            * character.status_speed_multiplier()
+            * mutators.speed_multiplier();
+        let speed = top_speed
+            .max(last_velocity.xz().length())
+            .max(character.velocity.xz().length());
+        let allowed = speed * fixed_time.delta_seconds() * SPEED_TOLERANCE;
+        if (transform.translation - last_translation).xz().length() > allowed {
+            violations.send(ViolationEvent {
+                client_id,
+                kind: ViolationKind::Speed,
+            });
+        }
+    }
+    last_movement.characters = movement;
+}
+
+fn report_violations_system(
+    time: Res<Time>,
+    server_settings: Res<ServerSettings>,
+    mut server_events: EventReader<ServerEvent>,
+    mut violations: EventReader<ViolationEvent>,
+    mut reports: ResMut<CheatReports>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = event {
+            if let Some(report) = reports.clients.remove(client_id) {
+                println!(
+                    "{} left with a cheat score of {:.1} ({})",
+                    client_id,
+                    report.score,
+                    describe_counts(&report)
+                );
+            }
+        }
+    }
+
+    for report in reports.clients.values_mut() {
+        report.score = (report.score - SCORE_DECAY * time.delta_seconds()).max(0.0);
+    }
+
+    for ViolationEvent { client_id, kind } in violations.read() {
+        if !server.is_connected(*client_id) {
+            continue;
+        }
+        let report = reports.clients.entry(*client_id).or_default();
+        let count = report.counts.entry(*kind).or_default();
+        *count += 1;
+        report.score += kind.weight();
+        // only the first few of each and then every power of two, like non-finite values
+        if *count <= 3 || count.is_power_of_two() {
+            println!(
+                "{} violation by {} ({} so far, score {:.1})",
+                describe_kind(*kind),
+                client_id,
+                count,
+                report.score
+            );
+        }
+    }
+
+    if let Some(kick_score) = server_settings.cheat_kick_score {
+        let kicked: Vec<ClientId> = reports
+            .clients
+            .iter()
+            .filter(|(_, report)| report.score >= kick_score)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in kicked {
+            if let Some(report) = reports.clients.remove(&client_id) {
+                println!(
+                    "Kicking {} with a cheat score of {:.1} ({})",
+                    client_id,
+                    report.score,
+                    describe_counts(&report)
+                );
+            }
+            server.disconnect(client_id);
+        }
+    }
+}
+
+fn describe_kind(kind: ViolationKind) -> &'static str {
+    match kind {
+        ViolationKind::Speed => "Speed",
+        ViolationKind::AngleSnap => "Angle snap",
+        ViolationKind::InputRate => "Input rate",
+        ViolationKind::NonFinite => "Non-finite input",
+    }
+}
+
+fn describe_counts(report: &CheatReport) -> String {
+    ViolationKind::ALL
+        .iter()
+        .map(|kind| {
+            format!(
+                "{} {}",
+                describe_kind(*kind).to_lowercase(),
+                report.counts.get(kind).copied().unwrap_or(0)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn admin_cheats_system(
+    reports: Res<CheatReports>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Cheats = command {
+            print!("{:<24}{:>8}", "Client", "Score");
+            for kind in ViolationKind::ALL {
+                print!("{:>18}", describe_kind(kind));
+            }
+            println!();
+            for (client_id, report) in reports.clients.iter() {
+                print!("{:<24}{:>8.1}", client_id.raw(), report.score);
+                for kind in ViolationKind::ALL {
+                    print!("{:>18}", report.counts.get(&kind).copied().unwrap_or(0));
+                }
+                println!();
+            }
+        }
+    }
+}
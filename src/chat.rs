@@ -0,0 +1,469 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    localization::Localization,
+    room::Rooms,
+    server::ReliableOutbox,
+    vote::{self, VoteCommandEvent},
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer, ServerEvent};
+use std::collections::VecDeque;
+
+const CHAT_MAX_LENGTH: usize = 200;
+const CHAT_RATE_LIMIT_MESSAGES: usize = 3;
+const CHAT_RATE_LIMIT_WINDOW: f32 = 5.0;
+/// lines shown at once, older ones can be scrolled back to while typing
+const CHAT_LOG_LINES: usize = 8;
+const CHAT_HISTORY: usize = 100;
+/// seconds a line stays up when nobody's typing
+const CHAT_LINE_DURATION: f32 = 10.0;
+
+/// chat lines go to the server, which moderates them and sends them on to everyone in the
+/// sender's room over the reliable channel. every app prints them, anything with a window also
+/// keeps a log on screen with an input line opened by enter
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_chat_input_system);
+        app.add_systems(
+            Update,
+            (
+                chat_typing_system,
+                chat_submit_system,
+                chat_input_text_system,
+                chat_log_system,
+                chat_log_text_system,
+            )
+                .chain(),
+        );
+        app.add_systems(
+            Update,
+            (chat_admin_command_system, moderate_chat_system)
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.init_resource::<ChatInput>();
+        app.init_resource::<ChatLog>();
+        app.init_resource::<LocalMutes>();
+        app.init_resource::<ChatModeration>();
+        app.add_event::<ChatSubmitEvent>();
+        app.add_event::<IncomingChatEvent>();
+        app.add_event::<ChatMessageEvent>();
+    }
+}
+
+/// what the local player is typing, movement keys are ignored while this is active
+#[derive(Resource, Default)]
+pub struct ChatInput {
+    pub active: bool,
+    pub buffer: String,
+}
+
+/// what was said, newest last, with when it arrived
+#[derive(Resource, Default)]
+struct ChatLog {
+    lines: VecDeque<(String, f32)>,
+    /// lines back from the newest, only while typing
+    scroll: usize,
+}
+
+/// players this client doesn't want to hear from, never sent to the server
+#[derive(Resource, Default)]
+struct LocalMutes {
+    client_ids: HashSet<ClientId>,
+}
+
+/// server-side rules every chat line goes through before it's broadcast
+#[derive(Resource, Default)]
+struct ChatModeration {
+    muted: HashSet<ClientId>,
+    filtered_words: HashSet<String>,
+    recent_messages: HashMap<ClientId, VecDeque<f32>>,
+}
+
+impl ChatModeration {
+    fn filter(&self, text: &str) -> String {
+        text.split(' ')
+            .map(|word| {
+                let normalized = word
+                    .trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase();
+                if self.filtered_words.contains(&normalized) {
+                    "*".repeat(word.chars().count())
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// true if the sender is still within their allowance for the current window
+    fn allow(&mut self, sender: ClientId, now: f32) -> bool {
+        let recent = self.recent_messages.entry(sender).or_default();
+        while recent
+            .front()
+            .is_some_and(|sent| now - sent > CHAT_RATE_LIMIT_WINDOW)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= CHAT_RATE_LIMIT_MESSAGES {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+}
+
+/// a line the local player just finished typing
+#[derive(Event)]
+struct ChatSubmitEvent(String);
+
+/// a chat line waiting to be moderated by the server
+#[derive(Event)]
+pub struct IncomingChatEvent {
+    pub sender: ClientId,
+    pub text: String,
+}
+
+/// a chat line ready to be shown, no sender means it's a notice from the server
+#[derive(Event)]
+pub struct ChatMessageEvent {
+    pub sender: Option<ClientId>,
+    pub text: String,
+}
+
+#[derive(Component)]
+struct ChatInputText;
+
+#[derive(Component)]
+struct ChatLogText;
+
+fn setup_chat_input_system(mut commands: Commands) {
+    // just above the input line
+    commands.spawn((
+        ChatLogText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(28.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        },
+    ));
+    commands.spawn((
+        ChatInputText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn chat_typing_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut chat_input: ResMut<ChatInput>,
+    mut submits: EventWriter<ChatSubmitEvent>,
+) {
+    if !chat_input.active {
+        received_characters.clear();
+        if keyboard.just_pressed(KeyCode::Enter) {
+            chat_input.active = true;
+        }
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        chat_input.active = false;
+        chat_input.buffer.clear();
+        received_characters.clear();
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Backspace) {
+        chat_input.buffer.pop();
+    }
+
+    for event in received_characters.read() {
+        for c in event.char.chars().filter(|c| !c.is_control()) {
+            if chat_input.buffer.chars().count() < CHAT_MAX_LENGTH {
+                chat_input.buffer.push(c);
+            }
+        }
+    }
+
+    if !keyboard.just_pressed(KeyCode::Enter) {
+        return;
+    }
+
+    chat_input.active = false;
+    let text = std::mem::take(&mut chat_input.buffer).trim().to_string();
+    if !text.is_empty() {
+        submits.send(ChatSubmitEvent(text));
+    }
+}
+
+fn chat_submit_system(
+    localization: Res<Localization>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut submits: EventReader<ChatSubmitEvent>,
+    mut local_mutes: ResMut<LocalMutes>,
+    mut incoming: EventWriter<IncomingChatEvent>,
+    mut messages: EventWriter<ChatMessageEvent>,
+    mut vote_commands: EventWriter<VoteCommandEvent>,
+    mut client: Option<ResMut<RenetClient>>,
+) {
+    for ChatSubmitEvent(text) in submits.read() {
+        if let Some(request) = vote::parse_vote_command(text) {
+            vote_commands.send(VoteCommandEvent(request));
+            continue;
+        }
+
+        // local commands
+        let mut words = text.split_whitespace();
+        match (
+            words.next(),
+            words.next().and_then(|id| id.parse::<u64>().ok()),
+        ) {
+            (Some("/mute"), Some(client_id)) => {
+                local_mutes.client_ids.insert(ClientId::from_raw(client_id));
+                messages.send(ChatMessageEvent {
+                    sender: None,
+                    text: localization.format("chat.muted_player", &[&client_id]),
+                });
+                continue;
+            }
+            (Some("/unmute"), Some(client_id)) => {
+                local_mutes
+                    .client_ids
+                    .remove(&ClientId::from_raw(client_id));
+                messages.send(ChatMessageEvent {
+                    sender: None,
+                    text: localization.format("chat.unmuted_player", &[&client_id]),
+                });
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(client) = client.as_mut() {
+            if let Ok(message) = bincode::serialize(&ReliableClientMessage::Chat(text.clone())) {
+                client.send_message(DefaultChannel::ReliableOrdered, message);
+            }
+        } else if let Some(local_player) = &local_player {
+            // the listen server host goes through the same moderation as everyone else
+            incoming.send(IncomingChatEvent {
+                sender: local_player.client_id,
+                text: text.clone(),
+            });
+        }
+    }
+}
+
+fn chat_input_text_system(
+    chat_input: Res<ChatInput>,
+    mut query: Query<(&mut Text, &mut Visibility), With<ChatInputText>>,
+) {
+    if !chat_input.is_changed() {
+        return;
+    }
+
+    for (mut text, mut visibility) in query.iter_mut() {
+        text.sections[0].value = format!("> {}", chat_input.buffer);
+        *visibility = if chat_input.active {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn chat_log_system(
+    time: Res<Time<Real>>,
+    localization: Res<Localization>,
+    local_mutes: Res<LocalMutes>,
+    characters: Query<(&Character, &PlayerName)>,
+    mut messages: EventReader<ChatMessageEvent>,
+    mut log: ResMut<ChatLog>,
+) {
+    for message in messages.read() {
+        let line = match message.sender {
+            Some(sender) if local_mutes.client_ids.contains(&sender) => continue,
+            Some(sender) => {
+                // players who didn't give a name go by their id
+                let name = characters
+                    .iter()
+                    .find(|(character, _)| character.owner_client_id == sender)
+                    .map(|(_, name)| name.0.as_str())
+                    .filter(|name| !name.is_empty());
+                match name {
+                    Some(name) => localization.format("chat.from_named", &[&name, &message.text]),
+                    None => localization.format("chat.from_player", &[&sender, &message.text]),
+                }
+            }
+            None => localization.format("chat.from_server", &[&message.text]),
+        };
+        println!("{}", line);
+        if log.lines.len() >= CHAT_HISTORY {
+            log.lines.pop_front();
+        }
+        log.lines.push_back((line, time.elapsed_seconds()));
+    }
+}
+
+/// while typing, page up and down scroll back through everything kept. otherwise only the
+/// newest lines are shown, each for a while after it arrived
+fn chat_log_text_system(
+    time: Res<Time<Real>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    mut texts: Query<&mut Text, With<ChatLogText>>,
+) {
+    if chat_input.active {
+        let max_scroll = log.lines.len().saturating_sub(CHAT_LOG_LINES);
+        if keyboard.just_pressed(KeyCode::PageUp) {
+            log.scroll = (log.scroll + CHAT_LOG_LINES).min(max_scroll);
+        }
+        if keyboard.just_pressed(KeyCode::PageDown) {
+            log.scroll = log.scroll.saturating_sub(CHAT_LOG_LINES);
+        }
+    } else if log.scroll != 0 {
+        log.scroll = 0;
+    }
+
+    let now = time.elapsed_seconds();
+    let end = log.lines.len() - log.scroll;
+    let start = end.saturating_sub(CHAT_LOG_LINES);
+    let value = log
+        .lines
+        .range(start..end)
+        .filter(|(_, received_at)| chat_input.active || now - received_at < CHAT_LINE_DURATION)
+        .map(|(line, _)| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+fn chat_admin_command_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut moderation: ResMut<ChatModeration>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        match command {
+            AdminCommand::Mute(client_id) => {
+                moderation.muted.insert(*client_id);
+                println!("Muted {}", client_id);
+            }
+            AdminCommand::Unmute(client_id) => {
+                moderation.muted.remove(client_id);
+                println!("Unmuted {}", client_id);
+            }
+            AdminCommand::FilterWord(word) => {
+                moderation.filtered_words.insert(word.clone());
+                println!("Filtering \"{}\"", word);
+            }
+            AdminCommand::UnfilterWord(word) => {
+                moderation.filtered_words.remove(word);
+                println!("No longer filtering \"{}\"", word);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn moderate_chat_system(
+    time: Res<Time>,
+    localization: Res<Localization>,
+    local_player: Res<LocalPlayer>,
+    rooms: Res<Rooms>,
+    mut server_events: EventReader<ServerEvent>,
+    mut incoming: EventReader<IncomingChatEvent>,
+    mut moderation: ResMut<ChatModeration>,
+    mut messages: EventWriter<ChatMessageEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = event {
+            moderation.recent_messages.remove(client_id);
+        }
+    }
+
+    for IncomingChatEvent { sender, text } in incoming.read() {
+        let text: String = text.trim().chars().take(CHAT_MAX_LENGTH).collect();
+        if text.is_empty() {
+            continue;
+        }
+
+        let notice = if moderation.muted.contains(sender) {
+            Some(localization.get("chat.you_are_muted"))
+        } else if !moderation.allow(*sender, time.elapsed_seconds()) {
+            Some(localization.get("chat.too_fast"))
+        } else {
+            None
+        };
+
+        if let Some(notice) = notice {
+            if *sender == local_player.client_id {
+                messages.send(ChatMessageEvent {
+                    sender: None,
+                    text: notice.into(),
+                });
+            } else {
+                outbox.send(
+                    &mut server,
+                    *sender,
+                    &ReliableServerMessage::Chat(None, notice.into()),
+                );
+            }
+            continue;
+        }
+
+        let text = moderation.filter(&text);
+        outbox.broadcast_filtered(
+            &mut server,
+            &ReliableServerMessage::Chat(Some(sender.raw()), text.clone()),
+            |client_id| rooms.same_room(client_id, *sender),
+        );
+        if rooms.same_room(local_player.client_id, *sender) {
+            messages.send(ChatMessageEvent {
+                sender: Some(*sender),
+                text,
+            });
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    core::*, replication::ReplicateAppExt, room::Rooms, server::ReliableOutbox, CHARACTER_ACCEL,
+    CHARACTER_FRICTION, CHARACTER_SPEED, MAX_HEALTH,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const CLASSES_PATH: &str = "assets/classes.ron";
+
+/// every character plays as a class from `assets/classes.ron`, which sets how it moves and how
+/// much health it has. clients ask for one with `--class` every time they connect and the server
+/// sets their character up for it. the movement goes out with every snapshot, so prediction
+/// moves the character the same way the server does without knowing about classes at all
+pub struct ClassPlugin;
+impl Plugin for ClassPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                choose_class_system,
+                host_class_system,
+                apply_class_system,
+                forget_choices_system,
+            )
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            request_class_system
+                .run_if(resource_exists::<RenetClient>)
+                .run_if(resource_exists::<ClassRequest>),
+        );
+        app.insert_resource(match ClassDefinitions::load(Path::new(CLASSES_PATH)) {
+            Ok(definitions) => definitions,
+            Err(e) => {
+                eprintln!("Failed to load classes {}: {}", CLASSES_PATH, e);
+                ClassDefinitions::default()
+            }
+        });
+        app.init_resource::<ClassChoices>();
+        app.add_event::<IncomingChooseClassEvent>();
+        app.replicate::<PlayedClass>();
+    }
+}
+
+/// a client wants to play as another class
+#[derive(Event)]
+pub struct IncomingChooseClassEvent {
+    pub sender: ClientId,
+    pub class: CharacterClass,
+}
+
+/// the class this player asks for every time it connects, or plays as when hosting
+#[derive(Resource)]
+pub struct ClassRequest(pub CharacterClass);
+
+#[derive(Deserialize, Clone)]
+pub struct ClassDefinition {
+    pub move_speed: f32,
+    pub move_accel: f32,
+    pub move_friction: f32,
+    pub max_health: f32,
+}
+
+/// what a class missing from the file falls back to
+const STANDARD: ClassDefinition = ClassDefinition {
+    move_speed: CHARACTER_SPEED,
+    move_accel: CHARACTER_ACCEL,
+    move_friction: CHARACTER_FRICTION,
+    max_health: MAX_HEALTH,
+};
+
+#[derive(Resource, Default)]
+pub struct ClassDefinitions {
+    classes: HashMap<CharacterClass, ClassDefinition>,
+}
+
+impl ClassDefinitions {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let classes: HashMap<CharacterClass, ClassDefinition> =
+            ron::from_str(&text).map_err(|e| e.to_string())?;
+        for (class, definition) in &classes {
+            let values = [
+                definition.move_speed,
+                definition.move_accel,
+                definition.move_friction,
+                definition.max_health,
+            ];
+            if values
+                .iter()
+                .any(|value| !value.is_finite() || *value <= 0.0)
+            {
+                return Err(format!("{:?} needs positive numbers", class));
+            }
+        }
+        Ok(Self { classes })
+    }
+
+    pub fn get(&self, class: CharacterClass) -> &ClassDefinition {
+        self.classes.get(&class).unwrap_or(&STANDARD)
+    }
+}
+
+/// the class each client plays as, anyone not in here plays the standard one
+#[derive(Resource, Default)]
+pub struct ClassChoices {
+    classes: HashMap<ClientId, CharacterClass>,
+}
+
+impl ClassChoices {
+    pub fn class_of(&self, client_id: ClientId) -> CharacterClass {
+        self.classes.get(&client_id).copied().unwrap_or_default()
+    }
+}
+
+/// the class a character is set up for, so a new choice can be told apart. replicated, so
+/// clients know what everyone plays as
+#[derive(Component, Serialize, Deserialize)]
+pub struct PlayedClass(pub CharacterClass);
+
+fn choose_class_system(
+    mut choices: EventReader<IncomingChooseClassEvent>,
+    mut classes: ResMut<ClassChoices>,
+) {
+    for IncomingChooseClassEvent { sender, class } in choices.read() {
+        if classes.class_of(*sender) != *class {
+            println!("Client {} plays as {:?}", sender, class);
+        }
+        classes.classes.insert(*sender, *class);
+    }
+}
+
+/// a listen server's host never connects, its request is taken as it is
+fn host_class_system(
+    local_player: Option<Res<LocalPlayer>>,
+    request: Option<Res<ClassRequest>>,
+    mut classes: ResMut<ClassChoices>,
+) {
+    if let (Some(local_player), Some(request)) = (local_player, request) {
+        if classes.classes.get(&local_player.client_id) != Some(&request.0) {
+            classes.classes.insert(local_player.client_id, request.0);
+        }
+    }
+}
+
+/// new characters are set up for their owner's class, and the owner is told its health.
+/// switching classes makes the character start over at its room's spawn with full health,
+/// instead of keeping a head start from the old one
+fn apply_class_system(
+    mut commands: Commands,
+    definitions: Res<ClassDefinitions>,
+    classes: Res<ClassChoices>,
+    rooms: Res<Rooms>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+    mut characters: Query<(
+        Entity,
+        &mut Character,
+        &mut Transform,
+        &mut Health,
+        Option<&PlayedClass>,
+    )>,
+) {
+    for (entity, mut character, mut transform, mut health, played) in characters.iter_mut() {
+        let class = classes.class_of(character.owner_client_id);
+        if played.is_some_and(|played| played.0 == class) {
+            continue;
+        }
+        let definition = definitions.get(class);
+        character.move_speed = definition.move_speed;
+        character.move_accel = definition.move_accel;
+        character.move_friction = definition.move_friction;
+        health.0 = definition.max_health;
+        if played.is_some() {
+            transform.translation = rooms.spawn_point_of(character.owner_client_id);
+            character.velocity = Vec3::ZERO;
+            character.mantle = None;
+            character.teleport_count = character.teleport_count.wrapping_add(1);
+        }
+        // clients start their own at the standard amount
+        outbox.send(
+            &mut server,
+            character.owner_client_id,
+            &ReliableServerMessage::Health(health.0),
+        );
+        commands
+            .entity(entity)
+            .insert((PlayedClass(class), MaxHealth(definition.max_health)));
+    }
+}
+
+fn forget_choices_system(
+    local_player: Option<Res<LocalPlayer>>,
+    server: Res<RenetServer>,
+    mut classes: ResMut<ClassChoices>,
+) {
+    let local_client_id = local_player.map(|local_player| local_player.client_id);
+    classes.classes.retain(|client_id, _| {
+        server.is_connected(*client_id) || Some(*client_id) == local_client_id
+    });
+}
+
+/// asked for again after every reconnect, the server forgets it when we leave
+fn request_class_system(
+    request: Res<ClassRequest>,
+    mut requested: Local<bool>,
+    mut client: ResMut<RenetClient>,
+) {
+    if !client.is_connected() {
+        *requested = false;
+        return;
+    }
+    if *requested {
+        return;
+    }
+    *requested = true;
+    if let Ok(message) = bincode::serialize(&ReliableClientMessage::ChooseClass(request.0)) {
+        client.send_message(DefaultChannel::ReliableOrdered, message);
+    }
+}
@@ -1,40 +1,240 @@
-use crate::core::*;
-use bevy::prelude::*;
+use crate::{
+    action::ActionResultEvent,
+    announcement::AnnouncementEvent,
+    chat::ChatMessageEvent,
+    core::*,
+    cosmetics::{self, CosmeticsRequest},
+    damage::ReceivedHealthEvent,
+    debug_draw::ReceivedDebugDrawEvent,
+    emote::EmoteEvent,
+    explosion::ExplosionVisualsEvent,
+    freeze::ReceivedFreezeEvent,
+    game_mode::MatchEvent,
+    hazard::ReceivedWorldClockEvent,
+    heartbeat::ReceivedHeartbeatEvent,
+    hit::ReceivedShotResultEvent,
+    interpolation::SnapshotBuffer,
+    inventory::ReceivedInventoryEvent,
+    localization::Localization,
+    map::Map,
+    mutators::ReceivedMutatorsEvent,
+    nameplate::{self, NameRequest},
+    palette::PlayerColor,
+    ping::PingEvent,
+    practice::Xorshift,
+    replication::{ReceivedComponents, ReplicationSet},
+    seed::ReceivedMatchSeedEvent,
+    server_time::ReceivedTimeResponseEvent,
+    time_scale::ReceivedTimeScaleEvent,
+    vote::ReceivedVoteEvent,
+    weapon::{self, WeaponDefinitions},
+};
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
 use bevy_renet::renet::transport::ClientAuthentication;
+use bevy_renet::renet::transport::ConnectToken;
 use bevy_renet::renet::transport::NetcodeClientTransport;
+use bevy_renet::renet::transport::NETCODE_KEY_BYTES;
 use bevy_renet::renet::ClientId;
 use bevy_renet::renet::ConnectionConfig;
 use bevy_renet::renet::DefaultChannel;
 use bevy_renet::renet::RenetClient;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
 use std::net::UdpSocket;
 use std::time::SystemTime;
 
+/// corrections bigger than this are teleports (admin, fell through the floor), not mispredictions
+const HARD_SNAP_DISTANCE: f32 = 2.0;
+const HARD_SNAP_INDICATOR_DURATION: f32 = 1.0;
+/// input messages between claims of where we ended up, about four a second at the default rate
+const CLAIM_INTERVAL: u32 = 16;
+/// the server ending up further than this from our claim is worth a line in the log
+const RECONCILIATION_LOG_DISTANCE: f32 = 0.01;
+/// seconds a connect token is good for, as long as renet gives unsecure ones
+const CONNECT_TOKEN_EXPIRY: u64 = 300;
+
 pub struct ClientPlugin;
 impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, start_client);
         app.add_systems(FixedPostUpdate, send_inputs_system);
-        app.add_systems(FixedPreUpdate, receive_snapshot_system);
+        app.add_systems(Startup, setup_hard_snap_indicator_system);
+        app.add_systems(
+            Update,
+            (receive_snapshots_system, hard_snap_indicator_system),
+        );
+        app.add_systems(
+            FixedPreUpdate,
+            (
+                enforce_replication_system,
+                receive_reliable_messages_system,
+                change_map_system,
+                reset_world_system,
+                apply_snapshots_system,
+                apply_impulses_system,
+            )
+                .chain(),
+        );
+        app.add_systems(
+            FixedPreUpdate,
+            (despawn_characters_system, spawn_characters_system)
+                .chain()
+                .after(reset_world_system),
+        );
+        app.add_systems(
+            FixedPreUpdate,
+            record_replication_system
+                .after(apply_snapshots_system)
+                .after(spawn_characters_system),
+        );
+        app.configure_sets(
+            FixedPreUpdate,
+            ReplicationSet::Apply
+                .after(apply_snapshots_system)
+                .after(spawn_characters_system),
+        );
+        app.init_resource::<SnapshotStaging>();
+        app.init_resource::<ReceivedComponents>();
+        app.init_resource::<CharacterIndex>();
+        app.init_resource::<PredictionError>();
+        app.init_resource::<ReconciliationError>();
+        app.init_resource::<SnapshotsReceived>();
+        app.add_event::<SpawnCharacterEvent>();
+        app.add_event::<DespawnCharacterEvent>();
+        app.add_event::<ReceivedImpulseEvent>();
+        app.add_event::<ReceivedResetWorldEvent>();
+        app.add_event::<ReceivedMapEvent>();
     }
 }
 
-fn start_client(mut commands: Commands, client_settings: Res<ClientSettings>) {
+/// how far off our own prediction turned out, each time a snapshot came in. the server moving
+/// us on purpose doesn't count
+#[derive(Resource, Default)]
+pub struct PredictionError {
+    pub latest: f32,
+    pub worst: f32,
+}
+
+/// how far the server's result for one of our inputs was from where we predicted it left us,
+/// each time it told us. unlike `PredictionError` this compares the very same input, so it
+/// measures the two sides simulating differently rather than anything that happened since
+#[derive(Resource, Default)]
+pub struct ReconciliationError {
+    pub latest: f32,
+    pub worst: f32,
+}
+
+/// every snapshot that arrived intact since we started, for working out how many come a second
+#[derive(Resource, Default)]
+pub struct SnapshotsReceived(pub u64);
+
+#[derive(Event)]
+struct ReceivedImpulseEvent {
+    after_input_id: Option<u32>,
+    impulse: Vec3,
+}
+
+/// every character's entity by its network id, kept up as they're spawned and despawned so a
+/// snapshot doesn't search through everyone for each character in it
+#[derive(Resource, Default)]
+struct CharacterIndex {
+    entities: HashMap<NetworkId, Entity>,
+    /// the first snapshot each was spawned for, anything earlier saying it's gone is old news
+    spawn_snapshot_ids: HashMap<NetworkId, u32>,
+}
+
+/// where the snapshots left a character we don't predict
+#[derive(Component)]
+struct Replicated {
+    translation: Vec3,
+    velocity: Vec3,
+}
+
+/// the name of the map the server is on
+#[derive(Event)]
+struct ReceivedMapEvent(String);
+
+/// the id of the first snapshot of the new world
+#[derive(Event)]
+struct ReceivedResetWorldEvent(u32);
+
+#[derive(Event)]
+struct DespawnCharacterEvent(NetworkId);
+
+#[derive(Event)]
+struct SpawnCharacterEvent {
+    network_id: NetworkId,
+    color: PlayerColor,
+    translation: Vec3,
+    velocity: Vec3,
+    cosmetics: Cosmetics,
+    name: String,
+    snapshot_id: u32,
+}
+
+/// snapshots decoded as soon as they arrive, waiting for the next fixed tick to be applied
+#[derive(Resource, Default)]
+struct SnapshotStaging {
+    snapshots: Vec<Snapshot>,
+}
+
+fn start_client(
+    mut commands: Commands,
+    client_settings: Res<ClientSettings>,
+    weapons: Res<WeaponDefinitions>,
+    cosmetics: Option<Res<CosmeticsRequest>>,
+    name: Option<Res<NameRequest>>,
+    client: Option<Res<RenetClient>>,
+) {
+    // practice mode brings its own, wired straight to the server
+    if client.is_some() {
+        return;
+    }
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
-    let client_id = ClientId::from_raw(current_time.as_secs());
-    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).unwrap();
+    // random rather than the time, two clients starting in the same second would clash. it's
+    // only what the connection goes by, the server's welcome says what we're known as
+    let mut random = Xorshift::seeded(current_time.as_nanos() as u64 ^ std::process::id() as u64);
+    let client_id = loop {
+        let id = random.next_u64();
+        if id >= RESERVED_CLIENT_IDS {
+            break ClientId::from_raw(id);
+        }
+    };
+    let mut user_data = weapon::handshake_user_data(&weapons);
+    if let Some(cosmetics) = cosmetics {
+        cosmetics::write_user_data(&mut user_data, cosmetics.0);
+    }
+    if let Some(name) = name {
+        nameplate::write_user_data(&mut user_data, &name.0);
+    }
+    let server_addr = SocketAddr::new(client_settings.address, client_settings.port);
+    // what `ClientAuthentication::Unsecure` makes, which always times out after 15 seconds.
+    // the server takes tokens signed with the all zero key as unsecure ones
+    let connect_token = match ConnectToken::generate(
+        current_time,
+        0,
+        CONNECT_TOKEN_EXPIRY,
+        client_id.raw(),
+        client_settings.timeout_seconds,
+        vec![server_addr],
+        Some(&user_data),
+        &[0; NETCODE_KEY_BYTES],
+    ) {
+        Ok(connect_token) => connect_token,
+        Err(e) => {
+            eprintln!("Failed to make a connect token: {}", e);
+            return;
+        }
+    };
+    let socket = UdpSocket::bind(local_address_for(server_addr)).unwrap();
     if let Ok(transport) = NetcodeClientTransport::new(
         current_time,
-        ClientAuthentication::Unsecure {
-            server_addr: SocketAddr::new(client_settings.address, client_settings.port),
-            client_id: client_id.raw(),
-            user_data: None,
-            protocol_id: 0,
-        },
+        ClientAuthentication::Secure { connect_token },
         socket,
     ) {
         commands.insert_resource(LocalPlayer { client_id });
@@ -43,121 +243,728 @@ fn start_client(mut commands: Commands, client_settings: Res<ClientSettings>) {
     }
 }
 
-fn send_inputs_system(history: Res<InputHistory>, mut client: ResMut<RenetClient>) {
+/// the first of the host's addresses there's a route to, a machine without working IPv6 often
+/// still gets IPv6 addresses for a name. IPv4 goes first when both work, servers listen on it
+/// unless bound to a single IPv6 address, while only dual-stack ones take IPv6
+pub fn resolve_server(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let mut addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .collect();
+    // stable, so each family keeps the order the system prefers
+    addresses.sort_by_key(|address| !address.is_ipv4());
+    addresses
+        .iter()
+        .copied()
+        .find(|address| has_route(*address))
+        .or_else(|| addresses.first().copied())
+        .ok_or_else(|| "it has no addresses".to_string())
+}
+
+/// connecting a UDP socket doesn't send anything, it only fails without a route there
+fn has_route(address: SocketAddr) -> bool {
+    UdpSocket::bind(local_address_for(address))
+        .and_then(|socket| socket.connect(address))
+        .is_ok()
+}
+
+/// any address of the same family, the server can only be reached from one of those
+fn local_address_for(server_addr: SocketAddr) -> SocketAddr {
+    match server_addr {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    }
+}
+
+fn send_inputs_system(
+    client_settings: Res<ClientSettings>,
+    mut ticks_since_send: Local<u32>,
+    mut sends_since_claim: Local<u32>,
+    mut history: ResMut<InputHistory>,
+    mut client: ResMut<RenetClient>,
+) {
+    // players with little upload can trade some input latency for fewer, bigger packets
+    *ticks_since_send += 1;
+    if *ticks_since_send < client_settings.input_send_interval.max(1) {
+        return;
+    }
+    *ticks_since_send = 0;
+
+    *sends_since_claim += 1;
+    let claimed_translation = if *sends_since_claim >= CLAIM_INTERVAL {
+        history
+            .input_groups_for_next_send
+            .last()
+            .and_then(|inputs| inputs.last())
+            .and_then(|input| Some((input.id, history.predicted_translation(input.id)?)))
+    } else {
+        None
+    };
+    if claimed_translation.is_some() {
+        *sends_since_claim = 0;
+    }
+
     if let Ok(encoded) = bincode::serialize(&UnreliableClientMessage::PlayerInputMessage(
         PlayerInputMessage {
             latest_processed_snapshot_id: history.latest_processed_snapshot_id,
-            inputs: history.inputs_for_next_send.clone(),
+            input_groups: std::mem::take(&mut history.input_groups_for_next_send),
+            claimed_translation,
         },
     )) {
         client.send_message(DefaultChannel::Unreliable, encoded);
     }
 }
 
-fn receive_snapshot_system(
-    fixed_time: Res<Time<Fixed>>,
-    local_player: Res<LocalPlayer>,
-    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
-    mut commands: Commands,
-    mut characters: Query<(&mut Character, &mut Transform), Without<CharacterVisuals>>,
-    mut input_history: ResMut<InputHistory>,
+/// what the server says about the session as a whole rather than any one character, together
+/// because a system only takes 16 parameters
+#[derive(SystemParam)]
+struct SessionEvents<'w> {
+    world_clocks: EventWriter<'w, ReceivedWorldClockEvent>,
+    announcements: EventWriter<'w, AnnouncementEvent>,
+    votes: EventWriter<'w, ReceivedVoteEvent>,
+    maps: EventWriter<'w, ReceivedMapEvent>,
+    time_scales: EventWriter<'w, ReceivedTimeScaleEvent>,
+    freezes: EventWriter<'w, ReceivedFreezeEvent>,
+    mutators: EventWriter<'w, ReceivedMutatorsEvent>,
+    seeds: EventWriter<'w, ReceivedMatchSeedEvent>,
+    shot_results: EventWriter<'w, ReceivedShotResultEvent>,
+}
+
+fn receive_reliable_messages_system(
+    mut spawns: EventWriter<SpawnCharacterEvent>,
+    mut despawns: EventWriter<DespawnCharacterEvent>,
+    mut match_events: EventWriter<MatchEvent>,
+    mut chat_messages: EventWriter<ChatMessageEvent>,
+    mut emotes: EventWriter<EmoteEvent>,
+    mut pings: EventWriter<PingEvent>,
+    mut impulses: EventWriter<ReceivedImpulseEvent>,
+    mut resets: EventWriter<ReceivedResetWorldEvent>,
+    mut action_results: EventWriter<ActionResultEvent>,
+    mut inventories: EventWriter<ReceivedInventoryEvent>,
+    mut explosions: EventWriter<ExplosionVisualsEvent>,
+    mut healths: EventWriter<ReceivedHealthEvent>,
+    mut session: SessionEvents,
+    mut local_player: ResMut<LocalPlayer>,
     mut client: ResMut<RenetClient>,
 ) {
-    while let Some(message) = client.receive_message(DefaultChannel::ReliableUnordered) {
+    while let Some(message) = client.receive_message(DefaultChannel::ReliableOrdered) {
         if let Ok(message) = bincode::deserialize::<ReliableServerMessage>(&message) {
             match message {
-                ReliableServerMessage::SpawnCharacter(client_id, translation, velocity) => {
-                    crate::spawn_character(
-                        ClientId::from_raw(client_id),
-                        &mut spawn_visuals,
-                        &mut commands,
+                ReliableServerMessage::Welcome(client_id) => {
+                    let client_id = ClientId::from_raw(client_id);
+                    if local_player.client_id != client_id {
+                        println!("The server knows us as {}", client_id);
+                        local_player.client_id = client_id;
+                    }
+                }
+                ReliableServerMessage::SpawnCharacter(
+                    client_id,
+                    color,
+                    translation,
+                    velocity,
+                    cosmetics,
+                    name,
+                    snapshot_id,
+                ) => {
+                    spawns.send(SpawnCharacterEvent {
+                        network_id: NetworkId(client_id),
+                        color: PlayerColor(color),
                         translation,
                         velocity,
-                    );
+                        cosmetics,
+                        name,
+                        snapshot_id,
+                    });
+                }
+                ReliableServerMessage::DespawnCharacter(client_id) => {
+                    despawns.send(DespawnCharacterEvent(NetworkId(client_id)));
+                }
+                ReliableServerMessage::MatchEnded(result) => {
+                    match_events.send(MatchEvent::Ended(result));
+                }
+                ReliableServerMessage::ShowMatchResults => {
+                    match_events.send(MatchEvent::ShowResults);
+                }
+                ReliableServerMessage::MatchStarted => {
+                    match_events.send(MatchEvent::Started);
+                }
+                ReliableServerMessage::Chat(sender, text) => {
+                    chat_messages.send(ChatMessageEvent {
+                        sender: sender.map(ClientId::from_raw),
+                        text,
+                    });
+                }
+                ReliableServerMessage::Emote(client_id, emote) => {
+                    emotes.send(EmoteEvent {
+                        client_id: ClientId::from_raw(client_id),
+                        emote,
+                        action_id: None,
+                    });
+                }
+                ReliableServerMessage::Ping(client_id, position) => {
+                    pings.send(PingEvent {
+                        client_id: ClientId::from_raw(client_id),
+                        position,
+                    });
+                }
+                ReliableServerMessage::Impulse(after_input_id, impulse) => {
+                    if impulse.is_finite() {
+                        impulses.send(ReceivedImpulseEvent {
+                            after_input_id,
+                            impulse,
+                        });
+                    } else {
+                        report_non_finite("impulse");
+                    }
+                }
+                ReliableServerMessage::ResetWorld(first_snapshot_id) => {
+                    resets.send(ReceivedResetWorldEvent(first_snapshot_id));
+                }
+                ReliableServerMessage::ActionResult(action_id, accepted) => {
+                    action_results.send(ActionResultEvent {
+                        action_id,
+                        accepted,
+                    });
+                }
+                ReliableServerMessage::Inventory(up_to_action_id, inventory) => {
+                    inventories.send(ReceivedInventoryEvent {
+                        up_to_action_id,
+                        inventory,
+                    });
+                }
+                ReliableServerMessage::Explosion(center, radius) => {
+                    if center.is_finite() && radius.is_finite() {
+                        explosions.send(ExplosionVisualsEvent { center, radius });
+                    } else {
+                        report_non_finite("explosion");
+                    }
+                }
+                ReliableServerMessage::Health(health) => {
+                    healths.send(ReceivedHealthEvent(health));
+                }
+                ReliableServerMessage::WorldClock(snapshot_id, tick) => {
+                    session
+                        .world_clocks
+                        .send(ReceivedWorldClockEvent { snapshot_id, tick });
+                }
+                ReliableServerMessage::Announcement(text) => {
+                    session.announcements.send(AnnouncementEvent(text));
+                }
+                ReliableServerMessage::Vote(status) => {
+                    session.votes.send(ReceivedVoteEvent(status));
+                }
+                ReliableServerMessage::Map(name) => {
+                    session.maps.send(ReceivedMapEvent(name));
+                }
+                ReliableServerMessage::TimeScale(scale) => {
+                    session.time_scales.send(ReceivedTimeScaleEvent(scale));
+                }
+                ReliableServerMessage::Freeze(frozen, steps) => {
+                    session.freezes.send(ReceivedFreezeEvent { frozen, steps });
+                }
+                ReliableServerMessage::Mutators(mutators) => {
+                    session.mutators.send(ReceivedMutatorsEvent(mutators));
+                }
+                ReliableServerMessage::MatchSeed(seed) => {
+                    session.seeds.send(ReceivedMatchSeedEvent(seed));
+                }
+                ReliableServerMessage::ShotResult(input_id, hit) => {
+                    session.shot_results.send(ReceivedShotResultEvent {
+                        input_id,
+                        hit: hit.map(|(client_id, part)| (ClientId::from_raw(client_id), part)),
+                    });
                 }
             }
         }
     }
+}
+
+/// every client has every map, only the name is sent. without the map we'd be predicting on
+/// the wrong one, so there's nothing better to do than say so
+fn change_map_system(mut received: EventReader<ReceivedMapEvent>, mut map: ResMut<Map>) {
+    for ReceivedMapEvent(name) in received.read() {
+        if *name == map.name {
+            continue;
+        }
+        match Map::load_named(name) {
+            Ok(new_map) => {
+                println!("Server changed the map to {}", name);
+                *map = new_map;
+            }
+            Err(e) => eprintln!("Failed to load map {}: {}", name, e),
+        }
+    }
+}
+
+/// everything we had goes, predictions included. the spawns that come right after the reset
+/// build the new world, and anything still in flight from the old one is ignored
+fn reset_world_system(
+    mut commands: Commands,
+    mut resets: EventReader<ReceivedResetWorldEvent>,
+    mut input_history: ResMut<InputHistory>,
+    mut staging: ResMut<SnapshotStaging>,
+    mut index: ResMut<CharacterIndex>,
+    mut received_components: ResMut<ReceivedComponents>,
+    characters: Query<Entity, With<Character>>,
+) {
+    let first_snapshot_id = match resets.read().last() {
+        Some(ReceivedResetWorldEvent(first_snapshot_id)) => *first_snapshot_id,
+        None => return,
+    };
+
+    // visuals notice their character is gone and follow it
+    for entity in characters.iter() {
+        commands.entity(entity).despawn();
+    }
+    index.entities.clear();
+    index.spawn_snapshot_ids.clear();
+    received_components.clear();
+
+    // input ids keep counting so nothing from before can be mistaken for an ack of a new one.
+    // acking the last old snapshot gets us a full one, the server has no old baselines left
+    *input_history = InputHistory {
+        next_id: input_history.next_id,
+        latest_processed_snapshot_id: first_snapshot_id.checked_sub(1),
+        ..default()
+    };
+    staging
+        .snapshots
+        .retain(|snapshot| snapshot.id >= first_snapshot_id);
+}
+
+/// runs before spawning, so a despawn followed by a spawn in the same tick leaves a character
+fn despawn_characters_system(
+    mut despawns: EventReader<DespawnCharacterEvent>,
+    mut commands: Commands,
+    mut index: ResMut<CharacterIndex>,
+) {
+    for DespawnCharacterEvent(network_id) in despawns.read() {
+        index.spawn_snapshot_ids.remove(network_id);
+        if let Some(entity) = index.entities.remove(network_id) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// the server can send a spawn for a character we already have (reconnects, duplicate delivery),
+/// in which case the existing one is moved instead of spawning a second
+fn spawn_characters_system(
+    local_player: Res<LocalPlayer>,
+    mut spawns: EventReader<SpawnCharacterEvent>,
+    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
+    mut commands: Commands,
+    mut index: ResMut<CharacterIndex>,
+    mut characters: Query<
+        (&mut Character, &mut Transform, &mut PlayerColor),
+        Without<CharacterVisuals>,
+    >,
+) {
+    // only the latest spawn for a character not yet in the world counts, a duplicate in the
+    // same tick couldn't be queried until commands are applied
+    let mut pending = HashMap::<NetworkId, &SpawnCharacterEvent>::new();
+
+    for event in spawns.read() {
+        index
+            .spawn_snapshot_ids
+            .insert(event.network_id, event.snapshot_id);
+        let existing = index.entities.get(&event.network_id).and_then(|entity| {
+            characters
+                .get_mut(*entity)
+                .ok()
+                .map(|existing| (*entity, existing))
+        });
+        if let Some((entity, (mut character, mut transform, mut color))) = existing {
+            transform.translation = event.translation;
+            character.velocity = event.velocity;
+            *color = event.color;
+            commands
+                .entity(entity)
+                .insert((event.cosmetics, PlayerName(event.name.clone())));
+        } else {
+            pending.insert(event.network_id, event);
+        }
+    }
+
+    for (network_id, event) in pending {
+        let owner_client_id = ClientId::from_raw(network_id.0);
+        let entity = crate::spawn_character(
+            owner_client_id,
+            event.color,
+            &mut spawn_visuals,
+            &mut commands,
+            event.translation,
+            event.velocity,
+        );
+        commands
+            .entity(entity)
+            .insert((event.cosmetics, PlayerName(event.name.clone())));
+        if owner_client_id == local_player.client_id {
+            commands.entity(entity).insert(Predicted);
+        } else {
+            commands.entity(entity).insert(SnapshotBuffer::default());
+        }
+        index.entities.insert(network_id, entity);
+    }
+}
+
+#[derive(Component)]
+struct HardSnapIndicator {
+    remaining: f32,
+}
+
+fn setup_hard_snap_indicator_system(mut commands: Commands) {
+    commands.spawn((
+        HardSnapIndicator { remaining: 0.0 },
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::RED,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn hard_snap_indicator_system(
+    time: Res<Time>,
+    localization: Res<Localization>,
+    mut hard_snaps: EventReader<HardSnapEvent>,
+    mut query: Query<(&mut HardSnapIndicator, &mut Text, &mut Visibility)>,
+) {
+    for (mut indicator, mut text, mut visibility) in query.iter_mut() {
+        for event in hard_snaps.read() {
+            indicator.remaining = HARD_SNAP_INDICATOR_DURATION;
+            text.sections[0].value =
+                localization.format("hud.hard_snap", &[&format!("{:.1}", event.distance)]);
+        }
+
+        indicator.remaining -= time.delta_seconds();
+        *visibility = if indicator.remaining > 0.0 {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn receive_snapshots_system(
+    mut staging: ResMut<SnapshotStaging>,
+    mut snapshots_received: ResMut<SnapshotsReceived>,
+    mut debug_draws: EventWriter<ReceivedDebugDrawEvent>,
+    mut time_responses: EventWriter<ReceivedTimeResponseEvent>,
+    mut heartbeats: EventWriter<ReceivedHeartbeatEvent>,
+    mut client: ResMut<RenetClient>,
+) {
     while let Some(message) = client.receive_message(DefaultChannel::Unreliable) {
         if let Ok(message) = bincode::deserialize::<UnreliableServerMessage>(&message) {
             match message {
                 UnreliableServerMessage::Snapshot(snapshot) => {
-                    let should_process = if let Some(latest_processed_snapshot_id) =
-                        input_history.latest_processed_snapshot_id
-                    {
-                        snapshot.id > latest_processed_snapshot_id
+                    if snapshot.is_finite() {
+                        staging.snapshots.push(*snapshot);
+                        snapshots_received.0 += 1;
                     } else {
-                        true
-                    };
+                        report_non_finite("snapshot");
+                    }
+                }
+                UnreliableServerMessage::DebugDraw(shapes) => {
+                    debug_draws.send(ReceivedDebugDrawEvent(shapes));
+                }
+                UnreliableServerMessage::TimeResponse(sent_at, server_tick) => {
+                    time_responses.send(ReceivedTimeResponseEvent {
+                        sent_at,
+                        server_tick,
+                    });
+                }
+                UnreliableServerMessage::Heartbeat => {
+                    heartbeats.send(ReceivedHeartbeatEvent);
+                }
+            }
+        }
+    }
+}
+
+fn apply_snapshots_system(
+    mut commands: Commands,
+    fixed_time: Res<Time<Fixed>>,
+    map: Res<Map>,
+    mutators: Res<Mutators>,
+    mut index: ResMut<CharacterIndex>,
+    mut characters: Query<
+        (
+            &mut Character,
+            &mut Transform,
+            Has<Predicted>,
+            Option<&mut SnapshotBuffer>,
+        ),
+        Without<CharacterVisuals>,
+    >,
+    mut input_history: ResMut<InputHistory>,
+    mut staging: ResMut<SnapshotStaging>,
+    mut received_components: ResMut<ReceivedComponents>,
+    mut prediction_error: ResMut<PredictionError>,
+    mut reconciliation_error: ResMut<ReconciliationError>,
+    mut hard_snaps: EventWriter<HardSnapEvent>,
+) {
+    // packets can arrive out of order
+    let mut snapshots = std::mem::take(&mut staging.snapshots);
+    snapshots.sort_by_key(|snapshot| snapshot.id);
+
+    for snapshot in snapshots {
+        let should_process = if let Some(latest_processed_snapshot_id) =
+            input_history.latest_processed_snapshot_id
+        {
+            snapshot.id > latest_processed_snapshot_id
+        } else {
+            true
+        };
 
-                    if !should_process {
+        if !should_process {
+            continue;
+        }
+
+        input_history.latest_processed_snapshot_id = Some(snapshot.id);
+        if let Some((input_id, error)) = snapshot.reconciliation {
+            let distance = error.length();
+            reconciliation_error.latest = distance;
+            reconciliation_error.worst = reconciliation_error.worst.max(distance);
+            if distance > RECONCILIATION_LOG_DISTANCE {
+                println!(
+                    "The server ended input {} {:.3} away from us ({:.3}, {:.3}, {:.3})",
+                    input_id, distance, error.x, error.y, error.z
+                );
+            }
+        }
+        if snapshot.latest_processed_input_id.is_some() {
+            input_history.latest_acked_input_id = snapshot.latest_processed_input_id;
+            // anything applied up to the acked input is already part of the snapshot state
+            let latest_acked_input_id = input_history.latest_acked_input_id;
+            input_history
+                .impulses
+                .retain(|impulse| match impulse.after_input_id {
+                    Some(_) => impulse.after_input_id > latest_acked_input_id,
+                    None => latest_acked_input_id < Some(impulse.arrived_before_input_id),
+                });
+            // the acked one is still compared with the server's result below
+            input_history
+                .predictions
+                .retain(|(input_id, _)| Some(*input_id) >= latest_acked_input_id);
+        }
+
+        received_components.receive(&snapshot.components);
+
+        // the same as a despawn message, which may still be on its way. one from before the
+        // character was spawned again is about the one before
+        for client_id in &snapshot.removed_client_ids {
+            let network_id = NetworkId(*client_id);
+            if index
+                .spawn_snapshot_ids
+                .get(&network_id)
+                .is_some_and(|spawn_snapshot_id| *spawn_snapshot_id > snapshot.id)
+            {
+                continue;
+            }
+            index.spawn_snapshot_ids.remove(&network_id);
+            if let Some(entity) = index.entities.remove(&network_id) {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        for character_snapshot in snapshot.character_snapshots {
+            let existing = index
+                .entities
+                .get(&NetworkId(character_snapshot.client_id))
+                .and_then(|entity| characters.get_mut(*entity).ok());
+            if let Some((mut character, mut character_transform, predicted, buffer)) = existing {
+                if predicted {
+                    // predict with whatever the server says we move like from now on
+                    character_snapshot.apply_movement(&mut character);
+
+                    // the server moved us on purpose, smoothing or replaying over it would
+                    // just fight the correction
+                    if character_snapshot.is_teleport(&character) {
+                        let distance = character_snapshot.translation.map_or(0.0, |translation| {
+                            translation.distance(character_transform.translation)
+                        });
+                        character_snapshot.apply(&mut character, &mut character_transform);
+                        input_history.input_groups.clear();
+                        input_history.predictions.clear();
+                        hard_snaps.send(HardSnapEvent { distance });
                         continue;
                     }
 
-                    input_history.latest_processed_snapshot_id = Some(snapshot.id);
-
-                    for character_snapshot in snapshot.character_snapshots {
-                        let client_id = ClientId::from_raw(character_snapshot.client_id);
-                        if let Some((mut character, mut character_transform)) = characters
-                            .iter_mut()
-                            .find(|(character, _)| character.owner_client_id == client_id)
+                    if let Some(snapshot_translation) = character_snapshot.translation {
+                        if let Some(latest_processed_input_id) = snapshot.latest_processed_input_id
                         {
-                            if client_id == local_player.client_id {
-                                if character_snapshot.translation.is_some() {
-                                    if let Some(latest_processed_input_id) =
-                                        snapshot.latest_processed_input_id
-                                    {
-                                        if let Some(latest_processed_input) = input_history
-                                            .input_groups
-                                            .iter()
-                                            .flat_map(|inputs| inputs.iter())
-                                            .find(|input| input.id == latest_processed_input_id)
-                                        {
-                                            let dist_diff = character_snapshot
-                                                .translation
-                                                .unwrap()
-                                                .distance_squared(
-                                                    latest_processed_input.final_translation,
-                                                );
+                            if let Some(predicted_translation) =
+                                input_history.predicted_translation(latest_processed_input_id)
+                            {
+                                let dist_diff =
+                                    snapshot_translation.distance_squared(predicted_translation);
+                                prediction_error.latest = dist_diff.sqrt();
+                                prediction_error.worst =
+                                    prediction_error.worst.max(prediction_error.latest);
 
-                                            if dist_diff > 0.0001 {
-                                                let pitch = character.pitch;
-                                                let yaw = character.yaw;
-                                                // correct the character's position
-                                                character_snapshot.apply(
-                                                    &mut character,
+                                if dist_diff > HARD_SNAP_DISTANCE * HARD_SNAP_DISTANCE {
+                                    // replaying inputs from a spot we were never at is pointless
+                                    character_snapshot
+                                        .apply(&mut character, &mut character_transform);
+                                    input_history.input_groups.clear();
+                                    input_history.predictions.clear();
+                                    hard_snaps.send(HardSnapEvent {
+                                        distance: dist_diff.sqrt(),
+                                    });
+                                } else if dist_diff > 0.0001 {
+                                    // correct the character's position
+                                    character_snapshot
+                                        .apply(&mut character, &mut character_transform);
+                                    // replay all input groups since the last processed input,
+                                    // each one with the view angles it was captured with.
+                                    // the camera reads `ViewAngles`, so this doesn't yank it around
+                                    let impulses = input_history.impulses.clone();
+                                    // the server pushed before any input it still had
+                                    for impulse in impulses
+                                        .iter()
+                                        .filter(|impulse| impulse.after_input_id.is_none())
+                                    {
+                                        character.velocity += impulse.impulse;
+                                    }
+                                    let mut replayed = Vec::new();
+                                    for input_group in input_history.input_groups.iter_mut() {
+                                        let chopped_delta =
+                                            fixed_time.delta_seconds() / input_group.len() as f32;
+                                        for input in input_group.iter_mut() {
+                                            if input.id > latest_processed_input_id {
+                                                character.process_input(
+                                                    input,
                                                     &mut character_transform,
+                                                    &map,
+                                                    &mutators,
+                                                    chopped_delta,
                                                 );
-                                                // replay all input groups since the last processed input
-                                                for input_group in
-                                                    input_history.input_groups.iter_mut()
-                                                {
-                                                    let chopped_delta = fixed_time.delta_seconds()
-                                                        / input_group.len() as f32;
-                                                    for mut input in input_group.iter_mut() {
-                                                        if input.id > latest_processed_input_id {
-                                                            character.process_input(
-                                                                &mut input,
-                                                                &mut character_transform,
-                                                                chopped_delta,
-                                                            );
-                                                        }
-                                                    }
+                                                // pushed at the same point the server did
+                                                for impulse in impulses.iter().filter(|impulse| {
+                                                    impulse.after_input_id == Some(input.id)
+                                                }) {
+                                                    character.velocity += impulse.impulse;
                                                 }
-
-                                                character.pitch = pitch;
-                                                character.yaw = yaw;
+                                                replayed.push((
+                                                    input.id,
+                                                    character_transform.translation,
+                                                ));
                                             }
                                         }
                                     }
+                                    for (input_id, translation) in replayed {
+                                        input_history.record_prediction(input_id, translation);
+                                    }
                                 }
-                            } else {
-                                character_snapshot.apply(&mut character, &mut character_transform);
                             }
                         }
                     }
+                } else {
+                    let teleported = character_snapshot.is_teleport(&character);
+                    character_snapshot.apply(&mut character, &mut character_transform);
+                    if let Some(mut buffer) = buffer.filter(|_| teleported) {
+                        buffer.clear();
+                    }
                 }
             }
         }
+
+        // the ones left out of a diff are where they were
+        for (_, transform, _, buffer) in characters.iter_mut() {
+            if let Some(mut buffer) = buffer {
+                buffer.push(snapshot.id, transform.translation);
+            }
+        }
+    }
+}
+
+/// anything that moved a character we don't predict since the last tick got it wrong, whatever
+/// it was is undone so it can't drift away from the server's until the next snapshot
+fn enforce_replication_system(
+    mut reported: Local<u32>,
+    mut characters: Query<(&mut Character, &mut Transform, &Replicated), Without<Predicted>>,
+) {
+    for (mut character, mut transform, replicated) in characters.iter_mut() {
+        if transform.translation == replicated.translation
+            && character.velocity == replicated.velocity
+        {
+            continue;
+        }
+        *reported += 1;
+        if *reported <= 10 || reported.is_power_of_two() {
+            eprintln!(
+                "Undid a change to {}'s replicated character ({} so far)",
+                character.owner_client_id, *reported
+            );
+        }
+        transform.translation = replicated.translation;
+        character.velocity = replicated.velocity;
+    }
+}
+
+fn record_replication_system(
+    mut commands: Commands,
+    mut characters: Query<
+        (Entity, &Character, &Transform, Option<&mut Replicated>),
+        Without<Predicted>,
+    >,
+) {
+    for (entity, character, transform, replicated) in characters.iter_mut() {
+        match replicated {
+            Some(mut replicated) => {
+                replicated.translation = transform.translation;
+                replicated.velocity = character.velocity;
+            }
+            None => {
+                commands.entity(entity).insert(Replicated {
+                    translation: transform.translation,
+                    velocity: character.velocity,
+                });
+            }
+        }
+    }
+}
+
+/// pushes the server gave our character, applied now and remembered for later replays
+fn apply_impulses_system(
+    local_player: Res<LocalPlayer>,
+    mut impulses: EventReader<ReceivedImpulseEvent>,
+    mut input_history: ResMut<InputHistory>,
+    mut characters: Query<&mut Character, With<Predicted>>,
+) {
+    for event in impulses.read() {
+        // a snapshot that already includes the push got here first
+        if event.after_input_id.is_some()
+            && event.after_input_id <= input_history.latest_acked_input_id
+        {
+            continue;
+        }
+        let impulse = Impulse {
+            after_input_id: event.after_input_id,
+            arrived_before_input_id: input_history.next_id,
+            impulse: event.impulse,
+        };
+
+        if let Some(mut character) = characters
+            .iter_mut()
+            .find(|character| character.owner_client_id == local_player.client_id)
+        {
+            character.velocity += impulse.impulse;
+        }
+        input_history.impulses.push(impulse);
+        evict_oldest(&mut input_history.impulses, MAX_IMPULSES, "impulses");
     }
 }
@@ -0,0 +1,144 @@
+use crate::{
+    chat::ChatInput,
+    localization::Localization,
+    settings::{ActionMode, Settings},
+};
+use bevy::{
+    input::keyboard::{Key, KeyboardInput},
+    prelude::*,
+    utils::HashMap,
+};
+
+const CONTROLS_KEY: KeyCode = KeyCode::F1;
+
+/// an overlay listing the bindings, labelled with what the keys say on the player's keyboard
+pub struct ControlsPlugin;
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeyLabels>();
+        app.add_systems(Startup, setup_controls_text);
+        app.add_systems(
+            Update,
+            (record_key_labels_system, controls_text_system).chain(),
+        );
+    }
+}
+
+/// what each physical key produced the last time it was pressed. the OS doesn't hand us the
+/// layout up front, so keys nobody has pressed yet fall back to their US QWERTY name
+#[derive(Resource, Default)]
+pub struct KeyLabels {
+    labels: HashMap<KeyCode, String>,
+}
+
+impl KeyLabels {
+    pub fn label(&self, key_code: KeyCode) -> String {
+        match self.labels.get(&key_code) {
+            Some(label) => label.clone(),
+            None => qwerty_label(key_code),
+        }
+    }
+}
+
+/// `KeyW` -> `W`, `Digit1` -> `1`, anything else keeps its name
+fn qwerty_label(key_code: KeyCode) -> String {
+    let name = format!("{:?}", key_code);
+    match name
+        .strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+    {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
+
+fn record_key_labels_system(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut key_labels: ResMut<KeyLabels>,
+) {
+    for event in keyboard_events.read() {
+        // named keys (shift, space...) are the same everywhere, only characters move around
+        if let Key::Character(character) = &event.logical_key {
+            let label = character.to_uppercase();
+            if key_labels.labels.get(&event.key_code) != Some(&label) {
+                key_labels.labels.insert(event.key_code, label);
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct ControlsText;
+
+fn setup_controls_text(mut commands: Commands) {
+    commands.spawn((
+        ControlsText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn controls_text_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    settings: Res<Settings>,
+    key_labels: Res<KeyLabels>,
+    localization: Res<Localization>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<ControlsText>>,
+) {
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active);
+    for (mut text, mut visibility) in texts.iter_mut() {
+        if !typing && keyboard.just_pressed(CONTROLS_KEY) {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let bindings = &settings.bindings;
+        let keys = bindings.keys;
+        let mode = |mode: ActionMode| match mode {
+            ActionMode::Hold => localization.get("controls.hold"),
+            ActionMode::Toggle => localization.get("controls.toggle"),
+        };
+        let lines = [
+            localization.format("controls.forward", &[&key_labels.label(keys.forward)]),
+            localization.format("controls.backward", &[&key_labels.label(keys.backward)]),
+            localization.format("controls.left", &[&key_labels.label(keys.left)]),
+            localization.format("controls.right", &[&key_labels.label(keys.right)]),
+            localization.format("controls.jump", &[&key_labels.label(keys.jump)]),
+            localization.format(
+                "controls.crouch",
+                &[&key_labels.label(keys.crouch), &mode(bindings.crouch)],
+            ),
+            localization.format(
+                "controls.sprint",
+                &[&key_labels.label(keys.sprint), &mode(bindings.sprint)],
+            ),
+            localization.format("controls.zoom", &[&mode(bindings.zoom)]),
+        ];
+        let value = lines.join("\n");
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
@@ -1,10 +1,60 @@
-use std::{net::IpAddr, time::Instant};
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use bevy::prelude::*;
+use crate::{
+    afk::AfkAction,
+    map::Map,
+    movement,
+    palette::PlayerColor,
+    weapon::{WeaponDefinition, WeaponDefinitions},
+    CHARACTER_HEIGHT, CHARACTER_RADIUS, CROUCH_SPEED_MULTIPLIER, SPRINT_SPEED_MULTIPLIER,
+};
+use bevy::{prelude::*, utils::HashMap};
 use bevy_renet::renet::ClientId;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Clone)]
+/// how many NaN/infinite values were caught since startup, whether dropped or sanitized
+static NON_FINITE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn non_finite_count() -> u64 {
+    NON_FINITE_COUNT.load(Ordering::Relaxed)
+}
+
+/// one NaN usually comes with many, so only the first few and then every power of two are logged
+pub fn report_non_finite(what: &str) {
+    let count = NON_FINITE_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    if count <= 10 || count.is_power_of_two() {
+        eprintln!("Caught non-finite {} ({} so far)", what, count);
+    }
+}
+
+/// how many history entries were thrown away for going over their cap since startup
+static EVICTED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn evicted_count() -> u64 {
+    EVICTED_COUNT.load(Ordering::Relaxed)
+}
+
+/// drops the oldest entries beyond `max`. histories are normally trimmed by age long before
+/// this, so it only happens when timestamps misbehave or a client floods us
+pub fn evict_oldest<T>(history: &mut Vec<T>, max: usize, what: &str) {
+    if history.len() <= max {
+        return;
+    }
+    let excess = history.len() - max;
+    history.drain(..excess);
+    let previous = EVICTED_COUNT.fetch_add(excess as u64, Ordering::Relaxed);
+    let count = previous + excess as u64;
+    // like non-finite values, only the first few and then every power of two are logged
+    if previous < 10 || previous.leading_zeros() != count.leading_zeros() {
+        eprintln!("Evicted {} old {} ({} so far)", excess, what, count);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct PlayerInput {
     // networked
     pub id: u32,
@@ -13,13 +63,13 @@ pub struct PlayerInput {
     pub left: bool,
     pub right: bool,
     pub jump: bool,
+    pub crouch: bool,
+    pub sprint: bool,
+    pub zoom: bool,
     pub pitch: f32,
     pub yaw: f32,
 
     // not networked
-    #[serde(skip)]
-    pub final_translation: Vec3,
-
     #[serde(skip)]
     pub timestamp: u128,
 }
@@ -44,6 +94,35 @@ impl PlayerInput {
         }
         direction
     }
+
+    pub fn is_finite(&self) -> bool {
+        self.pitch.is_finite() && self.yaw.is_finite()
+    }
+
+    /// whether this shows someone at the keyboard rather than the last input repeating
+    pub fn is_activity_since(&self, previous: &PlayerInput) -> bool {
+        self.forward
+            || self.backward
+            || self.left
+            || self.right
+            || self.jump
+            || self.crouch
+            || self.sprint
+            || self.zoom
+            || self.pitch != previous.pitch
+            || self.yaw != previous.yaw
+    }
+
+    /// crouching wins over sprinting
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.crouch {
+            CROUCH_SPEED_MULTIPLIER
+        } else if self.sprint {
+            SPRINT_SPEED_MULTIPLIER
+        } else {
+            1.0
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -56,22 +135,83 @@ pub struct PlayerInputOutcome {
 /// what we send to the server
 pub struct PlayerInputMessage {
     pub latest_processed_snapshot_id: Option<u32>,
-    pub inputs: Vec<PlayerInput>,
+    /// one group per fixed tick since the last send, oldest first
+    pub input_groups: Vec<Vec<PlayerInput>>,
+    /// every so often, an input's id and where we predicted it left us, for the server to
+    /// compare with where it actually did
+    pub claimed_translation: Option<(u32, Vec3)>,
 }
 
+/// about two seconds of fixed ticks, when trimming by age keeps only one
+pub const MAX_INPUT_GROUPS: usize = 128;
+/// snapshot views kept per client, also about two seconds
+pub const MAX_SNAPSHOT_VIEWS: usize = 128;
+/// unconfirmed pushes, a handful at a time is already a lot
+pub const MAX_IMPULSES: usize = 64;
+
 #[derive(Resource, Default)]
 pub struct InputHistory {
     pub next_id: u32,
-    pub input_group_for_next_fixed_tick: Vec<PlayerInput>,
-    pub inputs_for_next_send: Vec<PlayerInput>,
+    /// everything captured since the last fixed tick, turned into that tick's input
+    pub pending_input: Option<PlayerInput>,
+    pub input_groups_for_next_send: Vec<Vec<PlayerInput>>,
     pub input_groups: Vec<Vec<PlayerInput>>,
-    pub latest_processed_input_id: u32,
     pub latest_processed_snapshot_id: Option<u32>,
+    /// the latest of our inputs the server says it has processed
+    pub latest_acked_input_id: Option<u32>,
+    /// impulses the server hasn't confirmed in a snapshot yet, replayed along with the inputs
+    pub impulses: Vec<Impulse>,
+    /// where each of our inputs the server hasn't acked yet left us, by input id. only ever
+    /// compared with the server's result here, the server never sees them
+    pub predictions: Vec<(u32, Vec3)>,
+}
+
+impl InputHistory {
+    /// remembers where an input left us, over what an earlier replay of it thought
+    pub fn record_prediction(&mut self, input_id: u32, translation: Vec3) {
+        match self
+            .predictions
+            .iter_mut()
+            .find(|(predicted_id, _)| *predicted_id == input_id)
+        {
+            Some((_, predicted)) => *predicted = translation,
+            None => {
+                self.predictions.push((input_id, translation));
+                evict_oldest(&mut self.predictions, MAX_INPUT_GROUPS, "predictions");
+            }
+        }
+    }
+
+    pub fn predicted_translation(&self, input_id: u32) -> Option<Vec3> {
+        self.predictions
+            .iter()
+            .find(|(predicted_id, _)| *predicted_id == input_id)
+            .map(|(_, translation)| *translation)
+    }
+}
+
+/// a push the server gave a character, applied right after the owner's input `after_input_id`.
+/// none means before any of the owner's inputs the server still had (after a reset or a freeze)
+#[derive(Clone, Copy)]
+pub struct Impulse {
+    pub after_input_id: Option<u32>,
+    /// the first input we captured after it arrived, the server can only have processed that
+    /// one after the push
+    pub arrived_before_input_id: u32,
+    pub impulse: Vec3,
+}
+
+/// asks the server to push a character (explosions, knockback)
+#[derive(Event)]
+pub struct ImpulseEvent {
+    pub client_id: ClientId,
+    pub impulse: Vec3,
 }
 
 #[derive(Resource, Default)]
 pub struct SnapshotHistory {
-    pub snapshots: Vec<Snapshot>,
+    /// per client, the full state they end up with after applying each snapshot sent to them
+    pub client_views: HashMap<ClientId, Vec<Snapshot>>,
     pub next_id: u32,
 }
 
@@ -86,15 +226,54 @@ impl LocalPlayer {
     }
 }
 
+/// ids below this never come over the network: 0 is the host itself, 1 a split-screen guest or
+/// the practice player
+pub const RESERVED_CLIENT_IDS: u64 = 16;
+
 #[derive(Resource)]
 pub struct ClientSettings {
     pub address: IpAddr,
     pub port: u16,
+    /// how many fixed ticks of inputs go into each message sent to the server
+    pub input_send_interval: u32,
+    /// seconds without a packet before either end gives up on the connection, never if 0
+    pub timeout_seconds: i32,
+    /// seconds without hearing from the server before the connection is shown as unstable
+    pub unstable_after: f32,
 }
 
 #[derive(Resource)]
 pub struct ServerSettings {
+    /// `::` takes IPv4 as well where the system maps it onto IPv6 sockets, as Linux and macOS
+    /// do unless told otherwise
+    pub bind: IpAddr,
     pub port: u16,
+    /// outgoing bytes per second each client is allowed
+    pub client_bandwidth: u32,
+    /// where to record a demo of every match, if at all
+    pub demo_dir: Option<PathBuf>,
+    /// seconds without real input before `afk_action` is taken, never if unset
+    pub afk_timeout: Option<f32>,
+    pub afk_action: AfkAction,
+    /// a snapshot goes out every this many fixed ticks
+    pub snapshot_interval: u32,
+    /// shown to everyone as they join
+    pub motd: Option<String>,
+    /// clients whose cheat score reaches this are kicked, never if unset
+    pub cheat_kick_score: Option<f32>,
+}
+
+#[derive(Resource)]
+pub struct DemoSettings {
+    pub path: PathBuf,
+}
+
+/// where the local player is looking right now, owned by input capture and the camera.
+/// `Character::pitch`/`yaw` are simulation state instead and get rewritten by replays
+#[derive(Component, Default, Clone, Copy)]
+pub struct ViewAngles {
+    pub pitch: f32,
+    pub yaw: f32,
 }
 
 #[derive(Component)]
@@ -106,92 +285,316 @@ pub struct Character {
     pub velocity: Vec3,
     pub pitch: f32,
     pub yaw: f32,
+    /// bumped by the server whenever it moves the character on purpose
+    pub teleport_count: u32,
+    /// zoomed in, shown to others as a pose and there for accuracy modifiers
+    pub aiming: bool,
+    /// put on by the server, worn off by the character's own ticks so prediction agrees
+    pub status_effects: Vec<StatusEffect>,
+    /// climbing a low wall, input does nothing else until it's over
+    pub mantle: Option<Mantle>,
+}
+
+/// a climb onto or over a low wall, on rails from `start` to `end` so prediction and the server
+/// put the character in the same place on every tick of it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Mantle {
+    pub start: Vec3,
+    pub end: Vec3,
+    /// the height it goes up to on the way, just over the top of the wall
+    pub peak: f32,
+    /// seconds since it started
+    pub elapsed: f32,
+}
+
+impl Mantle {
+    pub fn is_finite(&self) -> bool {
+        self.start.is_finite()
+            && self.end.is_finite()
+            && self.peak.is_finite()
+            && self.elapsed.is_finite()
+    }
+}
+
+/// how far over the feet the top of a wall can be and still be walked onto instead of into
+const STEP_HEIGHT: f32 = 0.05;
+
+/// how far ahead a wall can be and still be climbed
+const MANTLE_REACH: f32 = 0.6;
+/// the highest a wall's top can be above the feet
+const MANTLE_MAX_HEIGHT: f32 = 1.0;
+/// walls up to this thick are vaulted over, thicker ones climbed onto
+const VAULT_MAX_DEPTH: f32 = 0.75;
+/// how far past the edge the climb puts the character down
+const MANTLE_LANDING: f32 = 0.3;
+/// how far over the top the feet go on the way
+const MANTLE_PEAK_CLEARANCE: f32 = 0.1;
+const MANTLE_DURATION: f32 = 0.45;
+/// straight up, friction and gravity bring it down to about half a meter
+const JUMP_SPEED: f32 = 7.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatusEffectKind {
+    SpeedBoost,
+    Slow,
+    Burn,
+}
+
+impl StatusEffectKind {
+    pub const ALL: [Self; 3] = [Self::SpeedBoost, Self::Slow, Self::Burn];
+
+    /// what goes over the network instead of the name
+    pub fn id(self) -> u8 {
+        match self {
+            Self::SpeedBoost => 0,
+            Self::Slow => 1,
+            Self::Burn => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// there's no health to take yet, so a burn only hobbles
+    pub fn speed_multiplier(self) -> f32 {
+        match self {
+            Self::SpeedBoost => 1.5,
+            Self::Slow => 0.5,
+            Self::Burn => 0.8,
+        }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusEffectKind,
+    /// in seconds
+    pub remaining: f32,
+}
+
+/// a status effect as it's replicated: 3 bytes instead of a name and a float
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct NetStatusEffect {
+    pub id: u8,
+    /// in tenths of a second
+    pub remaining: u16,
+}
+
+impl NetStatusEffect {
+    pub fn from_status_effect(status_effect: &StatusEffect) -> Self {
+        Self {
+            id: status_effect.kind.id(),
+            remaining: (status_effect.remaining * 10.0)
+                .ceil()
+                .clamp(0.0, u16::MAX as f32) as u16,
+        }
+    }
+
+    /// unknown ids are dropped, an older client shouldn't choke on a newer effect
+    pub fn to_status_effect(self) -> Option<StatusEffect> {
+        StatusEffectKind::from_id(self.id).map(|kind| StatusEffect {
+            kind,
+            remaining: self.remaining as f32 / 10.0,
+        })
+    }
+}
+
+/// only kept up to date on the server, and on clients for their own character
+#[derive(Component)]
+pub struct Health(pub f32);
+
+/// what `Health` goes back to, from the character's class. only on the server
+#[derive(Component)]
+pub struct MaxHealth(pub f32);
+
+/// damage is not applied to characters with this, toggled by admins
+#[derive(Component)]
+pub struct GodMode;
+
 impl Character {
+    /// the same kind again only ever lengthens what's left of it
+    pub fn add_status_effect(&mut self, kind: StatusEffectKind, duration: f32) {
+        if !duration.is_finite() || duration <= 0.0 {
+            return;
+        }
+        match self
+            .status_effects
+            .iter_mut()
+            .find(|status_effect| status_effect.kind == kind)
+        {
+            Some(status_effect) => status_effect.remaining = status_effect.remaining.max(duration),
+            None => self.status_effects.push(StatusEffect {
+                kind,
+                remaining: duration,
+            }),
+        }
+    }
+
+    pub fn status_speed_multiplier(&self) -> f32 {
+        self.status_effects
+            .iter()
+            .map(|status_effect| status_effect.kind.speed_multiplier())
+            .product()
+    }
+
     pub fn process_input(
         &mut self,
         input: &mut PlayerInput,
         transform: &mut Transform,
+        map: &Map,
+        mutators: &Mutators,
         delta_seconds: f32,
     ) {
+        // keep looking where we were rather than turning into NaN
+        if !input.is_finite() {
+            report_non_finite("input view angles");
+            input.pitch = self.pitch;
+            input.yaw = self.yaw;
+        }
+        let previous_translation = transform.translation;
+
         self.pitch = input.pitch;
         self.yaw = input.yaw;
+        self.aiming = input.zoom;
 
         let rotation = Quat::from_rotation_y(self.yaw);
         let wish_direction = input.compute_move_direction(rotation);
+        let wish_speed = self.move_speed
+            * input.speed_multiplier()
+            * self.status_speed_multiplier()
+            * mutators.speed_multiplier();
+        for status_effect in &mut self.status_effects {
+            status_effect.remaining -= delta_seconds;
+        }
+        self.status_effects
+            .retain(|status_effect| status_effect.remaining > 0.0);
 
-        // todo, refactor (cleanup)
-        self.velocity = Self::deccelerate(
-            self.velocity,
-            self.velocity.length(),
-            self.move_friction,
-            delta_seconds,
-        );
+        if self.mantle.is_some() {
+            self.continue_mantle(transform, delta_seconds);
+        } else {
+            self.velocity = movement::decelerate(
+                self.velocity,
+                self.velocity.length(),
+                self.move_friction,
+                delta_seconds,
+            );
 
-        self.velocity += Self::accelerate(
-            wish_direction,
-            self.move_speed,
-            self.velocity.length(),
-            self.move_accel,
-            delta_seconds,
-        );
+            self.velocity += movement::accelerate(
+                wish_direction,
+                wish_speed,
+                self.velocity.length(),
+                self.move_accel,
+                delta_seconds,
+            );
 
-        transform.translation += self.velocity * delta_seconds;
-        input.final_translation = transform.translation;
-    }
+            self.velocity.y -= mutators.gravity() * delta_seconds;
 
-    pub fn accelerate(
-        wish_direction: Vec3,
-        wish_speed: f32,
-        current_speed: f32,
-        accel: f32,
-        delta_seconds: f32,
-    ) -> Vec3 {
-        let add_speed = wish_speed - current_speed;
+            // launched after moving so the pad decides where the next tick takes us
+            let (translation, touched, grounded) = map.slide(
+                transform.translation,
+                self.velocity * delta_seconds,
+                CHARACTER_RADIUS,
+                CHARACTER_HEIGHT,
+                STEP_HEIGHT,
+            );
+            transform.translation = translation;
+            // whatever it ran into stops it going that way, the rest carries on along the wall,
+            // and whatever it landed on stops the fall
+            for normal in touched {
+                self.velocity -= normal * self.velocity.dot(normal).min(0.0);
+            }
+            if let Some(launch_volume) = map.launch_volume_at(transform.translation) {
+                self.velocity = launch_volume.launch_velocity;
+            }
+            // standing on something, unless a pad is sending it up
+            let grounded = grounded && self.velocity.y <= 0.0;
 
-        if add_speed <= 0.0 {
-            return Vec3::ZERO;
+            // a low wall ahead is climbed instead, and only the ground can be jumped off
+            if input.jump {
+                self.start_mantle(wish_direction, transform, map);
+                if self.mantle.is_none() && grounded {
+                    self.velocity.y = JUMP_SPEED;
+                }
+            }
         }
 
-        let mut accel_speed = accel * delta_seconds * wish_speed;
-        if accel_speed > add_speed {
-            accel_speed = add_speed;
+        // whatever went wrong, standing still where we were is better than spreading it
+        if !self.velocity.is_finite() || !transform.translation.is_finite() {
+            report_non_finite("character movement");
+            self.velocity = Vec3::ZERO;
+            transform.translation = if previous_translation.is_finite() {
+                previous_translation
+            } else {
+                Vec3::ZERO
+            };
         }
-
-        wish_direction * accel_speed
+        debug_assert!(self.velocity.is_finite() && transform.translation.is_finite());
     }
 
-    pub fn deccelerate(
-        velocity: Vec3,
-        current_speed: f32,
-        friction: f32,
-        delta_seconds: f32,
-    ) -> Vec3 {
-        let mut new_speed;
-        let mut drop = 0.0;
+    /// jumping toward a low wall climbs it, over if it's thin and onto it if not
+    fn start_mantle(&mut self, direction: Vec3, transform: &Transform, map: &Map) {
+        if direction == Vec3::ZERO {
+            return;
+        }
+        let start = transform.translation;
+        let ledge = match map.ledge_ahead(start, direction, MANTLE_REACH, MANTLE_MAX_HEIGHT) {
+            Some(ledge) => ledge,
+            None => return,
+        };
+        let end = if ledge.far - ledge.near <= VAULT_MAX_DEPTH {
+            start + direction * (ledge.far + MANTLE_LANDING)
+        } else {
+            let mut end = start + direction * (ledge.near + MANTLE_LANDING);
+            end.y = ledge.top;
+            end
+        };
+        self.mantle = Some(Mantle {
+            start,
+            end,
+            peak: ledge.top + MANTLE_PEAK_CLEARANCE,
+            elapsed: 0.0,
+        });
+    }
 
-        drop += current_speed * friction * delta_seconds;
+    fn continue_mantle(&mut self, transform: &mut Transform, delta_seconds: f32) {
+        let mantle = match self.mantle.as_mut() {
+            Some(mantle) => mantle,
+            None => return,
+        };
+        mantle.elapsed += delta_seconds;
+        let progress = mantle.elapsed / MANTLE_DURATION;
+        let previous_translation = transform.translation;
+        transform.translation =
+            movement::mantle_position(mantle.start, mantle.end, mantle.peak, progress);
 
-        new_speed = current_speed - drop;
-        if new_speed < 0.0 {
-            new_speed = 0.0;
+        // not what moves it, but what visuals carry it along with between ticks
+        if delta_seconds > 0.0 {
+            self.velocity = (transform.translation - previous_translation) / delta_seconds;
         }
-
-        if new_speed != 0.0 {
-            new_speed /= current_speed;
+        if progress >= 1.0 {
+            self.mantle = None;
+            // standing on the far side, the last bit of coming down isn't a fall
+            self.velocity.y = 0.0;
         }
-
-        velocity * new_speed
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Snapshot {
     // networked
     pub id: u32,
     pub latest_processed_input_id: Option<u32>,
-    pub character_snapshots: Vec<CharacterSnapshot>,
+    pub character_snapshots: CharacterColumns,
+    /// characters the baseline had that are gone now, a diff can't say so by leaving them out.
+    /// repeated in every diff until the client acks a snapshot without them, so they're gone
+    /// on the client even when the reliable despawn is held up
+    pub removed_client_ids: Vec<u64>,
+    /// everything registered with `replicate` on networked entities
+    pub components: ReplicatedComponents,
+    /// the id of an input this client claimed a translation for, and how far the server's
+    /// result for it was from the claim
+    pub reconciliation: Option<(u32, Vec3)>,
 
     // not networked
     #[serde(skip)]
@@ -199,38 +602,68 @@ pub struct Snapshot {
 }
 
 impl Snapshot {
+    pub fn is_finite(&self) -> bool {
+        self.character_snapshots.is_finite()
+            && self
+                .reconciliation
+                .is_none_or(|(_, error)| error.is_finite())
+    }
+
     pub fn diff(&self, old: &Self) -> Snapshot {
+        let (character_snapshots, removed_client_ids) =
+            self.character_snapshots.diff(&old.character_snapshots);
         Snapshot {
             id: self.id,
             timestamp: self.timestamp,
             latest_processed_input_id: self.latest_processed_input_id,
-            character_snapshots: {
-                let mut diffs = Vec::new();
-                for snapshot in &self.character_snapshots {
-                    if let Some(old_snapshot) = old
-                        .character_snapshots
-                        .iter()
-                        .find(|old_snapshot| old_snapshot.client_id == snapshot.client_id)
-                    {
-                        let diff = snapshot.diff(old_snapshot);
-                        if !diff.is_empty() {
-                            diffs.push(diff);
-                        }
-                    } else {
-                        diffs.push(snapshot.clone());
-                    }
-                }
-                diffs
-            },
+            reconciliation: self.reconciliation,
+            character_snapshots,
+            removed_client_ids,
+            components: self.components.diff(&old.components),
+        }
+    }
+
+    /// the full state a client ends up with after applying this (possibly partial) snapshot
+    /// on top of `baseline`
+    pub fn applied_to(&self, baseline: Option<&Snapshot>) -> Snapshot {
+        let nothing = CharacterColumns::default();
+        let no_components = ReplicatedComponents::default();
+        let components = baseline.map_or(&no_components, |baseline| &baseline.components);
+        let baseline = baseline.map_or(&nothing, |baseline| &baseline.character_snapshots);
+        Snapshot {
+            id: self.id,
+            timestamp: self.timestamp,
+            latest_processed_input_id: None,
+            character_snapshots: self
+                .character_snapshots
+                .applied_to(baseline, &self.removed_client_ids),
+            removed_client_ids: Vec::new(),
+            components: self.components.applied_to(components),
+            reconciliation: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct CharacterSnapshot {
     pub client_id: u64,
     pub translation: Option<Vec3>,
     pub velocity: Option<Vec3>,
+    pub teleport_count: Option<u32>,
+    pub movement: Option<MovementParameters>,
+    pub aiming: Option<bool>,
+    /// left out of full snapshots when there are none, in a diff an empty list clears them
+    pub status_effects: Option<Vec<NetStatusEffect>>,
+    /// left out of full snapshots when not mantling, in a diff `Some(None)` means it's over
+    pub mantle: Option<Option<Mantle>>,
+}
+
+/// replicated so server-side changes (pickups, slows) reach client prediction
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct MovementParameters {
+    pub speed: f32,
+    pub accel: f32,
+    pub friction: f32,
 }
 
 impl CharacterSnapshot {
@@ -239,70 +672,1121 @@ impl CharacterSnapshot {
             client_id: character.owner_client_id.raw(),
             translation: Some(transform.translation),
             velocity: Some(character.velocity),
+            teleport_count: Some(character.teleport_count),
+            movement: Some(MovementParameters {
+                speed: character.move_speed,
+                accel: character.move_accel,
+                friction: character.move_friction,
+            }),
+            aiming: Some(character.aiming),
+            status_effects: Some(
+                character
+                    .status_effects
+                    .iter()
+                    .map(NetStatusEffect::from_status_effect)
+                    .collect::<Vec<_>>(),
+            )
+            .filter(|status_effects| !status_effects.is_empty()),
+            mantle: character.mantle.map(Some),
         }
     }
 
+    /// non-finite fields are skipped, the rest still applies
     pub fn apply(&self, character: &mut Character, transform: &mut Transform) {
         if let Some(translation) = self.translation {
-            transform.translation = translation;
+            if translation.is_finite() {
+                transform.translation = translation;
+            } else {
+                report_non_finite("snapshot translation");
+            }
         }
         if let Some(velocity) = self.velocity {
-            character.velocity = velocity;
+            if velocity.is_finite() {
+                character.velocity = velocity;
+            } else {
+                report_non_finite("snapshot velocity");
+            }
+        }
+        if let Some(teleport_count) = self.teleport_count {
+            character.teleport_count = teleport_count;
+        }
+        if let Some(aiming) = self.aiming {
+            character.aiming = aiming;
         }
+        if let Some(mantle) = self.mantle {
+            character.mantle = mantle;
+        }
+        self.apply_movement(character);
     }
 
-    pub fn diff(&self, old: &Self) -> Self {
-        Self {
-            client_id: self.client_id,
-            translation: {
-                if let (Some(new), Some(old)) = (self.translation, old.translation) {
-                    if new != old {
-                        Some(new)
-                    } else {
-                        None
+    /// the movement parameters alone, safe to take before predicting even when the rest of
+    /// the snapshot isn't applied
+    pub fn apply_movement(&self, character: &mut Character) {
+        if let Some(movement) = self.movement {
+            if movement.speed.is_finite()
+                && movement.accel.is_finite()
+                && movement.friction.is_finite()
+            {
+                character.move_speed = movement.speed;
+                character.move_accel = movement.accel;
+                character.move_friction = movement.friction;
+            } else {
+                report_non_finite("snapshot movement parameters");
+            }
+        }
+        if let Some(status_effects) = &self.status_effects {
+            character.status_effects = status_effects
+                .iter()
+                .filter_map(|status_effect| status_effect.to_status_effect())
+                .collect();
+        }
+        debug_assert!(
+            character.move_speed.is_finite()
+                && character.move_accel.is_finite()
+                && character.move_friction.is_finite()
+        );
+    }
+
+    /// whether the server moved the character on purpose since `character` was last updated
+    pub fn is_teleport(&self, character: &Character) -> bool {
+        self.teleport_count
+            .is_some_and(|teleport_count| teleport_count != character.teleport_count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.translation.is_none()
+            && self.velocity.is_none()
+            && self.teleport_count.is_none()
+            && self.movement.is_none()
+            && self.aiming.is_none()
+            && self.status_effects.is_none()
+            && self.mantle.is_none()
+    }
+}
+
+/// `new` where both have a value and it changed, nothing otherwise
+fn changed<T: PartialEq + Copy>(new: Option<T>, old: Option<T>) -> Option<T> {
+    match (new, old) {
+        (Some(new), Some(old)) if new != old => Some(new),
+        _ => None,
+    }
+}
+
+/// keeps the entries of `column` whose flag in `kept` is set
+fn retain_by<T>(column: &mut Vec<T>, kept: &[bool]) {
+    let mut flags = kept.iter();
+    column.retain(|_| flags.next().copied().unwrap_or(false));
+}
+
+/// the characters of a snapshot, a column per field and sorted by client id, so two snapshots
+/// are compared in one pass over both instead of looking every character up in the other. sent
+/// as a plain list of `CharacterSnapshot`s
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct CharacterColumns {
+    client_ids: Vec<u64>,
+    translations: Vec<Option<Vec3>>,
+    velocities: Vec<Option<Vec3>>,
+    teleport_counts: Vec<Option<u32>>,
+    movements: Vec<Option<MovementParameters>>,
+    aimings: Vec<Option<bool>>,
+    status_effects: Vec<Option<Vec<NetStatusEffect>>>,
+    mantles: Vec<Option<Option<Mantle>>>,
+}
+
+impl CharacterColumns {
+    fn len(&self) -> usize {
+        self.client_ids.len()
+    }
+
+    /// in ascending order
+    pub fn client_ids(&self) -> &[u64] {
+        &self.client_ids
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = CharacterSnapshot> + '_ {
+        (0..self.len()).map(|index| self.row(index))
+    }
+
+    /// everyone whose translation is in here, without putting the rest of them together
+    pub fn translations(&self) -> impl Iterator<Item = (u64, Vec3)> + '_ {
+        self.client_ids
+            .iter()
+            .zip(&self.translations)
+            .filter_map(|(client_id, translation)| Some((*client_id, (*translation)?)))
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(u64) -> bool) {
+        let kept: Vec<bool> = self
+            .client_ids
+            .iter()
+            .map(|client_id| keep(*client_id))
+            .collect();
+        retain_by(&mut self.client_ids, &kept);
+        retain_by(&mut self.translations, &kept);
+        retain_by(&mut self.velocities, &kept);
+        retain_by(&mut self.teleport_counts, &kept);
+        retain_by(&mut self.movements, &kept);
+        retain_by(&mut self.aimings, &kept);
+        retain_by(&mut self.status_effects, &kept);
+        retain_by(&mut self.mantles, &kept);
+    }
+
+    /// fields that aren't there count as finite
+    pub fn is_finite(&self) -> bool {
+        self.translations
+            .iter()
+            .chain(&self.velocities)
+            .all(|value| value.is_none_or(|value| value.is_finite()))
+            && self.movements.iter().all(|movement| {
+                movement.is_none_or(|movement| {
+                    movement.speed.is_finite()
+                        && movement.accel.is_finite()
+                        && movement.friction.is_finite()
+                })
+            })
+            && self
+                .mantles
+                .iter()
+                .all(|mantle| mantle.flatten().is_none_or(|mantle| mantle.is_finite()))
+    }
+
+    fn row(&self, index: usize) -> CharacterSnapshot {
+        CharacterSnapshot {
+            client_id: self.client_ids[index],
+            translation: self.translations[index],
+            velocity: self.velocities[index],
+            teleport_count: self.teleport_counts[index],
+            movement: self.movements[index],
+            aiming: self.aimings[index],
+            status_effects: self.status_effects[index].clone(),
+            mantle: self.mantles[index],
+        }
+    }
+
+    /// only ever after everything with a lower client id
+    fn push(&mut self, character_snapshot: CharacterSnapshot) {
+        debug_assert!(self
+            .client_ids
+            .last()
+            .is_none_or(|last| *last < character_snapshot.client_id));
+        self.client_ids.push(character_snapshot.client_id);
+        self.translations.push(character_snapshot.translation);
+        self.velocities.push(character_snapshot.velocity);
+        self.teleport_counts.push(character_snapshot.teleport_count);
+        self.movements.push(character_snapshot.movement);
+        self.aimings.push(character_snapshot.aiming);
+        self.status_effects.push(character_snapshot.status_effects);
+        self.mantles.push(character_snapshot.mantle);
+    }
+
+    /// what changed since `old` for everyone here, and who `old` had that's gone
+    pub fn diff(&self, old: &Self) -> (Self, Vec<u64>) {
+        let mut diffs = Self::default();
+        let mut removed_client_ids = Vec::new();
+        let (mut new_index, mut old_index) = (0, 0);
+        loop {
+            match (
+                self.client_ids.get(new_index),
+                old.client_ids.get(old_index),
+            ) {
+                (Some(new_id), Some(old_id)) if new_id == old_id => {
+                    let diff = self.diff_row(new_index, old, old_index);
+                    if !diff.is_empty() {
+                        diffs.push(diff);
                     }
-                } else {
-                    None
+                    new_index += 1;
+                    old_index += 1;
                 }
-            },
-            velocity: {
-                if let (Some(new), Some(old)) = (self.velocity, old.velocity) {
-                    if new != old {
-                        Some(new)
+                (Some(new_id), old_id) if old_id.is_none_or(|old_id| new_id < old_id) => {
+                    diffs.push(self.row(new_index));
+                    new_index += 1;
+                }
+                (_, Some(old_id)) => {
+                    removed_client_ids.push(*old_id);
+                    old_index += 1;
+                }
+                (_, None) => break,
+            }
+        }
+        (diffs, removed_client_ids)
+    }
+
+    fn diff_row(&self, index: usize, old: &Self, old_index: usize) -> CharacterSnapshot {
+        let status_effects = &self.status_effects[index];
+        let mantle = self.mantles[index];
+        CharacterSnapshot {
+            client_id: self.client_ids[index],
+            translation: changed(self.translations[index], old.translations[old_index]),
+            velocity: changed(self.velocities[index], old.velocities[old_index]),
+            teleport_count: changed(self.teleport_counts[index], old.teleport_counts[old_index]),
+            movement: changed(self.movements[index], old.movements[old_index]),
+            aiming: changed(self.aimings[index], old.aimings[old_index]),
+            // nothing in a full snapshot means no effects here, not nothing new
+            status_effects: (*status_effects != old.status_effects[old_index])
+                .then(|| status_effects.clone().unwrap_or_default()),
+            // the same again, nothing in a full snapshot means not mantling
+            mantle: (mantle != old.mantles[old_index]).then(|| mantle.flatten()),
+        }
+    }
+
+    /// these (possibly partial) characters on top of `baseline`, without the ones in `removed`
+    pub fn applied_to(&self, baseline: &Self, removed_client_ids: &[u64]) -> Self {
+        let mut applied = Self::default();
+        let (mut index, mut baseline_index) = (0, 0);
+        loop {
+            match (
+                self.client_ids.get(index),
+                baseline.client_ids.get(baseline_index),
+            ) {
+                (Some(client_id), Some(baseline_id)) if client_id == baseline_id => {
+                    if removed_client_ids.contains(client_id) {
+                        applied.push(self.row(index));
                     } else {
-                        None
+                        applied.push(self.applied_row(index, baseline, baseline_index));
                     }
-                } else {
-                    None
+                    index += 1;
+                    baseline_index += 1;
                 }
-            },
+                (Some(client_id), baseline_id)
+                    if baseline_id.is_none_or(|baseline_id| client_id < baseline_id) =>
+                {
+                    applied.push(self.row(index));
+                    index += 1;
+                }
+                (_, Some(baseline_id)) => {
+                    if !removed_client_ids.contains(baseline_id) {
+                        applied.push(baseline.row(baseline_index));
+                    }
+                    baseline_index += 1;
+                }
+                (_, None) => break,
+            }
         }
+        applied
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.translation.is_none() && self.velocity.is_none()
+    fn applied_row(
+        &self,
+        index: usize,
+        baseline: &Self,
+        baseline_index: usize,
+    ) -> CharacterSnapshot {
+        let mut row = baseline.row(baseline_index);
+        row.translation = self.translations[index].or(row.translation);
+        row.velocity = self.velocities[index].or(row.velocity);
+        row.teleport_count = self.teleport_counts[index].or(row.teleport_count);
+        row.movement = self.movements[index].or(row.movement);
+        row.aiming = self.aimings[index].or(row.aiming);
+        if let Some(status_effects) = &self.status_effects[index] {
+            row.status_effects =
+                Some(status_effects.clone()).filter(|status_effects| !status_effects.is_empty());
+        }
+        if let Some(mantle) = self.mantles[index] {
+            row.mantle = mantle.map(Some);
+        }
+        row
     }
 }
 
+/// sorted by client id on the way in, a later duplicate replacing an earlier one
+impl FromIterator<CharacterSnapshot> for CharacterColumns {
+    fn from_iter<I: IntoIterator<Item = CharacterSnapshot>>(iter: I) -> Self {
+        let mut rows: Vec<CharacterSnapshot> = iter.into_iter().collect();
+        // stable, so duplicates stay in the order they came
+        rows.sort_by_key(|row| row.client_id);
+        let mut columns = Self::default();
+        let mut rows = rows.into_iter().peekable();
+        while let Some(row) = rows.next() {
+            if rows
+                .peek()
+                .is_some_and(|next| next.client_id == row.client_id)
+            {
+                continue;
+            }
+            columns.push(row);
+        }
+        columns
+    }
+}
+
+impl IntoIterator for CharacterColumns {
+    type Item = CharacterSnapshot;
+    type IntoIter = std::vec::IntoIter<CharacterSnapshot>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let rows: Vec<CharacterSnapshot> = self
+            .status_effects
+            .into_iter()
+            .enumerate()
+            .map(|(index, status_effects)| CharacterSnapshot {
+                client_id: self.client_ids[index],
+                translation: self.translations[index],
+                velocity: self.velocities[index],
+                teleport_count: self.teleport_counts[index],
+                movement: self.movements[index],
+                aiming: self.aimings[index],
+                status_effects,
+                mantle: self.mantles[index],
+            })
+            .collect();
+        rows.into_iter()
+    }
+}
+
+impl Serialize for CharacterColumns {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for CharacterColumns {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<CharacterSnapshot>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// one replicated component of one networked entity, serialized with bincode
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ComponentEntry {
+    pub network_id: u64,
+    /// which registered type it is, see `replication::component_id`
+    pub component_id: u32,
+    /// in a diff `None` means the entity doesn't have it anymore
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// every component registered with `replicate`, sorted by network id and then component id so
+/// two snapshots are compared in one pass like their characters
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ReplicatedComponents {
+    entries: Vec<ComponentEntry>,
+}
+
+impl ReplicatedComponents {
+    pub fn iter(&self) -> impl Iterator<Item = &ComponentEntry> {
+        self.entries.iter()
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(u64) -> bool) {
+        self.entries.retain(|entry| keep(entry.network_id));
+    }
+
+    /// what changed since `old`, with a `None` for every component that went away
+    pub fn diff(&self, old: &Self) -> Self {
+        let mut entries = Vec::new();
+        let mut new = self.entries.iter().peekable();
+        let mut old = old.entries.iter().peekable();
+        loop {
+            match (new.peek(), old.peek()) {
+                (Some(new_entry), Some(old_entry)) => {
+                    match entry_key(new_entry).cmp(&entry_key(old_entry)) {
+                        std::cmp::Ordering::Less => entries.extend(new.next().cloned()),
+                        std::cmp::Ordering::Greater => {
+                            entries.extend(old.next().map(removed_entry));
+                        }
+                        std::cmp::Ordering::Equal => {
+                            if new_entry.bytes != old_entry.bytes {
+                                entries.push((*new_entry).clone());
+                            }
+                            new.next();
+                            old.next();
+                        }
+                    }
+                }
+                (Some(_), None) => entries.extend(new.next().cloned()),
+                (None, Some(_)) => entries.extend(old.next().map(removed_entry)),
+                (None, None) => break,
+            }
+        }
+        Self { entries }
+    }
+
+    /// the full state after applying this (possibly partial) set on top of `baseline`
+    pub fn applied_to(&self, baseline: &Self) -> Self {
+        let mut entries = baseline.entries.clone();
+        for entry in &self.entries {
+            let index = entries.binary_search_by_key(&entry_key(entry), entry_key);
+            match (index, &entry.bytes) {
+                (Ok(index), Some(_)) => entries[index] = entry.clone(),
+                (Ok(index), None) => {
+                    entries.remove(index);
+                }
+                (Err(index), Some(_)) => entries.insert(index, entry.clone()),
+                (Err(_), None) => {}
+            }
+        }
+        Self { entries }
+    }
+}
+
+fn entry_key(entry: &ComponentEntry) -> (u64, u32) {
+    (entry.network_id, entry.component_id)
+}
+
+fn removed_entry(entry: &ComponentEntry) -> ComponentEntry {
+    ComponentEntry {
+        bytes: None,
+        ..entry.clone()
+    }
+}
+
+/// sorted on the way in, a later duplicate replacing an earlier one
+impl FromIterator<ComponentEntry> for ReplicatedComponents {
+    fn from_iter<I: IntoIterator<Item = ComponentEntry>>(iter: I) -> Self {
+        let mut entries: Vec<ComponentEntry> = iter.into_iter().collect();
+        // stable, so duplicates stay in the order they came
+        entries.sort_by_key(entry_key);
+        let mut deduplicated: Vec<ComponentEntry> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match deduplicated.last_mut() {
+                Some(last) if entry_key(last) == entry_key(&entry) => *last = entry,
+                _ => deduplicated.push(entry),
+            }
+        }
+        Self {
+            entries: deduplicated,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PlayerStats {
+    pub kills: u32,
+    pub deaths: u32,
+    pub damage_dealt: f32,
+    pub best_streak: u32,
+    pub shots_fired: u32,
+    pub shots_hit: u32,
+}
+
+impl PlayerStats {
+    /// none until the player has fired at least once
+    pub fn accuracy(&self) -> Option<f32> {
+        if self.shots_fired == 0 {
+            None
+        } else {
+            Some(self.shots_hit as f32 / self.shots_fired as f32)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MatchResult {
+    pub winner_client_id: Option<u64>,
+    /// sorted from highest to lowest
+    pub scores: Vec<(u64, u32)>,
+    pub stats: Vec<(u64, PlayerStats)>,
+    pub post_match_duration: f32,
+    pub results_duration: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum Emote {
+    Wave,
+    Laugh,
+    Cheer,
+    Taunt,
+}
+
+/// what pulls characters down every second, when nothing changes it
+const GRAVITY: f32 = 20.0;
+const LOW_GRAVITY_MULTIPLIER: f32 = 0.3;
+const HIGH_SPEED_MULTIPLIER: f32 = 1.6;
+
+/// a change to the rules for a whole match, picked when the server starts or by vote
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Mutator {
+    LowGravity,
+    HighSpeed,
+    /// any hit from another player kills
+    Instagib,
+}
+
+impl Mutator {
+    pub const ALL: [Self; 3] = [Self::LowGravity, Self::HighSpeed, Self::Instagib];
+
+    /// what it's called in commands, the same as on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LowGravity => "low-gravity",
+            Self::HighSpeed => "high-speed",
+            Self::Instagib => "instagib",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mutator| mutator.name() == name)
+    }
+}
+
+/// the mutators in play. the server sends them before anything spawns, so every client
+/// predicts with the same gravity and speed it moves characters with
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Mutators {
+    low_gravity: bool,
+    high_speed: bool,
+    instagib: bool,
+}
+
+impl Mutators {
+    pub fn from_list(mutators: &[Mutator]) -> Self {
+        let mut result = Self::default();
+        for mutator in mutators {
+            result.set(*mutator, true);
+        }
+        result
+    }
+
+    pub fn contains(self, mutator: Mutator) -> bool {
+        match mutator {
+            Mutator::LowGravity => self.low_gravity,
+            Mutator::HighSpeed => self.high_speed,
+            Mutator::Instagib => self.instagib,
+        }
+    }
+
+    pub fn set(&mut self, mutator: Mutator, on: bool) {
+        match mutator {
+            Mutator::LowGravity => self.low_gravity = on,
+            Mutator::HighSpeed => self.high_speed = on,
+            Mutator::Instagib => self.instagib = on,
+        }
+    }
+
+    pub fn list(self) -> Vec<Mutator> {
+        Mutator::ALL
+            .into_iter()
+            .filter(|mutator| self.contains(*mutator))
+            .collect()
+    }
+
+    pub fn gravity(self) -> f32 {
+        if self.low_gravity {
+            GRAVITY * LOW_GRAVITY_MULTIPLIER
+        } else {
+            GRAVITY
+        }
+    }
+
+    pub fn speed_multiplier(self) -> f32 {
+        if self.high_speed {
+            HIGH_SPEED_MULTIPLIER
+        } else {
+            1.0
+        }
+    }
+}
+
+/// what a match's random numbers are for. each has a stream of its own, so anything drawing
+/// numbers later on gets a new one here and leaves the spread of every shot as it was
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RandomStream {
+    Spread,
+}
+
+/// picked by the server for every match and sent to clients before anything spawns. randomness
+/// is asked for by tick, so the server, a client predicting that tick and a demo played back
+/// all get the same numbers
+#[derive(Resource, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MatchSeed(pub u64);
+
+impl MatchSeed {
+    /// `salt` tells apart draws from one stream on the same tick, like whose shot it is
+    pub fn random(self, tick: u32, stream: RandomStream, salt: u64) -> TickRandom {
+        let mut state = self.0;
+        for word in [tick as u64, stream as u64, salt] {
+            state = splitmix64(state ^ word);
+        }
+        TickRandom(state)
+    }
+}
+
+/// numbers for one tick, see `MatchSeed::random`
+pub struct TickRandom(u64);
+
+impl TickRandom {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = splitmix64(self.0);
+        self.0
+    }
+
+    /// between 0 and 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+/// every bit of the input shows up all over the output, so seeds one apart aren't alike
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// what a vote decides, see `vote`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum VoteKind {
+    /// client id
+    Kick(u64),
+    /// map name, the file in `assets/maps` without its extension
+    ChangeMap(String),
+    /// turn one on or off, starting a new match
+    Mutator(Mutator, bool),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum VoteRequest {
+    Start(VoteKind),
+    /// yes or no on the vote that's running
+    Cast(bool),
+}
+
+/// the running vote as everyone sees it, sent again whenever any of it changes
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct VoteStatus {
+    pub kind: VoteKind,
+    pub yes: u8,
+    pub no: u8,
+    /// yes votes that pass it straight away
+    pub needed: u8,
+    /// whole seconds
+    pub remaining: u16,
+}
+
+/// something a client shows the effects of before the server has agreed to it
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum PredictedAction {
+    Emote(Emote),
+    Inventory(InventoryAction),
+}
+
+/// what a player called themselves when connecting, empty if they didn't
+#[derive(Component, Clone, Default)]
+pub struct PlayerName(pub String);
+
+/// how a player's character looks besides its color, picked when connecting. it changes
+/// nothing about how it plays
+#[derive(Serialize, Deserialize, Component, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Cosmetics {
+    pub hat: Hat,
+    pub trail: Option<TrailColor>,
+}
+
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Hat {
+    #[default]
+    None,
+    Cap,
+    Topper,
+}
+
+/// a line following the character around
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrailColor {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+/// how fast a character moves and how much it takes, from `assets/classes.ron`
+#[derive(
+    Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Hash, Debug, Default,
+)]
+pub enum CharacterClass {
+    #[default]
+    Standard,
+    Scout,
+    Heavy,
+}
+
+/// what each one does comes from `assets/weapons.ron`, see `WeaponDefinitions`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Weapon {
+    Pistol,
+    Rifle,
+}
+
+impl Weapon {
+    pub const ALL: [Self; 2] = [Self::Pistol, Self::Rifle];
+}
+
+/// where a shot hit a character, weapons can do more damage to some parts
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BodyPart {
+    Head,
+    Body,
+}
+
+/// past this many shots in a row the spread stops growing
+pub const MAX_WEAPON_HEAT: f32 = 8.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Item {
+    /// fills the selected weapon back up
+    AmmoPack,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct WeaponSlot {
+    pub weapon: Weapon,
+    pub ammo: u32,
+    /// about how many shots in a row, as of the last one
+    pub heat: f32,
+    /// the input id of the last shot, heat goes down with every input since
+    pub last_shot: Option<u32>,
+}
+
+impl WeaponSlot {
+    fn new(weapon: Weapon, ammo: u32) -> Self {
+        Self {
+            weapon,
+            ammo,
+            heat: 0.0,
+            last_shot: None,
+        }
+    }
+
+    /// how hot a shot made with input `input_id` finds the weapon
+    pub fn heat_at(&self, input_id: u32, weapons: &WeaponDefinitions) -> f32 {
+        let inputs = self
+            .last_shot
+            .map_or(0, |last_shot| input_id.wrapping_sub(last_shot));
+        (self.heat - inputs as f32 * weapons.get(self.weapon).cooling).max(0.0)
+    }
+}
+
+/// a shot fired, with the input it was fired on and the aim at the time. the view is the
+/// owner's to say like it is in inputs, what comes of it is the same anywhere given the seed
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Shot {
+    pub input_id: u32,
+    pub pitch: f32,
+    pub yaw: f32,
+    /// the latest snapshot the shooter had, everyone else is checked for hits where it showed
+    /// them. none for players on the server's machine, who see everyone as they are
+    pub seen_snapshot_id: Option<u32>,
+}
+
+/// where a shot goes: the aim, strayed by a cone that grows with heat. the stray comes from
+/// the match seed with the input id and the shooter, so the owner predicting it and the server
+/// get the same direction
+pub fn shot_direction(
+    match_seed: MatchSeed,
+    client_id: u64,
+    shot: Shot,
+    definition: &WeaponDefinition,
+    heat: f32,
+) -> Vec3 {
+    let mut random = match_seed.random(shot.input_id, RandomStream::Spread, client_id);
+    let cone = definition.spread + definition.recoil * heat.min(MAX_WEAPON_HEAT);
+    // the square root spreads shots evenly over the cone instead of bunching them in the middle
+    let stray = cone * random.next_f32().sqrt();
+    let around = random.range(0.0, std::f32::consts::TAU);
+    let aim = Quat::from_euler(EulerRot::YXZ, shot.yaw, shot.pitch, 0.0);
+    aim * Quat::from_rotation_z(around) * Quat::from_rotation_x(stray) * Vec3::NEG_Z
+}
+
+/// what a player carries. the server's copy is the real one, the owner is sent it whenever it
+/// changes and predicts its own actions on top
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Inventory {
+    pub weapons: Vec<WeaponSlot>,
+    /// index into `weapons`
+    pub selected: u8,
+    /// how many of each item
+    pub items: Vec<(Item, u32)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum InventoryAction {
+    Fire(Shot),
+    SelectWeapon(u8),
+    UseItem(Item),
+}
+
+impl Inventory {
+    /// the loadout everyone spawns with
+    pub fn starting(weapons: &WeaponDefinitions) -> Self {
+        Self {
+            weapons: vec![
+                WeaponSlot::new(Weapon::Pistol, weapons.get(Weapon::Pistol).max_ammo),
+                WeaponSlot::new(Weapon::Rifle, weapons.get(Weapon::Rifle).max_ammo / 2),
+            ],
+            selected: 0,
+            items: vec![(Item::AmmoPack, 1)],
+        }
+    }
+
+    pub fn selected_weapon(&self) -> Option<Weapon> {
+        self.weapons
+            .get(self.selected as usize)
+            .map(|slot| slot.weapon)
+    }
+
+    /// where `shot` would go with the selected weapon, before firing it
+    pub fn shot_direction(
+        &self,
+        weapons: &WeaponDefinitions,
+        match_seed: MatchSeed,
+        client_id: u64,
+        shot: Shot,
+    ) -> Option<Vec3> {
+        let slot = self.weapons.get(self.selected as usize)?;
+        Some(shot_direction(
+            match_seed,
+            client_id,
+            shot,
+            weapons.get(slot.weapon),
+            slot.heat_at(shot.input_id, weapons),
+        ))
+    }
+
+    /// the same rules for the server and for a client's prediction. false (and nothing changed)
+    /// if the action isn't possible right now
+    pub fn apply(&mut self, action: InventoryAction, weapons: &WeaponDefinitions) -> bool {
+        match action {
+            InventoryAction::Fire(shot) => match self.weapons.get_mut(self.selected as usize) {
+                // at most one shot an input, so no two shots get the same spread
+                Some(slot)
+                    if slot.ammo > 0
+                        && shot.pitch.is_finite()
+                        && shot.yaw.is_finite()
+                        && slot.last_shot.is_none_or(|last_shot| {
+                            (shot.input_id.wrapping_sub(last_shot) as i32)
+                                >= weapons.get(slot.weapon).fire_interval.max(1) as i32
+                        }) =>
+                {
+                    slot.ammo -= 1;
+                    slot.heat = (slot.heat_at(shot.input_id, weapons) + 1.0).min(MAX_WEAPON_HEAT);
+                    slot.last_shot = Some(shot.input_id);
+                    true
+                }
+                _ => false,
+            },
+            InventoryAction::SelectWeapon(index) => {
+                if index != self.selected && (index as usize) < self.weapons.len() {
+                    self.selected = index;
+                    true
+                } else {
+                    false
+                }
+            }
+            InventoryAction::UseItem(item) => {
+                let count = match self.items.iter_mut().find(|(held, _)| *held == item) {
+                    Some((_, count)) if *count > 0 => count,
+                    _ => return false,
+                };
+                let used = match item {
+                    Item::AmmoPack => match self.weapons.get_mut(self.selected as usize) {
+                        Some(slot) if slot.ammo < weapons.get(slot.weapon).max_ammo => {
+                            slot.ammo = weapons.get(slot.weapon).max_ammo;
+                            true
+                        }
+                        _ => false,
+                    },
+                };
+                if used {
+                    *count -= 1;
+                }
+                used
+            }
+        }
+    }
+}
+
+/// always sent over `DefaultChannel::ReliableOrdered` so clients handle them in the order the
+/// server sent them, e.g. a character is spawned before anything that refers to it
 #[derive(Serialize, Deserialize)]
 pub enum ReliableServerMessage {
-    SpawnCharacter(u64, Vec3, Vec3),
+    /// the id the server knows the recipient by, the first thing it sends them
+    Welcome(u64),
+    /// client id, palette slot, translation, velocity, what they picked and called themselves
+    /// when connecting, and the id of the next snapshot. removals in snapshots before that one
+    /// are from before this spawn
+    SpawnCharacter(u64, u8, Vec3, Vec3, Cosmetics, String, u32),
+    /// client id, their character is gone (left, or moved to spectators)
+    DespawnCharacter(u64),
+    MatchEnded(MatchResult),
+    ShowMatchResults,
+    MatchStarted,
+    /// no sender means it's a notice from the server itself
+    Chat(Option<u64>, String),
+    Emote(u64, Emote),
+    Ping(u64, Vec3),
+    /// only sent to the owner of the pushed character, with the input it was applied after
+    Impulse(Option<u32>, Vec3),
+    /// the world started over, every character is gone and spawns for the new one follow.
+    /// snapshots before this id belong to the old world
+    ResetWorld(u32),
+    /// action id, whether it went through. only sent to whoever predicted it
+    ActionResult(u32, bool),
+    /// the owner's inventory after it changed, with the latest of their actions it includes
+    Inventory(Option<u32>, Inventory),
+    /// center, radius. the damage itself is the server's business, this is just for show
+    Explosion(Vec3, f32),
+    /// only sent to the owner, whenever their character's health changed
+    Health(f32),
+    /// sent once on connecting: the id of the server's next snapshot and the world clock tick
+    /// it will be taken on
+    WorldClock(u32, u32),
+    /// the message of the day on joining, or something an admin wants everyone to read
+    Announcement(String),
+    /// the vote that's running, none once it's over
+    Vote(Option<VoteStatus>),
+    /// the name of the map being played, on joining and whenever it changes. the world is
+    /// reset right after a change
+    Map(String),
+    /// how fast the simulation runs compared to real time, on joining and whenever it changes
+    TimeScale(f32),
+    /// whether an admin froze the simulation, and how many ticks to step it by while it is
+    Freeze(bool, u32),
+    /// the mutators in play, on joining before any spawns and whenever they change. the world
+    /// is reset right after a change
+    Mutators(Mutators),
+    /// the match seed, on joining before any spawns and for every new world
+    MatchSeed(u64),
+    /// input id of one of the owner's shots, and whose character it hit if anyone's and where.
+    /// only sent to whoever fired it
+    ShotResult(u32, Option<(u64, BodyPart)>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ReliableClientMessage {
+    Chat(String),
+    /// action id to answer with, see `ActionResult`
+    Action(u32, PredictedAction),
+    Ping(Vec3),
+    /// a spectator wants a character again
+    Rejoin,
+    Vote(VoteRequest),
+    /// move to another room, see `RoomPlugin`
+    JoinRoom(u32),
+    /// play as this from the next spawn on, see `ClassPlugin`
+    ChooseClass(CharacterClass),
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum UnreliableServerMessage {
-    Snapshot(Snapshot),
+    /// boxed, it's far bigger than anything else sent this way
+    Snapshot(Box<Snapshot>),
+    /// everything the server collides with or checks against right now, only sent to
+    /// clients an admin turned debug drawing on for
+    DebugDraw(Vec<DebugShape>),
+    /// the client's time from its `TimeRequest`, and the tick the server was on answering it
+    TimeResponse(f64, f64),
+    /// still here, sent while snapshots aren't
+    Heartbeat,
+}
+
+/// the server's idea of where something is, drawn over the client's own
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DebugShape {
+    /// center, half extents
+    Box(Vec3, Vec3, DebugColor),
+    Segment(Vec3, Vec3, DebugColor),
+    /// center, radius
+    Sphere(Vec3, f32, DebugColor),
+}
+
+/// what the shape is rather than an actual color, the client picks that
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DebugColor {
+    Wall,
+    LaunchVolume,
+    Teleporter,
+    Hazard,
+    Character,
+    /// a line of sight check that got through
+    ClearRay,
+    BlockedRay,
+    /// where the server sent a shot
+    Shot,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum UnreliableClientMessage {
     PlayerInputMessage(PlayerInputMessage),
+    /// the client's virtual time, answered right away with `TimeResponse`
+    TimeRequest(f64),
+    /// still here, sent while inputs aren't
+    Heartbeat,
 }
 
+/// what a client that opted in says about its setup, once per connection. it goes on its own
+/// channel, apart from gameplay, and is coarse on purpose
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TelemetryReport {
+    pub frame_rate: FrameRateBand,
+    /// physical pixels of the main window
+    pub resolution: (u32, u32),
+    pub input_device: InputDevice,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum FrameRateBand {
+    Below30,
+    From30To60,
+    From60To120,
+    From120To240,
+    Above240,
+}
+
+impl FrameRateBand {
+    pub const ALL: [Self; 5] = [
+        Self::Below30,
+        Self::From30To60,
+        Self::From60To120,
+        Self::From120To240,
+        Self::Above240,
+    ];
+
+    /// anything that isn't a number counts as slow
+    pub fn from_fps(fps: f64) -> Self {
+        match fps {
+            fps if fps >= 240.0 => Self::Above240,
+            fps if fps >= 120.0 => Self::From120To240,
+            fps if fps >= 60.0 => Self::From60To120,
+            fps if fps >= 30.0 => Self::From30To60,
+            _ => Self::Below30,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputDevice {
+    KeyboardMouse,
+    Gamepad,
+}
+
+/// the server moved our character so far that it was snapped there instead of smoothed
+#[derive(Event)]
+pub struct HardSnapEvent {
+    pub distance: f32,
+}
+
+/// identifies a replicated entity the same way on the server and every client
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkId(pub u64);
+
+/// on a client, a replicated entity it simulates ahead of the server: its own character, its
+/// own projectiles. everything else only ever changes by snapshot, and any other change is
+/// reported and put back before the next one is applied
+#[derive(Component)]
+pub struct Predicted;
+
 #[derive(Event)]
 pub struct SpawnCharacterVisualsEvent {
     pub entity: Entity,
     pub owner_client_id: ClientId,
+    pub color: PlayerColor,
     pub translation: Vec3,
 }
 
@@ -313,7 +1797,469 @@ pub struct CharacterVisuals {
     pub last_physics_translation: Vec3,
 }
 
-#[derive(Resource)]
-pub struct LastPhysicsUpdate {
-    pub time: Instant,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weapon::WEAPONS_PATH;
+    use std::path::Path;
+
+    /// one character's worth of state, as the server fills it in every tick
+    fn character_snapshot(client_id: u64) -> CharacterSnapshot {
+        let offset = client_id as f32;
+        CharacterSnapshot {
+            client_id,
+            translation: Some(Vec3::new(offset, 0.0, -offset)),
+            velocity: Some(Vec3::new(1.0, 0.0, 0.5)),
+            teleport_count: Some(0),
+            movement: Some(MovementParameters {
+                speed: 5.0,
+                accel: 8.0,
+                friction: 8.0,
+            }),
+            aiming: Some(false),
+            status_effects: None,
+            mantle: None,
+        }
+    }
+
+    fn standing_character() -> Character {
+        Character {
+            owner_client_id: ClientId::from_raw(0),
+            move_accel: 8.0,
+            move_speed: 5.0,
+            move_friction: 8.0,
+            velocity: Vec3::ZERO,
+            pitch: 0.0,
+            yaw: 0.0,
+            teleport_count: 0,
+            aiming: false,
+            status_effects: Vec::new(),
+            mantle: None,
+        }
+    }
+
+    fn snapshot(id: u32, character_count: u64) -> Snapshot {
+        Snapshot {
+            id,
+            latest_processed_input_id: Some(id),
+            character_snapshots: (0..character_count).map(character_snapshot).collect(),
+            removed_client_ids: Vec::new(),
+            components: ReplicatedComponents::default(),
+            reconciliation: None,
+            timestamp: 0,
+        }
+    }
+
+    /// the next tick of `snapshot` with everybody running, the common case for a delta
+    fn moved(snapshot: &Snapshot) -> Snapshot {
+        let mut moved = snapshot.clone();
+        moved.id += 1;
+        moved.character_snapshots = snapshot
+            .character_snapshots
+            .iter()
+            .map(|mut character_snapshot| {
+                character_snapshot.translation =
+                    character_snapshot.translation.map(|t| t + Vec3::X * 0.1);
+                character_snapshot.velocity = character_snapshot.velocity.map(|v| v * 1.1);
+                character_snapshot
+            })
+            .collect();
+        moved
+    }
+
+    fn message_size(snapshot: &Snapshot) -> u64 {
+        bincode::serialized_size(&UnreliableServerMessage::Snapshot(Box::new(
+            snapshot.clone(),
+        )))
+        .unwrap()
+    }
+
+    // budgets in bytes for the whole message. at the time of writing a full character is 56
+    // bytes, one that only moved is 39, and the message itself 37 on top
+    const FULL_1_MAX: u64 = 95;
+    const FULL_16_MAX: u64 = 940;
+    const FULL_64_MAX: u64 = 3640;
+    const DELTA_1_MAX: u64 = 80;
+    const DELTA_16_MAX: u64 = 670;
+    const DELTA_64_MAX: u64 = 2550;
+
+    fn assert_size(name: &str, size: u64, max: u64) {
+        assert!(
+            size <= max,
+            "{} snapshot is {} bytes, over its {} byte budget",
+            name,
+            size,
+            max
+        );
+    }
+
+    #[test]
+    fn full_snapshot_sizes() {
+        assert_size("full 1", message_size(&snapshot(0, 1)), FULL_1_MAX);
+        assert_size("full 16", message_size(&snapshot(0, 16)), FULL_16_MAX);
+        assert_size("full 64", message_size(&snapshot(0, 64)), FULL_64_MAX);
+    }
+
+    #[test]
+    fn delta_snapshot_sizes() {
+        for (count, max) in [(1, DELTA_1_MAX), (16, DELTA_16_MAX), (64, DELTA_64_MAX)] {
+            let baseline = snapshot(0, count);
+            let delta = moved(&baseline).diff(&baseline);
+            assert_eq!(delta.character_snapshots.len(), count as usize);
+            assert_size(&format!("delta {}", count), message_size(&delta), max);
+        }
+    }
+
+    #[test]
+    fn status_effects_cost_a_few_bytes() {
+        let mut affected = character_snapshot(0);
+        affected.status_effects = Some(vec![
+            NetStatusEffect {
+                id: StatusEffectKind::SpeedBoost.id(),
+                remaining: 50,
+            },
+            NetStatusEffect {
+                id: StatusEffectKind::Burn.id(),
+                remaining: 30,
+            },
+        ]);
+        let with_effects = Snapshot {
+            character_snapshots: [affected].into_iter().collect(),
+            ..snapshot(0, 1)
+        };
+        let extra = message_size(&with_effects) - message_size(&snapshot(0, 1));
+        assert!(extra <= 16, "two status effects cost {} bytes", extra);
+    }
+
+    #[test]
+    fn unchanged_delta_is_empty() {
+        let baseline = snapshot(0, 16);
+        let mut next = baseline.clone();
+        next.id += 1;
+        assert_eq!(next.diff(&baseline).character_snapshots.len(), 0);
+    }
+
+    #[test]
+    fn removals_repeat_until_a_snapshot_without_them_is_acked() {
+        let baseline = snapshot(0, 2);
+        let mut without_first = moved(&baseline);
+        without_first
+            .character_snapshots
+            .retain(|client_id| client_id != 0);
+        let sent = without_first.diff(&baseline);
+        assert_eq!(sent.removed_client_ids, vec![0]);
+
+        // the client never acked that one, the next is diffed against the same baseline
+        let later = moved(&without_first);
+        assert_eq!(later.diff(&baseline).removed_client_ids, vec![0]);
+
+        let acked = sent.applied_to(Some(&baseline));
+        assert!(later.diff(&acked).removed_client_ids.is_empty());
+    }
+
+    fn component(network_id: u64, component_id: u32, byte: u8) -> ComponentEntry {
+        ComponentEntry {
+            network_id,
+            component_id,
+            bytes: Some(vec![byte]),
+        }
+    }
+
+    #[test]
+    fn component_diffs_reproduce_the_new_state() {
+        let old: ReplicatedComponents =
+            [component(1, 7, 0), component(2, 7, 0), component(2, 9, 0)]
+                .into_iter()
+                .collect();
+        let new: ReplicatedComponents =
+            [component(3, 7, 0), component(2, 7, 1), component(1, 7, 0)]
+                .into_iter()
+                .collect();
+        let diff = new.diff(&old);
+        // 1 is unchanged, 2 changed one and lost the other, 3 is new
+        assert_eq!(
+            diff.iter().cloned().collect::<Vec<_>>(),
+            vec![
+                component(2, 7, 1),
+                ComponentEntry {
+                    network_id: 2,
+                    component_id: 9,
+                    bytes: None,
+                },
+                component(3, 7, 0),
+            ]
+        );
+        assert_eq!(diff.applied_to(&old), new);
+    }
+
+    #[test]
+    fn eviction_keeps_the_newest_entries() {
+        let mut history: Vec<u32> = (0..10).collect();
+        evict_oldest(&mut history, 4, "test entries");
+        assert_eq!(history, vec![6, 7, 8, 9]);
+
+        evict_oldest(&mut history, 4, "test entries");
+        assert_eq!(history.len(), 4);
+    }
+
+    #[test]
+    fn jumps_go_up_and_land_back_on_the_ground() {
+        let map = Map::default();
+        let mutators = Mutators::default();
+        let mut character = standing_character();
+        let mut transform = Transform::default();
+        let delta_seconds = 1.0 / 64.0;
+
+        let mut input = PlayerInput {
+            jump: true,
+            ..default()
+        };
+        character.process_input(&mut input, &mut transform, &map, &mutators, delta_seconds);
+        assert!(character.velocity.y > 0.0);
+
+        let mut input = PlayerInput::default();
+        let mut peak: f32 = 0.0;
+        for _ in 0..64 {
+            character.process_input(&mut input, &mut transform, &map, &mutators, delta_seconds);
+            peak = peak.max(transform.translation.y);
+            if transform.translation.y <= 0.0 {
+                break;
+            }
+        }
+        assert!(peak > 0.2, "only got {} up", peak);
+        assert!(
+            transform.translation.y.abs() < 1e-3,
+            "landed at {}",
+            transform.translation.y
+        );
+        assert_eq!(character.velocity.y, 0.0);
+
+        // no jumping off thin air
+        transform.translation.y = 1.0;
+        let mut input = PlayerInput {
+            jump: true,
+            ..default()
+        };
+        character.process_input(&mut input, &mut transform, &map, &mutators, delta_seconds);
+        assert!(character.velocity.y < 0.0);
+    }
+
+    #[test]
+    fn mutators_hold_what_they_were_made_from() {
+        let mutators = Mutators::from_list(&[Mutator::Instagib, Mutator::LowGravity]);
+        assert_eq!(
+            mutators.list(),
+            vec![Mutator::LowGravity, Mutator::Instagib]
+        );
+        assert!(mutators.gravity() < Mutators::default().gravity());
+        assert_eq!(mutators.speed_multiplier(), 1.0);
+        for mutator in Mutator::ALL {
+            assert_eq!(Mutator::from_name(mutator.name()), Some(mutator));
+        }
+    }
+
+    #[test]
+    fn match_seed_gives_the_same_numbers_for_the_same_tick() {
+        let draw = |seed: u64, tick, stream, salt| {
+            let mut random = MatchSeed(seed).random(tick, stream, salt);
+            [random.next_u64(), random.next_u64()]
+        };
+        let numbers = draw(7, 100, RandomStream::Spread, 3);
+        assert_eq!(draw(7, 100, RandomStream::Spread, 3), numbers);
+        assert_ne!(numbers[0], numbers[1]);
+        assert_ne!(draw(8, 100, RandomStream::Spread, 3), numbers);
+        assert_ne!(draw(7, 101, RandomStream::Spread, 3), numbers);
+        assert_ne!(draw(7, 100, RandomStream::Spread, 4), numbers);
+
+        let mut random = MatchSeed(7).random(100, RandomStream::Spread, 0);
+        for _ in 0..1000 {
+            let value = random.range(-2.0, 2.0);
+            assert!((-2.0..2.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn shots_stray_the_same_way_and_further_when_hot() {
+        let shot = |input_id| Shot {
+            input_id,
+            pitch: 0.2,
+            yaw: 1.0,
+            seen_snapshot_id: None,
+        };
+        let weapons = WeaponDefinitions::load(Path::new(WEAPONS_PATH)).unwrap();
+        let rifle = weapons.get(Weapon::Rifle);
+        let aim = Quat::from_euler(EulerRot::YXZ, 1.0, 0.2, 0.0) * Vec3::NEG_Z;
+        let direction = shot_direction(MatchSeed(3), 1, shot(10), rifle, 0.0);
+        assert_eq!(
+            shot_direction(MatchSeed(3), 1, shot(10), rifle, 0.0),
+            direction
+        );
+        assert_ne!(
+            shot_direction(MatchSeed(3), 2, shot(10), rifle, 0.0),
+            direction
+        );
+        assert!(direction.angle_between(aim) <= rifle.spread + 1e-3);
+
+        let mut inventory = Inventory::starting(&weapons);
+        let mut largest_stray: f32 = 0.0;
+        for input_id in 0..20 {
+            let direction = inventory.shot_direction(&weapons, MatchSeed(3), 1, shot(input_id));
+            largest_stray = largest_stray.max(direction.unwrap().angle_between(aim));
+            assert!(inventory.apply(InventoryAction::Fire(shot(input_id)), &weapons));
+        }
+        assert!(largest_stray > weapons.get(Weapon::Pistol).spread);
+        assert!(inventory.weapons[0].heat > 1.0);
+        // the same input can't fire twice, nor an older one
+        assert!(!inventory.apply(InventoryAction::Fire(shot(19)), &weapons));
+        assert!(!inventory.apply(InventoryAction::Fire(shot(5)), &weapons));
+        assert_eq!(inventory.weapons[0].heat_at(1000, &weapons), 0.0);
+    }
+
+    #[test]
+    fn frame_rate_bands_include_their_lower_bound() {
+        assert_eq!(FrameRateBand::from_fps(29.9), FrameRateBand::Below30);
+        assert_eq!(FrameRateBand::from_fps(30.0), FrameRateBand::From30To60);
+        assert_eq!(FrameRateBand::from_fps(60.0), FrameRateBand::From60To120);
+        assert_eq!(FrameRateBand::from_fps(144.0), FrameRateBand::From120To240);
+        assert_eq!(FrameRateBand::from_fps(1000.0), FrameRateBand::Above240);
+        assert_eq!(FrameRateBand::from_fps(f64::NAN), FrameRateBand::Below30);
+    }
+
+    mod diff_properties {
+        use super::super::*;
+        use proptest::prelude::*;
+
+        // values come from tiny sets so old and new often agree on some fields, which is
+        // where diffs get interesting
+        fn small_vec3() -> impl Strategy<Value = Vec3> {
+            (-1i8..=1, -1i8..=1, -1i8..=1)
+                .prop_map(|(x, y, z)| Vec3::new(x as f32, y as f32, z as f32))
+        }
+
+        /// never an empty list, a full snapshot leaves those out
+        fn status_effects() -> impl Strategy<Value = Option<Vec<NetStatusEffect>>> {
+            prop::collection::vec((0u8..3, 1u16..3), 0..3).prop_map(|status_effects| {
+                Some(
+                    status_effects
+                        .into_iter()
+                        .map(|(id, remaining)| NetStatusEffect { id, remaining })
+                        .collect::<Vec<_>>(),
+                )
+                .filter(|status_effects| !status_effects.is_empty())
+            })
+        }
+
+        /// left out when not mantling, like in a full snapshot
+        fn mantle() -> impl Strategy<Value = Option<Option<Mantle>>> {
+            prop::option::of((small_vec3(), 0u8..3).prop_map(|(end, elapsed)| {
+                Some(Mantle {
+                    start: Vec3::ZERO,
+                    end,
+                    peak: 1.0,
+                    elapsed: elapsed as f32 * 0.1,
+                })
+            }))
+        }
+
+        fn character(client_id: u64) -> impl Strategy<Value = CharacterSnapshot> {
+            (
+                small_vec3(),
+                small_vec3(),
+                0u32..3,
+                any::<bool>(),
+                1u8..=2,
+                status_effects(),
+                mantle(),
+            )
+                .prop_map(
+                    move |(
+                        translation,
+                        velocity,
+                        teleport_count,
+                        aiming,
+                        speed,
+                        status_effects,
+                        mantle,
+                    )| CharacterSnapshot {
+                        client_id,
+                        translation: Some(translation),
+                        velocity: Some(velocity),
+                        teleport_count: Some(teleport_count),
+                        movement: Some(MovementParameters {
+                            speed: speed as f32,
+                            accel: 8.0,
+                            friction: 8.0,
+                        }),
+                        aiming: Some(aiming),
+                        status_effects,
+                        mantle,
+                    },
+                )
+        }
+
+        /// a full snapshot of up to 8 characters picked out of 12 ids, so consecutive ones
+        /// have characters appearing and disappearing
+        fn full_snapshot() -> impl Strategy<Value = Snapshot> {
+            prop::collection::btree_set(0u64..12, 0..8)
+                .prop_flat_map(|client_ids| {
+                    client_ids.into_iter().map(character).collect::<Vec<_>>()
+                })
+                .prop_map(|character_snapshots| Snapshot {
+                    id: 0,
+                    latest_processed_input_id: None,
+                    character_snapshots: character_snapshots.into_iter().collect(),
+                    removed_client_ids: Vec::new(),
+                    components: ReplicatedComponents::default(),
+                    reconciliation: None,
+                    timestamp: 0,
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn applying_a_diff_reproduces_the_new_state(
+                old in full_snapshot(),
+                new in full_snapshot(),
+            ) {
+                let applied = new.diff(&old).applied_to(Some(&old));
+                prop_assert_eq!(applied.character_snapshots, new.character_snapshots);
+            }
+
+            #[test]
+            fn a_full_snapshot_applies_to_nothing_as_itself(snapshot in full_snapshot()) {
+                let applied = snapshot.applied_to(None);
+                prop_assert_eq!(applied.character_snapshots, snapshot.character_snapshots);
+            }
+
+            #[test]
+            fn diffs_leave_out_unchanged_characters(
+                old in full_snapshot(),
+                new in full_snapshot(),
+            ) {
+                let diff = new.diff(&old);
+                for character_snapshot in diff.character_snapshots.iter() {
+                    let client_id = character_snapshot.client_id;
+                    prop_assert!(!character_snapshot.is_empty());
+                    let find = |snapshot: &Snapshot| {
+                        snapshot
+                            .character_snapshots
+                            .iter()
+                            .find(|other| other.client_id == client_id)
+                    };
+                    prop_assert_ne!(find(&old), find(&new));
+                }
+            }
+
+            #[test]
+            fn diffs_chain_across_several_snapshots(
+                first in full_snapshot(),
+                second in full_snapshot(),
+                third in full_snapshot(),
+            ) {
+                let view = second.diff(&first).applied_to(Some(&first));
+                let view = third.diff(&second).applied_to(Some(&view));
+                prop_assert_eq!(view.character_snapshots, third.character_snapshots);
+            }
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use crate::{core::*, origin::RenderOrigin, CHARACTER_HEIGHT};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{transport::NETCODE_USER_DATA_BYTES, ClientId};
+use std::collections::VecDeque;
+
+/// where they go in the connect token's user data, right after the weapons hash
+const USER_DATA_OFFSET: usize = 8;
+const HATS: [Hat; 3] = [Hat::None, Hat::Cap, Hat::Topper];
+const TRAIL_COLORS: [TrailColor; 4] = [
+    TrailColor::Red,
+    TrailColor::Green,
+    TrailColor::Blue,
+    TrailColor::White,
+];
+/// meters between the points a trail is drawn through
+const TRAIL_SPACING: f32 = 0.25;
+const TRAIL_POINTS: usize = 24;
+
+/// clients say what they want to look like when connecting, and the server hands it out with
+/// every spawn of their character like it does their color. it's settled once per connection,
+/// nothing about it is sent again while playing
+pub struct CosmeticsPlugin;
+impl Plugin for CosmeticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (attach_cosmetics_system, trail_system).chain());
+    }
+}
+
+/// what each connected client picked, for every spawn of their character. only on the server
+#[derive(Resource, Default)]
+pub struct ClientCosmetics {
+    clients: HashMap<ClientId, Cosmetics>,
+}
+
+impl ClientCosmetics {
+    pub fn get(&self, client_id: ClientId) -> Cosmetics {
+        self.clients.get(&client_id).copied().unwrap_or_default()
+    }
+
+    pub fn insert(&mut self, client_id: ClientId, cosmetics: Cosmetics) {
+        self.clients.insert(client_id, cosmetics);
+    }
+
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+}
+
+/// what this client asks to look like when it connects
+#[derive(Resource)]
+pub struct CosmeticsRequest(pub Cosmetics);
+
+/// the character's trail, in world space so moving the render origin doesn't bend it
+#[derive(Component)]
+struct Trail {
+    color: Color,
+    points: VecDeque<Vec3>,
+}
+
+pub fn write_user_data(user_data: &mut [u8; NETCODE_USER_DATA_BYTES], cosmetics: Cosmetics) {
+    user_data[USER_DATA_OFFSET] = HATS
+        .iter()
+        .position(|hat| *hat == cosmetics.hat)
+        .unwrap_or(0) as u8;
+    // 0 is no trail
+    user_data[USER_DATA_OFFSET + 1] = cosmetics
+        .trail
+        .and_then(|trail| TRAIL_COLORS.iter().position(|color| *color == trail))
+        .map_or(0, |index| index as u8 + 1);
+}
+
+/// anything out of range is left off rather than turning the client away over how it looks
+pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Cosmetics {
+    Cosmetics {
+        hat: HATS
+            .get(user_data[USER_DATA_OFFSET] as usize)
+            .copied()
+            .unwrap_or_default(),
+        trail: (user_data[USER_DATA_OFFSET + 1] as usize)
+            .checked_sub(1)
+            .and_then(|index| TRAIL_COLORS.get(index))
+            .copied(),
+    }
+}
+
+fn trail_color(trail: TrailColor) -> Color {
+    match trail {
+        TrailColor::Red => Color::rgb(0.9, 0.2, 0.2),
+        TrailColor::Green => Color::rgb(0.2, 0.8, 0.3),
+        TrailColor::Blue => Color::rgb(0.2, 0.4, 0.9),
+        TrailColor::White => Color::WHITE,
+    }
+}
+
+/// bodies are dressed up once, when they're spawned for the character
+fn attach_cosmetics_system(
+    mut commands: Commands,
+    origin: Res<RenderOrigin>,
+    characters: Query<&Cosmetics, With<Character>>,
+    visuals: Query<(Entity, &CharacterVisuals, &Transform), Added<CharacterVisuals>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, visuals, transform) in visuals.iter() {
+        let cosmetics = characters
+            .get(visuals.character_entity)
+            .copied()
+            .unwrap_or_default();
+
+        // the body reaches a character height above its feet
+        let hat = match cosmetics.hat {
+            Hat::None => None,
+            Hat::Cap => Some((Cylinder::new(0.26, 0.08), 0.04, Color::rgb(0.8, 0.1, 0.1))),
+            Hat::Topper => Some((Cylinder::new(0.18, 0.35), 0.175, Color::rgb(0.1, 0.1, 0.1))),
+        };
+        if let Some((mesh, half_height, color)) = hat {
+            let hat = commands
+                .spawn(PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(color),
+                    transform: Transform::from_xyz(0.0, CHARACTER_HEIGHT + half_height, 0.0),
+                    ..default()
+                })
+                .id();
+            commands.entity(entity).add_child(hat);
+        }
+
+        if let Some(trail) = cosmetics.trail {
+            commands.entity(entity).insert(Trail {
+                color: trail_color(trail),
+                points: VecDeque::from([origin.to_world(transform.translation)]),
+            });
+        }
+    }
+}
+
+fn trail_system(
+    origin: Res<RenderOrigin>,
+    mut trails: Query<(&Transform, &mut Trail)>,
+    mut gizmos: Gizmos,
+) {
+    for (transform, mut trail) in trails.iter_mut() {
+        let position = origin.to_world(transform.translation);
+        if trail
+            .points
+            .back()
+            .is_none_or(|last| last.distance(position) >= TRAIL_SPACING)
+        {
+            if trail.points.len() >= TRAIL_POINTS {
+                trail.points.pop_front();
+            }
+            trail.points.push_back(position);
+        }
+        let points = trail
+            .points
+            .iter()
+            .chain(std::iter::once(&position))
+            .map(|point| origin.to_render(*point));
+        gizmos.linestrip(points, trail.color);
+    }
+}
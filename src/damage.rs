@@ -0,0 +1,264 @@
+use crate::{
+    core::*,
+    game_mode::Matches,
+    localization::Localization,
+    room::Rooms,
+    server::{ReliableOutbox, ResetWorldEvent},
+    CHARACTER_HEIGHT, MAX_HEALTH,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+
+/// health is only ever taken away by the server (or single player), through `DamageEvent`s.
+/// running out sends the character back to its room's spawn with full health
+pub struct DamagePlugin;
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_health_hud_system);
+        app.add_systems(
+            FixedUpdate,
+            (reset_health_system, apply_damage_system)
+                .chain()
+                .run_if(not(resource_exists::<RenetClient>)),
+        );
+        app.add_systems(
+            Update,
+            (
+                receive_health_system.run_if(resource_exists::<RenetClient>),
+                health_hud_system,
+            )
+                .chain(),
+        );
+        app.add_event::<DamageEvent>();
+        app.add_event::<ReceivedHealthEvent>();
+    }
+}
+
+/// hurts a character, anything that hurts goes through this
+#[derive(Event)]
+pub struct DamageEvent {
+    pub client_id: ClientId,
+    pub amount: f32,
+    /// credited with the damage and any kill, never themselves
+    pub instigator: Option<ClientId>,
+}
+
+/// the server sent us our character's health
+#[derive(Event)]
+pub struct ReceivedHealthEvent(pub f32);
+
+/// a part of a character shots can hit, a sphere this high above the feet
+pub struct Hitbox {
+    pub part: BodyPart,
+    pub height: f32,
+    pub radius: f32,
+}
+
+/// every character has the same ones, placed from wherever the snapshot has them, so the shooter
+/// can check against them on their own. the head sticks out above the body, a shot that gets
+/// to both hits whichever it reaches first
+pub const HITBOXES: [Hitbox; 2] = [
+    Hitbox {
+        part: BodyPart::Head,
+        height: CHARACTER_HEIGHT * 0.95,
+        radius: 0.15,
+    },
+    Hitbox {
+        part: BodyPart::Body,
+        height: CHARACTER_HEIGHT * 0.45,
+        radius: 0.35,
+    },
+];
+
+/// the hitboxes of a character whose feet are at `translation`, as what they belong to, their
+/// center and radius
+pub fn hitboxes<T: Copy>(
+    target: T,
+    translation: Vec3,
+) -> impl Iterator<Item = ((T, BodyPart), Vec3, f32)> {
+    HITBOXES.iter().map(move |hitbox| {
+        (
+            (target, hitbox.part),
+            translation + Vec3::Y * hitbox.height,
+            hitbox.radius,
+        )
+    })
+}
+
+/// a point roughly in the middle of the body, so low cover still protects
+pub fn body_center(transform: &Transform) -> Vec3 {
+    transform.translation + Vec3::Y * CHARACTER_HEIGHT * 0.5
+}
+
+/// how much of `amount` a character with `health` left actually loses. the instagib mutator makes
+/// anything someone else did take all of it, hazards and your own explosions still only hurt
+pub fn damage_taken(mutators: &Mutators, health: f32, amount: f32, instigated: bool) -> f32 {
+    if mutators.contains(Mutator::Instagib) && instigated {
+        health
+    } else {
+        amount.clamp(0.0, health)
+    }
+}
+
+/// clients spawn their characters again with full health, the server's need to catch up
+fn reset_health_system(
+    mut resets: EventReader<ResetWorldEvent>,
+    mut healths: Query<(&mut Health, Option<&MaxHealth>)>,
+) {
+    if resets.read().count() == 0 {
+        return;
+    }
+    for (mut health, max_health) in healths.iter_mut() {
+        health.0 = max_health.map_or(MAX_HEALTH, |max_health| max_health.0);
+    }
+}
+
+pub fn apply_damage_system(
+    mutators: Res<Mutators>,
+    rooms: Res<Rooms>,
+    mut damages: EventReader<DamageEvent>,
+    mut matches: Option<ResMut<Matches>>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: Option<ResMut<RenetServer>>,
+    mut characters: Query<(
+        &mut Character,
+        &mut Transform,
+        &mut Health,
+        Option<&MaxHealth>,
+        Has<GodMode>,
+    )>,
+) {
+    for damage in damages.read() {
+        if !damage.amount.is_finite() {
+            report_non_finite("damage");
+            continue;
+        }
+        let (mut character, mut transform, mut health, max_health, god_mode) = match characters
+            .iter_mut()
+            .find(|(character, _, _, _, _)| character.owner_client_id == damage.client_id)
+        {
+            Some(found) => found,
+            None => continue,
+        };
+        if god_mode {
+            continue;
+        }
+
+        let instigator = damage
+            .instigator
+            .filter(|instigator| *instigator != damage.client_id);
+
+        let previous_health = health.0;
+        let amount = damage_taken(&mutators, health.0, damage.amount, instigator.is_some());
+        health.0 -= amount;
+        if let (Some(instigator), Some(matches)) = (instigator, matches.as_mut()) {
+            matches
+                .of(rooms.room_of(instigator))
+                .stats
+                .entry(instigator)
+                .or_default()
+                .damage_dealt += amount;
+        }
+
+        if health.0 <= 0.0 {
+            println!("{} died", damage.client_id);
+            transform.translation = rooms.spawn_point_of(damage.client_id);
+            character.velocity = Vec3::ZERO;
+            character.mantle = None;
+            character.teleport_count = character.teleport_count.wrapping_add(1);
+            health.0 = max_health.map_or(MAX_HEALTH, |max_health| max_health.0);
+            if let Some(matches) = matches.as_mut() {
+                matches
+                    .of(rooms.room_of(damage.client_id))
+                    .record_death(damage.client_id, instigator);
+            }
+        }
+
+        // the owner only ever sees whole numbers, a trickle of damage needn't be sent every tick
+        if health.0.ceil() != previous_health.ceil() {
+            if let Some(server) = server.as_mut() {
+                outbox.send(
+                    server,
+                    damage.client_id,
+                    &ReliableServerMessage::Health(health.0),
+                );
+            }
+        }
+    }
+}
+
+fn receive_health_system(
+    local_player: Option<Res<LocalPlayer>>,
+    mut received: EventReader<ReceivedHealthEvent>,
+    mut characters: Query<(&Character, &mut Health)>,
+) {
+    let local_player = match local_player {
+        Some(local_player) => local_player,
+        None => return,
+    };
+    for ReceivedHealthEvent(received_health) in received.read() {
+        if !received_health.is_finite() {
+            report_non_finite("health");
+            continue;
+        }
+        if let Some((_, mut health)) = characters
+            .iter_mut()
+            .find(|(character, _)| character.owner_client_id == local_player.client_id)
+        {
+            health.0 = *received_health;
+        }
+    }
+}
+
+#[derive(Component)]
+struct HealthText;
+
+fn setup_health_hud_system(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(60.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                HealthText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+fn health_hud_system(
+    localization: Res<Localization>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<(&Character, &Health)>,
+    mut texts: Query<&mut Text, With<HealthText>>,
+) {
+    let health = local_player.and_then(|local_player| {
+        characters
+            .iter()
+            .find(|(character, _)| character.owner_client_id == local_player.client_id)
+            .map(|(_, health)| health.0)
+    });
+    let value = match health {
+        Some(health) => localization.format("health.label", &[&health.ceil()]),
+        None => String::new(),
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value.clone_from(&value);
+        }
+    }
+}
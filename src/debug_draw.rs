@@ -0,0 +1,268 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    damage::body_center,
+    hazard::WorldClock,
+    map::{Map, ShotLeg},
+    origin::RenderOrigin,
+    CHARACTER_HEIGHT, CHARACTER_RADIUS,
+};
+use bevy::{prelude::*, utils::HashSet};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+/// fixed ticks between updates, 8 a second is plenty to see what's going on
+const SEND_INTERVAL: u32 = 8;
+/// line of sight checks are over in a tick, they're kept around long enough to be seen
+const RAY_DURATION: f32 = 2.0;
+/// nothing new for this long means the server stopped sending
+const VIEW_TIMEOUT: f32 = 1.0;
+
+/// for "the server says I'm inside a wall": an admin picks a client with `debugdraw <client id>`
+/// and the server keeps sending it what it collides with and checks against, which that client
+/// draws over its own view with gizmos
+pub struct DebugDrawPlugin;
+impl Plugin for DebugDrawPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            admin_debug_draw_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            FixedPostUpdate,
+            send_debug_draw_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            (
+                receive_debug_draw_system.run_if(resource_exists::<RenetClient>),
+                draw_debug_shapes_system.run_if(|view: Res<DebugDrawView>| !view.shapes.is_empty()),
+            )
+                .chain(),
+        );
+        app.init_resource::<DebugDrawSubscribers>();
+        app.init_resource::<DebugDrawView>();
+        app.add_event::<ReceivedDebugDrawEvent>();
+    }
+}
+
+/// the server sent us its shapes
+#[derive(Event)]
+pub struct ReceivedDebugDrawEvent(pub Vec<DebugShape>);
+
+/// only exists while someone is watching, so checks elsewhere don't record anything otherwise
+#[derive(Resource, Default)]
+pub struct DebugRays {
+    rays: Vec<(DebugShape, f32)>,
+}
+
+impl DebugRays {
+    pub fn line_of_sight(&mut self, time: &Time, from: Vec3, to: Vec3, clear: bool) {
+        let color = if clear {
+            DebugColor::ClearRay
+        } else {
+            DebugColor::BlockedRay
+        };
+        self.rays.push((
+            DebugShape::Segment(from, to, color),
+            time.elapsed_seconds() + RAY_DURATION,
+        ));
+    }
+
+    pub fn shot(&mut self, time: &Time, legs: &[ShotLeg]) {
+        for leg in legs {
+            self.rays.push((
+                DebugShape::Segment(leg.start, leg.end, DebugColor::Shot),
+                time.elapsed_seconds() + RAY_DURATION,
+            ));
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct DebugDrawSubscribers {
+    clients: HashSet<ClientId>,
+}
+
+/// what's drawn here, from the server
+#[derive(Resource, Default)]
+struct DebugDrawView {
+    shapes: Vec<DebugShape>,
+    received_at: f32,
+}
+
+fn admin_debug_draw_system(
+    mut commands: Commands,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut subscribers: ResMut<DebugDrawSubscribers>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::DebugDraw(client_id) = command {
+            if subscribers.clients.remove(client_id) {
+                println!("Debug drawing off for {}", client_id);
+            } else {
+                subscribers.clients.insert(*client_id);
+                println!("Debug drawing on for {}", client_id);
+            }
+            if subscribers.clients.is_empty() {
+                commands.remove_resource::<DebugRays>();
+            } else {
+                commands.init_resource::<DebugRays>();
+            }
+        }
+    }
+}
+
+fn send_debug_draw_system(
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    map: Res<Map>,
+    world_clock: Res<WorldClock>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<(&Character, &Transform)>,
+    mut ticks: Local<u32>,
+    mut rays: Option<ResMut<DebugRays>>,
+    mut subscribers: ResMut<DebugDrawSubscribers>,
+    mut view: ResMut<DebugDrawView>,
+    mut server: ResMut<RenetServer>,
+) {
+    *ticks = ticks.wrapping_add(1);
+    if subscribers.clients.is_empty() || !ticks.is_multiple_of(SEND_INTERVAL) {
+        return;
+    }
+    let local_client_id = local_player.map(|local_player| local_player.client_id);
+    subscribers
+        .clients
+        .retain(|client_id| server.is_connected(*client_id) || Some(*client_id) == local_client_id);
+
+    let seconds = world_clock.seconds(&fixed_time, 0.0);
+    let mut shapes = Vec::new();
+    for wall in &map.walls {
+        shapes.push(DebugShape::Box(
+            wall.center,
+            wall.half_extents,
+            DebugColor::Wall,
+        ));
+    }
+    for launch_volume in &map.launch_volumes {
+        shapes.push(DebugShape::Box(
+            launch_volume.center,
+            launch_volume.half_extents,
+            DebugColor::LaunchVolume,
+        ));
+    }
+    for teleporter in &map.teleporters {
+        for pad in teleporter.pads {
+            shapes.push(DebugShape::Box(
+                pad,
+                teleporter.half_extents,
+                DebugColor::Teleporter,
+            ));
+        }
+    }
+    for fire_zone in map
+        .fire_zones
+        .iter()
+        .filter(|fire_zone| fire_zone.is_active(seconds))
+    {
+        shapes.push(DebugShape::Box(
+            fire_zone.center,
+            fire_zone.half_extents,
+            DebugColor::Hazard,
+        ));
+    }
+    for laser in &map.lasers {
+        let (start, end) = laser.segment_at(seconds);
+        shapes.push(DebugShape::Segment(start, end, DebugColor::Hazard));
+    }
+    for (_, transform) in characters.iter() {
+        shapes.push(DebugShape::Segment(
+            transform.translation,
+            transform.translation + Vec3::Y * CHARACTER_HEIGHT,
+            DebugColor::Character,
+        ));
+        shapes.push(DebugShape::Sphere(
+            body_center(transform),
+            CHARACTER_RADIUS,
+            DebugColor::Character,
+        ));
+    }
+    if let Some(rays) = rays.as_mut() {
+        let now = time.elapsed_seconds();
+        rays.rays.retain(|(_, until)| *until > now);
+        shapes.extend(rays.rays.iter().map(|(shape, _)| *shape));
+    }
+
+    for client_id in subscribers.clients.iter() {
+        if Some(*client_id) == local_client_id {
+            view.shapes.clone_from(&shapes);
+            view.received_at = time.elapsed_seconds();
+        } else if let Ok(message) =
+            bincode::serialize(&UnreliableServerMessage::DebugDraw(shapes.clone()))
+        {
+            server.send_message(*client_id, DefaultChannel::Unreliable, message);
+        }
+    }
+}
+
+fn receive_debug_draw_system(
+    time: Res<Time>,
+    mut received: EventReader<ReceivedDebugDrawEvent>,
+    mut view: ResMut<DebugDrawView>,
+) {
+    for ReceivedDebugDrawEvent(shapes) in received.read() {
+        view.shapes.clone_from(shapes);
+        view.received_at = time.elapsed_seconds();
+    }
+}
+
+fn draw_debug_shapes_system(
+    time: Res<Time>,
+    origin: Res<RenderOrigin>,
+    mut view: ResMut<DebugDrawView>,
+    mut gizmos: Gizmos,
+) {
+    if time.elapsed_seconds() - view.received_at > VIEW_TIMEOUT {
+        view.shapes.clear();
+        return;
+    }
+
+    for shape in &view.shapes {
+        match *shape {
+            DebugShape::Box(center, half_extents, color) => {
+                gizmos.cuboid(
+                    Transform::from_translation(origin.to_render(center))
+                        .with_scale(half_extents * 2.0),
+                    to_color(color),
+                );
+            }
+            DebugShape::Segment(start, end, color) => {
+                gizmos.line(
+                    origin.to_render(start),
+                    origin.to_render(end),
+                    to_color(color),
+                );
+            }
+            DebugShape::Sphere(center, radius, color) => {
+                gizmos.sphere(
+                    origin.to_render(center),
+                    Quat::IDENTITY,
+                    radius,
+                    to_color(color),
+                );
+            }
+        }
+    }
+}
+
+fn to_color(color: DebugColor) -> Color {
+    match color {
+        DebugColor::Wall => Color::GRAY,
+        DebugColor::LaunchVolume => Color::ORANGE,
+        DebugColor::Teleporter => Color::PURPLE,
+        DebugColor::Hazard => Color::RED,
+        DebugColor::Character => Color::CYAN,
+        DebugColor::ClearRay => Color::GREEN,
+        DebugColor::BlockedRay => Color::FUCHSIA,
+        DebugColor::Shot => Color::YELLOW,
+    }
+}
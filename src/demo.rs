@@ -0,0 +1,537 @@
+use crate::{
+    core::*,
+    game_mode::MatchEvent,
+    hazard::WorldClock,
+    localization::Localization,
+    origin::RenderOrigin,
+    palette::{PlayerColor, PALETTE_SIZE},
+};
+use bevy::{prelude::*, ui::RelativeCursorPosition};
+use bevy_renet::renet::ClientId;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+const DEMO_VERSION: u32 = 6;
+
+/// records every tick of the authoritative simulation on the server, one file per match
+pub struct DemoRecordingPlugin;
+impl Plugin for DemoRecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_recording_system);
+        app.add_systems(
+            FixedPostUpdate,
+            record_frame_system.run_if(resource_exists::<DemoRecorder>),
+        );
+        app.add_systems(
+            Update,
+            demo_match_system.run_if(resource_exists::<DemoRecorder>),
+        );
+    }
+}
+
+/// plays a recorded demo back offline, following any of the recorded players
+pub struct DemoPlaybackPlugin;
+impl Plugin for DemoPlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, (load_demo_system, setup_playback_ui_system));
+        app.add_systems(
+            FixedUpdate,
+            playback_system.run_if(resource_exists::<DemoPlayback>),
+        );
+        app.add_systems(
+            Update,
+            (
+                follow_player_system,
+                playback_controls_system,
+                playback_ui_system,
+                interpolation_overlay_system,
+            )
+                .chain()
+                .run_if(resource_exists::<DemoPlayback>),
+        );
+        app.init_resource::<WorldClock>();
+        app.init_resource::<MatchSeed>();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DemoHeader {
+    version: u32,
+    timestep_seconds: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DemoFrame {
+    tick: u32,
+    /// the world clock tick and match seed the frame was recorded with, so random numbers
+    /// come out the same in playback
+    world_tick: u32,
+    seed: u64,
+    character_snapshots: Vec<CharacterSnapshot>,
+    inputs: Vec<(u64, Vec<PlayerInput>)>,
+}
+
+#[derive(Resource)]
+pub struct DemoRecorder {
+    dir: PathBuf,
+    writer: Option<BufWriter<File>>,
+    tick: u32,
+    pending_inputs: Vec<(u64, Vec<PlayerInput>)>,
+}
+
+impl DemoRecorder {
+    /// inputs the server simulated this tick, written out with the next frame
+    pub fn record_inputs(&mut self, client_id: ClientId, inputs: &[PlayerInput]) {
+        if self.writer.is_some() {
+            self.pending_inputs.push((client_id.raw(), inputs.to_vec()));
+        }
+    }
+
+    fn start(&mut self, timestep_seconds: f64) {
+        self.finish();
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = self.dir.join(format!("match_{}.demo", timestamp));
+
+        match File::create(&path) {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                let header = DemoHeader {
+                    version: DEMO_VERSION,
+                    timestep_seconds,
+                };
+                if let Err(e) = bincode::serialize_into(&mut writer, &header) {
+                    eprintln!("Failed to write demo header: {}", e);
+                    return;
+                }
+                println!("Recording demo to {}", path.display());
+                self.writer = Some(writer);
+                self.tick = 0;
+            }
+            Err(e) => eprintln!("Failed to create demo {}: {}", path.display(), e),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.flush() {
+                eprintln!("Failed to finish demo: {}", e);
+            }
+        }
+        self.pending_inputs.clear();
+    }
+}
+
+#[derive(Resource)]
+struct DemoPlayback {
+    frames: Vec<DemoFrame>,
+    /// the frame applied on the last fixed tick
+    current_frame: usize,
+    paused: bool,
+}
+
+#[derive(Component)]
+struct PlaybackText;
+
+#[derive(Component)]
+struct Timeline;
+
+#[derive(Component)]
+struct TimelineFill;
+
+fn read_demo(path: &Path) -> Result<(DemoHeader, Vec<DemoFrame>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+
+    let header: DemoHeader = bincode::deserialize_from(&mut reader).map_err(|e| e.to_string())?;
+    if header.version != DEMO_VERSION {
+        return Err(format!(
+            "unsupported demo version {} (expected {})",
+            header.version, DEMO_VERSION
+        ));
+    }
+
+    // a server that crashed mid-match leaves a truncated file, play whatever made it to disk
+    let mut frames = Vec::new();
+    while let Ok(frame) = bincode::deserialize_from::<_, DemoFrame>(&mut reader) {
+        frames.push(frame);
+    }
+
+    Ok((header, frames))
+}
+
+fn start_recording_system(
+    mut commands: Commands,
+    server_settings: Res<ServerSettings>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    if let Some(dir) = &server_settings.demo_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Failed to create demo directory {}: {}", dir.display(), e);
+            return;
+        }
+
+        let mut recorder = DemoRecorder {
+            dir: dir.clone(),
+            writer: None,
+            tick: 0,
+            pending_inputs: Vec::new(),
+        };
+        recorder.start(fixed_time.timestep().as_secs_f64());
+        commands.insert_resource(recorder);
+    }
+}
+
+fn record_frame_system(
+    world_clock: Res<WorldClock>,
+    match_seed: Res<MatchSeed>,
+    characters: Query<(&Character, &Transform)>,
+    mut recorder: ResMut<DemoRecorder>,
+) {
+    let recorder = recorder.as_mut();
+    if let Some(writer) = recorder.writer.as_mut() {
+        let frame = DemoFrame {
+            tick: recorder.tick,
+            world_tick: world_clock.tick,
+            seed: match_seed.0,
+            character_snapshots: characters
+                .iter()
+                .map(|(character, transform)| {
+                    CharacterSnapshot::from_character(character, transform)
+                })
+                .collect(),
+            inputs: std::mem::take(&mut recorder.pending_inputs),
+        };
+
+        if let Err(e) = bincode::serialize_into(writer, &frame) {
+            eprintln!("Failed to write demo frame, stopping recording: {}", e);
+            recorder.writer = None;
+        }
+        recorder.tick += 1;
+    }
+}
+
+fn demo_match_system(
+    fixed_time: Res<Time<Fixed>>,
+    mut match_events: EventReader<MatchEvent>,
+    mut recorder: ResMut<DemoRecorder>,
+) {
+    for event in match_events.read() {
+        match event {
+            MatchEvent::Ended(_) => recorder.finish(),
+            MatchEvent::Started => recorder.start(fixed_time.timestep().as_secs_f64()),
+            MatchEvent::ShowResults => {}
+        }
+    }
+}
+
+fn load_demo_system(
+    mut commands: Commands,
+    demo_settings: Res<DemoSettings>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    match read_demo(&demo_settings.path) {
+        Ok((header, frames)) => {
+            println!(
+                "Loaded demo {} ({} ticks)",
+                demo_settings.path.display(),
+                frames.len()
+            );
+            fixed_time.set_timestep_seconds(header.timestep_seconds);
+            commands.insert_resource(DemoPlayback {
+                frames,
+                current_frame: 0,
+                paused: false,
+            });
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to load demo {}: {}",
+                demo_settings.path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn playback_system(
+    mut playback: ResMut<DemoPlayback>,
+    mut world_clock: ResMut<WorldClock>,
+    mut match_seed: ResMut<MatchSeed>,
+    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
+    mut commands: Commands,
+    mut characters: Query<(Entity, &mut Character, &mut ViewAngles, &mut Transform)>,
+) {
+    let playback = playback.as_mut();
+    if !playback.paused && playback.current_frame + 1 < playback.frames.len() {
+        playback.current_frame += 1;
+    }
+
+    // every frame holds the full state, so stepping back or seeking is just applying it again
+    if let Some(frame) = playback.frames.get(playback.current_frame) {
+        world_clock.tick = frame.world_tick;
+        match_seed.0 = frame.seed;
+
+        // anyone who isn't in this frame (they left, or hadn't joined yet when seeking back)
+        for (entity, character, _, _) in characters.iter() {
+            if !frame
+                .character_snapshots
+                .iter()
+                .any(|snapshot| snapshot.client_id == character.owner_client_id.raw())
+            {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        for (index, character_snapshot) in frame.character_snapshots.iter().enumerate() {
+            let client_id = ClientId::from_raw(character_snapshot.client_id);
+            if let Some((_, mut character, mut view, mut transform)) = characters
+                .iter_mut()
+                .find(|(_, character, _, _)| character.owner_client_id == client_id)
+            {
+                character_snapshot.apply(&mut character, &mut transform);
+
+                // the recorded view angles are only in the inputs
+                if let Some(input) = frame
+                    .inputs
+                    .iter()
+                    .filter(|(input_client_id, _)| *input_client_id == client_id.raw())
+                    .flat_map(|(_, inputs)| inputs.last())
+                    .last()
+                {
+                    character.pitch = input.pitch;
+                    character.yaw = input.yaw;
+                    view.pitch = input.pitch;
+                    view.yaw = input.yaw;
+                }
+            } else {
+                // colors aren't recorded, so they're handed out in the order players appear
+                crate::spawn_character(
+                    client_id,
+                    PlayerColor((index % PALETTE_SIZE) as u8),
+                    &mut spawn_visuals,
+                    &mut commands,
+                    character_snapshot.translation.unwrap_or_default(),
+                    character_snapshot.velocity.unwrap_or_default(),
+                );
+            }
+        }
+    }
+}
+
+fn setup_playback_ui_system(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(8.0),
+                left: Val::Px(8.0),
+                right: Val::Px(8.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                PlaybackText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+            parent
+                .spawn((
+                    Timeline,
+                    RelativeCursorPosition::default(),
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Px(12.0),
+                            ..default()
+                        },
+                        background_color: Color::rgba(1.0, 1.0, 1.0, 0.2).into(),
+                        ..default()
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TimelineFill,
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: Color::rgba(1.0, 1.0, 1.0, 0.7).into(),
+                            ..default()
+                        },
+                    ));
+                });
+        });
+}
+
+/// the camera follows the local player, so following someone just means becoming them
+fn follow_player_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    characters: Query<&Character>,
+    mut local_player: ResMut<LocalPlayer>,
+) {
+    let mut client_ids: Vec<ClientId> = characters
+        .iter()
+        .map(|character| character.owner_client_id)
+        .collect();
+    client_ids.sort_by_key(|client_id| client_id.raw());
+
+    let current = client_ids
+        .iter()
+        .position(|client_id| *client_id == local_player.client_id);
+
+    let next = match current {
+        Some(index) if keyboard.just_pressed(KeyCode::Tab) => {
+            client_ids.get((index + 1) % client_ids.len())
+        }
+        Some(_) => None,
+        None => client_ids.first(),
+    };
+    if let Some(next) = next {
+        local_player.client_id = *next;
+    }
+}
+
+/// space pauses, the arrow keys step a single tick, clicking or dragging on the timeline seeks
+fn playback_controls_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    timelines: Query<&RelativeCursorPosition, With<Timeline>>,
+    mut playback: ResMut<DemoPlayback>,
+) {
+    let last_frame = playback.frames.len().saturating_sub(1);
+
+    if keyboard.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        playback.paused = true;
+        playback.current_frame = (playback.current_frame + 1).min(last_frame);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        playback.paused = true;
+        playback.current_frame = playback.current_frame.saturating_sub(1);
+    }
+
+    if mouse.pressed(MouseButton::Left) {
+        for cursor in timelines.iter() {
+            if let (true, Some(normalized)) = (cursor.mouse_over(), cursor.normalized) {
+                playback.current_frame =
+                    (normalized.x.clamp(0.0, 1.0) * last_frame as f32).round() as usize;
+            }
+        }
+    }
+}
+
+fn playback_ui_system(
+    fixed_time: Res<Time<Fixed>>,
+    localization: Res<Localization>,
+    local_player: Res<LocalPlayer>,
+    playback: Res<DemoPlayback>,
+    mut texts: Query<&mut Text, With<PlaybackText>>,
+    mut fills: Query<&mut Style, With<TimelineFill>>,
+) {
+    let last_frame = playback.frames.len().saturating_sub(1);
+    let tick = |index: usize| playback.frames.get(index).map_or(0, |frame| frame.tick);
+
+    // the visuals extrapolate from the current frame towards the next one
+    let blending = if playback.paused || playback.current_frame == last_frame {
+        localization.format("demo.holding", &[&tick(playback.current_frame)])
+    } else {
+        localization.format(
+            "demo.blending",
+            &[
+                &tick(playback.current_frame),
+                &tick(playback.current_frame + 1),
+                &format!(
+                    "{:.0}",
+                    crate::visuals::compute_physics_interpolation_fraction(&fixed_time) * 100.0
+                ),
+            ],
+        )
+    };
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!(
+            "{}{}",
+            localization.format(
+                "demo.status",
+                &[
+                    &local_player.client_id,
+                    &tick(playback.current_frame),
+                    &tick(last_frame),
+                    &blending,
+                ],
+            ),
+            if playback.paused {
+                localization.get("demo.paused")
+            } else {
+                ""
+            }
+        );
+    }
+
+    let progress = if last_frame == 0 {
+        0.0
+    } else {
+        playback.current_frame as f32 / last_frame as f32
+    };
+    for mut style in fills.iter_mut() {
+        style.width = Val::Percent(progress * 100.0);
+    }
+}
+
+/// the recorded positions of the two frames being blended, and the line between them
+fn interpolation_overlay_system(
+    playback: Res<DemoPlayback>,
+    origin: Res<RenderOrigin>,
+    mut gizmos: Gizmos,
+) {
+    let from = playback.frames.get(playback.current_frame);
+    let to = playback.frames.get(playback.current_frame + 1);
+
+    if let Some(from) = from {
+        for from_snapshot in &from.character_snapshots {
+            if let Some(from_translation) = from_snapshot
+                .translation
+                .map(|translation| origin.to_render(translation))
+            {
+                gizmos.sphere(from_translation, Quat::IDENTITY, 0.1, Color::CYAN);
+
+                if let Some(to_translation) = to
+                    .and_then(|to| {
+                        to.character_snapshots
+                            .iter()
+                            .find(|to_snapshot| to_snapshot.client_id == from_snapshot.client_id)
+                    })
+                    .and_then(|to_snapshot| to_snapshot.translation)
+                    .map(|translation| origin.to_render(translation))
+                {
+                    gizmos.sphere(to_translation, Quat::IDENTITY, 0.1, Color::ORANGE);
+                    gizmos.line(from_translation, to_translation, Color::WHITE);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    action::{self, ActionDeniedEvent, PendingActions},
+    chat::ChatInput,
+    core::*,
+    room::Rooms,
+    server::ReliableOutbox,
+    CHARACTER_HEIGHT,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+
+const EMOTE_COOLDOWN: f32 = 1.5;
+const EMOTE_DURATION: f32 = 3.0;
+const EMOTE_RISE: f32 = 0.5;
+
+pub struct EmotePlugin;
+impl Plugin for EmotePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                emote_key_system,
+                validate_emotes_system.run_if(resource_exists::<RenetServer>),
+                spawn_emote_icons_system,
+                emote_icons_system,
+                revert_denied_emotes_system.run_if(resource_exists::<RenetClient>),
+            )
+                .chain(),
+        );
+        app.add_event::<IncomingEmoteEvent>();
+        app.add_event::<EmoteEvent>();
+    }
+}
+
+/// an emote a player asked for, waiting for the server to validate it
+#[derive(Event)]
+pub struct IncomingEmoteEvent {
+    pub sender: ClientId,
+    pub emote: Emote,
+    /// set when the sender is already showing it and waits for an answer
+    pub action_id: Option<u32>,
+}
+
+/// an emote the server accepted, to be shown above the emoting character
+#[derive(Event)]
+pub struct EmoteEvent {
+    pub client_id: ClientId,
+    pub emote: Emote,
+    /// our own emote shown before the server agreed to it
+    pub action_id: Option<u32>,
+}
+
+#[derive(Component)]
+struct EmoteIcon {
+    visuals_entity: Entity,
+    remaining: f32,
+    action_id: Option<u32>,
+}
+
+impl Emote {
+    fn from_key(keyboard: &ButtonInput<KeyCode>) -> Option<Self> {
+        if keyboard.just_pressed(KeyCode::Digit1) {
+            Some(Self::Wave)
+        } else if keyboard.just_pressed(KeyCode::Digit2) {
+            Some(Self::Laugh)
+        } else if keyboard.just_pressed(KeyCode::Digit3) {
+            Some(Self::Cheer)
+        } else if keyboard.just_pressed(KeyCode::Digit4) {
+            Some(Self::Taunt)
+        } else {
+            None
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Self::Wave => Color::rgb(0.2, 0.6, 1.0),
+            Self::Laugh => Color::rgb(1.0, 0.85, 0.1),
+            Self::Cheer => Color::rgb(0.2, 1.0, 0.3),
+            Self::Taunt => Color::rgb(1.0, 0.2, 0.2),
+        }
+    }
+}
+
+/// on a client the emote shows right away, the server can still take it back
+fn emote_key_system(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<&Character>,
+    mut last_emote_time: Local<Option<f32>>,
+    mut incoming: EventWriter<IncomingEmoteEvent>,
+    mut emotes: EventWriter<EmoteEvent>,
+    mut pending_actions: Option<ResMut<PendingActions>>,
+    mut client: Option<ResMut<RenetClient>>,
+) {
+    if chat_input.is_some_and(|chat_input| chat_input.active) {
+        return;
+    }
+    let (emote, local_player) = match (Emote::from_key(&keyboard), local_player) {
+        (Some(emote), Some(local_player)) => (emote, local_player),
+        _ => return,
+    };
+
+    if let (Some(client), Some(pending_actions)) = (client.as_mut(), pending_actions.as_mut()) {
+        // the same checks the server makes, so a prediction is rarely taken back
+        let now = time.elapsed_seconds();
+        let cooling_down = last_emote_time.is_some_and(|last| now - last < EMOTE_COOLDOWN);
+        let has_body = characters
+            .iter()
+            .any(|character| character.owner_client_id == local_player.client_id);
+        if cooling_down || !has_body {
+            return;
+        }
+        *last_emote_time = Some(now);
+
+        let action_id = pending_actions.predict(client, now, PredictedAction::Emote(emote));
+        emotes.send(EmoteEvent {
+            client_id: local_player.client_id,
+            emote,
+            action_id: Some(action_id),
+        });
+    } else {
+        incoming.send(IncomingEmoteEvent {
+            sender: local_player.client_id,
+            emote,
+            action_id: None,
+        });
+    }
+}
+
+fn validate_emotes_system(
+    time: Res<Time>,
+    rooms: Res<Rooms>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<&Character>,
+    mut last_emote_times: Local<HashMap<ClientId, f32>>,
+    mut incoming: EventReader<IncomingEmoteEvent>,
+    mut emotes: EventWriter<EmoteEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    let now = time.elapsed_seconds();
+    for IncomingEmoteEvent {
+        sender,
+        emote,
+        action_id,
+    } in incoming.read()
+    {
+        // no body to emote with
+        let has_body = characters
+            .iter()
+            .any(|character| character.owner_client_id == *sender);
+        let cooling_down = last_emote_times
+            .get(sender)
+            .is_some_and(|last_emote_time| now - last_emote_time < EMOTE_COOLDOWN);
+        if !has_body || cooling_down {
+            action::answer_action(&mut outbox, &mut server, *sender, *action_id, false);
+            continue;
+        }
+        last_emote_times.insert(*sender, now);
+
+        action::answer_action(&mut outbox, &mut server, *sender, *action_id, true);
+        // whoever predicted it is already showing it, nobody outside the room sees it at all
+        outbox.broadcast_filtered(
+            &mut server,
+            &ReliableServerMessage::Emote(sender.raw(), *emote),
+            |client_id| {
+                rooms.same_room(client_id, *sender) && (action_id.is_none() || client_id != *sender)
+            },
+        );
+        if local_player
+            .as_ref()
+            .is_none_or(|local_player| rooms.same_room(local_player.client_id, *sender))
+        {
+            emotes.send(EmoteEvent {
+                client_id: *sender,
+                emote: *emote,
+                action_id: None,
+            });
+        }
+    }
+}
+
+fn spawn_emote_icons_system(
+    mut commands: Commands,
+    mut emotes: EventReader<EmoteEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    visuals: Query<(Entity, &CharacterVisuals)>,
+    icons: Query<(Entity, &EmoteIcon)>,
+) {
+    for event in emotes.read() {
+        if let Some((visuals_entity, _)) = visuals
+            .iter()
+            .find(|(_, visuals)| visuals.owner_client_id == event.client_id)
+        {
+            // a new emote replaces whatever the character was already showing
+            for (icon_entity, icon) in icons.iter() {
+                if icon.visuals_entity == visuals_entity {
+                    commands.entity(icon_entity).despawn();
+                }
+            }
+
+            commands.spawn((
+                EmoteIcon {
+                    visuals_entity,
+                    remaining: EMOTE_DURATION,
+                    action_id: event.action_id,
+                },
+                PbrBundle {
+                    mesh: meshes.add(Sphere::new(0.15)),
+                    material: materials.add(StandardMaterial {
+                        base_color: event.emote.color(),
+                        unlit: true,
+                        ..default()
+                    }),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+fn emote_icons_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut icons: Query<(Entity, &mut EmoteIcon, &mut Transform)>,
+    visuals: Query<&Transform, (With<CharacterVisuals>, Without<EmoteIcon>)>,
+) {
+    for (entity, mut icon, mut transform) in icons.iter_mut() {
+        icon.remaining -= time.delta_seconds();
+        match visuals.get(icon.visuals_entity) {
+            Ok(visuals_transform) if icon.remaining > 0.0 => {
+                let progress = 1.0 - icon.remaining / EMOTE_DURATION;
+                transform.translation = visuals_transform.translation
+                    + Vec3::Y * (CHARACTER_HEIGHT + 0.4 + progress * EMOTE_RISE);
+                transform.scale = Vec3::splat(1.0 - progress * 0.5);
+            }
+            _ => {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+fn revert_denied_emotes_system(
+    mut commands: Commands,
+    mut denials: EventReader<ActionDeniedEvent>,
+    icons: Query<(Entity, &EmoteIcon)>,
+) {
+    for ActionDeniedEvent { action_id, action } in denials.read() {
+        if let PredictedAction::Emote(_) = action {
+            for (entity, icon) in icons.iter() {
+                if icon.action_id == Some(*action_id) {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    damage::{self, body_center, DamageEvent},
+    debug_draw::DebugRays,
+    map::Map,
+    origin::RenderOrigin,
+    room::Rooms,
+    server::ReliableOutbox,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+
+const DEFAULT_RADIUS: f32 = 4.0;
+const DEFAULT_DAMAGE: f32 = 60.0;
+/// speed given to a character right at the center, less further out
+const KNOCKBACK: f32 = 10.0;
+/// how long the fireball takes to grow to full size and fade, in seconds
+const VFX_DURATION: f32 = 0.4;
+
+/// explosions hurt and push every character in range in their room the server can draw a line
+/// to from the center, clients in the room are only told where it went off so they can show it
+pub struct ExplosionPlugin;
+impl Plugin for ExplosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            admin_explode_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            explosion_system
+                .before(damage::apply_damage_system)
+                .run_if(not(resource_exists::<RenetClient>)),
+        );
+        app.add_systems(
+            Update,
+            (spawn_explosion_vfx_system, explosion_vfx_system).chain(),
+        );
+        app.add_event::<ExplosionEvent>();
+        app.add_event::<ExplosionVisualsEvent>();
+    }
+}
+
+/// asks the server to set off an explosion
+#[derive(Event)]
+pub struct ExplosionEvent {
+    pub center: Vec3,
+    pub radius: f32,
+    /// at the center, falling off to nothing at the edge
+    pub damage: f32,
+    /// credited with the damage and any kills
+    pub instigator: Option<ClientId>,
+    /// the only one it goes off in, the instigator's
+    pub room: u32,
+}
+
+/// an explosion to show, the server's own or one we were told about
+#[derive(Event)]
+pub struct ExplosionVisualsEvent {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+fn admin_explode_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut explosions: EventWriter<ExplosionEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Explode(center, room) = command {
+            println!("Explosion at {} in room {}", center, room);
+            explosions.send(ExplosionEvent {
+                center: *center,
+                radius: DEFAULT_RADIUS,
+                damage: DEFAULT_DAMAGE,
+                instigator: None,
+                room: *room,
+            });
+        }
+    }
+}
+
+fn explosion_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    mutators: Res<Mutators>,
+    rooms: Res<Rooms>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut debug_rays: Option<ResMut<DebugRays>>,
+    mut explosions: EventReader<ExplosionEvent>,
+    mut damages: EventWriter<DamageEvent>,
+    mut impulses: EventWriter<ImpulseEvent>,
+    mut visuals: EventWriter<ExplosionVisualsEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: Option<ResMut<RenetServer>>,
+    characters: Query<(&Character, &Transform, &Health, Has<GodMode>)>,
+) {
+    for explosion in explosions.read() {
+        if !explosion.center.is_finite()
+            || !explosion.radius.is_finite()
+            || !explosion.damage.is_finite()
+        {
+            report_non_finite("explosion");
+            continue;
+        }
+        if explosion.radius <= 0.0 {
+            continue;
+        }
+
+        // one message for everyone in the room, each client works out what it looks like from
+        // there
+        let in_room = |client_id: ClientId| rooms.room_of(client_id) == explosion.room;
+        if let Some(server) = server.as_mut() {
+            outbox.broadcast_filtered(
+                server,
+                &ReliableServerMessage::Explosion(explosion.center, explosion.radius),
+                in_room,
+            );
+        }
+        if local_player
+            .as_ref()
+            .is_none_or(|local_player| in_room(local_player.client_id))
+        {
+            visuals.send(ExplosionVisualsEvent {
+                center: explosion.center,
+                radius: explosion.radius,
+            });
+        }
+
+        for (character, transform, health, god_mode) in characters.iter() {
+            if !in_room(character.owner_client_id) {
+                continue;
+            }
+            let target = body_center(transform);
+            let distance = explosion.center.distance(target);
+            if distance > explosion.radius {
+                continue;
+            }
+            let clear = map.line_of_sight(explosion.center, target);
+            if let Some(debug_rays) = debug_rays.as_mut() {
+                debug_rays.line_of_sight(&time, explosion.center, target, clear);
+            }
+            if !clear {
+                continue;
+            }
+            let falloff = 1.0 - distance / explosion.radius;
+            let damage = explosion.damage * falloff;
+
+            damages.send(DamageEvent {
+                client_id: character.owner_client_id,
+                amount: damage,
+                instigator: explosion.instigator,
+            });
+            // someone about to be sent back to the spawn shouldn't be flung away from it
+            let instigated = explosion
+                .instigator
+                .is_some_and(|instigator| instigator != character.owner_client_id);
+            if god_mode || damage::damage_taken(&mutators, health.0, damage, instigated) < health.0
+            {
+                // straight up when it went off inside them
+                let direction = (target - explosion.center)
+                    .try_normalize()
+                    .unwrap_or(Vec3::Y);
+                impulses.send(ImpulseEvent {
+                    client_id: character.owner_client_id,
+                    impulse: direction * KNOCKBACK * falloff,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct ExplosionVfx {
+    radius: f32,
+    age: f32,
+    material: Handle<StandardMaterial>,
+}
+
+fn spawn_explosion_vfx_system(
+    origin: Res<RenderOrigin>,
+    mut explosions: EventReader<ExplosionVisualsEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    for explosion in explosions.read() {
+        let material = materials.add(StandardMaterial {
+            base_color: Color::rgba(1.0, 0.6, 0.1, 0.8),
+            emissive: Color::rgb(1.0, 0.4, 0.0),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        });
+        commands.spawn((
+            ExplosionVfx {
+                radius: explosion.radius,
+                age: 0.0,
+                material: material.clone(),
+            },
+            PbrBundle {
+                mesh: meshes.add(Sphere::new(1.0)),
+                material,
+                transform: Transform::from_translation(origin.to_render(explosion.center))
+                    .with_scale(Vec3::splat(0.01)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// grows to the explosion's radius while fading out, then goes away
+fn explosion_vfx_system(
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+    mut vfxs: Query<(Entity, &mut ExplosionVfx, &mut Transform)>,
+) {
+    for (entity, mut vfx, mut transform) in vfxs.iter_mut() {
+        vfx.age += time.delta_seconds();
+        let progress = vfx.age / VFX_DURATION;
+        if progress >= 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        transform.scale = Vec3::splat((vfx.radius * progress).max(0.01));
+        if let Some(material) = materials.get_mut(&vfx.material) {
+            material.base_color.set_a(0.8 * (1.0 - progress));
+        }
+    }
+}
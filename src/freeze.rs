@@ -0,0 +1,228 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    localization::Localization,
+    server::ReliableOutbox,
+};
+use bevy::{app::FixedMain, prelude::*};
+use bevy_renet::{
+    renet::{
+        transport::{NetcodeClientTransport, NetcodeServerTransport},
+        RenetClient, RenetServer, ServerEvent,
+    },
+    transport::{NetcodeClientPlugin, NetcodeServerPlugin},
+    RenetServerPlugin,
+};
+
+/// most ticks one step command can run, a couple of seconds
+const MAX_STEPS: u32 = 128;
+
+/// for debugging replication with a debugger attached: an admin freezes the simulation on the
+/// server and every client, then steps it a tick at a time. virtual time is paused like in
+/// single player, connections are kept going on real time meanwhile
+pub struct FreezePlugin;
+impl Plugin for FreezePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_frozen_text_system);
+        app.add_systems(
+            PreUpdate,
+            (
+                server_keep_alive_system
+                    .after(NetcodeServerPlugin::update_system)
+                    .before(RenetServerPlugin::emit_server_events_system)
+                    .run_if(resource_exists::<RenetServer>),
+                client_keep_alive_system
+                    .after(NetcodeClientPlugin::update_system)
+                    .run_if(resource_exists::<RenetClient>),
+            )
+                .run_if(|freeze: Res<Freeze>| freeze.frozen),
+        );
+        app.add_systems(
+            Update,
+            (
+                (admin_freeze_system, send_freeze_system).run_if(resource_exists::<RenetServer>),
+                receive_freeze_system.run_if(resource_exists::<RenetClient>),
+                step_system,
+                frozen_text_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<Freeze>();
+        app.add_event::<ReceivedFreezeEvent>();
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Freeze {
+    pub frozen: bool,
+    /// ticks to run this frame
+    steps: u32,
+}
+
+/// the server froze or stepped the simulation
+#[derive(Event)]
+pub struct ReceivedFreezeEvent {
+    pub frozen: bool,
+    pub steps: u32,
+}
+
+fn admin_freeze_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut freeze: ResMut<Freeze>,
+    mut time: ResMut<Time<Virtual>>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        match command {
+            AdminCommand::ToggleFreeze => {
+                freeze.frozen = !freeze.frozen;
+                freeze.steps = 0;
+                if freeze.frozen {
+                    time.pause();
+                    println!("Simulation frozen");
+                } else {
+                    time.unpause();
+                    println!("Simulation running");
+                }
+                outbox.broadcast(
+                    &mut server,
+                    &ReliableServerMessage::Freeze(freeze.frozen, 0),
+                );
+            }
+            AdminCommand::Step(steps) => {
+                if !freeze.frozen {
+                    eprintln!("the simulation isn't frozen");
+                    continue;
+                }
+                let steps = (*steps).min(MAX_STEPS);
+                freeze.steps += steps;
+                println!("Stepping {} ticks", steps);
+                outbox.broadcast(&mut server, &ReliableServerMessage::Freeze(true, steps));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// anyone joining a frozen server freezes too
+fn send_freeze_system(
+    freeze: Res<Freeze>,
+    mut server_events: EventReader<ServerEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let (ServerEvent::ClientConnected { client_id }, true) = (event, freeze.frozen) {
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::Freeze(true, 0),
+            );
+        }
+    }
+}
+
+fn receive_freeze_system(
+    mut received: EventReader<ReceivedFreezeEvent>,
+    mut freeze: ResMut<Freeze>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    for ReceivedFreezeEvent { frozen, steps } in received.read() {
+        freeze.frozen = *frozen;
+        if *frozen {
+            time.pause();
+            freeze.steps += (*steps).min(MAX_STEPS);
+        } else {
+            time.unpause();
+            freeze.steps = 0;
+        }
+    }
+}
+
+/// the fixed schedule run by hand, the way the fixed main loop would. the fixed clock itself
+/// doesn't move, everything that matters counts ticks instead
+fn step_system(world: &mut World) {
+    if world.resource::<Freeze>().steps == 0 {
+        return;
+    }
+    let steps = std::mem::take(&mut world.resource_mut::<Freeze>().steps);
+    for _ in 0..steps {
+        *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+        world.run_schedule(FixedMain);
+    }
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+}
+
+/// renet goes by virtual time, which stands still while frozen, so nothing would ever be
+/// resent or acknowledged and clients would time out
+fn server_keep_alive_system(
+    real_time: Res<Time<Real>>,
+    transport: Option<ResMut<NetcodeServerTransport>>,
+    mut server: ResMut<RenetServer>,
+) {
+    server.update(real_time.delta());
+    if let Some(mut transport) = transport {
+        if let Err(e) = transport.update(real_time.delta(), &mut server) {
+            eprintln!("Transport error while frozen: {}", e);
+        }
+    }
+}
+
+fn client_keep_alive_system(
+    real_time: Res<Time<Real>>,
+    transport: Option<ResMut<NetcodeClientTransport>>,
+    mut client: ResMut<RenetClient>,
+) {
+    client.update(real_time.delta());
+    if let Some(mut transport) = transport {
+        if let Err(e) = transport.update(real_time.delta(), &mut client) {
+            eprintln!("Transport error while frozen: {}", e);
+        }
+    }
+}
+
+#[derive(Component)]
+struct FrozenText;
+
+fn setup_frozen_text_system(mut commands: Commands) {
+    commands.spawn((
+        FrozenText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 28.0,
+                    color: Color::rgb(0.5, 0.8, 1.0),
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn frozen_text_system(
+    freeze: Res<Freeze>,
+    localization: Res<Localization>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<FrozenText>>,
+) {
+    if !freeze.is_changed() && !localization.is_changed() {
+        return;
+    }
+    for (mut text, mut visibility) in texts.iter_mut() {
+        text.sections[0].value = localization.get("freeze.server_paused").to_string();
+        *visibility = if freeze.frozen {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
@@ -0,0 +1,537 @@
+use crate::{
+    core::*,
+    localization::Localization,
+    map::Map,
+    room::Rooms,
+    server::{ChangeMapEvent, ReliableOutbox, ResetWorldEvent},
+    MAX_HEALTH,
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_renet::renet::{ClientId, RenetServer};
+
+const POST_MATCH_DURATION: f32 = 10.0;
+const RESULTS_DURATION: f32 = 10.0;
+
+pub struct GameModePlugin;
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            match_flow_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            (
+                match_event_system,
+                post_match_countdown_system.run_if(not(resource_exists::<RenetServer>)),
+                post_match_countdown_text_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<GameMode>();
+        app.init_resource::<MatchState>();
+        app.init_resource::<Matches>();
+        app.add_event::<MatchEvent>();
+    }
+}
+
+pub enum WinCondition {
+    ScoreLimit(u32),
+    /// in seconds
+    TimeLimit(f32),
+}
+
+/// the rules of the match currently being played, only the server evaluates these
+#[derive(Resource)]
+pub struct GameMode {
+    pub name: &'static str,
+    pub win_conditions: Vec<WinCondition>,
+    /// how long the post-match scoreboard is shown before the results screen, in seconds
+    pub post_match_duration: f32,
+    /// how long the results screen is shown before the next match starts, in seconds
+    pub results_duration: f32,
+}
+
+impl Default for GameMode {
+    fn default() -> Self {
+        Self {
+            name: "Deathmatch",
+            win_conditions: vec![WinCondition::ScoreLimit(20), WinCondition::TimeLimit(600.0)],
+            post_match_duration: POST_MATCH_DURATION,
+            results_duration: RESULTS_DURATION,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum MatchPhase {
+    InProgress,
+    /// seconds left until the results screen
+    PostMatch(f32),
+    /// seconds left until the next match starts
+    Results(f32),
+}
+
+/// one room's match. the server keeps one for each room in `Matches` and drives them, the
+/// resource is the one shown here, which clients and the host follow along with through
+/// `MatchEvent`s
+#[derive(Resource)]
+pub struct MatchState {
+    pub phase: MatchPhase,
+    pub elapsed: f32,
+    pub scores: HashMap<ClientId, u32>,
+    /// only filled in on the server
+    pub stats: HashMap<ClientId, PlayerStats>,
+    /// kills since each player last died, only filled in on the server
+    pub streaks: HashMap<ClientId, u32>,
+    /// kept around so the results screen can be shown after the scoreboard
+    pub last_result: Option<MatchResult>,
+}
+
+impl Default for MatchState {
+    fn default() -> Self {
+        Self {
+            phase: MatchPhase::InProgress,
+            elapsed: 0.0,
+            scores: HashMap::new(),
+            stats: HashMap::new(),
+            streaks: HashMap::new(),
+            last_result: None,
+        }
+    }
+}
+
+impl MatchState {
+    pub fn is_in_progress(&self) -> bool {
+        self.phase == MatchPhase::InProgress
+    }
+
+    pub fn score(&self, client_id: ClientId) -> u32 {
+        self.scores.get(&client_id).copied().unwrap_or(0)
+    }
+
+    /// anything that kills a character goes through here, `killer` is credited with the kill
+    /// and scores for it. dying ends the victim's streak
+    pub fn record_death(&mut self, victim: ClientId, killer: Option<ClientId>) {
+        self.stats.entry(victim).or_default().deaths += 1;
+        self.streaks.remove(&victim);
+        if let Some(killer) = killer {
+            let streak = self.streaks.entry(killer).or_default();
+            *streak += 1;
+            let stats = self.stats.entry(killer).or_default();
+            stats.kills += 1;
+            stats.best_streak = stats.best_streak.max(*streak);
+            *self.scores.entry(killer).or_default() += 1;
+        }
+    }
+}
+
+/// every room's match, only filled in on the server
+#[derive(Resource, Default)]
+pub struct Matches(HashMap<u32, MatchState>);
+
+impl Matches {
+    /// the match being played in `room`, a new one if there wasn't one yet
+    pub fn of(&mut self, room: u32) -> &mut MatchState {
+        self.0.entry(room).or_default()
+    }
+
+    pub fn is_in_progress(&self, room: u32) -> bool {
+        self.0
+            .get(&room)
+            .is_none_or(|match_state| match_state.is_in_progress())
+    }
+}
+
+/// runs on the server and on clients so the host and remote players see the same flow
+#[derive(Event)]
+pub enum MatchEvent {
+    Ended(MatchResult),
+    ShowResults,
+    Started,
+}
+
+/// the scoreboard and the results screen, anything shown between matches
+#[derive(Component)]
+struct PostMatchScreen;
+
+/// if there's no match being played (single player) inputs are never frozen
+pub fn inputs_frozen(match_state: Option<Res<MatchState>>) -> bool {
+    match_state.is_some_and(|match_state| !match_state.is_in_progress())
+}
+
+/// each room plays a match of its own. when the first room's is over everyone moves on to the
+/// next map, the map being shared, the other rooms start over where they are
+fn match_flow_system(
+    fixed_time: Res<Time<Fixed>>,
+    game_mode: Res<GameMode>,
+    map: Res<Map>,
+    local_player: Option<Res<LocalPlayer>>,
+    rooms: Res<Rooms>,
+    mut matches: ResMut<Matches>,
+    mut match_events: EventWriter<MatchEvent>,
+    mut change_map: EventWriter<ChangeMapEvent>,
+    mut reset_world: EventWriter<ResetWorldEvent>,
+    mut characters: Query<(
+        &mut Character,
+        &mut Transform,
+        &mut Health,
+        Option<&MaxHealth>,
+    )>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    // a room nobody's in has no match, whoever goes in next starts a new one
+    let occupied: HashSet<u32> = characters
+        .iter()
+        .map(|(character, _, _, _)| rooms.room_of(character.owner_client_id))
+        .collect();
+    matches.0.retain(|room, _| occupied.contains(room));
+    // the host's room is the one shown here, a dedicated server follows the first
+    let shown_room = local_player.map_or(0, |local_player| rooms.room_of(local_player.client_id));
+
+    for room in occupied {
+        let match_state = matches.of(room);
+        let in_room = |client_id: ClientId| rooms.room_of(client_id) == room;
+        match match_state.phase {
+            MatchPhase::InProgress => {
+                match_state.elapsed += fixed_time.delta_seconds();
+
+                let mut scores: Vec<(u64, u32)> = characters
+                    .iter()
+                    .filter(|(character, _, _, _)| in_room(character.owner_client_id))
+                    .map(|(character, _, _, _)| {
+                        (
+                            character.owner_client_id.raw(),
+                            match_state.score(character.owner_client_id),
+                        )
+                    })
+                    .collect();
+                scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+
+                let top_score = scores.first().map(|(_, score)| *score).unwrap_or(0);
+                let is_met = game_mode
+                    .win_conditions
+                    .iter()
+                    .any(|condition| match condition {
+                        WinCondition::ScoreLimit(limit) => top_score >= *limit,
+                        WinCondition::TimeLimit(limit) => match_state.elapsed >= *limit,
+                    });
+
+                if !is_met {
+                    continue;
+                }
+
+                // a tie for first place is a draw
+                let winner_client_id = match scores.as_slice() {
+                    [(_, first), (_, second), ..] if first == second => None,
+                    [(winner, _), ..] => Some(*winner),
+                    [] => None,
+                };
+
+                let stats = characters
+                    .iter()
+                    .filter(|(character, _, _, _)| in_room(character.owner_client_id))
+                    .map(|(character, _, _, _)| {
+                        (
+                            character.owner_client_id.raw(),
+                            match_state
+                                .stats
+                                .get(&character.owner_client_id)
+                                .cloned()
+                                .unwrap_or_default(),
+                        )
+                    })
+                    .collect();
+
+                let result = MatchResult {
+                    winner_client_id,
+                    scores,
+                    stats,
+                    post_match_duration: game_mode.post_match_duration,
+                    results_duration: game_mode.results_duration,
+                };
+
+                println!(
+                    "{} match over in room {}, winner: {:?}",
+                    game_mode.name, room, result.winner_client_id
+                );
+                match_state.phase = MatchPhase::PostMatch(result.post_match_duration);
+                match_state.last_result = Some(result.clone());
+
+                // nobody should keep sliding around while the scoreboard is up
+                for (mut character, _, _, _) in characters.iter_mut() {
+                    if in_room(character.owner_client_id) {
+                        character.velocity = Vec3::ZERO;
+                    }
+                }
+
+                outbox.broadcast_filtered(
+                    &mut server,
+                    &ReliableServerMessage::MatchEnded(result.clone()),
+                    in_room,
+                );
+                if room == shown_room {
+                    match_events.send(MatchEvent::Ended(result));
+                }
+            }
+            MatchPhase::PostMatch(remaining) => {
+                let remaining = remaining - fixed_time.delta_seconds();
+                if remaining > 0.0 {
+                    match_state.phase = MatchPhase::PostMatch(remaining);
+                    continue;
+                }
+
+                match_state.phase = MatchPhase::Results(game_mode.results_duration);
+                outbox.broadcast_filtered(
+                    &mut server,
+                    &ReliableServerMessage::ShowMatchResults,
+                    in_room,
+                );
+                if room == shown_room {
+                    match_events.send(MatchEvent::ShowResults);
+                }
+            }
+            MatchPhase::Results(remaining) => {
+                let remaining = remaining - fixed_time.delta_seconds();
+                if remaining > 0.0 {
+                    match_state.phase = MatchPhase::Results(remaining);
+                    continue;
+                }
+
+                println!("Starting next match in room {}", room);
+                *match_state = MatchState::default();
+                outbox.broadcast_filtered(
+                    &mut server,
+                    &ReliableServerMessage::MatchStarted,
+                    in_room,
+                );
+                if room == shown_room {
+                    match_events.send(MatchEvent::Started);
+                }
+
+                if room != 0 {
+                    // back at the room's spawn with full health, the teleport count is bumped so
+                    // the owning client snaps there instead of fighting it
+                    for (mut character, mut transform, mut health, max_health) in
+                        characters.iter_mut()
+                    {
+                        let owner = character.owner_client_id;
+                        if !in_room(owner) {
+                            continue;
+                        }
+                        transform.translation = rooms.spawn_point(room);
+                        character.velocity = Vec3::ZERO;
+                        character.mantle = None;
+                        character.teleport_count = character.teleport_count.wrapping_add(1);
+                        health.0 = max_health.map_or(MAX_HEALTH, |max_health| max_health.0);
+                        outbox.send(&mut server, owner, &ReliableServerMessage::Health(health.0));
+                    }
+                    continue;
+                }
+
+                // on to the next map, changing it resets the world. with only the one map the
+                // match restarts in place with everyone back at the spawn
+                match Map::next_in_rotation(&map.name) {
+                    Some(next) => {
+                        change_map.send(ChangeMapEvent(next));
+                    }
+                    None => {
+                        reset_world.send(ResetWorldEvent);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn match_event_system(
+    mut commands: Commands,
+    localization: Res<Localization>,
+    mut match_events: EventReader<MatchEvent>,
+    mut match_state: ResMut<MatchState>,
+    screens: Query<Entity, With<PostMatchScreen>>,
+) {
+    for event in match_events.read() {
+        for entity in screens.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        // the server already made these transitions in the room's own match, what's shown here
+        // catches up
+        match event {
+            MatchEvent::Ended(result) => {
+                if match_state.is_in_progress() {
+                    match_state.phase = MatchPhase::PostMatch(result.post_match_duration);
+                    match_state.last_result = Some(result.clone());
+                }
+                spawn_scoreboard(&mut commands, &localization, result);
+            }
+            MatchEvent::ShowResults => {
+                if let Some(result) = &match_state.last_result {
+                    spawn_results_screen(&mut commands, &localization, result);
+                    if let MatchPhase::PostMatch(_) = match_state.phase {
+                        match_state.phase = MatchPhase::Results(result.results_duration);
+                    }
+                }
+            }
+            MatchEvent::Started => {
+                if !match_state.is_in_progress() {
+                    *match_state = MatchState::default();
+                }
+            }
+        }
+    }
+}
+
+fn spawn_scoreboard(commands: &mut Commands, localization: &Localization, result: &MatchResult) {
+    let style = TextStyle {
+        font_size: 24.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    let mut sections = vec![TextSection {
+        value: match result.winner_client_id {
+            Some(winner) => format!("{}\n\n", localization.format("match.winner", &[&winner])),
+            None => format!("{}\n\n", localization.get("match.draw")),
+        },
+        style: style.clone(),
+    }];
+    for (client_id, score) in &result.scores {
+        sections.push(TextSection {
+            value: format!(
+                "{}\n",
+                localization.format("match.score", &[client_id, score])
+            ),
+            style: style.clone(),
+        });
+    }
+    spawn_post_match_screen(commands, sections, style);
+}
+
+fn spawn_results_screen(
+    commands: &mut Commands,
+    localization: &Localization,
+    result: &MatchResult,
+) {
+    let style = TextStyle {
+        font_size: 20.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    let mut sections = vec![TextSection {
+        value: format!(
+            "{:<24}{:>8}{:>8}{:>10}{:>8}{:>10}\n\n",
+            localization.get("results.player"),
+            localization.get("results.kills"),
+            localization.get("results.deaths"),
+            localization.get("results.damage"),
+            localization.get("results.streak"),
+            localization.get("results.accuracy")
+        ),
+        style: style.clone(),
+    }];
+    for (client_id, stats) in &result.stats {
+        let accuracy = match stats.accuracy() {
+            Some(accuracy) => format!("{:.0}%", accuracy * 100.0),
+            None => "-".into(),
+        };
+        sections.push(TextSection {
+            value: format!(
+                "{:<24}{:>8}{:>8}{:>10.0}{:>8}{:>10}\n",
+                localization.format("results.player_name", &[client_id]),
+                stats.kills,
+                stats.deaths,
+                stats.damage_dealt,
+                stats.best_streak,
+                accuracy
+            ),
+            style: style.clone(),
+        });
+    }
+
+    spawn_post_match_screen(commands, sections, style);
+}
+
+/// the last section is left empty for the countdown
+fn spawn_post_match_screen(
+    commands: &mut Commands,
+    mut sections: Vec<TextSection>,
+    style: TextStyle,
+) {
+    sections.push(TextSection {
+        value: String::new(),
+        style,
+    });
+
+    commands
+        .spawn((
+            PostMatchScreen,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text::from_sections(sections),
+                ..default()
+            });
+        });
+}
+
+fn post_match_countdown_system(time: Res<Time>, mut match_state: ResMut<MatchState>) {
+    match match_state.phase {
+        MatchPhase::PostMatch(remaining) => {
+            match_state.phase = MatchPhase::PostMatch((remaining - time.delta_seconds()).max(0.0));
+        }
+        MatchPhase::Results(remaining) => {
+            match_state.phase = MatchPhase::Results((remaining - time.delta_seconds()).max(0.0));
+        }
+        MatchPhase::InProgress => {}
+    }
+}
+
+fn post_match_countdown_text_system(
+    localization: Res<Localization>,
+    match_state: Res<MatchState>,
+    screens: Query<&Children, With<PostMatchScreen>>,
+    mut texts: Query<&mut Text>,
+) {
+    let countdown = match match_state.phase {
+        MatchPhase::PostMatch(remaining) => format!(
+            "\n{}",
+            localization.format("match.results_in", &[&format!("{:.0}", remaining.max(0.0))])
+        ),
+        MatchPhase::Results(remaining) => format!(
+            "\n{}",
+            localization.format(
+                "match.next_match_in",
+                &[&format!("{:.0}", remaining.max(0.0))]
+            )
+        ),
+        MatchPhase::InProgress => return,
+    };
+
+    for children in screens.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                if let Some(section) = text.sections.last_mut() {
+                    section.value.clone_from(&countdown);
+                }
+            }
+        }
+    }
+}
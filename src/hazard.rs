@@ -0,0 +1,272 @@
+use crate::{
+    core::*,
+    damage::{self, body_center, DamageEvent},
+    map::Map,
+    origin::RenderOrigin,
+    server::ReliableOutbox,
+    visuals::compute_physics_interpolation_fraction,
+    FLOOR_THICKNESS, FLOOR_Y,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer, ServerEvent};
+
+/// how close to the beam counts as touching it
+const LASER_RADIUS: f32 = 0.3;
+/// per second of touching
+const LASER_DAMAGE: f32 = 40.0;
+const FIRE_DAMAGE: f32 = 15.0;
+/// refreshed every tick spent in the fire, so it lingers this long after stepping out
+const FIRE_BURN_DURATION: f32 = 1.0;
+/// clients further ahead of the snapshots than this start over from them
+const MAX_CLOCK_LEAD: i64 = 64;
+
+/// the map's lasers and fire zones. they move with the world clock, a count of fixed ticks the
+/// server sends each client once, so clients show them in step without any updates after that
+pub struct HazardPlugin;
+impl Plugin for HazardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                advance_world_clock_system,
+                (
+                    hazard_damage_system
+                        .before(damage::apply_damage_system)
+                        .run_if(not(resource_exists::<RenetClient>)),
+                    sync_world_clock_system.run_if(resource_exists::<RenetClient>),
+                ),
+            )
+                .chain(),
+        );
+        app.add_systems(
+            Update,
+            (
+                send_world_clock_system.run_if(resource_exists::<RenetServer>),
+                receive_world_clock_system.run_if(resource_exists::<RenetClient>),
+                spawn_hazard_visuals_system.run_if(resource_changed::<Map>),
+                hazard_visuals_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<WorldClock>();
+        app.add_event::<ReceivedWorldClockEvent>();
+    }
+}
+
+/// fixed ticks since the server started, the only time hazards go by
+#[derive(Resource, Default)]
+pub struct WorldClock {
+    pub tick: u32,
+    /// on clients, what to add to a snapshot id to get the tick it was taken on
+    snapshot_offset: Option<i64>,
+}
+
+impl WorldClock {
+    pub fn seconds(&self, fixed_time: &Time<Fixed>, fraction: f32) -> f32 {
+        (self.tick as f32 + fraction) * fixed_time.timestep().as_secs_f32()
+    }
+}
+
+/// the server told us which tick its next snapshot will be taken on
+#[derive(Event)]
+pub struct ReceivedWorldClockEvent {
+    pub snapshot_id: u32,
+    pub tick: u32,
+}
+
+fn advance_world_clock_system(mut world_clock: ResMut<WorldClock>) {
+    world_clock.tick = world_clock.tick.wrapping_add(1);
+}
+
+/// snapshot ids and the world clock both go up once a fixed tick, so one pair of them is
+/// enough to line the two up for good
+fn send_world_clock_system(
+    world_clock: Res<WorldClock>,
+    snapshot_history: Res<SnapshotHistory>,
+    mut server_events: EventReader<ServerEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientConnected { client_id } = event {
+            // outside the fixed schedule, so the next snapshot goes with the next tick
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::WorldClock(
+                    snapshot_history.next_id,
+                    world_clock.tick.wrapping_add(1),
+                ),
+            );
+        }
+    }
+}
+
+fn receive_world_clock_system(
+    mut received: EventReader<ReceivedWorldClockEvent>,
+    mut world_clock: ResMut<WorldClock>,
+) {
+    for ReceivedWorldClockEvent { snapshot_id, tick } in received.read() {
+        world_clock.snapshot_offset = Some(*tick as i64 - *snapshot_id as i64);
+        world_clock.tick = *tick;
+    }
+}
+
+/// between snapshots the clock runs on by itself, each snapshot pulls it forward if it fell
+/// behind. running a little ahead is fine, it's only ever shown
+fn sync_world_clock_system(input_history: Res<InputHistory>, mut world_clock: ResMut<WorldClock>) {
+    let (snapshot_id, snapshot_offset) = match (
+        input_history.latest_processed_snapshot_id,
+        world_clock.snapshot_offset,
+    ) {
+        (Some(snapshot_id), Some(snapshot_offset)) => (snapshot_id, snapshot_offset),
+        _ => return,
+    };
+    let snapshot_tick = snapshot_id as i64 + snapshot_offset;
+    let lead = world_clock.tick as i64 - snapshot_tick;
+    if !(0..=MAX_CLOCK_LEAD).contains(&lead) {
+        world_clock.tick = snapshot_tick.max(0) as u32;
+    }
+}
+
+fn hazard_damage_system(
+    map: Res<Map>,
+    world_clock: Res<WorldClock>,
+    fixed_time: Res<Time<Fixed>>,
+    mut damages: EventWriter<DamageEvent>,
+    mut characters: Query<(&mut Character, &Transform)>,
+) {
+    let seconds = world_clock.seconds(&fixed_time, 0.0);
+    let delta_seconds = fixed_time.delta_seconds();
+
+    for (mut character, transform) in characters.iter_mut() {
+        let mut amount = 0.0;
+        if map
+            .lasers
+            .iter()
+            .any(|laser| laser.touches(seconds, body_center(transform), LASER_RADIUS))
+        {
+            amount += LASER_DAMAGE * delta_seconds;
+        }
+        if map.fire_zones.iter().any(|fire_zone| {
+            fire_zone.is_active(seconds) && fire_zone.contains(transform.translation)
+        }) {
+            amount += FIRE_DAMAGE * delta_seconds;
+            character.add_status_effect(StatusEffectKind::Burn, FIRE_BURN_DURATION);
+        }
+
+        if amount > 0.0 {
+            damages.send(DamageEvent {
+                client_id: character.owner_client_id,
+                amount,
+                instigator: None,
+            });
+        }
+    }
+}
+
+#[derive(Component)]
+struct LaserVisuals(usize);
+
+#[derive(Component)]
+struct FireZoneVisuals(usize);
+
+/// runs again whenever the map changes, after clearing out the old map's hazards
+fn spawn_hazard_visuals_system(
+    map: Res<Map>,
+    origin: Res<RenderOrigin>,
+    visuals: Query<Entity, Or<(With<LaserVisuals>, With<FireZoneVisuals>)>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    for entity in visuals.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let laser_material = materials.add(StandardMaterial {
+        base_color: Color::RED,
+        emissive: Color::RED,
+        unlit: true,
+        ..default()
+    });
+    for (index, laser) in map.lasers.iter().enumerate() {
+        // a unit cylinder along y, stretched and turned to fit the beam every frame
+        commands.spawn((
+            LaserVisuals(index),
+            PbrBundle {
+                mesh: meshes.add(Cylinder::new(0.05, 1.0)),
+                material: laser_material.clone(),
+                transform: Transform::from_translation(origin.to_render(laser.start)),
+                ..default()
+            },
+        ));
+    }
+
+    let fire_material = materials.add(StandardMaterial {
+        base_color: Color::rgba(1.0, 0.3, 0.0, 0.6),
+        emissive: Color::rgb(1.0, 0.3, 0.0),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    for (index, fire_zone) in map.fire_zones.iter().enumerate() {
+        commands.spawn((
+            FireZoneVisuals(index),
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(
+                    fire_zone.half_extents.x * 2.0,
+                    FLOOR_THICKNESS,
+                    fire_zone.half_extents.z * 2.0,
+                )),
+                material: fire_material.clone(),
+                transform: Transform::from_translation(origin.to_render(Vec3::new(
+                    fire_zone.center.x,
+                    FLOOR_Y + FLOOR_THICKNESS,
+                    fire_zone.center.z,
+                ))),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ));
+    }
+}
+
+/// worked out from the clock every frame, in between ticks too
+fn hazard_visuals_system(
+    map: Res<Map>,
+    origin: Res<RenderOrigin>,
+    world_clock: Res<WorldClock>,
+    fixed_time: Res<Time<Fixed>>,
+    mut lasers: Query<(&LaserVisuals, &mut Transform)>,
+    mut fire_zones: Query<(&FireZoneVisuals, &mut Visibility)>,
+) {
+    let seconds = world_clock.seconds(
+        &fixed_time,
+        compute_physics_interpolation_fraction(&fixed_time),
+    );
+
+    for (LaserVisuals(index), mut transform) in lasers.iter_mut() {
+        if let Some(laser) = map.lasers.get(*index) {
+            let (start, end) = laser.segment_at(seconds);
+            let beam = end - start;
+            transform.translation = origin.to_render((start + end) * 0.5);
+            transform.rotation =
+                Quat::from_rotation_arc(Vec3::Y, beam.try_normalize().unwrap_or(Vec3::Y));
+            transform.scale = Vec3::new(1.0, beam.length().max(0.01), 1.0);
+        }
+    }
+
+    for (FireZoneVisuals(index), mut visibility) in fire_zones.iter_mut() {
+        if let Some(fire_zone) = map.fire_zones.get(*index) {
+            let target = if fire_zone.is_active(seconds) {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+            if *visibility != target {
+                *visibility = target;
+            }
+        }
+    }
+}
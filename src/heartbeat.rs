@@ -0,0 +1,168 @@
+use crate::{client::SnapshotsReceived, core::*, localization::Localization};
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient, RenetServer};
+
+/// real seconds between heartbeats while nothing else is going out
+const HEARTBEAT_INTERVAL: f64 = 0.5;
+
+/// inputs and snapshots go out every fixed tick, so they stop whenever virtual time does:
+/// single player paused before opening to LAN, an admin freeze. netcode keeps the connection
+/// itself open meanwhile, but nothing tells the game on the other end that we're still here.
+/// both sides send a heartbeat every so often while their fixed schedule isn't running, and a
+/// client that hears nothing from the server for `--unstable-after` seconds says so on screen
+pub struct HeartbeatPlugin;
+impl Plugin for HeartbeatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_unstable_text_system);
+        app.add_systems(FixedPostUpdate, mark_tick_system);
+        app.add_systems(
+            Update,
+            (
+                send_heartbeats_system,
+                (connection_health_system, unstable_text_system)
+                    .chain()
+                    .run_if(resource_exists::<RenetClient>),
+            ),
+        );
+        app.init_resource::<Heartbeats>();
+        app.init_resource::<ConnectionHealth>();
+        app.add_event::<ReceivedHeartbeatEvent>();
+    }
+}
+
+/// the server sent a heartbeat
+#[derive(Event)]
+pub struct ReceivedHeartbeatEvent;
+
+/// in real seconds since startup
+#[derive(Resource, Default)]
+struct Heartbeats {
+    last_tick: f64,
+    last_sent: f64,
+}
+
+/// how long it's been since the server was last heard from, only on clients
+#[derive(Resource, Default)]
+struct ConnectionHealth {
+    /// in real seconds since startup, unset while not connected
+    last_heard: Option<f64>,
+    silent_for: f64,
+    unstable: bool,
+}
+
+#[derive(Component)]
+struct UnstableText;
+
+fn mark_tick_system(real_time: Res<Time<Real>>, mut heartbeats: ResMut<Heartbeats>) {
+    heartbeats.last_tick = real_time.elapsed_seconds_f64();
+}
+
+/// practice mode is a server and a client in one, each gets its heartbeat
+fn send_heartbeats_system(
+    real_time: Res<Time<Real>>,
+    mut heartbeats: ResMut<Heartbeats>,
+    server: Option<ResMut<RenetServer>>,
+    client: Option<ResMut<RenetClient>>,
+) {
+    let now = real_time.elapsed_seconds_f64();
+    if now - heartbeats.last_tick < HEARTBEAT_INTERVAL
+        || now - heartbeats.last_sent < HEARTBEAT_INTERVAL
+    {
+        return;
+    }
+    heartbeats.last_sent = now;
+
+    if let Some(mut server) = server {
+        if let Ok(message) = bincode::serialize(&UnreliableServerMessage::Heartbeat) {
+            server.broadcast_message(DefaultChannel::Unreliable, message);
+        }
+    }
+    if let Some(mut client) = client.filter(|client| client.is_connected()) {
+        if let Ok(message) = bincode::serialize(&UnreliableClientMessage::Heartbeat) {
+            client.send_message(DefaultChannel::Unreliable, message);
+        }
+    }
+}
+
+/// snapshots count as hearing from the server as much as heartbeats do
+fn connection_health_system(
+    real_time: Res<Time<Real>>,
+    client_settings: Res<ClientSettings>,
+    client: Res<RenetClient>,
+    snapshots_received: Res<SnapshotsReceived>,
+    mut heartbeats_received: EventReader<ReceivedHeartbeatEvent>,
+    mut previous_snapshots_received: Local<u64>,
+    mut health: ResMut<ConnectionHealth>,
+) {
+    let now = real_time.elapsed_seconds_f64();
+    let heard = heartbeats_received.read().count() > 0
+        || snapshots_received.0 != *previous_snapshots_received;
+    *previous_snapshots_received = snapshots_received.0;
+
+    // the loading screen covers connecting, and losing the connection is another matter
+    if !client.is_connected() {
+        *health = default();
+        return;
+    }
+    let last_heard = match health.last_heard {
+        Some(last_heard) if !heard => last_heard,
+        _ => now,
+    };
+    health.last_heard = Some(last_heard);
+    health.silent_for = now - last_heard;
+
+    let unstable = health.silent_for > client_settings.unstable_after as f64;
+    if unstable != health.unstable {
+        if unstable {
+            println!(
+                "Nothing from the server for {:.1} seconds",
+                health.silent_for
+            );
+        } else {
+            println!("Hearing from the server again");
+        }
+        health.unstable = unstable;
+    }
+}
+
+fn setup_unstable_text_system(mut commands: Commands) {
+    commands.spawn((
+        UnstableText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::ORANGE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(28.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn unstable_text_system(
+    localization: Res<Localization>,
+    health: Res<ConnectionHealth>,
+    mut query: Query<(&mut Text, &mut Visibility), With<UnstableText>>,
+) {
+    for (mut text, mut visibility) in query.iter_mut() {
+        if health.unstable {
+            text.sections[0].value = localization.format(
+                "hud.connection_unstable",
+                &[&format!("{:.0}", health.silent_for)],
+            );
+            *visibility = Visibility::Visible;
+        } else {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
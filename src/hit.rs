@@ -0,0 +1,508 @@
+use crate::{
+    action::ActionDeniedEvent,
+    core::*,
+    damage::{hitboxes, DamageEvent},
+    game_mode::Matches,
+    interpolation::{InterpolationClock, SnapshotBuffer},
+    inventory::ShotFiredEvent,
+    localization::Localization,
+    map::Map,
+    room::Rooms,
+    server::ReliableOutbox,
+    weapon::{FireMode, WeaponDefinitions},
+};
+use bevy::{
+    audio::{Pitch, PitchBundle, Volume},
+    prelude::*,
+};
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+use std::time::Duration;
+
+/// a shot the server hasn't ruled on after this long is given up on, in seconds
+const VERDICT_TIMEOUT: f32 = 2.0;
+/// seconds the hit marker stays up
+const MARKER_DURATION: f32 = 0.3;
+const PREDICTED_COLOR: Color = Color::WHITE;
+const CONFIRMED_COLOR: Color = Color::RED;
+
+/// shots hit whoever the server finds in their way, checked against where the shooter saw
+/// everyone: the snapshot they had when firing. heads and bodies are told apart, and each weapon
+/// says how much more a headshot does and how its damage falls off with distance. the shooter
+/// checks the same thing on their own and shows a hit right away, the server's verdict then
+/// confirms it or takes it back. how often the two agreed is shown once there's been a hit
+/// either way. projectiles take the same path but only hit whoever is in the way when they get
+/// there, so those the shooter hears about from the server
+pub struct HitPlugin;
+impl Plugin for HitPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, (load_hit_sound_system, setup_hit_hud_system));
+        app.add_systems(
+            Update,
+            (
+                resolve_shots_system.run_if(not(resource_exists::<RenetClient>)),
+                (predict_hits_system, receive_shot_results_system)
+                    .chain()
+                    .run_if(resource_exists::<RenetClient>),
+                hit_marker_system,
+                hit_prediction_text_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<HitMarker>();
+        app.init_resource::<PendingShots>();
+        app.init_resource::<HitPrediction>();
+        app.init_resource::<InFlightShots>();
+        app.add_event::<ReceivedShotResultEvent>();
+    }
+}
+
+/// the server ruled on one of our shots
+#[derive(Event)]
+pub struct ReceivedShotResultEvent {
+    pub input_id: u32,
+    pub hit: Option<(ClientId, BodyPart)>,
+}
+
+/// how often a hit we showed right away turned out the way the server saw it
+#[derive(Resource, Default)]
+pub struct HitPrediction {
+    /// hits we predicted on the same character and part the server hit
+    pub right: u32,
+    /// predicted hits the server didn't agree with, and hits we didn't see coming
+    pub wrong: u32,
+}
+
+/// our shots waiting for the server, with whoever we think they hit
+#[derive(Resource, Default)]
+struct PendingShots {
+    shots: Vec<PendingShot>,
+}
+
+struct PendingShot {
+    input_id: u32,
+    predicted: Option<(ClientId, BodyPart)>,
+    /// false for projectiles, which hit whoever is there by the time they arrive
+    guessed: bool,
+    fired_at: f32,
+}
+
+#[derive(Resource, Default)]
+struct HitMarker {
+    until: f32,
+    /// the shot it's shown for, so another shot's verdict doesn't take it down
+    input_id: u32,
+    confirmed: bool,
+    headshot: bool,
+    visible: bool,
+    play_sound: bool,
+}
+
+impl HitMarker {
+    fn show(&mut self, now: f32, input_id: u32, confirmed: bool, part: BodyPart) {
+        self.until = now + MARKER_DURATION;
+        self.input_id = input_id;
+        self.confirmed = confirmed;
+        self.headshot = part == BodyPart::Head;
+        self.visible = true;
+        self.play_sound = true;
+    }
+
+    fn confirm(&mut self, input_id: u32) {
+        if self.visible && self.input_id == input_id {
+            self.confirmed = true;
+        }
+    }
+
+    fn cancel(&mut self, input_id: u32) {
+        if self.visible && self.input_id == input_id {
+            self.visible = false;
+        }
+    }
+}
+
+#[derive(Resource)]
+struct HitSound(Handle<Pitch>);
+
+#[derive(Component)]
+struct HitMarkerText;
+
+#[derive(Component)]
+struct HitPredictionText;
+
+fn load_hit_sound_system(mut commands: Commands, mut pitches: ResMut<Assets<Pitch>>) {
+    commands.insert_resource(HitSound(
+        pitches.add(Pitch::new(880.0, Duration::from_millis(50))),
+    ));
+}
+
+/// a projectile on its way down its shot's path, checked against everyone as they are now
+struct InFlight {
+    fired: ShotFiredEvent,
+    fired_at: f32,
+    speed: f32,
+    /// meters along the path it had come last time it was checked
+    travelled: f32,
+    /// the length of the whole path, it's a miss once it gets there
+    range: f32,
+}
+
+/// only filled in on the server
+#[derive(Resource, Default)]
+struct InFlightShots {
+    shots: Vec<InFlight>,
+}
+
+/// the server's side: every shot that went off is checked against everyone but the shooter.
+/// hitscan shots are ruled on right away, projectiles once they get to something or run out
+fn resolve_shots_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    local_player: Res<LocalPlayer>,
+    rooms: Res<Rooms>,
+    weapons: Res<WeaponDefinitions>,
+    snapshot_history: Res<SnapshotHistory>,
+    characters: Query<(&Character, &Transform)>,
+    mut shots: EventReader<ShotFiredEvent>,
+    mut in_flight: ResMut<InFlightShots>,
+    mut damages: EventWriter<DamageEvent>,
+    mut hit_marker: ResMut<HitMarker>,
+    mut matches: Option<ResMut<Matches>>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: Option<ResMut<RenetServer>>,
+) {
+    let now = time.elapsed_seconds();
+    // whoever left since, or is in another room, can't be hit
+    let is_target = |shooter: ClientId, client_id: &ClientId| {
+        *client_id != shooter
+            && rooms.same_room(shooter, *client_id)
+            && characters
+                .iter()
+                .any(|(character, _)| character.owner_client_id == *client_id)
+    };
+
+    let mut landed = Vec::new();
+    for event in shots.read() {
+        let shooter = event.client_id;
+        if let FireMode::Projectile { speed } = weapons.get(event.weapon).fire_mode {
+            let range = map
+                .shot_path(event.origin, event.direction)
+                .iter()
+                .map(|leg| leg.start.distance(leg.end))
+                .sum();
+            in_flight.shots.push(InFlight {
+                fired: *event,
+                fired_at: now,
+                speed,
+                travelled: 0.0,
+                range,
+            });
+            continue;
+        }
+
+        let seen = event.shot.seen_snapshot_id.and_then(|snapshot_id| {
+            snapshot_history
+                .client_views
+                .get(&shooter)?
+                .iter()
+                .find(|snapshot| snapshot.id == snapshot_id)
+        });
+        let feet: Vec<(ClientId, Vec3)> = match seen {
+            Some(snapshot) => snapshot
+                .character_snapshots
+                .translations()
+                .map(|(client_id, translation)| (ClientId::from_raw(client_id), translation))
+                .filter(|(client_id, _)| is_target(shooter, client_id))
+                .collect(),
+            // too long ago to still have, or nothing in between
+            None => characters
+                .iter()
+                .map(|(character, transform)| (character.owner_client_id, transform.translation))
+                .filter(|(client_id, _)| is_target(shooter, client_id))
+                .collect(),
+        };
+        let targets = feet
+            .into_iter()
+            .flat_map(|(client_id, translation)| hitboxes(client_id, translation));
+        landed.push((*event, map.shot_hit(event.origin, event.direction, targets)));
+    }
+
+    // it's in the server's world by now, so it hits everyone where they are
+    in_flight.shots.retain_mut(|projectile| {
+        let shooter = projectile.fired.client_id;
+        let reached = ((now - projectile.fired_at) * projectile.speed).min(projectile.range);
+        let targets = characters
+            .iter()
+            .filter(|(character, _)| is_target(shooter, &character.owner_client_id))
+            .flat_map(|(character, transform)| {
+                hitboxes(character.owner_client_id, transform.translation)
+            });
+        let shot_hit = map
+            .shot_hit_beyond(
+                projectile.fired.origin,
+                projectile.fired.direction,
+                targets,
+                projectile.travelled,
+            )
+            .filter(|shot_hit| shot_hit.distance <= reached);
+        projectile.travelled = reached;
+        if shot_hit.is_some() || reached >= projectile.range {
+            landed.push((projectile.fired, shot_hit));
+            return false;
+        }
+        true
+    });
+
+    for (event, shot_hit) in landed {
+        let shooter = event.client_id;
+        let hit = shot_hit.map(|shot_hit| shot_hit.target);
+
+        if let Some(shot_hit) = shot_hit {
+            let (target, part) = shot_hit.target;
+            damages.send(DamageEvent {
+                client_id: target,
+                amount: weapons.get(event.weapon).damage
+                    * shot_hit.damage
+                    * weapons.multiplier(event.weapon, part, shot_hit.distance),
+                instigator: Some(shooter),
+            });
+            if let Some(matches) = matches.as_mut() {
+                matches
+                    .of(rooms.room_of(shooter))
+                    .stats
+                    .entry(shooter)
+                    .or_default()
+                    .shots_hit += 1;
+            }
+        }
+
+        if shooter == local_player.client_id {
+            if let Some((_, part)) = hit {
+                hit_marker.show(now, event.shot.input_id, true, part);
+            }
+        } else if let Some(server) = server.as_mut() {
+            outbox.send(
+                server,
+                shooter,
+                &ReliableServerMessage::ShotResult(
+                    event.shot.input_id,
+                    hit.map(|(client_id, part)| (client_id.raw(), part)),
+                ),
+            );
+        }
+    }
+}
+
+/// the same check on our own shots, against everyone where we see them
+fn predict_hits_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    local_player: Res<LocalPlayer>,
+    weapons: Res<WeaponDefinitions>,
+    interpolation_clock: Option<Res<InterpolationClock>>,
+    characters: Query<(&Character, &Transform, Option<&SnapshotBuffer>)>,
+    mut shots: EventReader<ShotFiredEvent>,
+    mut pending_shots: ResMut<PendingShots>,
+    mut hit_marker: ResMut<HitMarker>,
+) {
+    let now = time.elapsed_seconds();
+    let tick = interpolation_clock.and_then(|clock| clock.tick());
+    for event in shots
+        .read()
+        .filter(|event| event.client_id == local_player.client_id)
+    {
+        let guessed = weapons.get(event.weapon).fire_mode == FireMode::Hitscan;
+        let targets = characters
+            .iter()
+            .filter(|(character, _, _)| character.owner_client_id != local_player.client_id)
+            .flat_map(|(character, transform, buffer)| {
+                let drawn = buffer
+                    .zip(tick)
+                    .and_then(|(buffer, tick)| buffer.sample(tick))
+                    .map_or(*transform, Transform::from_translation);
+                hitboxes(character.owner_client_id, drawn.translation)
+            });
+        let predicted = map
+            .shot_hit(event.origin, event.direction, targets)
+            .filter(|_| guessed)
+            .map(|shot_hit| shot_hit.target);
+        if let Some((_, part)) = predicted {
+            hit_marker.show(now, event.shot.input_id, false, part);
+        }
+        pending_shots.shots.push(PendingShot {
+            input_id: event.shot.input_id,
+            predicted,
+            guessed,
+            fired_at: now,
+        });
+    }
+}
+
+fn receive_shot_results_system(
+    time: Res<Time>,
+    mut results: EventReader<ReceivedShotResultEvent>,
+    mut denials: EventReader<ActionDeniedEvent>,
+    mut pending_shots: ResMut<PendingShots>,
+    mut hit_marker: ResMut<HitMarker>,
+    mut hit_prediction: ResMut<HitPrediction>,
+) {
+    let now = time.elapsed_seconds();
+    for ReceivedShotResultEvent { input_id, hit } in results.read() {
+        let index = match pending_shots
+            .shots
+            .iter()
+            .position(|shot| shot.input_id == *input_id)
+        {
+            Some(index) => index,
+            None => continue,
+        };
+        let pending = pending_shots.shots.remove(index);
+        if !pending.guessed {
+            if let Some((_, part)) = hit {
+                hit_marker.show(now, *input_id, true, *part);
+            }
+            continue;
+        }
+        match (pending.predicted, hit) {
+            (None, None) => {}
+            (Some(predicted), Some(hit)) if predicted == *hit => {
+                hit_prediction.right += 1;
+                hit_marker.confirm(*input_id);
+            }
+            (_, hit) => {
+                hit_prediction.wrong += 1;
+                hit_marker.cancel(*input_id);
+                if let Some((_, part)) = hit {
+                    hit_marker.show(now, *input_id, true, *part);
+                }
+            }
+        }
+    }
+
+    // a shot that was never let through never hit anyone either
+    for ActionDeniedEvent { action, .. } in denials.read() {
+        if let PredictedAction::Inventory(InventoryAction::Fire(shot)) = action {
+            pending_shots
+                .shots
+                .retain(|pending| pending.input_id != shot.input_id);
+            hit_marker.cancel(shot.input_id);
+        }
+    }
+
+    pending_shots.shots.retain(|shot| {
+        let expired = now - shot.fired_at > VERDICT_TIMEOUT;
+        if expired {
+            hit_marker.cancel(shot.input_id);
+        }
+        !expired
+    });
+}
+
+fn setup_hit_hud_system(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                HitMarkerText,
+                TextBundle {
+                    text: Text::from_section(
+                        "x",
+                        TextStyle {
+                            font_size: 28.0,
+                            color: PREDICTED_COLOR,
+                            ..default()
+                        },
+                    ),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ));
+        });
+    commands.spawn((
+        HitPredictionText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(96.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn hit_marker_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    hit_sound: Res<HitSound>,
+    mut hit_marker: ResMut<HitMarker>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<HitMarkerText>>,
+) {
+    if hit_marker.play_sound {
+        hit_marker.play_sound = false;
+        commands.spawn(PitchBundle {
+            source: hit_sound.0.clone(),
+            settings: PlaybackSettings::DESPAWN.with_volume(Volume::new(0.5)),
+        });
+    }
+    if hit_marker.visible && time.elapsed_seconds() > hit_marker.until {
+        hit_marker.visible = false;
+    }
+    if !hit_marker.is_changed() {
+        return;
+    }
+
+    for (mut text, mut visibility) in texts.iter_mut() {
+        // bigger for a headshot
+        text.sections[0].value = if hit_marker.headshot { "X" } else { "x" }.to_string();
+        text.sections[0].style.color = if hit_marker.confirmed {
+            CONFIRMED_COLOR
+        } else {
+            PREDICTED_COLOR
+        };
+        *visibility = if hit_marker.visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn hit_prediction_text_system(
+    localization: Res<Localization>,
+    hit_prediction: Res<HitPrediction>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<HitPredictionText>>,
+) {
+    if !hit_prediction.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let judged = hit_prediction.right + hit_prediction.wrong;
+    for (mut text, mut visibility) in texts.iter_mut() {
+        text.sections[0].value =
+            localization.format("hit.prediction", &[&hit_prediction.right, &judged]);
+        *visibility = if judged == 0 {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use std::{collections::VecDeque, time::Instant};
+
+/// seconds between reads of the process' own numbers
+const SAMPLE_INTERVAL: f32 = 1.0;
+/// fixed ticks the jitter is taken over, 4 seconds at 64 Hz
+const JITTER_WINDOW: usize = 256;
+/// what `/proc/self/stat` counts cpu time in. it's a kernel build option, but 100 everywhere
+/// that matters
+#[cfg(target_os = "linux")]
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// how hard the machine hosting the server is working, for telling a complaint about lag from
+/// a host that's out of cpu. shown by the `status` admin command. cpu and memory are read from
+/// `/proc`, so they're only known on linux
+pub struct HostStatsPlugin;
+impl Plugin for HostStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedFirst,
+            tick_jitter_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            sample_process_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.init_resource::<HostStats>();
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct HostStats {
+    /// of one core, so a busy process on several goes past 100
+    pub cpu_percent: Option<f32>,
+    /// resident memory
+    pub rss_bytes: Option<u64>,
+    /// how far apart recent ticks were from the timestep on average, in milliseconds
+    pub tick_jitter_ms: f32,
+    /// the furthest off any of them was
+    pub worst_tick_jitter_ms: f32,
+    /// the latest ticks' distances from the timestep, oldest first
+    tick_offsets: VecDeque<f32>,
+    last_tick: Option<Instant>,
+    /// when the cpu time was last read and what it was, in seconds
+    last_sample: Option<(Instant, f64)>,
+}
+
+/// ticks that had to catch up run back to back, so they show up as far too close together
+fn tick_jitter_system(fixed_time: Res<Time<Fixed>>, mut host_stats: ResMut<HostStats>) {
+    let now = Instant::now();
+    let last_tick = host_stats.last_tick.replace(now);
+    let interval = match last_tick {
+        Some(last_tick) => now.duration_since(last_tick).as_secs_f32(),
+        None => return,
+    };
+    let offset_ms = (interval - fixed_time.timestep().as_secs_f32()).abs() * 1000.0;
+
+    if host_stats.tick_offsets.len() >= JITTER_WINDOW {
+        host_stats.tick_offsets.pop_front();
+    }
+    host_stats.tick_offsets.push_back(offset_ms);
+    let count = host_stats.tick_offsets.len() as f32;
+    host_stats.tick_jitter_ms = host_stats.tick_offsets.iter().sum::<f32>() / count;
+    host_stats.worst_tick_jitter_ms = host_stats.tick_offsets.iter().copied().fold(0.0, f32::max);
+}
+
+fn sample_process_system(mut host_stats: ResMut<HostStats>) {
+    let now = Instant::now();
+    if let Some((sampled_at, _)) = host_stats.last_sample {
+        if now.duration_since(sampled_at).as_secs_f32() < SAMPLE_INTERVAL {
+            return;
+        }
+    }
+
+    host_stats.rss_bytes = read_rss_bytes();
+    let cpu_seconds = match read_cpu_seconds() {
+        Some(cpu_seconds) => cpu_seconds,
+        None => return,
+    };
+    if let Some((sampled_at, last_cpu_seconds)) = host_stats.last_sample {
+        let elapsed = now.duration_since(sampled_at).as_secs_f64();
+        host_stats.cpu_percent = Some(((cpu_seconds - last_cpu_seconds) / elapsed * 100.0) as f32);
+    }
+    host_stats.last_sample = Some((now, cpu_seconds));
+}
+
+/// user and system time together since the process started
+#[cfg(target_os = "linux")]
+fn read_cpu_seconds() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the name in parentheses can have spaces of its own, everything after it is split cleanly.
+    // the first field after it is the 3rd, utime and stime are the 14th and 15th
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    let user: u64 = fields.get(11)?.parse().ok()?;
+    let system: u64 = fields.get(12)?.parse().ok()?;
+    Some((user + system) as f64 / CLOCK_TICKS_PER_SECOND)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_cpu_seconds() -> Option<f64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn read_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    // "VmRSS:     1234 kB"
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_rss_bytes() -> Option<u64> {
+    None
+}
@@ -1,13 +1,23 @@
-use crate::{core::*, MOUSE_SENSITIVITY};
+use crate::{
+    chat::ChatInput,
+    core::*,
+    game_mode,
+    map::Map,
+    settings::{ActionMode, Settings},
+    split_screen::GuestCamera,
+    visuals::{self, CameraMode},
+    MOUSE_SENSITIVITY,
+};
 use bevy::{input::mouse::MouseMotion, prelude::*};
+use bevy_renet::renet::RenetServer;
 use core::f32::consts::FRAC_PI_2;
 use std::{
     f32::consts::{PI, TAU},
-    time::{Instant, SystemTime},
+    time::SystemTime,
 };
 
 const ANGLE_EPSILON: f32 = 0.001953125;
-const SMOOTHING_FACTOR: f32 = 0.1;
+const ZOOM_BUTTON: MouseButton = MouseButton::Right;
 
 pub struct InputPlugin;
 impl Plugin for InputPlugin {
@@ -18,119 +28,226 @@ impl Plugin for InputPlugin {
     }
 }
 
+/// exponential low-pass over how fast the mouse is moving, so the result only depends on the
+/// time constant and not on how often frames happen
+#[derive(Default)]
+struct MouseFilter {
+    rate: Vec2,
+}
+
+impl MouseFilter {
+    fn filter(&mut self, delta: Vec2, delta_seconds: f32, time_constant: f32) -> Vec2 {
+        if time_constant <= 0.0 || delta_seconds <= 0.0 {
+            self.rate = Vec2::ZERO;
+            return delta;
+        }
+
+        let alpha = 1.0 - (-delta_seconds / time_constant).exp();
+        self.rate = self.rate.lerp(delta / delta_seconds, alpha);
+        self.rate * delta_seconds
+    }
+}
+
+/// whether each toggled action is currently switched on, held actions don't need any state
+#[derive(Default)]
+struct ToggledActions {
+    crouch: bool,
+    sprint: bool,
+    zoom: bool,
+}
+
+/// turns the raw button state into whether the action is on, depending on its mode
+fn action_active(mode: ActionMode, toggled: &mut bool, pressed: bool, just_pressed: bool) -> bool {
+    match mode {
+        ActionMode::Hold => {
+            *toggled = false;
+            pressed
+        }
+        ActionMode::Toggle => {
+            if just_pressed {
+                *toggled = !*toggled;
+            }
+            *toggled
+        }
+    }
+}
+
+/// runs every frame: the view turns right away and key presses are remembered until the next
+/// fixed tick samples them, no matter how many frames happen in between
 fn capture_inputs_system(
+    // real time, so turning feels the same in slow motion
+    time: Res<Time<Real>>,
+    virtual_time: Res<Time<Virtual>>,
+    settings: Res<Settings>,
     local_player: Res<LocalPlayer>,
     mut mouse_motion_events: EventReader<MouseMotion>,
     keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    chat_input: Option<Res<ChatInput>>,
+    camera_mode: Option<Res<CameraMode>>,
     mut history: ResMut<InputHistory>,
-    mut characters: Query<&mut Character>,
+    mut mouse_filter: Local<MouseFilter>,
+    mut toggled: Local<ToggledActions>,
+    mut characters: Query<(&Character, &mut ViewAngles)>,
+    projections: Query<&Projection, (With<Camera3d>, Without<GuestCamera>)>,
 ) {
-    for mut character in characters.iter_mut() {
-        if character.owner_client_id != local_player.client_id {
-            return;
-        }
-
-        let mut input = PlayerInput {
-            id: history.next_id,
-            timestamp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis(),
-            forward: keyboard.pressed(KeyCode::KeyW),
-            backward: keyboard.pressed(KeyCode::KeyS),
-            left: keyboard.pressed(KeyCode::KeyA),
-            right: keyboard.pressed(KeyCode::KeyD),
-            jump: keyboard.pressed(KeyCode::Space),
-            final_translation: Vec3::ZERO,
-            pitch: character.pitch,
-            yaw: character.yaw,
-        };
-
-        // Calculate the total mouse delta as before but apply smoothing
-        let mut total_mouse_delta = Vec2::ZERO;
-        for mouse_event in mouse_motion_events.read() {
-            total_mouse_delta += mouse_event.delta;
+    // don't walk around while typing, while the keys and mouse are flying the camera, or while
+    // the game is paused and the menu has them
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active);
+    let free_flying = camera_mode.is_some_and(|camera_mode| *camera_mode == CameraMode::FreeFly);
+    let paused = virtual_time.is_paused();
+    let blocked = typing || free_flying || paused;
+    let pressed = |key| !blocked && keyboard.pressed(key);
+    let just_pressed = |key| !blocked && keyboard.just_pressed(key);
+    let bindings = &settings.bindings;
+    let keys = bindings.keys;
+
+    let crouch = action_active(
+        bindings.crouch,
+        &mut toggled.crouch,
+        pressed(keys.crouch),
+        just_pressed(keys.crouch),
+    );
+    let sprint = action_active(
+        bindings.sprint,
+        &mut toggled.sprint,
+        pressed(keys.sprint),
+        just_pressed(keys.sprint),
+    );
+    let zoom = action_active(
+        bindings.zoom,
+        &mut toggled.zoom,
+        !blocked && mouse_buttons.pressed(ZOOM_BUTTON),
+        !blocked && mouse_buttons.just_pressed(ZOOM_BUTTON),
+    );
+
+    let mut total_mouse_delta = Vec2::ZERO;
+    for mouse_event in mouse_motion_events.read() {
+        total_mouse_delta += mouse_event.delta;
+    }
+    if free_flying || paused {
+        total_mouse_delta = Vec2::ZERO;
+    }
+    // turning slows down with the zoom so aiming stays as precise as without it
+    let zoom_scale = match projections.iter().next() {
+        Some(Projection::Perspective(perspective)) => {
+            visuals::zoom_sensitivity_scale(perspective.fov)
         }
-        total_mouse_delta *= MOUSE_SENSITIVITY;
-
-        // Smoothly interpolate the mouse delta using a smoothing factor
-        let smoothed_mouse_delta =
-            total_mouse_delta * SMOOTHING_FACTOR + (1.0 - SMOOTHING_FACTOR) * Vec2::ZERO; // Vec2::ZERO can be replaced with the previous frame's delta if available
-
-        // Update pitch and yaw with the smoothed deltas
-        input.pitch = (input.pitch - smoothed_mouse_delta.y)
+        _ => 1.0,
+    };
+    total_mouse_delta *= MOUSE_SENSITIVITY * zoom_scale;
+
+    let smoothed_mouse_delta = if settings.raw_mouse_input {
+        total_mouse_delta
+    } else {
+        mouse_filter.filter(
+            total_mouse_delta,
+            time.delta_seconds(),
+            settings.mouse_smoothing,
+        )
+    };
+
+    if let Some((_, mut view)) = characters
+        .iter_mut()
+        .find(|(character, _)| character.owner_client_id == local_player.client_id)
+    {
+        view.pitch = (view.pitch - smoothed_mouse_delta.y)
             .clamp(-FRAC_PI_2 + ANGLE_EPSILON, FRAC_PI_2 - ANGLE_EPSILON);
-        input.yaw -= smoothed_mouse_delta.x;
+        view.yaw -= smoothed_mouse_delta.x;
 
         // Normalize yaw to prevent large values and potential precision issues
-        if input.yaw.abs() > PI {
-            input.yaw = input.yaw.rem_euclid(TAU);
+        if view.yaw.abs() > PI {
+            view.yaw = view.yaw.rem_euclid(TAU);
         }
 
-        character.pitch = input.pitch;
-        character.yaw = input.yaw;
-
-        history.input_group_for_next_fixed_tick.push(input);
-        history.next_id += 1;
-
-        // only keep inputs up to a second ago
-        history.input_groups = history
-            .input_groups
-            .iter()
-            .filter(|inputs| {
-                if let Some(input) = inputs.last() {
-                    let age = SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis()
-                        - input.timestamp;
-                    age < 1000
-                } else {
-                    false
-                }
-            })
-            .cloned()
-            .collect();
+        // a key tapped between two ticks still counts for the next one
+        let pending = history
+            .pending_input
+            .get_or_insert_with(PlayerInput::default);
+        pending.forward |= pressed(keys.forward);
+        pending.backward |= pressed(keys.backward);
+        pending.left |= pressed(keys.left);
+        pending.right |= pressed(keys.right);
+        pending.jump |= pressed(keys.jump);
+        pending.crouch |= crouch;
+        pending.sprint |= sprint;
+        pending.zoom |= zoom;
+        pending.pitch = view.pitch;
+        pending.yaw = view.yaw;
     }
 }
 
-fn apply_inputs_system(
+pub fn apply_inputs_system(
     local_player: Res<LocalPlayer>,
+    server: Option<Res<RenetServer>>,
+    match_state: Option<Res<game_mode::MatchState>>,
     fixed_time: Res<Time<Fixed>>,
-    mut last_physics_update: ResMut<LastPhysicsUpdate>,
+    map: Res<Map>,
+    mutators: Res<Mutators>,
     mut history: ResMut<InputHistory>,
     mut characters: Query<(&mut Character, &mut Transform), Without<CharacterVisuals>>,
 ) {
-    last_physics_update.time = Instant::now();
-
-    for (mut character, mut transform) in characters.iter_mut() {
-        if character.owner_client_id != local_player.client_id {
-            continue;
-        }
-
-        let mut latest_processed_input_id = history.latest_processed_input_id;
-
-        if history.input_group_for_next_fixed_tick.is_empty() {
-            return;
-        }
+    // inputs captured while the match is frozen are thrown away instead of piling up
+    if game_mode::inputs_frozen(match_state) {
+        history.pending_input = None;
+        history.input_groups_for_next_send.clear();
+        return;
+    }
 
-        let chopped_delta =
-            fixed_time.delta_seconds() / history.input_group_for_next_fixed_tick.len() as f32;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    // only keep inputs up to a second ago
+    history.input_groups.retain(|inputs| {
+        inputs
+            .last()
+            .is_some_and(|input| now.saturating_sub(input.timestamp) < 1000)
+    });
+
+    if let Some((mut character, mut transform)) = characters
+        .iter_mut()
+        .find(|(character, _)| character.owner_client_id == local_player.client_id)
+    {
+        // several ticks in one frame have no new frame in between, so they repeat the last input
+        let mut input = match history.pending_input.take() {
+            Some(input) => input,
+            None => match history.input_groups.last().and_then(|inputs| inputs.last()) {
+                Some(input) => input.clone(),
+                None => return,
+            },
+        };
+        input.id = history.next_id;
+        input.timestamp = now;
+        history.next_id += 1;
 
-        for mut input in history.input_group_for_next_fixed_tick.iter_mut() {
-            if input.id > latest_processed_input_id {
-                character.process_input(&mut input, &mut transform, chopped_delta);
-                latest_processed_input_id = input.id;
+        // a listen server's host is "sent" to its own server like any client, so it moves by
+        // exactly the same processing. without a server we're the only one and move right away
+        let through_server = local_player.is_authority() && server.is_some();
+        if !through_server {
+            character.process_input(
+                &mut input,
+                &mut transform,
+                &map,
+                &mutators,
+                fixed_time.delta_seconds(),
+            );
+            if !local_player.is_authority() {
+                history.record_prediction(input.id, transform.translation);
             }
         }
 
-        history.latest_processed_input_id = latest_processed_input_id;
-
-        let input_group = history.input_group_for_next_fixed_tick.clone();
-        history.input_groups.push(input_group);
-        history.inputs_for_next_send = history.input_group_for_next_fixed_tick.clone();
-        history.input_group_for_next_fixed_tick.clear();
-
-        return;
+        let input_group = vec![input];
+        history.input_groups.push(input_group.clone());
+        evict_oldest(&mut history.input_groups, MAX_INPUT_GROUPS, "input groups");
+        if !local_player.is_authority() || through_server {
+            history.input_groups_for_next_send.push(input_group);
+            evict_oldest(
+                &mut history.input_groups_for_next_send,
+                MAX_INPUT_GROUPS,
+                "unsent input groups",
+            );
+        }
     }
 }
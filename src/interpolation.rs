@@ -0,0 +1,161 @@
+use crate::{core::*, server_time::ServerTime};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use std::collections::VecDeque;
+
+/// snapshots kept per character, a quarter of a second at 64 Hz
+const BUFFER_LENGTH: usize = 16;
+/// the clock jumps instead of catching up when it's this many ticks off
+const CLOCK_SNAP_TICKS: f32 = 16.0;
+/// how much faster the clock runs per tick it's behind, or slower per tick it's ahead
+const CLOCK_CORRECTION: f32 = 0.1;
+/// the most it runs faster or slower while catching up, as a fraction of real time
+const MAX_CLOCK_WARP: f32 = 0.1;
+
+/// characters we don't predict are drawn a little in the past, between the two snapshots around
+/// that moment, so a late or lost snapshot doesn't make them stutter. the simulation still has
+/// them where the latest snapshot put them, only where they're drawn (and what our shots are
+/// checked against) is behind
+pub struct InterpolationPlugin;
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            interpolation_clock_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.init_resource::<InterpolationDelay>();
+        app.init_resource::<InterpolationClock>();
+    }
+}
+
+/// how far in the past other characters are drawn, in seconds
+#[derive(Resource, Clone, Copy)]
+pub struct InterpolationDelay(pub f32);
+
+impl Default for InterpolationDelay {
+    fn default() -> Self {
+        Self(0.1)
+    }
+}
+
+/// the server tick being drawn, which runs along with our own time and is kept around the delay
+/// behind the latest snapshot
+#[derive(Resource, Default)]
+pub struct InterpolationClock {
+    tick: Option<f32>,
+    /// ids of the latest snapshots we got, oldest first
+    received: VecDeque<u32>,
+}
+
+impl InterpolationClock {
+    pub fn tick(&self) -> Option<f32> {
+        self.tick
+    }
+
+    /// the latest snapshot we got that isn't ahead of what's drawn, the one the server should
+    /// check our shots against
+    pub fn shown_snapshot_id(&self) -> Option<u32> {
+        let tick = self.tick?;
+        self.received
+            .iter()
+            .rev()
+            .find(|snapshot_id| **snapshot_id as f32 <= tick)
+            .copied()
+    }
+}
+
+/// where a character was in the latest snapshots that had it
+#[derive(Component, Default)]
+pub struct SnapshotBuffer {
+    samples: VecDeque<(u32, Vec3)>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot_id: u32, translation: Vec3) {
+        // snapshots are only applied in order, ids going back means the server started over
+        if self
+            .samples
+            .back()
+            .is_some_and(|(latest_id, _)| *latest_id >= snapshot_id)
+        {
+            self.samples.clear();
+        }
+        if self.samples.len() >= BUFFER_LENGTH {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((snapshot_id, translation));
+    }
+
+    /// teleports aren't smoothed over
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// where the character was at `tick`, held at either end when it's outside what's kept
+    pub fn sample(&self, tick: f32) -> Option<Vec3> {
+        let (first_id, first) = *self.samples.front()?;
+        if tick <= first_id as f32 {
+            return Some(first);
+        }
+        let next = self
+            .samples
+            .iter()
+            .position(|(snapshot_id, _)| *snapshot_id as f32 > tick);
+        match next {
+            Some(index) => {
+                let (from_id, from) = self.samples[index - 1];
+                let (to_id, to) = self.samples[index];
+                let t = (tick - from_id as f32) / (to_id - from_id) as f32;
+                Some(from.lerp(to, t))
+            }
+            None => self.samples.back().map(|(_, translation)| *translation),
+        }
+    }
+}
+
+fn interpolation_clock_system(
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    delay: Res<InterpolationDelay>,
+    server_time: Option<Res<ServerTime>>,
+    input_history: Res<InputHistory>,
+    mut clock: ResMut<InterpolationClock>,
+) {
+    let latest_id = match input_history.latest_processed_snapshot_id {
+        Some(latest_id) => latest_id,
+        None => {
+            *clock = default();
+            return;
+        }
+    };
+    // the server started over
+    if clock.received.back().is_some_and(|id| *id > latest_id) {
+        *clock = default();
+    }
+    if clock.received.back() != Some(&latest_id) {
+        if clock.received.len() >= BUFFER_LENGTH {
+            clock.received.pop_front();
+        }
+        clock.received.push_back(latest_id);
+    }
+
+    let tick_seconds = fixed_time.timestep().as_secs_f32();
+    // the latest snapshot's id jumps whenever one arrives, the server's clock moves smoothly
+    let arriving = server_time
+        .and_then(|server_time| {
+            server_time.arriving_tick(time.elapsed_seconds_f64(), 1.0 / tick_seconds as f64)
+        })
+        .map_or(latest_id as f32, |tick| tick as f32);
+    let target = arriving - delay.0 / tick_seconds;
+    let elapsed_ticks = time.delta_seconds() / tick_seconds;
+    let tick = match clock.tick {
+        // speeding up or slowing down a little keeps them moving smoothly
+        Some(tick) if (target - tick).abs() < CLOCK_SNAP_TICKS => {
+            let warp = ((target - tick) * CLOCK_CORRECTION).clamp(-MAX_CLOCK_WARP, MAX_CLOCK_WARP);
+            tick + elapsed_ticks * (1.0 + warp)
+        }
+        _ => target,
+    };
+    // nothing to draw past the latest snapshot
+    clock.tick = Some(tick.min(latest_id as f32));
+}
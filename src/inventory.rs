@@ -0,0 +1,498 @@
+use crate::{
+    action::{self, ActionDeniedEvent, PendingActions},
+    chat::ChatInput,
+    core::*,
+    debug_draw::DebugRays,
+    game_mode::Matches,
+    interpolation::InterpolationClock,
+    localization::Localization,
+    map::Map,
+    room::Rooms,
+    server::{ReliableOutbox, ResetWorldEvent},
+    weapon::WeaponDefinitions,
+    CHARACTER_HEIGHT,
+};
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+use std::f32::consts::FRAC_PI_2;
+
+const FIRE_BUTTON: MouseButton = MouseButton::Left;
+const NEXT_WEAPON_KEY: KeyCode = KeyCode::KeyE;
+const USE_ITEM_KEY: KeyCode = KeyCode::KeyG;
+/// recoil never kicks the view past straight up
+const MAX_KICKED_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// the server (or single player) keeps everyone's inventory, clients get their own whenever it
+/// changes and show their actions on it before the server confirms them
+pub struct InventoryPlugin;
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_inventory_hud_system);
+        app.add_systems(
+            Update,
+            (
+                inventory_key_system,
+                (
+                    give_loadouts_system,
+                    inventory_actions_system,
+                    sync_inventories_system.run_if(resource_exists::<RenetServer>),
+                )
+                    .chain()
+                    .run_if(not(resource_exists::<RenetClient>)),
+                (receive_inventory_system, forget_denied_actions_system)
+                    .run_if(resource_exists::<RenetClient>),
+                inventory_hud_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<Inventories>();
+        app.init_resource::<LocalInventory>();
+        app.add_event::<IncomingInventoryActionEvent>();
+        app.add_event::<ReceivedInventoryEvent>();
+        app.add_event::<ShotFiredEvent>();
+    }
+}
+
+/// an inventory action by a player, for the server to carry out
+#[derive(Event)]
+pub struct IncomingInventoryActionEvent {
+    pub sender: ClientId,
+    pub action: InventoryAction,
+    /// set when the sender predicted it and waits for an answer
+    pub action_id: Option<u32>,
+}
+
+/// the server sent us our inventory
+#[derive(Event)]
+pub struct ReceivedInventoryEvent {
+    pub up_to_action_id: Option<u32>,
+    pub inventory: Inventory,
+}
+
+/// a shot went off: predicted by its owner, or carried out by the server, see `hit`
+#[derive(Event, Clone, Copy)]
+pub struct ShotFiredEvent {
+    pub client_id: ClientId,
+    pub shot: Shot,
+    pub weapon: Weapon,
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// where shots come from, the eyes
+fn shot_origin(transform: &Transform) -> Vec3 {
+    transform.translation + Vec3::Y * CHARACTER_HEIGHT
+}
+
+/// only filled in on the server, one per character
+#[derive(Resource, Default)]
+struct Inventories {
+    players: HashMap<ClientId, PlayerInventory>,
+}
+
+struct PlayerInventory {
+    inventory: Inventory,
+    /// the latest of the owner's predicted actions that was dealt with, accepted or not
+    last_action_id: Option<u32>,
+    /// changed since the owner was last sent it
+    dirty: bool,
+}
+
+/// a client's own inventory: the last one the server sent and what we did since
+#[derive(Resource, Default)]
+struct LocalInventory {
+    confirmed: Option<Inventory>,
+    pending: Vec<(u32, InventoryAction)>,
+}
+
+impl LocalInventory {
+    fn predicted(&self, weapons: &WeaponDefinitions) -> Option<Inventory> {
+        let mut inventory = self.confirmed.clone()?;
+        for (_, action) in &self.pending {
+            inventory.apply(*action, weapons);
+        }
+        Some(inventory)
+    }
+}
+
+/// what decides where a shot goes and whether it can be fired, together because a system only
+/// takes 16 parameters
+#[derive(SystemParam)]
+struct ShotRules<'w> {
+    match_seed: Res<'w, MatchSeed>,
+    weapons: Res<'w, WeaponDefinitions>,
+}
+
+fn inventory_key_system(
+    time: Res<Time>,
+    virtual_time: Res<Time<Virtual>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    chat_input: Option<Res<ChatInput>>,
+    local_player: Option<Res<LocalPlayer>>,
+    inventories: Res<Inventories>,
+    input_history: Res<InputHistory>,
+    shot_rules: ShotRules,
+    interpolation_clock: Option<Res<InterpolationClock>>,
+    mut local_inventory: ResMut<LocalInventory>,
+    mut pending_actions: Option<ResMut<PendingActions>>,
+    mut client: Option<ResMut<RenetClient>>,
+    mut incoming: EventWriter<IncomingInventoryActionEvent>,
+    mut shots: EventWriter<ShotFiredEvent>,
+    mut characters: Query<(&Character, &Transform, &mut ViewAngles)>,
+) {
+    let local_player = match local_player {
+        Some(local_player) => local_player,
+        None => return,
+    };
+    if chat_input.is_some_and(|chat_input| chat_input.active) || virtual_time.is_paused() {
+        return;
+    }
+
+    let inventory = if client.is_some() {
+        local_inventory.predicted(&shot_rules.weapons)
+    } else {
+        inventories
+            .players
+            .get(&local_player.client_id)
+            .map(|player| player.inventory.clone())
+    };
+    let mut inventory = match inventory {
+        Some(inventory) => inventory,
+        None => return,
+    };
+
+    let mut own_character = characters
+        .iter_mut()
+        .find(|(character, _, _)| character.owner_client_id == local_player.client_id);
+
+    let action = if mouse_buttons.just_pressed(FIRE_BUTTON) {
+        let view = match own_character.as_ref() {
+            Some((_, _, view)) => view,
+            None => return,
+        };
+        // it goes out with the next tick's input
+        InventoryAction::Fire(Shot {
+            input_id: input_history.next_id,
+            pitch: view.pitch,
+            yaw: view.yaw,
+            // what we're shown of everyone else is a little behind the latest snapshot
+            seen_snapshot_id: client.as_ref().and(
+                interpolation_clock
+                    .and_then(|clock| clock.shown_snapshot_id())
+                    .or(input_history.latest_processed_snapshot_id),
+            ),
+        })
+    } else if keyboard.just_pressed(NEXT_WEAPON_KEY) && !inventory.weapons.is_empty() {
+        InventoryAction::SelectWeapon((inventory.selected + 1) % inventory.weapons.len() as u8)
+    } else if keyboard.just_pressed(USE_ITEM_KEY) {
+        InventoryAction::UseItem(Item::AmmoPack)
+    } else {
+        return;
+    };
+    let shot_direction = match action {
+        InventoryAction::Fire(shot) => inventory.shot_direction(
+            &shot_rules.weapons,
+            *shot_rules.match_seed,
+            local_player.client_id.raw(),
+            shot,
+        ),
+        _ => None,
+    };
+    // the server would only say no
+    if !inventory.apply(action, &shot_rules.weapons) {
+        return;
+    }
+
+    // clients show their own shot right away, on the server's machine it goes off once it's
+    // carried out
+    if let (Some(client), Some(pending_actions)) = (client.as_mut(), pending_actions.as_mut()) {
+        if let (
+            InventoryAction::Fire(shot),
+            Some(direction),
+            Some(weapon),
+            Some((_, transform, _)),
+        ) = (
+            action,
+            shot_direction,
+            inventory.selected_weapon(),
+            own_character.as_ref(),
+        ) {
+            shots.send(ShotFiredEvent {
+                client_id: local_player.client_id,
+                shot,
+                weapon,
+                origin: shot_origin(transform),
+                direction,
+            });
+        }
+        let action_id = pending_actions.predict(
+            client,
+            time.elapsed_seconds(),
+            PredictedAction::Inventory(action),
+        );
+        local_inventory.pending.push((action_id, action));
+    } else {
+        incoming.send(IncomingInventoryActionEvent {
+            sender: local_player.client_id,
+            action,
+            action_id: None,
+        });
+    }
+
+    if let (InventoryAction::Fire(_), Some(weapon), Some((_, _, view))) =
+        (action, inventory.selected_weapon(), own_character.as_mut())
+    {
+        view.pitch = (view.pitch + shot_rules.weapons.get(weapon).recoil).min(MAX_KICKED_PITCH);
+    }
+}
+
+/// everyone with a character gets the starting loadout, a new world starts everyone over
+fn give_loadouts_system(
+    weapons: Res<WeaponDefinitions>,
+    mut resets: EventReader<ResetWorldEvent>,
+    mut inventories: ResMut<Inventories>,
+    characters: Query<&Character>,
+) {
+    // the last action id stays, clients still need it to let go of their predictions
+    if resets.read().count() > 0 {
+        for player in inventories.players.values_mut() {
+            player.inventory = Inventory::starting(&weapons);
+            player.dirty = true;
+        }
+    }
+
+    inventories.players.retain(|client_id, _| {
+        characters
+            .iter()
+            .any(|character| character.owner_client_id == *client_id)
+    });
+    for character in characters.iter() {
+        inventories
+            .players
+            .entry(character.owner_client_id)
+            .or_insert_with(|| PlayerInventory {
+                inventory: Inventory::starting(&weapons),
+                last_action_id: None,
+                dirty: true,
+            });
+    }
+}
+
+fn inventory_actions_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    match_seed: Res<MatchSeed>,
+    weapons: Res<WeaponDefinitions>,
+    characters: Query<(&Character, &Transform)>,
+    mut incoming: EventReader<IncomingInventoryActionEvent>,
+    rooms: Res<Rooms>,
+    mut inventories: ResMut<Inventories>,
+    mut matches: Option<ResMut<Matches>>,
+    mut shots: EventWriter<ShotFiredEvent>,
+    mut debug_rays: Option<ResMut<DebugRays>>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: Option<ResMut<RenetServer>>,
+) {
+    for IncomingInventoryActionEvent {
+        sender,
+        action,
+        action_id,
+    } in incoming.read()
+    {
+        let mut shot_direction = None;
+        let accepted = match inventories.players.get_mut(sender) {
+            Some(player) => {
+                if let InventoryAction::Fire(shot) = action {
+                    let inventory = &player.inventory;
+                    shot_direction = inventory.selected_weapon().zip(inventory.shot_direction(
+                        &weapons,
+                        *match_seed,
+                        sender.raw(),
+                        *shot,
+                    ));
+                }
+                let accepted = player.inventory.apply(*action, &weapons);
+                if action_id.is_some() {
+                    player.last_action_id = *action_id;
+                }
+                // a denied prediction needs the real inventory to fall back to as well
+                player.dirty = true;
+                accepted
+            }
+            None => false,
+        };
+
+        if let (true, InventoryAction::Fire(shot), Some((weapon, direction))) =
+            (accepted, action, shot_direction)
+        {
+            if let Some(matches) = matches.as_mut() {
+                matches
+                    .of(rooms.room_of(*sender))
+                    .stats
+                    .entry(*sender)
+                    .or_default()
+                    .shots_fired += 1;
+            }
+            if let Some((_, transform)) = characters
+                .iter()
+                .find(|(character, _)| character.owner_client_id == *sender)
+            {
+                let origin = shot_origin(transform);
+                // drawn next to the owner's own tracer, the two should line up exactly
+                if let Some(debug_rays) = debug_rays.as_mut() {
+                    debug_rays.shot(&time, &map.shot_path(origin, direction));
+                }
+                shots.send(ShotFiredEvent {
+                    client_id: *sender,
+                    shot: *shot,
+                    weapon,
+                    origin,
+                    direction,
+                });
+            }
+        }
+        if let Some(server) = server.as_mut() {
+            action::answer_action(&mut outbox, server, *sender, *action_id, accepted);
+        }
+    }
+}
+
+/// only the owner is told, nobody else needs to know what's in someone's pockets
+fn sync_inventories_system(
+    mut inventories: ResMut<Inventories>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for (client_id, player) in inventories.players.iter_mut() {
+        if player.dirty {
+            player.dirty = false;
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::Inventory(player.last_action_id, player.inventory.clone()),
+            );
+        }
+    }
+}
+
+fn receive_inventory_system(
+    mut received: EventReader<ReceivedInventoryEvent>,
+    mut local_inventory: ResMut<LocalInventory>,
+) {
+    for ReceivedInventoryEvent {
+        up_to_action_id,
+        inventory,
+    } in received.read()
+    {
+        local_inventory.confirmed = Some(inventory.clone());
+        if let Some(up_to_action_id) = up_to_action_id {
+            local_inventory
+                .pending
+                .retain(|(action_id, _)| action_id > up_to_action_id);
+        }
+    }
+}
+
+/// a denied action just stops being predicted, what's shown falls back to the server's copy
+fn forget_denied_actions_system(
+    mut denials: EventReader<ActionDeniedEvent>,
+    mut local_inventory: ResMut<LocalInventory>,
+) {
+    for ActionDeniedEvent { action_id, action } in denials.read() {
+        if let PredictedAction::Inventory(_) = action {
+            local_inventory
+                .pending
+                .retain(|(pending_id, _)| pending_id != action_id);
+        }
+    }
+}
+
+#[derive(Component)]
+struct InventoryText;
+
+fn setup_inventory_hud_system(mut commands: Commands) {
+    commands.spawn((
+        InventoryText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+    ));
+}
+
+fn weapon_name(localization: &Localization, weapon: Weapon) -> &str {
+    match weapon {
+        Weapon::Pistol => localization.get("inventory.pistol"),
+        Weapon::Rifle => localization.get("inventory.rifle"),
+    }
+}
+
+fn item_name(localization: &Localization, item: Item) -> &str {
+    match item {
+        Item::AmmoPack => localization.get("inventory.ammo_pack"),
+    }
+}
+
+fn inventory_hud_system(
+    localization: Res<Localization>,
+    weapons: Res<WeaponDefinitions>,
+    local_player: Option<Res<LocalPlayer>>,
+    client: Option<Res<RenetClient>>,
+    inventories: Res<Inventories>,
+    local_inventory: Res<LocalInventory>,
+    mut texts: Query<&mut Text, With<InventoryText>>,
+) {
+    let inventory = match (client, local_player) {
+        (Some(_), _) => local_inventory.predicted(&weapons),
+        (None, Some(local_player)) => inventories
+            .players
+            .get(&local_player.client_id)
+            .map(|player| player.inventory.clone()),
+        (None, None) => None,
+    };
+
+    let mut lines = Vec::new();
+    if let Some(inventory) = inventory {
+        for (index, slot) in inventory.weapons.iter().enumerate() {
+            let key = if index == inventory.selected as usize {
+                "inventory.selected_weapon"
+            } else {
+                "inventory.weapon"
+            };
+            lines.push(localization.format(
+                key,
+                &[
+                    &weapon_name(&localization, slot.weapon),
+                    &slot.ammo,
+                    &weapons.get(slot.weapon).max_ammo,
+                ],
+            ));
+        }
+        for (item, count) in &inventory.items {
+            lines.push(
+                localization.format("inventory.item", &[&item_name(&localization, *item), count]),
+            );
+        }
+    }
+
+    let value = lines.join("\n");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value.clone_from(&value);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{core::*, localization::Localization, LevelAssets};
+use bevy::{asset::LoadState, prelude::*};
+use bevy_renet::renet::RenetClient;
+
+/// joining has two halves that don't wait on each other: the level's assets loading in the
+/// background and the server taking us through the handshake and sending a first snapshot. both
+/// start right away and the loading screen stays up until both are done. losing the connection
+/// puts it back up until we're in again
+pub struct LoadingPlugin;
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<JoinState>();
+        app.add_systems(Startup, setup_loading_screen);
+        app.add_systems(
+            Update,
+            (join_progress_system, loading_screen_system)
+                .chain()
+                .run_if(resource_exists::<RenetClient>),
+        );
+        app.add_systems(OnEnter(JoinState::InGame), joined_system);
+        app.init_resource::<JoinProgress>();
+    }
+}
+
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JoinState {
+    #[default]
+    Joining,
+    InGame,
+}
+
+#[derive(Resource, Default)]
+struct JoinProgress {
+    /// real seconds, when the latest attempt started
+    started_at: f32,
+    /// the latest snapshot we had when it started, the first one of this attempt is any other
+    snapshot_before: Option<u32>,
+    assets_loaded: bool,
+    connected: bool,
+    has_snapshot: bool,
+}
+
+#[derive(Component)]
+struct LoadingScreen;
+
+#[derive(Component)]
+struct LoadingText;
+
+fn setup_loading_screen(mut commands: Commands) {
+    commands
+        .spawn((
+            LoadingScreen,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::BLACK.into(),
+                // above every other hud element
+                z_index: ZIndex::Global(100),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                LoadingText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+fn join_progress_system(
+    time: Res<Time<Real>>,
+    asset_server: Res<AssetServer>,
+    level_assets: Option<Res<LevelAssets>>,
+    client: Res<RenetClient>,
+    input_history: Res<InputHistory>,
+    state: Res<State<JoinState>>,
+    mut next_state: ResMut<NextState<JoinState>>,
+    mut progress: ResMut<JoinProgress>,
+) {
+    if *state.get() == JoinState::InGame {
+        if !client.is_disconnected() {
+            return;
+        }
+        println!("Lost the connection, joining again");
+        *progress = JoinProgress {
+            started_at: time.elapsed_seconds(),
+            snapshot_before: input_history.latest_processed_snapshot_id,
+            // they're still loaded
+            assets_loaded: progress.assets_loaded,
+            ..default()
+        };
+        next_state.set(JoinState::Joining);
+        return;
+    }
+
+    // one that failed to load is drawn without it, that's no reason to keep everyone out
+    progress.assets_loaded = level_assets.is_some_and(|level_assets| {
+        matches!(
+            asset_server.get_load_state(&level_assets.ground_texture),
+            Some(LoadState::Loaded | LoadState::Failed)
+        )
+    });
+    progress.connected = client.is_connected();
+    progress.has_snapshot = progress.connected
+        && input_history.latest_processed_snapshot_id.is_some()
+        && input_history.latest_processed_snapshot_id != progress.snapshot_before;
+
+    if progress.assets_loaded && progress.has_snapshot {
+        next_state.set(JoinState::InGame);
+    }
+}
+
+fn joined_system(time: Res<Time<Real>>, progress: Res<JoinProgress>) {
+    println!(
+        "Joined in {:.2}s",
+        time.elapsed_seconds() - progress.started_at
+    );
+}
+
+fn loading_screen_system(
+    state: Res<State<JoinState>>,
+    localization: Res<Localization>,
+    progress: Res<JoinProgress>,
+    mut screens: Query<&mut Visibility, With<LoadingScreen>>,
+    mut texts: Query<&mut Text, With<LoadingText>>,
+) {
+    let target = match state.get() {
+        JoinState::Joining => Visibility::Visible,
+        JoinState::InGame => Visibility::Hidden,
+    };
+    for mut visibility in screens.iter_mut() {
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+
+    let mut lines = Vec::new();
+    if !progress.assets_loaded {
+        lines.push(localization.get("loading.assets"));
+    }
+    if !progress.connected {
+        lines.push(localization.get("loading.connecting"));
+    } else if !progress.has_snapshot {
+        lines.push(localization.get("loading.world"));
+    }
+    let value = lines.join("\n");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value.clone_from(&value);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
+
+const LOCALES_DIR: &str = "assets/locales";
+/// every key exists here, other locales can leave some out
+const FALLBACK_LOCALE: &str = "en";
+
+/// every string shown to the player, looked up by key in the locale picked in the settings
+#[derive(Resource)]
+pub struct Localization {
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Localization {
+    pub fn load(locale: &str) -> Self {
+        let fallback = read_locale(FALLBACK_LOCALE).unwrap_or_else(|e| {
+            eprintln!("Failed to load locale {}: {}", FALLBACK_LOCALE, e);
+            HashMap::new()
+        });
+        let strings = if locale == FALLBACK_LOCALE {
+            HashMap::new()
+        } else {
+            read_locale(locale).unwrap_or_else(|e| {
+                eprintln!("Failed to load locale {}: {}", locale, e);
+                HashMap::new()
+            })
+        };
+
+        Self { strings, fallback }
+    }
+
+    /// missing keys show up as the key itself, so they're easy to spot
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// replaces `{0}`, `{1}`... with the arguments, translations are free to reorder them
+    pub fn format(&self, key: &str, args: &[&dyn Display]) -> String {
+        let mut text = self.get(key).to_string();
+        for (index, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", index), &arg.to_string());
+        }
+        text
+    }
+}
+
+fn read_locale(locale: &str) -> Result<HashMap<String, String>, String> {
+    let path = PathBuf::from(LOCALES_DIR).join(format!("{}.ron", locale));
+    let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    ron::from_str(&text).map_err(|e| e.to_string())
+}
@@ -1,59 +1,379 @@
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
-use bevy::{prelude::*, winit::WinitSettings};
+use bevy::{
+    app::{PluginGroupBuilder, ScheduleRunnerPlugin},
+    prelude::*,
+    winit::WinitSettings,
+};
 use bevy_renet::{
     renet::ClientId,
     transport::{NetcodeClientPlugin, NetcodeServerPlugin},
     RenetClientPlugin, RenetServerPlugin,
 };
 use clap::Parser;
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+mod action;
+mod admin;
+mod afk;
+mod announcement;
+mod anticheat;
+mod chat;
+mod class;
 mod client;
+mod controls;
 mod core;
+mod cosmetics;
+mod damage;
+mod debug_draw;
+mod demo;
+mod emote;
+mod explosion;
+mod freeze;
+mod game_mode;
+mod hazard;
+mod heartbeat;
+mod hit;
+mod host_stats;
 mod input;
+mod interpolation;
+mod inventory;
+mod loading;
+mod localization;
+mod map;
+mod movement;
+mod mutators;
+mod nameplate;
+mod origin;
+mod palette;
+mod pause;
+mod ping;
+mod practice;
+mod replication;
+mod room;
+mod seed;
 mod server;
+mod server_time;
+mod settings;
+mod soak;
+mod sound;
+mod split_screen;
 mod stats;
+mod status_effect;
+mod telemetry;
+mod teleporter;
+mod time_scale;
+mod tracer;
+mod visuals;
+mod vote;
+mod weapon;
 
 use core::*;
-use std::time::Instant;
+use map::Map;
+use palette::PlayerColor;
 
-const MOUSE_SENSITIVITY: f32 = 0.01;
+/// radians per pixel
+const MOUSE_SENSITIVITY: f32 = 0.001;
+/// vertical, in radians
+const FIELD_OF_VIEW: f32 = std::f32::consts::FRAC_PI_4;
+const ZOOM_FIELD_OF_VIEW: f32 = std::f32::consts::FRAC_PI_4 / 3.0;
 
 const CHARACTER_HEIGHT: f32 = 0.7;
+/// how far out from its feet a character's body reaches, for running into walls
+const CHARACTER_RADIUS: f32 = 0.3;
 const CHARACTER_SPEED: f32 = 5.0;
 const CHARACTER_ACCEL: f32 = 8.0;
 const CHARACTER_FRICTION: f32 = 8.0;
+const MAX_HEALTH: f32 = 100.0;
+const SPRINT_SPEED_MULTIPLIER: f32 = 1.5;
+const CROUCH_SPEED_MULTIPLIER: f32 = 0.5;
+
+const FLOOR_SIZE: f32 = 20.0;
+const FLOOR_THICKNESS: f32 = 0.1;
+const FLOOR_Y: f32 = -0.5;
+
+const SPAWN_POSITION: Vec3 = Vec3::ZERO;
+const WORLD_HALF_EXTENT: f32 = 50.0;
+const KILL_Z: f32 = -20.0;
 
-const SMOOTH_CORRECTION_DISTANCE_THRESHOLD: f32 = 0.001;
-const SMOOTH_CORRECTION_STEP_MIN: f32 = 0.25;
-const SMOOTH_CORRECTION_STEP_MAX: f32 = 0.75;
 const DEFAULT_PORT: u16 = 7777;
+const MAP_PATH: &str = "assets/maps/default.ron";
+const DEFAULT_CLIENT_BANDWIDTH: u32 = 64_000;
+/// what renet's unsecure connections always used
+const DEFAULT_TIMEOUT_SECONDS: i32 = 15;
+/// three heartbeats missed in a row
+const DEFAULT_UNSTABLE_AFTER: f32 = 1.5;
+
+/// the game itself, the same for the server and every client whichever way they're run. each
+/// mode adds what it has on top: a connection, something to draw, things only a host does
+pub struct GameplayPlugins;
+impl PluginGroup for GameplayPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(afk::AfkPlugin)
+            .add(room::RoomPlugin)
+            .add(class::ClassPlugin)
+            .add(game_mode::GameModePlugin)
+            .add(chat::ChatPlugin)
+            .add(announcement::AnnouncementPlugin)
+            .add(vote::VotePlugin)
+            .add(mutators::MutatorsPlugin)
+            .add(seed::MatchSeedPlugin)
+            .add(time_scale::TimeScalePlugin)
+            .add(freeze::FreezePlugin)
+            .add(emote::EmotePlugin)
+            .add(inventory::InventoryPlugin)
+            .add(hit::HitPlugin)
+            .add(damage::DamagePlugin)
+            .add(explosion::ExplosionPlugin)
+            .add(debug_draw::DebugDrawPlugin)
+            .add(hazard::HazardPlugin)
+            .add(teleporter::TeleporterPlugin)
+            .add(ping::PingPlugin)
+            .add(heartbeat::HeartbeatPlugin)
+            .add(telemetry::TelemetryPlugin)
+            .add(weapon::WeaponPlugin)
+    }
+}
 
 #[derive(Parser, PartialEq, Resource, Clone)]
 pub enum Cli {
-    SinglePlayer,
+    SinglePlayer {
+        /// a second player with a gamepad on the right half of the screen
+        #[arg(long)]
+        split_screen: bool,
+
+        /// play against a server in the same process, with sliders for latency, packet loss
+        /// and snapshot rate
+        #[arg(long, conflicts_with = "split_screen")]
+        practice: bool,
+
+        /// the practice round trip to start with, in milliseconds
+        #[arg(long, requires = "practice")]
+        simulate_latency: Option<f32>,
+
+        /// how much later than that any one practice packet can arrive, in milliseconds
+        #[arg(long, requires = "practice")]
+        simulate_jitter: Option<f32>,
+
+        /// the chance of any one practice packet being dropped to start with
+        #[arg(long, requires = "practice")]
+        simulate_loss: Option<f32>,
+    },
     DedicatedServer {
+        /// the address to listen on, `::` for IPv6 and IPv4 both where the system allows
+        #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED.into())]
+        bind: IpAddr,
+
         #[arg(short, long, default_value_t = DEFAULT_PORT)]
         port: u16,
+
+        /// record a demo of every match into this directory
+        #[arg(long)]
+        demo_dir: Option<PathBuf>,
+
+        /// outgoing bytes per second each client is allowed
+        #[arg(long, default_value_t = DEFAULT_CLIENT_BANDWIDTH)]
+        client_bandwidth: u32,
+
+        /// seconds without input before an idle client is dealt with, never if not given
+        #[arg(long)]
+        afk_timeout: Option<f32>,
+
+        /// what to do with idle clients
+        #[arg(long, value_enum, default_value_t = afk::AfkAction::Spectate)]
+        afk_action: afk::AfkAction,
+
+        /// message of the day, shown to everyone as they join
+        #[arg(long)]
+        motd: Option<String>,
+
+        /// kick clients whose cheat score reaches this, never if not given
+        #[arg(long)]
+        cheat_kick_score: Option<f32>,
+
+        /// a mutator to start with, can be given more than once
+        #[arg(long = "mutator", value_enum)]
+        mutators: Vec<Mutator>,
     },
     ListenServer {
+        /// the address to listen on, `::` for IPv6 and IPv4 both where the system allows
+        #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED.into())]
+        bind: IpAddr,
+
         #[arg(short, long, default_value_t = DEFAULT_PORT)]
         port: u16,
+
+        /// record a demo of every match into this directory
+        #[arg(long)]
+        demo_dir: Option<PathBuf>,
+
+        /// outgoing bytes per second each client is allowed
+        #[arg(long, default_value_t = DEFAULT_CLIENT_BANDWIDTH)]
+        client_bandwidth: u32,
+
+        /// seconds without input before an idle client is dealt with, never if not given
+        #[arg(long)]
+        afk_timeout: Option<f32>,
+
+        /// what to do with idle clients
+        #[arg(long, value_enum, default_value_t = afk::AfkAction::Spectate)]
+        afk_action: afk::AfkAction,
+
+        /// message of the day, shown to everyone as they join
+        #[arg(long)]
+        motd: Option<String>,
+
+        /// kick clients whose cheat score reaches this, never if not given
+        #[arg(long)]
+        cheat_kick_score: Option<f32>,
+
+        /// a mutator to start with, can be given more than once
+        #[arg(long = "mutator", value_enum)]
+        mutators: Vec<Mutator>,
+
+        /// a second player with a gamepad on the right half of the screen
+        #[arg(long)]
+        split_screen: bool,
+
+        /// what the host plays as
+        #[arg(long, value_enum, default_value_t = CharacterClass::Standard)]
+        class: CharacterClass,
     },
     Client {
-        #[arg(short, long, default_value_t = Ipv4Addr::LOCALHOST.into())]
-        ip: IpAddr,
+        /// the server's address or host name
+        #[arg(short, long, default_value_t = Ipv4Addr::LOCALHOST.to_string())]
+        ip: String,
 
         #[arg(short, long, default_value_t = DEFAULT_PORT)]
         port: u16,
+
+        /// fixed ticks of inputs bundled into each message, higher saves upload at the cost of latency
+        #[arg(long, default_value_t = 1)]
+        input_send_interval: u32,
+
+        /// seconds without hearing from the other end before either side drops the connection,
+        /// 0 never does, for sitting in a debugger
+        #[arg(long, default_value_t = DEFAULT_TIMEOUT_SECONDS)]
+        timeout: i32,
+
+        /// seconds without hearing from the server before the connection is shown as unstable
+        #[arg(long, default_value_t = DEFAULT_UNSTABLE_AFTER)]
+        unstable_after: f32,
+
+        /// the room to play in on the server, 0 is where everyone starts
+        #[arg(long)]
+        room: Option<u32>,
+
+        /// how far in the past other players are drawn, higher hides more jitter and loss
+        #[arg(long, default_value_t = 100.0)]
+        interpolation_delay_ms: f32,
+
+        /// what to play as on the server
+        #[arg(long, value_enum, default_value_t = CharacterClass::Standard)]
+        class: CharacterClass,
+
+        /// worn by our character, everyone sees it
+        #[arg(long, value_enum, default_value_t = Hat::None)]
+        hat: Hat,
+
+        /// a line left behind our character wherever it goes
+        #[arg(long, value_enum)]
+        trail: Option<TrailColor>,
+
+        /// shown above our character to everyone else
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// watch a demo recorded by a server
+    Demo { path: PathBuf },
+    /// run a server and two clients pressing random keys without a window, failing if any
+    /// history keeps growing or predictions drift
+    Soak {
+        #[arg(long, default_value_t = 10.0)]
+        minutes: f32,
+
+        /// round trip, in milliseconds
+        #[arg(long, default_value_t = 100.0)]
+        latency_ms: f32,
+
+        /// how much later than that any one packet can arrive, in milliseconds
+        #[arg(long, default_value_t = 0.0)]
+        jitter_ms: f32,
+
+        /// the chance of any one packet being dropped
+        #[arg(long, default_value_t = 0.05)]
+        loss: f32,
     },
 }
 
 fn main() {
     let mut app = App::new();
 
+    let settings = settings::Settings::load();
+    app.insert_resource(localization::Localization::load(&settings.locale));
+    app.insert_resource(settings);
+
     match Cli::try_parse() {
-        Ok(Cli::SinglePlayer) => {
+        Ok(Cli::SinglePlayer {
+            practice: true,
+            simulate_latency,
+            simulate_jitter,
+            simulate_loss,
+            ..
+        }) => {
+            println!("Starting practice game");
+            // the panel starts from these and can change them from there
+            let defaults = practice::NetConditions::default();
+            app.insert_resource(practice::NetConditions {
+                latency_ms: simulate_latency.unwrap_or(defaults.latency_ms),
+                jitter_ms: simulate_jitter.unwrap_or(defaults.jitter_ms),
+                loss: simulate_loss.unwrap_or(defaults.loss),
+                ..defaults
+            });
+            // the address is never used, practice mode brings its own connection
+            app.insert_resource(ClientSettings {
+                address: Ipv4Addr::LOCALHOST.into(),
+                port: DEFAULT_PORT,
+                input_send_interval: 1,
+                timeout_seconds: DEFAULT_TIMEOUT_SECONDS,
+                unstable_after: DEFAULT_UNSTABLE_AFTER,
+            });
+            app.add_plugins(DefaultPlugins.set(WindowPlugin {
+                primary_window: Some(Window {
+                    //present_mode: PresentMode::Immediate,
+                    ..default()
+                }),
+                ..default()
+            }));
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+            app.add_plugins(stats::FpsCounterPlugin);
+            app.add_plugins(settings::SettingsPlugin);
+            app.add_plugins(sound::SoundPlugin);
+            app.add_plugins(input::InputPlugin);
+            app.add_plugins(controls::ControlsPlugin);
+            app.add_plugins(practice::PracticePlugin);
+            app.add_plugins(client::ClientPlugin);
+            app.add_plugins(loading::LoadingPlugin);
+            app.add_plugins(interpolation::InterpolationPlugin);
+            app.add_plugins(server_time::ServerTimePlugin);
+            app.add_plugins(stats::NetStatsPlugin);
+            app.add_plugins(action::ActionPlugin);
+            app.add_plugins(GameplayPlugins);
+            app.add_plugins(status_effect::StatusEffectPlugin);
+            app.add_plugins(RenetClientPlugin);
+            app.add_plugins(visuals::VisualsPlugin);
+            app.add_plugins(cosmetics::CosmeticsPlugin);
+            app.add_plugins(nameplate::NameplatePlugin);
+            app.add_plugins(origin::FloatingOriginPlugin);
+            app.add_plugins(tracer::TracerPlugin);
+            app.add_plugins(palette::PalettePlugin);
+        }
+
+        Ok(Cli::SinglePlayer { split_screen, .. }) => {
             println!("Starting single player game");
             app.add_plugins(DefaultPlugins.set(WindowPlugin {
                 primary_window: Some(Window {
@@ -64,23 +384,107 @@ fn main() {
             }));
             app.add_plugins(FrameTimeDiagnosticsPlugin::default());
             app.add_plugins(stats::FpsCounterPlugin);
+            app.add_plugins(settings::SettingsPlugin);
+            app.add_plugins(sound::SoundPlugin);
             app.add_plugins(input::InputPlugin);
+            app.add_plugins(controls::ControlsPlugin);
+            // everything a listen server has, the server itself only starts when opened to LAN
+            app.add_plugins(server::ServerPlugin);
+            app.add_plugins(admin::AdminPlugin);
+            app.add_plugins(anticheat::AntiCheatPlugin);
+            app.add_plugins(GameplayPlugins);
+            app.add_plugins(status_effect::StatusEffectPlugin);
+            app.add_plugins(RenetServerPlugin);
+            app.add_plugins(NetcodeServerPlugin);
             app.add_systems(Startup, spawn_authority_character_system);
             app.insert_resource(LocalPlayer {
                 client_id: ClientId::from_raw(0),
             });
-            app.add_systems(Update, spawn_character_visuals_system);
-            app.add_systems(
-                Update,
-                (extrapolate_player_visuals_system, camera_system).chain(),
-            );
-            app.add_systems(FixedPostUpdate, post_fixed_player_visuals_system);
+            app.add_plugins(visuals::VisualsPlugin);
+            app.add_plugins(cosmetics::CosmeticsPlugin);
+            app.add_plugins(nameplate::NameplatePlugin);
+            app.add_plugins(origin::FloatingOriginPlugin);
+            app.add_plugins(tracer::TracerPlugin);
+            app.add_plugins(palette::PalettePlugin);
+            app.add_plugins(pause::PausePlugin);
+            if split_screen {
+                app.add_plugins(split_screen::SplitScreenPlugin);
+            }
         }
 
-        Ok(Cli::DedicatedServer { port }) => {}
+        Ok(Cli::DedicatedServer {
+            bind,
+            port,
+            demo_dir,
+            client_bandwidth,
+            afk_timeout,
+            afk_action,
+            motd,
+            cheat_kick_score,
+            mutators,
+        }) => {
+            println!("Starting dedicated server on port {}", port);
+            app.insert_resource(Mutators::from_list(&mutators));
+            app.insert_resource(ServerSettings {
+                bind,
+                port,
+                client_bandwidth,
+                demo_dir,
+                afk_timeout,
+                afk_action,
+                snapshot_interval: 1,
+                motd,
+                cheat_kick_score,
+            });
+            // no window to wait on, so the runner sleeps out the rest of each tick itself
+            app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
+                Duration::from_secs_f64(1.0 / practice::FIXED_HZ),
+            )));
+            practice::add_headless_server_plugins(&mut app);
+            app.add_plugins(demo::DemoRecordingPlugin);
+            app.add_plugins(admin::AdminPlugin);
+            app.add_plugins(host_stats::HostStatsPlugin);
+            app.add_plugins(NetcodeServerPlugin);
+            match Map::load(Path::new(MAP_PATH)) {
+                Ok(map) => {
+                    app.insert_resource(map);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load map {}: {}", MAP_PATH, e);
+                    std::process::exit(1);
+                }
+            }
+            // nothing below is needed without anything to draw
+            app.run();
+            return;
+        }
 
-        Ok(Cli::ListenServer { port }) => {
-            app.insert_resource(ServerSettings { port });
+        Ok(Cli::ListenServer {
+            bind,
+            port,
+            demo_dir,
+            client_bandwidth,
+            afk_timeout,
+            afk_action,
+            motd,
+            cheat_kick_score,
+            mutators,
+            split_screen,
+            class,
+        }) => {
+            app.insert_resource(Mutators::from_list(&mutators));
+            app.insert_resource(class::ClassRequest(class));
+            app.insert_resource(ServerSettings {
+                bind,
+                port,
+                client_bandwidth,
+                demo_dir,
+                afk_timeout,
+                afk_action,
+                snapshot_interval: 1,
+                motd,
+                cheat_kick_score,
+            });
             app.add_plugins(DefaultPlugins.set(WindowPlugin {
                 primary_window: Some(Window {
                     //present_mode: PresentMode::Immediate,
@@ -90,21 +494,72 @@ fn main() {
             }));
             app.add_plugins(FrameTimeDiagnosticsPlugin::default());
             app.add_plugins(stats::FpsCounterPlugin);
+            app.add_plugins(settings::SettingsPlugin);
+            app.add_plugins(sound::SoundPlugin);
             app.add_plugins(input::InputPlugin);
+            app.add_plugins(controls::ControlsPlugin);
             app.add_plugins(server::ServerPlugin);
+            app.add_plugins(demo::DemoRecordingPlugin);
+            app.add_plugins(admin::AdminPlugin);
+            app.add_plugins(anticheat::AntiCheatPlugin);
+            app.add_plugins(GameplayPlugins);
+            app.add_plugins(status_effect::StatusEffectPlugin);
             app.add_plugins(RenetServerPlugin);
             app.add_plugins(NetcodeServerPlugin);
             app.add_systems(Startup, spawn_authority_character_system);
-            app.add_systems(Update, spawn_character_visuals_system);
-            app.add_systems(
-                Update,
-                (extrapolate_player_visuals_system, camera_system).chain(),
-            );
-            app.add_systems(FixedPostUpdate, post_fixed_player_visuals_system);
+            app.insert_resource(LocalPlayer {
+                client_id: ClientId::from_raw(0),
+            });
+            app.add_plugins(visuals::VisualsPlugin);
+            app.add_plugins(cosmetics::CosmeticsPlugin);
+            app.add_plugins(nameplate::NameplatePlugin);
+            app.add_plugins(origin::FloatingOriginPlugin);
+            app.add_plugins(tracer::TracerPlugin);
+            app.add_plugins(palette::PalettePlugin);
+            app.add_plugins(pause::PausePlugin);
+            if split_screen {
+                app.add_plugins(split_screen::SplitScreenPlugin);
+            }
         }
 
-        Ok(Cli::Client { ip, port }) => {
-            app.insert_resource(ClientSettings { address: ip, port });
+        Ok(Cli::Client {
+            ip,
+            port,
+            input_send_interval,
+            timeout,
+            unstable_after,
+            room,
+            interpolation_delay_ms,
+            class,
+            hat,
+            trail,
+            name,
+        }) => {
+            let server_addr = match client::resolve_server(&ip, port) {
+                Ok(server_addr) => server_addr,
+                Err(e) => {
+                    eprintln!("Failed to find server {}: {}", ip, e);
+                    std::process::exit(1);
+                }
+            };
+            app.insert_resource(ClientSettings {
+                address: server_addr.ip(),
+                port,
+                input_send_interval,
+                timeout_seconds: timeout.max(0),
+                unstable_after,
+            });
+            if let Some(room) = room {
+                app.insert_resource(room::RoomRequest(room));
+            }
+            app.insert_resource(class::ClassRequest(class));
+            app.insert_resource(cosmetics::CosmeticsRequest(Cosmetics { hat, trail }));
+            if let Some(name) = name {
+                app.insert_resource(nameplate::NameRequest(name));
+            }
+            app.insert_resource(interpolation::InterpolationDelay(
+                interpolation_delay_ms.max(0.0) / 1000.0,
+            ));
             app.add_plugins(DefaultPlugins.set(WindowPlugin {
                 primary_window: Some(Window {
                     //present_mode: PresentMode::Immediate,
@@ -114,16 +569,75 @@ fn main() {
             }));
             app.add_plugins(FrameTimeDiagnosticsPlugin::default());
             app.add_plugins(stats::FpsCounterPlugin);
+            app.add_plugins(settings::SettingsPlugin);
+            app.add_plugins(sound::SoundPlugin);
             app.add_plugins(input::InputPlugin);
+            app.add_plugins(controls::ControlsPlugin);
             app.add_plugins(client::ClientPlugin);
+            app.add_plugins(loading::LoadingPlugin);
+            app.add_plugins(interpolation::InterpolationPlugin);
+            app.add_plugins(server_time::ServerTimePlugin);
+            app.add_plugins(stats::NetStatsPlugin);
+            app.add_plugins(action::ActionPlugin);
+            app.add_plugins(GameplayPlugins);
+            app.add_plugins(status_effect::StatusEffectPlugin);
             app.add_plugins(RenetClientPlugin);
             app.add_plugins(NetcodeClientPlugin);
-            app.add_systems(Update, spawn_character_visuals_system);
-            app.add_systems(
-                Update,
-                (extrapolate_player_visuals_system, camera_system).chain(),
-            );
-            app.add_systems(FixedPostUpdate, post_fixed_player_visuals_system);
+            app.add_plugins(visuals::VisualsPlugin);
+            app.add_plugins(cosmetics::CosmeticsPlugin);
+            app.add_plugins(nameplate::NameplatePlugin);
+            app.add_plugins(origin::FloatingOriginPlugin);
+            app.add_plugins(tracer::TracerPlugin);
+            app.add_plugins(palette::PalettePlugin);
+        }
+
+        Ok(Cli::Demo { path }) => {
+            app.insert_resource(DemoSettings { path });
+            app.add_plugins(DefaultPlugins.set(WindowPlugin {
+                primary_window: Some(Window {
+                    //present_mode: PresentMode::Immediate,
+                    ..default()
+                }),
+                ..default()
+            }));
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+            app.add_plugins(stats::FpsCounterPlugin);
+            app.add_plugins(settings::SettingsPlugin);
+            app.add_plugins(sound::SoundPlugin);
+            app.add_plugins(demo::DemoPlaybackPlugin);
+            app.insert_resource(LocalPlayer {
+                client_id: ClientId::from_raw(0),
+            });
+            app.add_plugins(visuals::VisualsPlugin);
+            app.add_plugins(cosmetics::CosmeticsPlugin);
+            app.add_plugins(nameplate::NameplatePlugin);
+            app.add_plugins(origin::FloatingOriginPlugin);
+            app.add_plugins(palette::PalettePlugin);
+        }
+
+        Ok(Cli::Soak {
+            minutes,
+            latency_ms,
+            jitter_ms,
+            loss,
+        }) => {
+            let locale = app.world.resource::<settings::Settings>().locale.clone();
+            let conditions = practice::NetConditions {
+                latency_ms,
+                jitter_ms,
+                loss,
+                snapshot_interval: 1,
+            };
+            match soak::run(&locale, minutes, &conditions) {
+                Ok(()) => {
+                    println!("Soak test passed");
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Soak test failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
         }
 
         Err(e) => {
@@ -132,16 +646,25 @@ fn main() {
         }
     }
 
+    match Map::load(Path::new(MAP_PATH)) {
+        Ok(map) => {
+            app.insert_resource(map);
+        }
+        Err(e) => {
+            eprintln!("Failed to load map {}: {}", MAP_PATH, e);
+            std::process::exit(1);
+        }
+    }
+
     app.add_systems(Startup, setup_level);
+    app.add_systems(Update, spawn_map_geometry.run_if(resource_changed::<Map>));
     app.insert_resource(WinitSettings {
         focused_mode: bevy::winit::UpdateMode::Continuous,
         unfocused_mode: bevy::winit::UpdateMode::Continuous,
     });
     app.insert_resource(Time::<Fixed>::from_hz(64.0));
-    app.insert_resource(LastPhysicsUpdate {
-        time: std::time::Instant::now(),
-    });
     app.add_event::<SpawnCharacterVisualsEvent>();
+    app.add_event::<HardSnapEvent>();
     app.run();
 }
 
@@ -154,15 +677,18 @@ fn setup_level(
     println!("Setting up level");
 
     let ground_texture = asset_server.load("texture_04.png");
+    commands.insert_resource(LevelAssets {
+        ground_texture: ground_texture.clone(),
+    });
 
     // floor
     commands.spawn(PbrBundle {
-        mesh: meshes.add(Cuboid::new(20.0, 0.1, 20.0)),
+        mesh: meshes.add(Cuboid::new(FLOOR_SIZE, FLOOR_THICKNESS, FLOOR_SIZE)),
         material: materials.add(StandardMaterial {
             base_color_texture: Some(ground_texture.clone()),
             ..default()
         }),
-        transform: Transform::from_xyz(0.0, -0.5, 0.0),
+        transform: Transform::from_xyz(0.0, FLOOR_Y, 0.0),
         ..default()
     });
 
@@ -179,19 +705,108 @@ fn setup_level(
     // camera
     commands.spawn(Camera3dBundle {
         transform: Transform::from_xyz(-2.5, 4.5, 9.0),
+        projection: Projection::Perspective(PerspectiveProjection {
+            fov: FIELD_OF_VIEW,
+            ..default()
+        }),
         ..default()
     });
 }
 
+/// everything the level is drawn with, loaded in the background from startup on
+#[derive(Resource)]
+struct LevelAssets {
+    ground_texture: Handle<Image>,
+}
+
+/// what the map puts on the floor, the floor itself stays the same whichever map is loaded
+#[derive(Component)]
+struct MapGeometry;
+
+/// runs again whenever the map changes, after clearing out the old one
+fn spawn_map_geometry(
+    map: Res<Map>,
+    origin: Res<origin::RenderOrigin>,
+    geometry: Query<Entity, With<MapGeometry>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    for entity in geometry.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // launch volumes
+    for launch_volume in &map.launch_volumes {
+        commands.spawn((
+            MapGeometry,
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(
+                    launch_volume.half_extents.x * 2.0,
+                    FLOOR_THICKNESS,
+                    launch_volume.half_extents.z * 2.0,
+                )),
+                material: materials.add(Color::rgb(0.9, 0.5, 0.0)),
+                transform: Transform::from_translation(origin.to_render(Vec3::new(
+                    launch_volume.center.x,
+                    FLOOR_Y + FLOOR_THICKNESS,
+                    launch_volume.center.z,
+                ))),
+                ..default()
+            },
+        ));
+    }
+
+    // teleporter pads, the same color at both ends
+    for teleporter in &map.teleporters {
+        for pad in teleporter.pads {
+            commands.spawn((
+                MapGeometry,
+                PbrBundle {
+                    mesh: meshes.add(Cuboid::new(
+                        teleporter.half_extents.x * 2.0,
+                        FLOOR_THICKNESS,
+                        teleporter.half_extents.z * 2.0,
+                    )),
+                    material: materials.add(Color::rgb(0.4, 0.2, 0.9)),
+                    transform: Transform::from_translation(origin.to_render(Vec3::new(
+                        pad.x,
+                        FLOOR_Y + FLOOR_THICKNESS,
+                        pad.z,
+                    ))),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    // walls
+    for wall in &map.walls {
+        commands.spawn((
+            MapGeometry,
+            PbrBundle {
+                mesh: meshes.add(Cuboid {
+                    half_size: wall.half_extents,
+                }),
+                material: materials.add(Color::rgb(0.5, 0.5, 0.55)),
+                transform: Transform::from_translation(origin.to_render(wall.center)),
+                ..default()
+            },
+        ));
+    }
+}
+
 fn spawn_character(
     owner_client_id: ClientId,
+    color: PlayerColor,
     event: &mut EventWriter<SpawnCharacterVisualsEvent>,
     commands: &mut Commands,
     translation: Vec3,
     velocity: Vec3,
-) {
+) -> Entity {
     let entity = commands
         .spawn((
+            NetworkId(owner_client_id.raw()),
             Character {
                 owner_client_id,
                 move_friction: CHARACTER_FRICTION,
@@ -200,7 +815,14 @@ fn spawn_character(
                 velocity: velocity,
                 pitch: 0.0,
                 yaw: 0.0,
+                teleport_count: 0,
+                aiming: false,
+                status_effects: Vec::new(),
+                mantle: None,
             },
+            ViewAngles::default(),
+            Health(MAX_HEALTH),
+            color,
             TransformBundle {
                 global: GlobalTransform::from_translation(translation),
                 ..default()
@@ -212,132 +834,17 @@ fn spawn_character(
         translation,
         entity,
         owner_client_id,
+        color,
     });
+
+    entity
 }
 
-fn spawn_authority_character_system(
-    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
-    mut commands: Commands,
-) {
-    spawn_character(
+fn spawn_authority_character_system(mut spawner: server::NetSpawner) {
+    spawner.spawn_character(
         ClientId::from_raw(0),
-        &mut spawn_visuals,
-        &mut commands,
+        PlayerColor(0),
         Vec3::ZERO,
         Vec3::ZERO,
     );
 }
-
-fn spawn_character_visuals_system(
-    mut spawn_visuals: EventReader<SpawnCharacterVisualsEvent>,
-    mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-) {
-    for event in spawn_visuals.read() {
-        if commands.get_entity(event.entity).is_some() {
-            commands.spawn((
-                CharacterVisuals {
-                    owner_client_id: event.owner_client_id,
-                    character_entity: event.entity,
-                    last_physics_translation: event.translation,
-                },
-                PbrBundle {
-                    mesh: meshes.add(Cuboid::new(0.465, CHARACTER_HEIGHT * 2.0, 0.465)),
-                    material: materials.add(Color::rgb(0.0, 0.0, 0.5)),
-                    transform: Transform::from_translation(event.translation),
-                    ..default()
-                },
-            ));
-        }
-    }
-}
-
-fn compute_physics_interpolation_fraction(
-    fixed_time: &Res<Time<Fixed>>,
-    last_physics_update: Instant,
-) -> f32 {
-    let time_since_last_physics_update = Instant::now() - last_physics_update;
-    ((time_since_last_physics_update.as_secs_f64() / fixed_time.delta_seconds_f64()) as f32)
-        .clamp(0.0, 1.0)
-}
-
-fn extrapolate_player_visuals_system(
-    fixed_time: Res<Time<Fixed>>,
-    last_physics_update: Res<LastPhysicsUpdate>,
-    mut visuals: Query<(&CharacterVisuals, &mut Transform)>,
-    characters: Query<&Character>,
-) {
-    for (visuals, mut visuals_transform) in visuals.iter_mut() {
-        if let Ok(character) = characters.get(visuals.character_entity) {
-            let fraction =
-                compute_physics_interpolation_fraction(&fixed_time, last_physics_update.time);
-            if character.velocity.is_finite() {
-                visuals_transform.translation = visuals.last_physics_translation
-                    + character.velocity * fixed_time.delta_seconds() * fraction;
-            }
-        }
-    }
-}
-
-fn post_fixed_player_visuals_system(
-    local_player: Res<LocalPlayer>,
-    mut last_physics_update: ResMut<LastPhysicsUpdate>,
-    characters: Query<(&Character, &Transform)>,
-    mut visuals: Query<(&mut CharacterVisuals, &Transform), Without<Character>>,
-) {
-    for (mut visuals, visuals_transform) in visuals.iter_mut() {
-        if let Ok((character, character_transform)) = characters.get(visuals.character_entity) {
-            // simulated characters ("we" aren't controlling these, just observing)
-            if character.owner_client_id != local_player.client_id {
-                visuals.last_physics_translation = character_transform.translation;
-            }
-            // owned characters ("we" are controlling these)
-            else {
-                // if we're the server player, we can just use the physics translation
-                if local_player.is_authority() {
-                    visuals.last_physics_translation = character_transform.translation;
-                }
-                // if we're a client and this is our character
-                else {
-                    let diff = visuals_transform
-                        .translation
-                        .distance(character_transform.translation);
-                    if diff > SMOOTH_CORRECTION_DISTANCE_THRESHOLD {
-                        let step_scale = (SMOOTH_CORRECTION_DISTANCE_THRESHOLD - diff).max(0.0)
-                            / SMOOTH_CORRECTION_DISTANCE_THRESHOLD;
-                        let dynamic_step = SMOOTH_CORRECTION_STEP_MIN
-                            + (SMOOTH_CORRECTION_STEP_MAX - SMOOTH_CORRECTION_STEP_MIN)
-                                * step_scale;
-                        visuals.last_physics_translation = visuals_transform
-                            .translation
-                            .lerp(character_transform.translation, dynamic_step);
-                    } else {
-                        visuals.last_physics_translation = character_transform.translation;
-                    }
-                }
-            }
-        }
-    }
-    last_physics_update.time = Instant::now();
-}
-
-fn camera_system(
-    local_player: Res<LocalPlayer>,
-    characters: Query<&Character>,
-    visuals: Query<(&CharacterVisuals, &Transform)>,
-    mut camera: Query<&mut Transform, (With<Camera>, Without<CharacterVisuals>)>,
-) {
-    for mut camera_transform in camera.iter_mut() {
-        for (visuals, visuals_transform) in visuals.iter() {
-            if visuals.owner_client_id == local_player.client_id {
-                if let Ok(character) = characters.get(visuals.character_entity) {
-                    camera_transform.rotation =
-                        Quat::from_euler(EulerRot::YXZ, character.yaw, character.pitch, 0.0);
-                    camera_transform.translation =
-                        visuals_transform.translation + Vec3::new(0.0, CHARACTER_HEIGHT, 0.0);
-                }
-            }
-        }
-    }
-}
@@ -0,0 +1,998 @@
+use bevy::prelude::*;
+use rapier3d::{
+    control::{CharacterLength, KinematicCharacterController},
+    geometry::{Collider, ColliderBuilder, ColliderSet, Cuboid},
+    math::{Isometry, Vector},
+    pipeline::{QueryFilter, QueryPipeline},
+    prelude::RigidBodySet,
+};
+use serde::Deserialize;
+use std::{f32::consts::TAU, path::Path, sync::OnceLock};
+
+const MAPS_DIR: &str = "assets/maps";
+/// the height characters stand at on the floor itself
+pub const GROUND_LEVEL: f32 = 0.0;
+/// how far shots reach
+pub const SHOT_RANGE: f32 = 50.0;
+/// how much of its damage a shot keeps going through a wall, and bouncing off one
+const PENETRATION_DAMAGE: f32 = 0.5;
+const RICOCHET_DAMAGE: f32 = 0.5;
+/// straight stretches a shot is followed for, walls it goes through or off count one each
+const MAX_SHOT_LEGS: usize = 4;
+
+/// everything about the level that affects movement or cover, loaded by the server and every client
+/// from the same file so prediction agrees with the server
+#[derive(Resource, Deserialize, Default)]
+pub struct Map {
+    /// the file it came from without the extension, what servers and clients call it by
+    #[serde(skip)]
+    pub name: String,
+    pub launch_volumes: Vec<LaunchVolume>,
+    #[serde(default)]
+    pub walls: Vec<Wall>,
+    #[serde(default)]
+    pub lasers: Vec<Laser>,
+    #[serde(default)]
+    pub fire_zones: Vec<FireZone>,
+    #[serde(default)]
+    pub teleporters: Vec<Teleporter>,
+    /// the floor and walls characters run into, built the first time anything moves
+    #[serde(skip)]
+    colliders: OnceLock<Colliders>,
+}
+
+/// what rapier's character controller moves characters against, the floor and the walls that
+/// block players as boxes with their index into `Map::walls` as user data
+#[derive(Default)]
+struct Colliders {
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    queries: QueryPipeline,
+}
+
+/// a beam from `start` to `end` that sweeps out to `sweep` further along and back every
+/// `period` seconds. where it is depends only on the time, so nothing about it is sent
+#[derive(Deserialize, Clone)]
+pub struct Laser {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub sweep: Vec3,
+    pub period: f32,
+}
+
+impl Laser {
+    /// the beam's ends at `seconds` of world time, easing in and out at either side
+    pub fn segment_at(&self, seconds: f32) -> (Vec3, Vec3) {
+        let phase = if self.period > 0.0 {
+            (seconds / self.period).fract()
+        } else {
+            0.0
+        };
+        let offset = self.sweep * (0.5 - 0.5 * (phase * TAU).cos());
+        (self.start + offset, self.end + offset)
+    }
+
+    pub fn touches(&self, seconds: f32, point: Vec3, radius: f32) -> bool {
+        let (start, end) = self.segment_at(seconds);
+        let beam = end - start;
+        let along = if beam.length_squared() > 0.0 {
+            ((point - start).dot(beam) / beam.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        point.distance(start + beam * along) <= radius
+    }
+}
+
+/// burns for the first `active` seconds of every `period`, starting `offset` seconds in
+#[derive(Deserialize, Clone)]
+pub struct FireZone {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    pub period: f32,
+    pub active: f32,
+    #[serde(default)]
+    pub offset: f32,
+}
+
+impl FireZone {
+    pub fn is_active(&self, seconds: f32) -> bool {
+        if self.period <= 0.0 {
+            return true;
+        }
+        (seconds + self.offset).rem_euclid(self.period) < self.active
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        let offset = (point - self.center).abs();
+        offset.x <= self.half_extents.x
+            && offset.y <= self.half_extents.y
+            && offset.z <= self.half_extents.z
+    }
+}
+
+/// what something moving through the level is, as far as what stops it goes
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    /// characters moving, standing and climbing
+    Players,
+    /// loose things in the level, there aren't any yet
+    Props,
+    /// shots, and explosions reaching whoever is behind cover
+    Projectiles,
+    /// whatever sets off launch volumes, teleporters and the like
+    Triggers,
+}
+
+/// a set of layers, written as a list of them in map files
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(from = "Vec<Layer>")]
+pub struct LayerMask(u8);
+
+impl LayerMask {
+    pub const ALL: Self = Self(0b1111);
+
+    pub fn contains(self, layer: Layer) -> bool {
+        self.0 & (1 << layer as u8) != 0
+    }
+}
+
+impl From<Vec<Layer>> for LayerMask {
+    fn from(layers: Vec<Layer>) -> Self {
+        Self(
+            layers
+                .iter()
+                .fold(0, |mask, layer| mask | 1 << *layer as u8),
+        )
+    }
+}
+
+/// solid to everything unless a map says otherwise
+impl Default for LayerMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// what a wall is made of, as far as shots go
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Surface {
+    /// stops every shot
+    #[default]
+    Concrete,
+    /// thin enough planks of it are shot through
+    Wood,
+    /// too hard to shoot through, but shots coming in flat glance off it
+    Metal,
+}
+
+impl Surface {
+    /// the thickest wall of it a shot goes through
+    fn penetration(self) -> f32 {
+        match self {
+            Self::Concrete | Self::Metal => 0.0,
+            Self::Wood => 0.3,
+        }
+    }
+
+    /// shots coming in flatter than this glance off, as the sine of their angle to the surface
+    fn ricochet_below(self) -> f32 {
+        match self {
+            Self::Concrete | Self::Wood => 0.0,
+            Self::Metal => 0.5,
+        }
+    }
+}
+
+/// how far a character is kept off a wall it ran into, so it's clearly outside on the next move
+const SKIN: f32 = 0.001;
+/// the floor's collider, wider than anywhere a map goes and too deep to fall through in a tick
+const FLOOR_HALF_WIDTH: f32 = 10_000.0;
+const FLOOR_DEPTH: f32 = 10.0;
+/// the floor's user data, past the index of any wall
+const FLOOR: u128 = u128::MAX;
+
+/// cover from explosions, something to stand on and something to run into. a fence shots go
+/// through only blocks `[Players]`, so does a clip brush that keeps players out of somewhere
+#[derive(Deserialize, Clone)]
+pub struct Wall {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    /// the layers it stops, all of them if the map doesn't say
+    #[serde(default)]
+    pub blocks: LayerMask,
+    /// concrete if the map doesn't say
+    #[serde(default)]
+    pub surface: Surface,
+}
+
+impl Wall {
+    /// whether the segment from `from` to `to` passes through the wall, touching counts
+    pub fn blocks(&self, from: Vec3, to: Vec3) -> bool {
+        self.crossing(from, to).is_some()
+    }
+
+    /// which way the face `point`, somewhere on the surface, is on faces
+    fn normal_at(&self, point: Vec3) -> Vec3 {
+        let offset = (point - self.center) / self.half_extents;
+        let axis = if offset.x.abs() >= offset.y.abs() && offset.x.abs() >= offset.z.abs() {
+            0
+        } else if offset.y.abs() >= offset.z.abs() {
+            1
+        } else {
+            2
+        };
+        Vec3::AXES[axis] * offset[axis].signum()
+    }
+
+    /// how far along the segment, from 0 to 1, it goes into the wall and comes out again
+    fn crossing(&self, from: Vec3, to: Vec3) -> Option<(f32, f32)> {
+        let min = self.center - self.half_extents;
+        let max = self.center + self.half_extents;
+        let direction = to - from;
+
+        // the part of the segment inside the box, narrowed one axis at a time
+        let mut enter = 0.0f32;
+        let mut exit = 1.0f32;
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if from[axis] < min[axis] || from[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let near = (min[axis] - from[axis]) / direction[axis];
+            let far = (max[axis] - from[axis]) / direction[axis];
+            enter = enter.max(near.min(far));
+            exit = exit.min(near.max(far));
+            if enter > exit {
+                return None;
+            }
+        }
+        Some((enter, exit))
+    }
+
+    pub fn top(&self) -> f32 {
+        self.center.y + self.half_extents.y
+    }
+}
+
+/// a wall low enough to climb, as seen from where a character stands
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Ledge {
+    pub top: f32,
+    /// distances along the way the character faces to the near and far side of the wall
+    pub near: f32,
+    pub far: f32,
+}
+
+/// sets the velocity of any character standing in it, jump pads and the like
+#[derive(Deserialize, Clone)]
+pub struct LaunchVolume {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    pub launch_velocity: Vec3,
+}
+
+impl LaunchVolume {
+    pub fn contains(&self, point: Vec3) -> bool {
+        let offset = (point - self.center).abs();
+        offset.x <= self.half_extents.x
+            && offset.y <= self.half_extents.y
+            && offset.z <= self.half_extents.z
+    }
+}
+
+/// two pads the same size, walking onto either one puts a character on the other
+#[derive(Deserialize, Clone)]
+pub struct Teleporter {
+    pub pads: [Vec3; 2],
+    pub half_extents: Vec3,
+}
+
+impl Teleporter {
+    /// which of the two pads the point is on
+    pub fn pad_at(&self, point: Vec3) -> Option<usize> {
+        (0..2).find(|pad| {
+            let offset = (point - self.pads[*pad]).abs();
+            offset.x <= self.half_extents.x
+                && offset.y <= self.half_extents.y
+                && offset.z <= self.half_extents.z
+        })
+    }
+}
+
+impl Map {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut map: Self = ron::from_str(&text).map_err(|e| e.to_string())?;
+        map.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(map)
+    }
+
+    /// one of the maps in `assets/maps`. names come from players, so anything that could
+    /// reach outside that directory is refused
+    pub fn load_named(name: &str) -> Result<Self, String> {
+        if !is_valid_name(name) {
+            return Err(format!("invalid map name: {}", name));
+        }
+        Self::load(&Path::new(MAPS_DIR).join(format!("{}.ron", name)))
+    }
+
+    /// the map after `current` in `assets/maps`, in name order and back to the first after the
+    /// last. none if there's nothing else to go to
+    pub fn next_in_rotation(current: &str) -> Option<String> {
+        let mut names: Vec<String> = std::fs::read_dir(MAPS_DIR)
+            .ok()?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "ron" {
+                    return None;
+                }
+                let name = path.file_stem()?.to_str()?.to_string();
+                is_valid_name(&name).then_some(name)
+            })
+            .collect();
+        names.sort();
+        let next = match names.iter().position(|name| name == current) {
+            Some(index) => names[(index + 1) % names.len()].clone(),
+            None => names.first()?.clone(),
+        };
+        (next != current).then_some(next)
+    }
+
+    /// the first volume the point is in, overlapping volumes don't stack
+    pub fn launch_volume_at(&self, point: Vec3) -> Option<&LaunchVolume> {
+        self.launch_volumes
+            .iter()
+            .find(|launch_volume| launch_volume.contains(point))
+    }
+
+    /// the teleporter and which of its pads the point is on, the first one found
+    pub fn teleporter_pad_at(&self, point: Vec3) -> Option<(usize, usize)> {
+        self.teleporters
+            .iter()
+            .enumerate()
+            .find_map(|(index, teleporter)| teleporter.pad_at(point).map(|pad| (index, pad)))
+    }
+
+    /// the nearest wall within `reach` in `direction` (flat and normalized) whose top is above
+    /// `feet` by no more than `max_height`. how deep it is counts from wherever it's entered
+    pub fn ledge_ahead(
+        &self,
+        feet: Vec3,
+        direction: Vec3,
+        reach: f32,
+        max_height: f32,
+    ) -> Option<Ledge> {
+        self.walls_blocking(Layer::Players)
+            .filter(|wall| wall.top() > feet.y && wall.top() - feet.y <= max_height)
+            .filter_map(|wall| {
+                // just under the top, the part that has to be climbed over
+                let from = Vec3::new(feet.x, wall.top() - 0.01, feet.z);
+                let (enter, _) = wall.crossing(from, from + direction * reach)?;
+                // out the other side, however far that is
+                let through = wall.half_extents.length() * 2.0 + reach;
+                let (_, exit) = wall.crossing(from, from + direction * through)?;
+                Some(Ledge {
+                    top: wall.top(),
+                    near: enter * reach,
+                    far: exit * through,
+                })
+            })
+            .min_by(|a, b| a.near.total_cmp(&b.near))
+    }
+
+    /// the highest thing to stand on under the point, the floor or the top of a wall, but none
+    /// above `max_y`: a wall whose top is higher is being walked through, not stood on
+    pub fn ground_height(&self, point: Vec3, max_y: f32) -> f32 {
+        self.walls_blocking(Layer::Players)
+            .filter(|wall| {
+                let offset = (point - wall.center).abs();
+                offset.x <= wall.half_extents.x
+                    && offset.z <= wall.half_extents.z
+                    && wall.top() <= max_y
+            })
+            .map(|wall| wall.top())
+            .fold(GROUND_LEVEL, f32::max)
+    }
+
+    /// moves a body `radius` wide and `height` tall with its feet at `feet` by `motion`,
+    /// sliding along the sides of walls it runs into, with rapier's character controller as a
+    /// box against the floor and the walls that block players. it goes across first, where walls
+    /// no more than `step` over the feet don't get in the way, then up or down onto whatever it
+    /// comes down on, those walls included. returns where it ends up, the sides it touched, facing
+    /// out, and whether it's standing on something
+    pub fn slide(
+        &self,
+        feet: Vec3,
+        motion: Vec3,
+        radius: f32,
+        height: f32,
+        step: f32,
+    ) -> (Vec3, Vec<Vec3>, bool) {
+        let colliders = self.colliders.get_or_init(|| self.build_colliders());
+        let controller = KinematicCharacterController {
+            offset: CharacterLength::Absolute(SKIN),
+            autostep: None,
+            snap_to_ground: None,
+            ..default()
+        };
+        let shape = Cuboid::new(Vector::new(radius, height / 2.0, radius));
+        let mut touched = Vec::new();
+        let mut move_by = |from: Vec3, motion: Vec3, filter: QueryFilter| {
+            let movement = controller.move_shape(
+                // only moving platforms care how long the move took, there aren't any
+                0.0,
+                &colliders.bodies,
+                &colliders.colliders,
+                &colliders.queries,
+                &shape,
+                &Isometry::translation(from.x, from.y + height / 2.0, from.z),
+                Vector::new(motion.x, motion.y, motion.z),
+                filter,
+                |collision| {
+                    let normal = collision.toi.normal1;
+                    touched.push(Vec3::new(normal.x, normal.y, normal.z));
+                },
+            );
+            let moved = movement.translation;
+            (
+                from + Vec3::new(moved.x, moved.y, moved.z),
+                movement.grounded,
+            )
+        };
+
+        let in_the_way = |_, collider: &Collider| match self.wall_collided(collider) {
+            Some(wall) => wall.top() > feet.y + step,
+            None => false,
+        };
+        let (across, _) = move_by(
+            feet,
+            Vec3::new(motion.x, 0.0, motion.z),
+            QueryFilter::new().predicate(&in_the_way),
+        );
+
+        // a wall it's inside of doesn't stop it going up or down, one under or over it does
+        let under_or_over = |_, collider: &Collider| match self.wall_collided(collider) {
+            Some(wall) => {
+                wall.top() <= across.y + step
+                    || wall.center.y - wall.half_extents.y >= across.y + height
+            }
+            None => true,
+        };
+        let (end, grounded) = move_by(
+            across,
+            Vec3::new(0.0, motion.y, 0.0),
+            QueryFilter::new().predicate(&under_or_over),
+        );
+        (end, touched, grounded)
+    }
+
+    /// the wall a collider was built from, none for the floor
+    fn wall_collided(&self, collider: &Collider) -> Option<&Wall> {
+        self.walls.get(collider.user_data as usize)
+    }
+
+    fn build_colliders(&self) -> Colliders {
+        let mut colliders = Colliders::default();
+        colliders.colliders.insert(
+            ColliderBuilder::cuboid(FLOOR_HALF_WIDTH, FLOOR_DEPTH, FLOOR_HALF_WIDTH)
+                // sunk by the skin it keeps characters off it, so they stand right at ground level
+                .translation(Vector::new(0.0, GROUND_LEVEL - SKIN - FLOOR_DEPTH, 0.0))
+                .user_data(FLOOR),
+        );
+        for (index, wall) in self.walls.iter().enumerate() {
+            if !wall.blocks.contains(Layer::Players) {
+                continue;
+            }
+            let half_extents = wall.half_extents;
+            colliders.colliders.insert(
+                ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+                    .translation(Vector::new(wall.center.x, wall.center.y, wall.center.z))
+                    .user_data(index as u128),
+            );
+        }
+        colliders
+            .queries
+            .update(&colliders.bodies, &colliders.colliders);
+        colliders
+    }
+
+    /// nothing that stops projectiles between the two points
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        !self
+            .walls_blocking(Layer::Projectiles)
+            .any(|wall| wall.blocks(from, to))
+    }
+
+    /// every check against walls goes through here, with the layer of what's being checked
+    fn walls_blocking(&self, layer: Layer) -> impl Iterator<Item = &Wall> {
+        self.walls
+            .iter()
+            .filter(move |wall| wall.blocks.contains(layer))
+    }
+
+    /// where a shot from `origin` goes: straight on until a wall, then through it if it's thin
+    /// enough for what it's made of, off it if it came in flat enough, or nowhere. the server
+    /// and the shooter work it out from the same map, so tracers follow what the server sees
+    pub fn shot_path(&self, origin: Vec3, direction: Vec3) -> Vec<ShotLeg> {
+        let mut legs = Vec::new();
+        let mut start = origin;
+        let mut direction = direction.normalize_or_zero();
+        let mut range = SHOT_RANGE;
+        let mut damage = 1.0;
+        // the wall the leg starts on, coming out of it or off it isn't running into it again
+        let mut left_wall = None;
+        while range > 0.0 {
+            let end = start + direction * range;
+            let nearest = self
+                .walls
+                .iter()
+                .enumerate()
+                .filter(|(index, wall)| {
+                    wall.blocks.contains(Layer::Projectiles) && Some(*index) != left_wall
+                })
+                .filter_map(|(index, wall)| Some((index, wall, wall.crossing(start, end)?)))
+                .min_by(|(_, _, a), (_, _, b)| a.0.total_cmp(&b.0));
+            let (index, wall, (enter, exit)) = match nearest {
+                Some(nearest) => nearest,
+                None => {
+                    legs.push(ShotLeg { start, end, damage });
+                    break;
+                }
+            };
+            let hit = start + (end - start) * enter;
+            legs.push(ShotLeg {
+                start,
+                end: hit,
+                damage,
+            });
+            if legs.len() >= MAX_SHOT_LEGS {
+                break;
+            }
+
+            let normal = wall.normal_at(hit);
+            let thickness = (exit - enter) * range;
+            // 0 running along the surface, 1 going straight into it
+            let steepness = -direction.dot(normal);
+            if steepness < wall.surface.ricochet_below() {
+                direction -= 2.0 * direction.dot(normal) * normal;
+                start = hit + normal * SKIN;
+                range -= range * enter;
+                damage *= RICOCHET_DAMAGE;
+            } else if thickness <= wall.surface.penetration() {
+                start = start + (end - start) * exit;
+                range -= range * exit;
+                damage *= PENETRATION_DAMAGE;
+            } else {
+                break;
+            }
+            left_wall = Some(index);
+        }
+        legs
+    }
+
+    /// the first of `targets`, spheres as a center and radius, that a shot from `origin` hits
+    /// along its path
+    pub fn shot_hit<T: Copy>(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        targets: impl IntoIterator<Item = (T, Vec3, f32)>,
+    ) -> Option<ShotHit<T>> {
+        self.shot_hit_beyond(origin, direction, targets, 0.0)
+    }
+
+    /// the same, leaving out the first `from` meters of the path. a projectile that's already
+    /// been there doesn't hit whoever steps in behind it
+    pub fn shot_hit_beyond<T: Copy>(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        targets: impl IntoIterator<Item = (T, Vec3, f32)>,
+        from: f32,
+    ) -> Option<ShotHit<T>> {
+        let targets: Vec<(T, Vec3, f32)> = targets.into_iter().collect();
+        let mut travelled = 0.0;
+        self.shot_path(origin, direction)
+            .into_iter()
+            .find_map(|leg| {
+                let length = leg.start.distance(leg.end);
+                let direction = (leg.end - leg.start).normalize_or_zero();
+                let hit = targets
+                    .iter()
+                    .filter_map(|(target, center, radius)| {
+                        // how far along the leg it comes closest, and how close
+                        let along = (*center - leg.start).dot(direction);
+                        let miss_squared = (*center - leg.start).length_squared() - along * along;
+                        if along < 0.0 || miss_squared > radius * radius {
+                            return None;
+                        }
+                        let reach = (radius * radius - miss_squared).sqrt();
+                        // where it goes in, or where the path picks up if that's inside it
+                        let distance = (along - reach).max(from - travelled).max(0.0);
+                        (distance <= length && distance <= along + reach)
+                            .then_some((*target, distance))
+                    })
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(target, distance)| ShotHit {
+                        target,
+                        distance: travelled + distance,
+                        damage: leg.damage,
+                    });
+                travelled += length;
+                hit
+            })
+    }
+}
+
+/// what a shot hit first
+#[derive(Clone, Copy, Debug)]
+pub struct ShotHit<T> {
+    pub target: T,
+    /// how far the shot went to get there, through and off walls included
+    pub distance: f32,
+    /// the fraction of the shot's damage left there
+    pub damage: f32,
+}
+
+/// a straight stretch of a shot's path
+#[derive(Clone, Copy, Debug)]
+pub struct ShotLeg {
+    pub start: Vec3,
+    pub end: Vec3,
+    /// the fraction of the shot's damage left along it
+    pub damage: f32,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// about a character's size
+    const TARGET_RADIUS: f32 = 0.4;
+
+    fn map_with_wall() -> Map {
+        Map {
+            walls: vec![Wall {
+                center: Vec3::new(0.0, 0.5, 0.0),
+                half_extents: Vec3::new(2.0, 1.0, 0.25),
+                blocks: LayerMask::ALL,
+                surface: Surface::Concrete,
+            }],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn walls_stop_characters_and_let_them_slide_along() {
+        let map = map_with_wall();
+        let feet = Vec3::new(0.0, 0.0, -2.0);
+
+        let (stopped, touched, _) = map.slide(feet, Vec3::new(0.0, 0.0, 3.0), 0.3, 0.7, 0.05);
+        assert!(
+            stopped.z < -0.55 && stopped.z > -0.56,
+            "stopped at {}",
+            stopped
+        );
+        assert_eq!(touched.len(), 1);
+        assert!(
+            touched[0].abs_diff_eq(Vec3::NEG_Z, 1e-4),
+            "touched {}",
+            touched[0]
+        );
+
+        let (slid, _, _) = map.slide(feet, Vec3::new(1.0, 0.0, 3.0), 0.3, 0.7, 0.05);
+        assert!((slid.x - 1.0).abs() < 1e-4, "slid to {}", slid);
+        assert!(slid.z < -0.55);
+
+        // beside it, and already inside it, nothing's in the way
+        let beside = Vec3::new(3.0, 0.0, -2.0);
+        let (past, touched, _) = map.slide(beside, Vec3::new(0.0, 0.0, 4.0), 0.3, 0.7, 0.05);
+        assert_eq!(past, beside + Vec3::new(0.0, 0.0, 4.0));
+        assert!(touched.is_empty());
+        let (out, _, _) = map.slide(Vec3::ZERO, Vec3::new(0.0, 0.0, 1.0), 0.3, 0.7, 0.05);
+        assert_eq!(out, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn walls_below_the_step_or_the_feet_are_walked_over() {
+        let map = map_with_wall();
+        // standing on top of it
+        let on_top = Vec3::new(0.0, 1.5, -2.0);
+        let (moved, touched, _) = map.slide(on_top, Vec3::new(0.0, 0.0, 3.0), 0.3, 0.7, 0.05);
+        assert_eq!(moved, on_top + Vec3::new(0.0, 0.0, 3.0));
+        assert!(touched.is_empty());
+    }
+
+    #[test]
+    fn falling_lands_on_the_floor_and_on_walls() {
+        let map = map_with_wall();
+        let (landed, touched, grounded) =
+            map.slide(Vec3::new(0.0, 0.5, -2.0), Vec3::NEG_Y, 0.3, 0.7, 0.05);
+        assert!(
+            (landed.y - GROUND_LEVEL).abs() < 0.01,
+            "landed at {}",
+            landed
+        );
+        assert!(grounded);
+        assert!(
+            touched[0].abs_diff_eq(Vec3::Y, 1e-2),
+            "touched {}",
+            touched[0]
+        );
+
+        let (landed, _, grounded) =
+            map.slide(Vec3::new(0.0, 2.0, 0.0), Vec3::NEG_Y, 0.3, 0.7, 0.05);
+        assert!(landed.y >= 1.5 && landed.y < 1.51, "landed at {}", landed);
+        assert!(grounded);
+
+        // in the air the whole way
+        let (_, touched, grounded) =
+            map.slide(Vec3::new(0.0, 3.0, -2.0), Vec3::NEG_Y, 0.3, 0.7, 0.05);
+        assert!(!grounded);
+        assert!(touched.is_empty());
+    }
+
+    #[test]
+    fn wall_blocks_what_is_behind_it() {
+        let map = map_with_wall();
+        assert!(!map.line_of_sight(Vec3::new(0.0, 0.5, -3.0), Vec3::new(0.0, 0.5, 3.0)));
+        assert!(!map.line_of_sight(Vec3::new(1.0, 0.0, 3.0), Vec3::new(-1.0, 1.0, -3.0)));
+    }
+
+    #[test]
+    fn wall_leaves_other_lines_alone() {
+        let map = map_with_wall();
+        // beside it, over it, and stopping short of it
+        assert!(map.line_of_sight(Vec3::new(3.0, 0.5, -3.0), Vec3::new(3.0, 0.5, 3.0)));
+        assert!(map.line_of_sight(Vec3::new(0.0, 2.0, -3.0), Vec3::new(0.0, 2.0, 3.0)));
+        assert!(map.line_of_sight(Vec3::new(0.0, 0.5, -3.0), Vec3::new(0.0, 0.5, -1.0)));
+    }
+
+    #[test]
+    fn walls_only_stop_the_layers_they_block() {
+        let fence: Wall = ron::from_str(
+            "(center: (0.0, 0.5, 0.0), half_extents: (2.0, 1.0, 0.25), blocks: [Players])",
+        )
+        .unwrap();
+        assert_eq!(fence.blocks, LayerMask::from(vec![Layer::Players]));
+        let map = Map {
+            walls: vec![fence],
+            ..default()
+        };
+        // shots go through, players don't
+        assert!(map.line_of_sight(Vec3::new(0.0, 0.5, -3.0), Vec3::new(0.0, 0.5, 3.0)));
+        let (stopped, touched, _) = map.slide(
+            Vec3::new(0.0, 0.0, -2.0),
+            Vec3::new(0.0, 0.0, 3.0),
+            0.3,
+            0.7,
+            0.05,
+        );
+        assert!(stopped.z < -0.5);
+        assert_eq!(touched.len(), 1);
+        assert!(
+            touched[0].abs_diff_eq(Vec3::NEG_Z, 1e-4),
+            "touched {}",
+            touched[0]
+        );
+
+        // left out, it's solid to everything
+        let wall: Wall =
+            ron::from_str("(center: (0.0, 0.5, 0.0), half_extents: (2.0, 1.0, 0.25))").unwrap();
+        assert_eq!(wall.blocks, LayerMask::ALL);
+    }
+
+    #[test]
+    fn low_walls_are_ledges_and_tall_ones_are_not() {
+        let low = Map {
+            walls: vec![Wall {
+                center: Vec3::new(0.0, 0.0, -1.0),
+                half_extents: Vec3::new(2.0, 0.5, 0.25),
+                blocks: LayerMask::ALL,
+                surface: Surface::Concrete,
+            }],
+            ..default()
+        };
+        let ledge = low
+            .ledge_ahead(Vec3::ZERO, Vec3::NEG_Z, 1.0, 1.0)
+            .expect("a ledge");
+        assert_eq!(ledge.top, 0.5);
+        assert!((ledge.near - 0.75).abs() < 1e-4);
+        assert!((ledge.far - 1.25).abs() < 1e-4);
+        // facing away, or too far off
+        assert_eq!(low.ledge_ahead(Vec3::ZERO, Vec3::Z, 1.0, 1.0), None);
+        assert_eq!(low.ledge_ahead(Vec3::ZERO, Vec3::NEG_Z, 0.5, 1.0), None);
+        // the wall from earlier is too tall to climb
+        assert_eq!(
+            map_with_wall().ledge_ahead(Vec3::new(0.0, 0.0, 1.0), Vec3::NEG_Z, 1.0, 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn ground_is_the_floor_or_a_wall_top_not_above_the_feet() {
+        let map = map_with_wall();
+        assert_eq!(map.ground_height(Vec3::new(0.0, 1.5, 0.0), 1.6), 1.5);
+        // inside the wall, or beside it
+        assert_eq!(
+            map.ground_height(Vec3::new(0.0, 0.0, 0.0), 0.1),
+            GROUND_LEVEL
+        );
+        assert_eq!(
+            map.ground_height(Vec3::new(3.0, 1.5, 0.0), 1.6),
+            GROUND_LEVEL
+        );
+    }
+
+    #[test]
+    fn laser_sweeps_out_and_back_each_period() {
+        let laser = Laser {
+            start: Vec3::ZERO,
+            end: Vec3::X,
+            sweep: Vec3::Z * 4.0,
+            period: 2.0,
+        };
+        assert_eq!(laser.segment_at(0.0), (Vec3::ZERO, Vec3::X));
+        let (start, _) = laser.segment_at(1.0);
+        assert!((start - Vec3::Z * 4.0).length() < 1e-4);
+        // the same time always gives the same place, however many periods in
+        assert!((laser.segment_at(101.0).0 - start).length() < 1e-3);
+        assert!(laser.touches(0.0, Vec3::new(0.5, 0.1, 0.0), 0.2));
+        assert!(!laser.touches(1.0, Vec3::new(0.5, 0.1, 0.0), 0.2));
+    }
+
+    #[test]
+    fn fire_zone_burns_part_of_each_period() {
+        let fire_zone = FireZone {
+            center: Vec3::ZERO,
+            half_extents: Vec3::ONE,
+            period: 4.0,
+            active: 1.0,
+            offset: 0.0,
+        };
+        assert!(fire_zone.is_active(0.5));
+        assert!(!fire_zone.is_active(2.0));
+        assert!(fire_zone.is_active(8.5));
+        let shifted = FireZone {
+            offset: 2.0,
+            ..fire_zone
+        };
+        assert!(shifted.is_active(2.5));
+        assert!(!shifted.is_active(0.5));
+    }
+
+    #[test]
+    fn teleporter_pads_are_found_by_index() {
+        let map = Map {
+            teleporters: vec![Teleporter {
+                pads: [Vec3::new(-5.0, 0.0, 0.0), Vec3::new(5.0, 0.0, 0.0)],
+                half_extents: Vec3::ONE,
+            }],
+            ..default()
+        };
+        assert_eq!(
+            map.teleporter_pad_at(Vec3::new(-4.5, 0.5, 0.5)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            map.teleporter_pad_at(Vec3::new(5.0, 0.0, -1.0)),
+            Some((0, 1))
+        );
+        assert_eq!(map.teleporter_pad_at(Vec3::ZERO), None);
+    }
+
+    #[test]
+    fn rotation_goes_through_every_map_and_back_around() {
+        assert_eq!(Map::next_in_rotation("arena").as_deref(), Some("default"));
+        assert_eq!(Map::next_in_rotation("default").as_deref(), Some("arena"));
+        assert_eq!(Map::next_in_rotation("gone").as_deref(), Some("arena"));
+    }
+
+    #[test]
+    fn map_names_stay_inside_the_maps_directory() {
+        assert!(is_valid_name("default"));
+        assert!(is_valid_name("big_arena-2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("../secrets"));
+        assert!(!is_valid_name("maps/default"));
+        assert!(!is_valid_name("C:default"));
+    }
+
+    #[test]
+    fn shots_hit_the_nearest_target_not_behind_a_wall() {
+        let map = map_with_wall();
+        let origin = Vec3::new(0.0, 0.5, 3.0);
+        let targets = [
+            (1, Vec3::new(0.1, 0.5, 1.0), TARGET_RADIUS),
+            (2, Vec3::new(0.0, 0.6, 0.5), TARGET_RADIUS),
+            (3, Vec3::new(0.0, 0.5, -1.0), TARGET_RADIUS),
+        ];
+        let hit = map.shot_hit(origin, Vec3::NEG_Z, targets).unwrap();
+        assert_eq!(hit.target, 1);
+        assert_eq!(hit.damage, 1.0);
+        // nothing in the way, so it got there in a straight line
+        let point = origin + Vec3::NEG_Z * hit.distance;
+        assert!(point.distance(targets[0].1) <= TARGET_RADIUS + 1e-4);
+
+        // past the part of the path left out, a target it's still inside of is hit right there
+        let hit = map
+            .shot_hit_beyond(origin, Vec3::NEG_Z, targets, 2.0)
+            .unwrap();
+        assert_eq!((hit.target, hit.distance), (1, 2.0));
+        assert!(map
+            .shot_hit_beyond(origin, Vec3::NEG_Z, [targets[0]], 2.0 + TARGET_RADIUS)
+            .is_none());
+
+        // a smaller one in front of a bigger one is hit when the shot reaches it first
+        let head = (4, Vec3::new(0.0, 0.5, 1.6), 0.15);
+        assert_eq!(
+            map.shot_hit(origin, Vec3::NEG_Z, [targets[0], head])
+                .unwrap()
+                .target,
+            4
+        );
+
+        // the nearest one in line is behind the wall, so nothing is hit
+        assert!(map.shot_hit(origin, Vec3::NEG_Z, [targets[2]]).is_none());
+        // nor is anything off to the side, behind the shooter or out of range
+        assert!(map.shot_hit(origin, Vec3::X, targets).is_none());
+        assert!(map.shot_hit(origin, Vec3::Z, targets).is_none());
+        let far = (4, origin + Vec3::X * (SHOT_RANGE + 1.0), TARGET_RADIUS);
+        assert!(map.shot_hit(origin, Vec3::X, [far]).is_none());
+    }
+
+    #[test]
+    fn shots_go_through_thin_wood_and_glance_off_metal() {
+        let mut map = map_with_wall();
+        let origin = Vec3::new(0.0, 0.5, 3.0);
+        let behind = [(1, Vec3::new(0.0, 0.5, -1.0), TARGET_RADIUS)];
+
+        // half a meter of wood is too much, a fence's worth isn't
+        map.walls[0].surface = Surface::Wood;
+        assert!(map.shot_hit(origin, Vec3::NEG_Z, behind).is_none());
+        map.walls[0].half_extents.z = 0.1;
+        let hit = map.shot_hit(origin, Vec3::NEG_Z, behind).unwrap();
+        assert_eq!(hit.target, 1);
+        assert_eq!(hit.damage, PENETRATION_DAMAGE);
+
+        // metal stops a shot coming straight at it, one coming in flat bounces off to the side
+        map.walls[0].surface = Surface::Metal;
+        assert!(map.shot_hit(origin, Vec3::NEG_Z, behind).is_none());
+        let grazing = Vec3::new(-1.8, 0.5, 0.5);
+        let flat = Vec3::new(1.0, 0.0, -0.3).normalize();
+        let legs = map.shot_path(grazing, flat);
+        assert_eq!(legs.len(), 2);
+        assert!((legs[0].end.z - 0.1).abs() < 1e-4);
+        assert!(legs[1].end.z > legs[1].start.z);
+        let beside = [(
+            2,
+            legs[1].start + Vec3::new(1.0, 0.0, 0.3).normalize() * 2.0,
+            TARGET_RADIUS,
+        )];
+        let hit = map.shot_hit(grazing, flat, beside).unwrap();
+        assert_eq!(hit.target, 2);
+        assert_eq!(hit.damage, RICOCHET_DAMAGE);
+        // the distance goes on from the wall, not the start of the second leg alone
+        assert!(hit.distance > legs[0].start.distance(legs[0].end) + 1.0);
+    }
+
+    #[test]
+    fn no_walls_means_clear_sight() {
+        let map = Map::default();
+        assert!(map.line_of_sight(Vec3::ZERO, Vec3::new(10.0, 0.0, 0.0)));
+        assert!(map.line_of_sight(Vec3::ZERO, Vec3::ZERO));
+    }
+}
@@ -0,0 +1,173 @@
+//! the movement math on its own, no ECS, so it can be tested and reused by prediction
+
+use bevy::math::Vec3;
+
+/// the velocity to add so the speed along `wish_direction` approaches `wish_speed`,
+/// never overshooting it and never pushing backwards
+pub fn accelerate(
+    wish_direction: Vec3,
+    wish_speed: f32,
+    current_speed: f32,
+    accel: f32,
+    delta_seconds: f32,
+) -> Vec3 {
+    let add_speed = wish_speed - current_speed;
+
+    if add_speed.is_nan() || add_speed <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    // a NaN here comes out the other end and is caught below
+    let accel_speed = (accel * delta_seconds * wish_speed).clamp(0.0, add_speed);
+    let added = wish_direction * accel_speed;
+    if added.is_finite() {
+        added
+    } else {
+        Vec3::ZERO
+    }
+}
+
+/// `velocity` slowed down by friction, stopping at zero rather than reversing
+pub fn decelerate(velocity: Vec3, current_speed: f32, friction: f32, delta_seconds: f32) -> Vec3 {
+    // a broken velocity is better stopped than carried along
+    if !current_speed.is_finite() {
+        return Vec3::ZERO;
+    }
+    // nothing to slow down, and dividing by it below would make a NaN
+    if current_speed <= 0.0 {
+        return velocity;
+    }
+
+    // negative friction or time would speed things up instead, NaN drops nothing
+    let drop = (current_speed * friction * delta_seconds).max(0.0);
+    let new_speed = (current_speed - drop).max(0.0);
+
+    velocity * (new_speed / current_speed)
+}
+
+/// where a mantle from `start` to `end` over something `peak` high is, `progress` from 0 to 1
+/// of the way: straight up, across, then down, so it never cuts through a corner of what it
+/// climbs
+pub fn mantle_position(start: Vec3, end: Vec3, peak: f32, progress: f32) -> Vec3 {
+    let progress = progress.clamp(0.0, 1.0);
+    let rise = (progress * 3.0).min(1.0);
+    let across = (progress * 3.0 - 1.0).clamp(0.0, 1.0);
+    let fall = (progress * 3.0 - 2.0).clamp(0.0, 1.0);
+
+    let mut position = start.lerp(end, across);
+    position.y = if fall > 0.0 {
+        peak + (end.y - peak) * fall
+    } else {
+        start.y + (peak - start.y) * rise
+    };
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEED: f32 = 5.0;
+    const ACCEL: f32 = 8.0;
+    const FRICTION: f32 = 8.0;
+    const DELTA: f32 = 1.0 / 64.0;
+
+    #[test]
+    fn accelerates_towards_wish_direction() {
+        let added = accelerate(Vec3::X, SPEED, 0.0, ACCEL, DELTA);
+        assert!(added.x > 0.0);
+        assert_eq!(added.y, 0.0);
+        assert_eq!(added.z, 0.0);
+    }
+
+    #[test]
+    fn acceleration_never_overshoots_wish_speed() {
+        let added = accelerate(Vec3::X, SPEED, SPEED - 0.01, 1000.0, 1.0);
+        assert!((added.length() - 0.01).abs() < 1e-4);
+    }
+
+    #[test]
+    fn no_acceleration_above_wish_speed() {
+        assert_eq!(
+            accelerate(Vec3::X, SPEED, SPEED * 2.0, ACCEL, DELTA),
+            Vec3::ZERO
+        );
+        assert_eq!(accelerate(Vec3::X, SPEED, SPEED, ACCEL, DELTA), Vec3::ZERO);
+    }
+
+    #[test]
+    fn no_acceleration_for_zero_or_negative_delta() {
+        assert_eq!(accelerate(Vec3::X, SPEED, 0.0, ACCEL, 0.0), Vec3::ZERO);
+        assert_eq!(accelerate(Vec3::X, SPEED, 0.0, ACCEL, -DELTA), Vec3::ZERO);
+    }
+
+    #[test]
+    fn acceleration_ignores_nan() {
+        assert_eq!(accelerate(Vec3::X, f32::NAN, 0.0, ACCEL, DELTA), Vec3::ZERO);
+        assert_eq!(
+            accelerate(Vec3::X, SPEED, f32::NAN, ACCEL, DELTA),
+            Vec3::ZERO
+        );
+        assert_eq!(accelerate(Vec3::X, SPEED, 0.0, f32::NAN, DELTA), Vec3::ZERO);
+        assert_eq!(accelerate(Vec3::NAN, SPEED, 0.0, ACCEL, DELTA), Vec3::ZERO);
+    }
+
+    #[test]
+    fn friction_slows_down_along_velocity() {
+        let velocity = Vec3::new(3.0, 0.0, 4.0);
+        let slowed = decelerate(velocity, velocity.length(), FRICTION, DELTA);
+        assert!(slowed.length() < velocity.length());
+        assert!((slowed.normalize() - velocity.normalize()).length() < 1e-6);
+    }
+
+    #[test]
+    fn friction_stops_without_reversing() {
+        let velocity = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(decelerate(velocity, 1.0, 1000.0, 1.0), Vec3::ZERO);
+    }
+
+    #[test]
+    fn friction_at_zero_speed_stays_zero() {
+        let slowed = decelerate(Vec3::ZERO, 0.0, FRICTION, DELTA);
+        assert_eq!(slowed, Vec3::ZERO);
+        assert!(slowed.is_finite());
+    }
+
+    #[test]
+    fn zero_delta_leaves_velocity_alone() {
+        let velocity = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(
+            decelerate(velocity, velocity.length(), FRICTION, 0.0),
+            velocity
+        );
+    }
+
+    #[test]
+    fn negative_drop_never_speeds_up() {
+        let velocity = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(decelerate(velocity, 1.0, -FRICTION, DELTA), velocity);
+        assert_eq!(decelerate(velocity, 1.0, FRICTION, -DELTA), velocity);
+    }
+
+    #[test]
+    fn friction_handles_nan() {
+        let velocity = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(decelerate(velocity, 1.0, f32::NAN, DELTA), velocity);
+        assert_eq!(decelerate(Vec3::NAN, f32::NAN, FRICTION, DELTA), Vec3::ZERO);
+    }
+
+    #[test]
+    fn mantle_goes_up_across_and_down() {
+        let start = Vec3::ZERO;
+        let end = Vec3::new(0.0, 0.0, -2.0);
+        assert_eq!(mantle_position(start, end, 1.0, 0.0), start);
+        let up = mantle_position(start, end, 1.0, 1.0 / 3.0);
+        assert!((up - Vec3::Y).length() < 1e-5);
+        let across = mantle_position(start, end, 1.0, 2.0 / 3.0);
+        assert!((across - Vec3::new(0.0, 1.0, -2.0)).length() < 1e-5);
+        assert_eq!(mantle_position(start, end, 1.0, 1.0), end);
+        // past either end stays at that end
+        assert_eq!(mantle_position(start, end, 1.0, 2.0), end);
+        assert_eq!(mantle_position(start, end, 1.0, -1.0), start);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    core::*,
+    localization::Localization,
+    server::{ReliableOutbox, ResetWorldEvent},
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer};
+
+/// match mutators: the server starts with whatever `--mutator` asked for, a vote can turn them
+/// on or off, which starts the match over. clients are sent them before their character
+/// spawns and move everyone with them like the server does. the HUD lists the ones in play
+pub struct MutatorsPlugin;
+impl Plugin for MutatorsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_mutators_text_system);
+        app.add_systems(
+            Update,
+            (
+                set_mutators_system.run_if(resource_exists::<RenetServer>),
+                receive_mutators_system.run_if(resource_exists::<RenetClient>),
+                mutators_text_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<Mutators>();
+        app.add_event::<SetMutatorsEvent>();
+        app.add_event::<ReceivedMutatorsEvent>();
+    }
+}
+
+/// play with these from the next match on, which starts right away
+#[derive(Event)]
+pub struct SetMutatorsEvent(pub Mutators);
+
+/// the server told us which mutators are in play
+#[derive(Event)]
+pub struct ReceivedMutatorsEvent(pub Mutators);
+
+pub fn describe(localization: &Localization, mutator: Mutator) -> String {
+    let key = match mutator {
+        Mutator::LowGravity => "mutator.low_gravity",
+        Mutator::HighSpeed => "mutator.high_speed",
+        Mutator::Instagib => "mutator.instagib",
+    };
+    localization.get(key).to_string()
+}
+
+fn set_mutators_system(
+    mut changes: EventReader<SetMutatorsEvent>,
+    mut mutators: ResMut<Mutators>,
+    mut reset_world: EventWriter<ResetWorldEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for SetMutatorsEvent(new_mutators) in changes.read() {
+        if *new_mutators == *mutators {
+            continue;
+        }
+        println!("Mutators set to {:?}", new_mutators.list());
+        *mutators = *new_mutators;
+        outbox.broadcast(&mut server, &ReliableServerMessage::Mutators(*mutators));
+        reset_world.send(ResetWorldEvent);
+    }
+}
+
+fn receive_mutators_system(
+    mut received: EventReader<ReceivedMutatorsEvent>,
+    mut mutators: ResMut<Mutators>,
+) {
+    if let Some(ReceivedMutatorsEvent(new_mutators)) = received.read().last() {
+        if *new_mutators != *mutators {
+            *mutators = *new_mutators;
+        }
+    }
+}
+
+#[derive(Component)]
+struct MutatorsText;
+
+fn setup_mutators_text_system(mut commands: Commands) {
+    commands.spawn((
+        MutatorsText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::rgb(1.0, 0.8, 0.3),
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(72.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+fn mutators_text_system(
+    mutators: Res<Mutators>,
+    localization: Res<Localization>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<MutatorsText>>,
+) {
+    if !mutators.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let names = mutators
+        .list()
+        .into_iter()
+        .map(|mutator| describe(&localization, mutator))
+        .collect::<Vec<_>>()
+        .join(", ");
+    for (mut text, mut visibility) in texts.iter_mut() {
+        text.sections[0].value = localization.format("mutator.active", &[&names]);
+        *visibility = if mutators.list().is_empty() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    core::*,
+    palette::{ColorFilter, PlayerColor},
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{transport::NETCODE_USER_DATA_BYTES, ClientId};
+use std::path::Path;
+
+/// longest name there's room for, in bytes
+pub const MAX_NAME_BYTES: usize = 32;
+/// where it goes in the connect token's user data, right after the cosmetics: its length, then
+/// the name itself
+const USER_DATA_OFFSET: usize = 10;
+/// how far above the feet a nameplate floats
+const NAMEPLATE_HEIGHT: f32 = 2.3;
+const BLOCKLIST_PATH: &str = "assets/name_blocklist.ron";
+/// parts of web addresses, checked like blocked words
+const URL_PARTS: [&str; 3] = ["http", "www", "dotcom"];
+
+/// clients give a name with `--name` when connecting, which the server sends along with every
+/// spawn of their character. everyone else's is shown above their character, turned toward
+/// whoever's looking since it's drawn on the screen rather than in the world
+pub struct NameplatePlugin;
+impl Plugin for NameplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (spawn_nameplates_system, nameplates_system).chain());
+    }
+}
+
+/// what each connected client is called, for every spawn of their character. only on the server
+#[derive(Resource, Default)]
+pub struct ClientNames {
+    clients: HashMap<ClientId, String>,
+}
+
+impl ClientNames {
+    pub fn get(&self, client_id: ClientId) -> String {
+        self.clients.get(&client_id).cloned().unwrap_or_default()
+    }
+
+    pub fn insert(&mut self, client_id: ClientId, name: String) {
+        self.clients.insert(client_id, name);
+    }
+
+    /// whether the client was let in and hasn't left yet, with a name or not
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.clients.contains_key(&client_id)
+    }
+
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+
+    /// `name` as is when nobody has it yet, otherwise with the first free `#2`, `#3`... after
+    /// it, cut short to make room
+    pub fn unique(&self, name: &str) -> String {
+        let taken = |candidate: &str| {
+            self.clients
+                .values()
+                .any(|other| other.eq_ignore_ascii_case(candidate))
+        };
+        if name.is_empty() || !taken(name) {
+            return name.to_string();
+        }
+        (2..)
+            .map(|number| {
+                let suffix = format!("#{}", number);
+                let mut base = name.to_string();
+                while base.len() + suffix.len() > MAX_NAME_BYTES {
+                    base.pop();
+                }
+                format!("{}{}", base.trim_end(), suffix)
+            })
+            .find(|candidate| !taken(candidate))
+            .unwrap_or_default()
+    }
+}
+
+/// words names can't have, from `assets/name_blocklist.ron`. the server checks every name with
+/// it before letting its client in. only on the server
+#[derive(Resource, Default)]
+pub struct NameFilter {
+    blocked: Vec<String>,
+}
+
+impl NameFilter {
+    /// an empty blocklist when the file can't be read, names are still checked otherwise
+    pub fn load_or_default() -> Self {
+        match Self::load(Path::new(BLOCKLIST_PATH)) {
+            Ok(filter) => filter,
+            Err(e) => {
+                eprintln!(
+                    "Failed to load the name blocklist {}: {}",
+                    BLOCKLIST_PATH, e
+                );
+                default()
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let blocked: Vec<String> = ron::from_str(&text).map_err(|e| e.to_string())?;
+        Ok(Self {
+            blocked: blocked.iter().map(|word| normalize(word)).collect(),
+        })
+    }
+
+    /// names are checked as they came in, a client that went through `sanitize` never fails
+    /// the first two
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        if name.len() > MAX_NAME_BYTES {
+            return Err(format!("it's longer than {} bytes", MAX_NAME_BYTES));
+        }
+        if name.trim() != name || !name.chars().all(allowed_in_name) {
+            return Err("it has characters names can't have".to_string());
+        }
+        let normalized = normalize(name);
+        if URL_PARTS.iter().any(|part| normalized.contains(part)) {
+            return Err("it looks like a web address".to_string());
+        }
+        if self
+            .blocked
+            .iter()
+            .any(|word| !word.is_empty() && normalized.contains(word.as_str()))
+        {
+            return Err("it has a blocked word".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// letters and digits in any script, and a few separators. no `#`, that's for telling the same
+/// names apart
+fn allowed_in_name(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '_' | '-')
+}
+
+/// lowercase without separators or the digits commonly swapped for letters, so spacing a word
+/// out or writing it in leetspeak doesn't get around the blocklist
+fn normalize(name: &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| match c {
+            '0' => Some('o'),
+            '1' => Some('i'),
+            '3' => Some('e'),
+            '4' => Some('a'),
+            '5' => Some('s'),
+            '7' => Some('t'),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// the name this client connects with
+#[derive(Resource)]
+pub struct NameRequest(pub String);
+
+#[derive(Component)]
+struct Nameplate {
+    visuals_entity: Entity,
+}
+
+/// only what the server lets names have, cut short at a character boundary
+pub fn sanitize(name: &str) -> String {
+    let mut sanitized = String::new();
+    for c in name.trim().chars().filter(|c| allowed_in_name(*c)) {
+        if sanitized.len() + c.len_utf8() > MAX_NAME_BYTES {
+            break;
+        }
+        sanitized.push(c);
+    }
+    sanitized.trim_end().to_string()
+}
+
+pub fn write_user_data(user_data: &mut [u8; NETCODE_USER_DATA_BYTES], name: &str) {
+    let name = sanitize(name);
+    user_data[USER_DATA_OFFSET] = name.len() as u8;
+    user_data[USER_DATA_OFFSET + 1..][..name.len()].copy_from_slice(name.as_bytes());
+}
+
+/// empty when there wasn't one, `None` when it isn't a name at all
+pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<String> {
+    let length = user_data[USER_DATA_OFFSET] as usize;
+    let bytes = user_data[USER_DATA_OFFSET + 1..].get(..length)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+/// our own character doesn't get one, neither does anyone who didn't give a name
+fn spawn_nameplates_system(
+    mut commands: Commands,
+    local_player: Res<LocalPlayer>,
+    color_filter: Res<ColorFilter>,
+    characters: Query<(&PlayerName, &PlayerColor), With<Character>>,
+    visuals: Query<(Entity, &CharacterVisuals), Added<CharacterVisuals>>,
+) {
+    for (visuals_entity, visuals) in visuals.iter() {
+        if visuals.owner_client_id == local_player.client_id {
+            continue;
+        }
+        let (name, color) = match characters.get(visuals.character_entity) {
+            Ok((name, color)) if !name.0.is_empty() => (name, color),
+            _ => continue,
+        };
+        commands.spawn((
+            Nameplate { visuals_entity },
+            TextBundle {
+                text: Text::from_section(
+                    name.0.clone(),
+                    TextStyle {
+                        font_size: 16.0,
+                        color: color_filter.color(*color),
+                        ..default()
+                    },
+                ),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                ..default()
+            },
+        ));
+    }
+}
+
+fn nameplates_system(
+    mut commands: Commands,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    visuals: Query<(&Transform, &Visibility), With<CharacterVisuals>>,
+    mut nameplates: Query<(Entity, &Nameplate, &Node, &mut Style)>,
+) {
+    let camera = cameras.iter().next();
+
+    for (entity, nameplate, node, mut style) in nameplates.iter_mut() {
+        let (transform, visibility) = match visuals.get(nameplate.visuals_entity) {
+            Ok(found) => found,
+            Err(_) => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+        let top = transform.translation + Vec3::Y * NAMEPLATE_HEIGHT;
+        let screen_position = camera
+            .filter(|_| *visibility != Visibility::Hidden)
+            .and_then(|(camera, camera_transform)| camera.world_to_viewport(camera_transform, top));
+        match screen_position {
+            Some(screen_position) => {
+                // centered over the character
+                style.left = Val::Px(screen_position.x - node.size().x / 2.0);
+                style.top = Val::Px(screen_position.y);
+                style.display = Display::Flex;
+            }
+            // behind the camera, or the character isn't drawn
+            None => style.display = Display::None,
+        }
+    }
+}
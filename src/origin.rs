@@ -0,0 +1,66 @@
+use crate::core::*;
+use bevy::{prelude::*, transform::TransformSystem};
+
+/// how far the local player gets from the render origin before it's moved to them. f32 is
+/// still good to a fraction of a millimeter out here
+const SHIFT_DISTANCE: f32 = 1024.0;
+
+/// far from the world's origin f32 positions get coarse enough for bodies and the camera to
+/// jitter. everything drawn is placed relative to a render origin, which is moved to the local
+/// player whenever they get far from it. characters, snapshots and everything else simulated
+/// stay in absolute world coordinates like on the server, so only what turns a world position
+/// into something drawn goes through `RenderOrigin`
+pub struct FloatingOriginPlugin;
+impl Plugin for FloatingOriginPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            shift_origin_system.before(TransformSystem::TransformPropagate),
+        );
+        app.init_resource::<RenderOrigin>();
+    }
+}
+
+/// the world position drawn at zero
+#[derive(Resource, Default, Clone, Copy)]
+pub struct RenderOrigin(pub Vec3);
+
+impl RenderOrigin {
+    pub fn to_render(self, world: Vec3) -> Vec3 {
+        world - self.0
+    }
+
+    pub fn to_world(self, render: Vec3) -> Vec3 {
+        render + self.0
+    }
+}
+
+/// everything drawn at the top of its hierarchy moves along, children follow their parents and
+/// UI isn't in the world at all
+fn shift_origin_system(
+    local_player: Option<Res<LocalPlayer>>,
+    mut origin: ResMut<RenderOrigin>,
+    characters: Query<(&Character, &Transform)>,
+    mut drawn: Query<&mut Transform, (Without<Character>, Without<Parent>, Without<Node>)>,
+) {
+    let local_player = match local_player {
+        Some(local_player) => local_player,
+        None => return,
+    };
+    let translation = match characters
+        .iter()
+        .find(|(character, _)| character.owner_client_id == local_player.client_id)
+    {
+        Some((_, transform)) => transform.translation,
+        None => return,
+    };
+    if origin.to_render(translation).length() < SHIFT_DISTANCE {
+        return;
+    }
+
+    let shift = translation.round() - origin.0;
+    origin.0 += shift;
+    for mut transform in drawn.iter_mut() {
+        transform.translation -= shift;
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{chat::ChatInput, core::*};
+use bevy::prelude::*;
+
+const COLOR_FILTER_KEY: KeyCode = KeyCode::F7;
+
+/// Okabe-Ito, distinguishable for most kinds of color vision
+const DEFAULT_PALETTE: [Color; PALETTE_SIZE] = [
+    Color::rgb(0.0, 0.447, 0.698),
+    Color::rgb(0.902, 0.624, 0.0),
+    Color::rgb(0.0, 0.620, 0.451),
+    Color::rgb(0.800, 0.475, 0.655),
+    Color::rgb(0.337, 0.706, 0.914),
+    Color::rgb(0.835, 0.369, 0.0),
+    Color::rgb(0.941, 0.894, 0.259),
+    Color::rgb(0.6, 0.6, 0.6),
+];
+
+/// reds also look dark to these players and run into greens and browns, so there are none at
+/// all, only blue/yellow and lightness
+const PROTANOPIA_PALETTE: [Color; PALETTE_SIZE] = [
+    Color::rgb(0.0, 0.447, 0.698),
+    Color::rgb(0.941, 0.894, 0.259),
+    Color::rgb(0.337, 0.706, 0.914),
+    Color::rgb(0.6, 0.6, 0.6),
+    Color::rgb(0.0, 0.2, 0.4),
+    Color::rgb(1.0, 1.0, 1.0),
+    Color::rgb(0.45, 0.35, 0.0),
+    Color::rgb(0.15, 0.15, 0.15),
+];
+
+/// red and green collapse for these players, but reds keep their brightness, so an orange still
+/// stands apart from yellow next to blue/yellow and lightness
+const DEUTERANOPIA_PALETTE: [Color; PALETTE_SIZE] = [
+    Color::rgb(0.0, 0.447, 0.698),
+    Color::rgb(0.941, 0.894, 0.259),
+    Color::rgb(0.337, 0.706, 0.914),
+    Color::rgb(0.902, 0.624, 0.0),
+    Color::rgb(0.6, 0.6, 0.6),
+    Color::rgb(0.0, 0.2, 0.4),
+    Color::rgb(1.0, 1.0, 1.0),
+    Color::rgb(0.45, 0.35, 0.0),
+];
+
+pub const PALETTE_SIZE: usize = 8;
+
+pub struct PalettePlugin;
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (color_filter_key_system, recolor_system).chain());
+        app.init_resource::<ColorFilter>();
+    }
+}
+
+/// which palette slot a player was given by the server, the same on every client
+#[derive(Component, Clone, Copy, PartialEq)]
+pub struct PlayerColor(pub u8);
+
+impl PlayerColor {
+    /// the lowest slot nobody is using yet, or shared slots once everyone has one
+    pub fn first_free(taken: &[PlayerColor]) -> Self {
+        (0..PALETTE_SIZE as u8)
+            .map(PlayerColor)
+            .find(|color| !taken.contains(color))
+            .unwrap_or(PlayerColor((taken.len() % PALETTE_SIZE) as u8))
+    }
+}
+
+/// how this client turns palette slots into actual colors, never sent to the server
+#[derive(Resource, Default, Clone, Copy, PartialEq, Debug)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    Protanopia,
+    Deuteranopia,
+}
+
+impl ColorFilter {
+    pub fn color(&self, player_color: PlayerColor) -> Color {
+        let palette = match self {
+            Self::None => &DEFAULT_PALETTE,
+            Self::Protanopia => &PROTANOPIA_PALETTE,
+            Self::Deuteranopia => &DEUTERANOPIA_PALETTE,
+        };
+        palette[player_color.0 as usize % PALETTE_SIZE]
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::None => Self::Protanopia,
+            Self::Protanopia => Self::Deuteranopia,
+            Self::Deuteranopia => Self::None,
+        }
+    }
+}
+
+fn color_filter_key_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    mut color_filter: ResMut<ColorFilter>,
+) {
+    if chat_input.is_some_and(|chat_input| chat_input.active) {
+        return;
+    }
+
+    if keyboard.just_pressed(COLOR_FILTER_KEY) {
+        *color_filter = color_filter.next();
+        println!("Color filter: {:?}", *color_filter);
+    }
+}
+
+/// character bodies take their player's color, again whenever it or the filter changes
+fn recolor_system(
+    color_filter: Res<ColorFilter>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    characters: Query<Ref<PlayerColor>>,
+    visuals: Query<(&CharacterVisuals, &Handle<StandardMaterial>)>,
+) {
+    for (visuals, material) in visuals.iter() {
+        if let Ok(player_color) = characters.get(visuals.character_entity) {
+            if !color_filter.is_changed() && !player_color.is_changed() {
+                continue;
+            }
+            if let Some(material) = materials.get_mut(material) {
+                material.base_color = color_filter.color(*player_color);
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    afk::AfkAction, chat::ChatInput, controls::KeyLabels, core::ServerSettings, freeze::Freeze,
+    localization::Localization, DEFAULT_CLIENT_BANDWIDTH, DEFAULT_PORT,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use std::net::Ipv4Addr;
+
+const PAUSE_KEY: KeyCode = KeyCode::Escape;
+const OPEN_TO_LAN_KEY: KeyCode = KeyCode::KeyL;
+
+/// a real pause for when nobody else is playing. virtual time stops, so the fixed schedule
+/// stops with it and resuming doesn't try to catch up on the ticks that were skipped
+pub struct PausePlugin;
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_pause_menu);
+        app.add_systems(
+            Update,
+            (
+                pause_toggle_system,
+                open_to_lan_system,
+                unpause_on_connect_system.run_if(resource_exists::<RenetServer>),
+                pause_menu_system,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// a listen server can only pause while it has nobody connected
+fn can_pause(server: Option<&RenetServer>) -> bool {
+    server.is_none_or(|server| server.connected_clients() == 0)
+}
+
+fn pause_toggle_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    server: Option<Res<RenetServer>>,
+    freeze: Res<Freeze>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    // escape also closes the chat, that press shouldn't pause as well
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active || chat_input.is_changed());
+    // only the admin gets to unfreeze
+    if typing || freeze.frozen || !keyboard.just_pressed(PAUSE_KEY) {
+        return;
+    }
+
+    if time.is_paused() {
+        time.unpause();
+    } else if can_pause(server.as_deref()) {
+        time.pause();
+    }
+}
+
+/// turns single player into a listen server, the server plugin starts it once the settings
+/// show up. the world and our character (client 0) carry on as they are
+fn open_to_lan_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    time: Res<Time<Virtual>>,
+    server_settings: Option<Res<ServerSettings>>,
+) {
+    if time.is_paused() && server_settings.is_none() && keyboard.just_pressed(OPEN_TO_LAN_KEY) {
+        println!("Opening to LAN on port {}", DEFAULT_PORT);
+        commands.insert_resource(ServerSettings {
+            bind: Ipv4Addr::UNSPECIFIED.into(),
+            port: DEFAULT_PORT,
+            client_bandwidth: DEFAULT_CLIENT_BANDWIDTH,
+            demo_dir: None,
+            afk_timeout: None,
+            afk_action: AfkAction::Spectate,
+            snapshot_interval: 1,
+            motd: None,
+            cheat_kick_score: None,
+        });
+    }
+}
+
+/// someone joining can't be kept waiting on our menu, an admin freeze they can wait out
+fn unpause_on_connect_system(
+    server: Res<RenetServer>,
+    freeze: Res<Freeze>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if time.is_paused() && !freeze.frozen && !can_pause(Some(&server)) {
+        time.unpause();
+    }
+}
+
+#[derive(Component)]
+struct PauseMenu;
+
+#[derive(Component)]
+struct OpenToLanText;
+
+fn setup_pause_menu(mut commands: Commands, localization: Res<Localization>) {
+    commands
+        .spawn((
+            PauseMenu,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                localization.get("pause.title"),
+                TextStyle {
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            parent.spawn(TextBundle::from_section(
+                localization.get("pause.resume"),
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                OpenToLanText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+fn pause_menu_system(
+    time: Res<Time<Virtual>>,
+    freeze: Res<Freeze>,
+    localization: Res<Localization>,
+    key_labels: Res<KeyLabels>,
+    server: Option<Res<RenetServer>>,
+    server_settings: Option<Res<ServerSettings>>,
+    mut menus: Query<&mut Visibility, With<PauseMenu>>,
+    mut open_to_lan_texts: Query<&mut Text, With<OpenToLanText>>,
+) {
+    let target = if time.is_paused() && !freeze.frozen {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    for mut visibility in menus.iter_mut() {
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+
+    let open_to_lan = match (server, server_settings) {
+        (Some(_), Some(server_settings)) => {
+            localization.format("pause.open_on_port", &[&server_settings.port])
+        }
+        (None, None) => {
+            localization.format("pause.open_to_lan", &[&key_labels.label(OPEN_TO_LAN_KEY)])
+        }
+        _ => String::new(),
+    };
+    for mut text in open_to_lan_texts.iter_mut() {
+        if text.sections[0].value != open_to_lan {
+            text.sections[0].value = open_to_lan.clone();
+        }
+    }
+}
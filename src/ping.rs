@@ -0,0 +1,262 @@
+use crate::{
+    chat::ChatInput,
+    core::*,
+    origin::RenderOrigin,
+    palette::{ColorFilter, PlayerColor},
+    room::Rooms,
+    server::ReliableOutbox,
+    split_screen::GuestCamera,
+    FLOOR_THICKNESS, FLOOR_Y,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+const PING_KEY: KeyCode = KeyCode::KeyQ;
+const PING_MAX_DISTANCE: f32 = 100.0;
+const PING_COOLDOWN: f32 = 1.0;
+const PING_DURATION: f32 = 5.0;
+
+pub struct PingPlugin;
+impl Plugin for PingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                ping_key_system,
+                validate_pings_system.run_if(resource_exists::<RenetServer>),
+                spawn_ping_markers_system,
+                ping_markers_system,
+            )
+                .chain(),
+        );
+        app.add_event::<IncomingPingEvent>();
+        app.add_event::<PingEvent>();
+    }
+}
+
+/// a ping a player asked for, waiting for the server to validate it
+#[derive(Event)]
+pub struct IncomingPingEvent {
+    pub sender: ClientId,
+    pub position: Vec3,
+}
+
+/// a ping the server accepted, shown to the sender's team (everyone, until teams exist)
+#[derive(Event)]
+pub struct PingEvent {
+    pub client_id: ClientId,
+    pub position: Vec3,
+}
+
+#[derive(Component)]
+struct PingMarker {
+    client_id: ClientId,
+    remaining: f32,
+    label: Entity,
+}
+
+#[derive(Component)]
+struct PingLabel;
+
+/// where the camera is looking on the floor, if it's looking at the floor at all
+fn raycast_floor(camera_transform: &GlobalTransform, render_origin: RenderOrigin) -> Option<Vec3> {
+    let origin = render_origin.to_world(camera_transform.translation());
+    let direction = camera_transform.forward();
+    let floor_top = FLOOR_Y + FLOOR_THICKNESS / 2.0;
+
+    if direction.y >= 0.0 {
+        return None;
+    }
+
+    let distance = (floor_top - origin.y) / direction.y;
+    if !(0.0..=PING_MAX_DISTANCE).contains(&distance) {
+        return None;
+    }
+
+    Some(origin + direction * distance)
+}
+
+fn ping_key_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    local_player: Option<Res<LocalPlayer>>,
+    render_origin: Res<RenderOrigin>,
+    cameras: Query<&GlobalTransform, (With<Camera>, Without<GuestCamera>)>,
+    mut incoming: EventWriter<IncomingPingEvent>,
+    mut client: Option<ResMut<RenetClient>>,
+) {
+    if chat_input.is_some_and(|chat_input| chat_input.active) || !keyboard.just_pressed(PING_KEY) {
+        return;
+    }
+
+    if let Some(position) = cameras
+        .iter()
+        .next()
+        .and_then(|camera_transform| raycast_floor(camera_transform, *render_origin))
+    {
+        if let Some(client) = client.as_mut() {
+            if let Ok(message) = bincode::serialize(&ReliableClientMessage::Ping(position)) {
+                client.send_message(DefaultChannel::ReliableOrdered, message);
+            }
+        } else if let Some(local_player) = local_player {
+            incoming.send(IncomingPingEvent {
+                sender: local_player.client_id,
+                position,
+            });
+        }
+    }
+}
+
+fn validate_pings_system(
+    time: Res<Time>,
+    rooms: Res<Rooms>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<(&Character, &Transform)>,
+    mut last_ping_times: Local<HashMap<ClientId, f32>>,
+    mut incoming: EventReader<IncomingPingEvent>,
+    mut pings: EventWriter<PingEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    let now = time.elapsed_seconds();
+    for IncomingPingEvent { sender, position } in incoming.read() {
+        // the ping has to be something the sender could actually have seen
+        if !position.is_finite()
+            || !characters.iter().any(|(character, transform)| {
+                character.owner_client_id == *sender
+                    && transform.translation.distance(*position) <= PING_MAX_DISTANCE
+            })
+        {
+            continue;
+        }
+
+        if let Some(last_ping_time) = last_ping_times.get(sender) {
+            if now - last_ping_time < PING_COOLDOWN {
+                continue;
+            }
+        }
+        last_ping_times.insert(*sender, now);
+
+        // a spot in the sender's room, meaningless anywhere else
+        outbox.broadcast_filtered(
+            &mut server,
+            &ReliableServerMessage::Ping(sender.raw(), *position),
+            |client_id| rooms.same_room(client_id, *sender),
+        );
+        if local_player
+            .as_ref()
+            .is_none_or(|local_player| rooms.same_room(local_player.client_id, *sender))
+        {
+            pings.send(PingEvent {
+                client_id: *sender,
+                position: *position,
+            });
+        }
+    }
+}
+
+fn spawn_ping_markers_system(
+    mut commands: Commands,
+    color_filter: Res<ColorFilter>,
+    origin: Res<RenderOrigin>,
+    characters: Query<(&Character, &PlayerColor)>,
+    mut pings: EventReader<PingEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    markers: Query<(Entity, &PingMarker)>,
+) {
+    for event in pings.read() {
+        let color = characters
+            .iter()
+            .find(|(character, _)| character.owner_client_id == event.client_id)
+            .map_or(Color::YELLOW, |(_, player_color)| {
+                color_filter.color(*player_color)
+            });
+
+        // each player only gets one marker at a time
+        for (marker_entity, marker) in markers.iter() {
+            if marker.client_id == event.client_id {
+                commands.entity(marker.label).despawn();
+                commands.entity(marker_entity).despawn();
+            }
+        }
+
+        let label = commands
+            .spawn((
+                PingLabel,
+                TextBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 14.0,
+                            color,
+                            ..default()
+                        },
+                    ),
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    },
+                    ..default()
+                },
+            ))
+            .id();
+
+        commands.spawn((
+            PingMarker {
+                client_id: event.client_id,
+                remaining: PING_DURATION,
+                label,
+            },
+            PbrBundle {
+                mesh: meshes.add(Cylinder::new(0.05, 1.0)),
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(
+                    origin.to_render(event.position + Vec3::Y * 0.5),
+                ),
+                ..default()
+            },
+        ));
+    }
+}
+
+fn ping_markers_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut markers: Query<(Entity, &mut PingMarker, &Transform)>,
+    mut labels: Query<(&mut Text, &mut Style), With<PingLabel>>,
+) {
+    let camera = cameras.iter().next();
+
+    for (entity, mut marker, transform) in markers.iter_mut() {
+        marker.remaining -= time.delta_seconds();
+        if marker.remaining <= 0.0 {
+            commands.entity(marker.label).despawn();
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        if let (Some((camera, camera_transform)), Ok((mut text, mut style))) =
+            (camera, labels.get_mut(marker.label))
+        {
+            let top = transform.translation + Vec3::Y * 0.6;
+            if let Some(screen_position) = camera.world_to_viewport(camera_transform, top) {
+                let distance = camera_transform
+                    .translation()
+                    .distance(transform.translation);
+                text.sections[0].value = format!("{:.0}m", distance);
+                style.left = Val::Px(screen_position.x);
+                style.top = Val::Px(screen_position.y);
+                style.display = Display::Flex;
+            } else {
+                // behind the camera
+                style.display = Display::None;
+            }
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{
+    admin::AdminCommandEvent, afk, anticheat, controls::KeyLabels, core::*,
+    localization::Localization, map::Map, origin::RenderOrigin, palette::ColorFilter, server,
+    settings::Settings, GameplayPlugins, DEFAULT_CLIENT_BANDWIDTH, DEFAULT_PORT, MAP_PATH,
+};
+use bevy::{audio::Pitch, prelude::*, ui::RelativeCursorPosition, window::ReceivedCharacter};
+use bevy_renet::{
+    renet::{ClientId, ConnectionConfig, RenetClient, RenetServer},
+    RenetServerPlugin,
+};
+use std::{collections::VecDeque, net::Ipv4Addr, path::Path, time::SystemTime};
+
+const PANEL_KEY: KeyCode = KeyCode::F4;
+/// like the split-screen guest, a reserved id no netcode client can get. the server's own
+/// `LocalPlayer` is 0 and never gets a character
+const PRACTICE_CLIENT_ID: u64 = 1;
+const MAX_LATENCY_MS: f32 = 500.0;
+const MAX_JITTER_MS: f32 = 200.0;
+const MAX_LOSS: f32 = 0.5;
+const MAX_SNAPSHOT_INTERVAL: u32 = 8;
+pub const FIXED_HZ: f64 = 64.0;
+
+/// single player as a client of a server running in the same process. packets between the two
+/// go through in-memory queues that can be slowed down, shuffled and thinned out from a panel or
+/// the command line, so prediction, reconciliation and interpolation can be tried out under bad
+/// conditions alone
+pub struct PracticePlugin;
+impl Plugin for PracticePlugin {
+    fn build(&self, app: &mut App) {
+        let mut client = RenetClient::new(ConnectionConfig::default());
+        client.set_connected();
+        app.insert_resource(client);
+        app.insert_resource(LocalPlayer {
+            client_id: ClientId::from_raw(PRACTICE_CLIENT_ID),
+        });
+        app.init_resource::<NetConditions>();
+        app.init_resource::<Loopback>();
+        app.add_systems(Startup, (start_practice_server_system, setup_panel_system));
+        app.add_systems(
+            Update,
+            (panel_toggle_system, slider_system, panel_text_system).chain(),
+        );
+        app.add_systems(PostUpdate, loopback_system);
+    }
+}
+
+/// what the loopback does to packets, changed from the panel
+#[derive(Resource)]
+pub struct NetConditions {
+    /// round trip, half of it each way
+    pub latency_ms: f32,
+    /// on top of the latency, each packet arrives up to this much later at random, so they can
+    /// come in out of order
+    pub jitter_ms: f32,
+    /// the chance of any one packet being dropped, either way
+    pub loss: f32,
+    /// fixed ticks between snapshots
+    pub snapshot_interval: u32,
+}
+
+impl Default for NetConditions {
+    fn default() -> Self {
+        Self {
+            latency_ms: 100.0,
+            jitter_ms: 0.0,
+            loss: 0.0,
+            snapshot_interval: 1,
+        }
+    }
+}
+
+/// the server's own app, updated by hand once a frame. not `Send`, so kept outside of resources
+struct PracticeServer {
+    app: App,
+}
+
+/// nowhere near good randomness, but enough to pick which packets get lost
+pub struct Xorshift(u64);
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Self::seeded(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_nanos() as u64),
+        )
+    }
+}
+
+impl Xorshift {
+    pub fn seeded(seed: u64) -> Self {
+        // all zeroes would stay zero forever
+        Self(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// between 0 and 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+struct InFlight {
+    /// when it would arrive without jitter, never before anything sent earlier
+    in_order_at: f64,
+    deliver_at: f64,
+    packet: Vec<u8>,
+}
+
+/// one client's packets on their way, both directions
+#[derive(Default)]
+pub struct Link {
+    to_server: VecDeque<InFlight>,
+    to_client: VecDeque<InFlight>,
+}
+
+impl Link {
+    /// takes what the client sent and hands the server whatever is due by `now`, in seconds
+    pub fn carry_to_server(
+        &mut self,
+        conditions: &NetConditions,
+        random: &mut Xorshift,
+        now: f64,
+        client: &mut RenetClient,
+        server: &mut RenetServer,
+        client_id: ClientId,
+    ) {
+        send(
+            &mut self.to_server,
+            conditions,
+            random,
+            now,
+            client.get_packets_to_send(),
+        );
+        for packet in take_due(&mut self.to_server, now) {
+            // fails once the client was kicked, there's nobody left to deliver to
+            if server.process_packet_from(&packet, client_id).is_err() {
+                break;
+            }
+        }
+    }
+
+    pub fn carry_to_client(
+        &mut self,
+        conditions: &NetConditions,
+        random: &mut Xorshift,
+        now: f64,
+        server: &mut RenetServer,
+        client: &mut RenetClient,
+        client_id: ClientId,
+    ) {
+        send(
+            &mut self.to_client,
+            conditions,
+            random,
+            now,
+            server.get_packets_to_send(client_id).unwrap_or_default(),
+        );
+        for packet in take_due(&mut self.to_client, now) {
+            client.process_packet(&packet);
+        }
+    }
+}
+
+fn send(
+    queue: &mut VecDeque<InFlight>,
+    conditions: &NetConditions,
+    random: &mut Xorshift,
+    now: f64,
+    packets: Vec<Vec<u8>>,
+) {
+    let in_order_at = queue.back().map_or(0.0, |in_flight| in_flight.in_order_at);
+    let in_order_at = in_order_at.max(now + conditions.latency_ms as f64 / 2000.0);
+    for packet in packets {
+        if random.next_f32() >= conditions.loss {
+            // only drawn with jitter on, so the same seed loses the same packets either way
+            let jitter = if conditions.jitter_ms > 0.0 {
+                random.next_f32() as f64 * conditions.jitter_ms as f64 / 1000.0
+            } else {
+                0.0
+            };
+            queue.push_back(InFlight {
+                in_order_at,
+                deliver_at: in_order_at + jitter,
+                packet,
+            });
+        }
+    }
+}
+
+/// in the order they arrive. without jitter that's the order they were sent in, even when the
+/// latency drops, with it one can overtake another like it would on the internet
+fn take_due(queue: &mut VecDeque<InFlight>, now: f64) -> Vec<Vec<u8>> {
+    let (mut due, waiting): (Vec<_>, Vec<_>) = queue
+        .drain(..)
+        .partition(|in_flight| in_flight.deliver_at <= now);
+    *queue = waiting.into();
+    due.sort_by(|a, b| a.deliver_at.total_cmp(&b.deliver_at));
+    due.into_iter().map(|in_flight| in_flight.packet).collect()
+}
+
+#[derive(Resource, Default)]
+struct Loopback {
+    link: Link,
+    random: Xorshift,
+}
+
+/// a server for `client_ids`, connected from the start and updated by hand
+pub fn build_server_app(locale: &str, client_ids: &[ClientId]) -> Result<App, String> {
+    let map = Map::load(Path::new(MAP_PATH))?;
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.insert_resource(Localization::load(locale));
+    app.insert_resource(map);
+    // never bound, practice mode brings its own connection
+    app.insert_resource(ServerSettings {
+        bind: Ipv4Addr::UNSPECIFIED.into(),
+        port: DEFAULT_PORT,
+        client_bandwidth: DEFAULT_CLIENT_BANDWIDTH,
+        demo_dir: None,
+        afk_timeout: None,
+        afk_action: afk::AfkAction::Spectate,
+        snapshot_interval: 1,
+        motd: None,
+        cheat_kick_score: None,
+    });
+    let mut renet_server = RenetServer::new(ConnectionConfig::default());
+    for client_id in client_ids {
+        renet_server.add_connection(*client_id);
+    }
+    app.insert_resource(renet_server);
+    add_headless_server_plugins(&mut app);
+    app.finish();
+    app.cleanup();
+    Ok(app)
+}
+
+/// everything a server needs and nothing that draws, the input and asset resources some
+/// shared systems ask for are just left empty. the app brings its own `MinimalPlugins`, map
+/// and `ServerSettings`
+pub fn add_headless_server_plugins(app: &mut App) {
+    app.insert_resource(Time::<Fixed>::from_hz(FIXED_HZ));
+    app.insert_resource(LocalPlayer {
+        client_id: ClientId::from_raw(0),
+    });
+    app.init_resource::<ButtonInput<KeyCode>>();
+    app.init_resource::<ButtonInput<MouseButton>>();
+    app.init_resource::<Assets<Mesh>>();
+    app.init_resource::<Assets<StandardMaterial>>();
+    app.init_resource::<Assets<Pitch>>();
+    app.init_resource::<ColorFilter>();
+    app.init_resource::<RenderOrigin>();
+    app.init_resource::<InputHistory>();
+    app.add_event::<ReceivedCharacter>();
+    app.add_event::<AdminCommandEvent>();
+    app.add_event::<SpawnCharacterVisualsEvent>();
+    app.add_event::<HardSnapEvent>();
+
+    app.add_plugins(server::ServerPlugin);
+    app.add_plugins(anticheat::AntiCheatPlugin);
+    app.add_plugins(GameplayPlugins);
+    app.add_plugins(RenetServerPlugin);
+}
+
+fn start_practice_server_system(world: &mut World) {
+    let locale = world.resource::<Settings>().locale.clone();
+    match build_server_app(&locale, &[ClientId::from_raw(PRACTICE_CLIENT_ID)]) {
+        Ok(app) => {
+            println!("Practice server started");
+            world.insert_non_send_resource(PracticeServer { app });
+        }
+        Err(e) => eprintln!("Failed to start the practice server: {}", e),
+    }
+}
+
+/// after everything this frame was sent: carry what's due across, run the server, carry back
+fn loopback_system(
+    time: Res<Time<Real>>,
+    conditions: Res<NetConditions>,
+    practice_server: Option<NonSendMut<PracticeServer>>,
+    mut loopback: ResMut<Loopback>,
+    mut client: ResMut<RenetClient>,
+) {
+    let mut practice_server = match practice_server {
+        Some(practice_server) => practice_server,
+        None => return,
+    };
+    let now = time.elapsed_seconds_f64();
+    let client_id = ClientId::from_raw(PRACTICE_CLIENT_ID);
+    let Loopback { link, random } = &mut *loopback;
+
+    let world = &mut practice_server.app.world;
+    world.resource_mut::<ServerSettings>().snapshot_interval = conditions.snapshot_interval;
+    link.carry_to_server(
+        &conditions,
+        random,
+        now,
+        &mut client,
+        &mut world.resource_mut::<RenetServer>(),
+        client_id,
+    );
+    practice_server.app.update();
+    link.carry_to_client(
+        &conditions,
+        random,
+        now,
+        &mut practice_server.app.world.resource_mut::<RenetServer>(),
+        &mut client,
+        client_id,
+    );
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Setting {
+    Latency,
+    Jitter,
+    Loss,
+    SnapshotRate,
+}
+
+impl Setting {
+    const ALL: [Self; 4] = [Self::Latency, Self::Jitter, Self::Loss, Self::SnapshotRate];
+
+    /// where the slider sits, 0 to 1
+    fn fraction(self, conditions: &NetConditions) -> f32 {
+        match self {
+            Self::Latency => conditions.latency_ms / MAX_LATENCY_MS,
+            Self::Jitter => conditions.jitter_ms / MAX_JITTER_MS,
+            Self::Loss => conditions.loss / MAX_LOSS,
+            // fastest on the right
+            Self::SnapshotRate => {
+                (MAX_SNAPSHOT_INTERVAL - conditions.snapshot_interval) as f32
+                    / (MAX_SNAPSHOT_INTERVAL - 1) as f32
+            }
+        }
+    }
+
+    fn set_fraction(self, conditions: &mut NetConditions, fraction: f32) {
+        let fraction = fraction.clamp(0.0, 1.0);
+        match self {
+            Self::Latency => conditions.latency_ms = (fraction * MAX_LATENCY_MS).round(),
+            Self::Jitter => conditions.jitter_ms = (fraction * MAX_JITTER_MS).round(),
+            Self::Loss => conditions.loss = (fraction * MAX_LOSS * 100.0).round() / 100.0,
+            Self::SnapshotRate => {
+                conditions.snapshot_interval = MAX_SNAPSHOT_INTERVAL
+                    - (fraction * (MAX_SNAPSHOT_INTERVAL - 1) as f32).round() as u32
+            }
+        }
+    }
+
+    fn label(self, localization: &Localization, conditions: &NetConditions) -> String {
+        match self {
+            Self::Latency => localization.format("practice.latency", &[&conditions.latency_ms]),
+            Self::Jitter => localization.format("practice.jitter", &[&conditions.jitter_ms]),
+            Self::Loss => localization.format("practice.loss", &[&(conditions.loss * 100.0)]),
+            Self::SnapshotRate => localization.format(
+                "practice.snapshot_rate",
+                &[&(FIXED_HZ as u32 / conditions.snapshot_interval.max(1))],
+            ),
+        }
+    }
+}
+
+#[derive(Component)]
+struct PracticePanel;
+
+#[derive(Component)]
+struct SettingText(Setting);
+
+#[derive(Component)]
+struct Slider(Setting);
+
+#[derive(Component)]
+struct SliderFill(Setting);
+
+fn setup_panel_system(
+    mut commands: Commands,
+    localization: Res<Localization>,
+    key_labels: Res<KeyLabels>,
+) {
+    let text_style = TextStyle {
+        font_size: 16.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            PracticePanel,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(40.0),
+                    left: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(4.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                localization.format("practice.title", &[&key_labels.label(PANEL_KEY)]),
+                text_style.clone(),
+            ));
+            for setting in Setting::ALL {
+                parent.spawn((
+                    SettingText(setting),
+                    TextBundle::from_section("", text_style.clone()),
+                ));
+                parent
+                    .spawn((
+                        Slider(setting),
+                        Interaction::default(),
+                        RelativeCursorPosition::default(),
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(200.0),
+                                height: Val::Px(12.0),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.3, 0.3, 0.3).into(),
+                            ..default()
+                        },
+                    ))
+                    .with_children(|slider| {
+                        slider.spawn((
+                            SliderFill(setting),
+                            NodeBundle {
+                                style: Style {
+                                    height: Val::Percent(100.0),
+                                    ..default()
+                                },
+                                background_color: Color::rgb(0.9, 0.6, 0.1).into(),
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn panel_toggle_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut panels: Query<&mut Visibility, With<PracticePanel>>,
+) {
+    if !keyboard.just_pressed(PANEL_KEY) {
+        return;
+    }
+    for mut visibility in panels.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+/// held down, the value follows the cursor along the track
+fn slider_system(
+    mut conditions: ResMut<NetConditions>,
+    sliders: Query<(&Slider, &Interaction, &RelativeCursorPosition)>,
+) {
+    for (Slider(setting), interaction, cursor) in sliders.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(position) = cursor.normalized {
+            setting.set_fraction(&mut conditions, position.x);
+        }
+    }
+}
+
+fn panel_text_system(
+    localization: Res<Localization>,
+    conditions: Res<NetConditions>,
+    mut texts: Query<(&SettingText, &mut Text)>,
+    mut fills: Query<(&SliderFill, &mut Style)>,
+) {
+    if !conditions.is_changed() && !localization.is_changed() {
+        return;
+    }
+    for (SettingText(setting), mut text) in texts.iter_mut() {
+        text.sections[0].value = setting.label(&localization, &conditions);
+    }
+    for (SliderFill(setting), mut style) in fills.iter_mut() {
+        style.width = Val::Percent(setting.fraction(&conditions) * 100.0);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{core::*, weapon::fnv1a};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{RenetClient, RenetServer};
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::type_name;
+
+/// components beyond what `CharacterSnapshot` covers, replicated on every entity with a
+/// `NetworkId`: `app.replicate::<T>()` and the server puts each one in its snapshots, diffed
+/// against what the client acked like characters are, and clients insert, update and remove
+/// them to match. they change by snapshot only, so anything the client predicts shouldn't be
+/// replicated this way
+pub trait ReplicateAppExt {
+    fn replicate<T: Component + Serialize + DeserializeOwned>(&mut self) -> &mut Self;
+}
+
+impl ReplicateAppExt for App {
+    fn replicate<T: Component + Serialize + DeserializeOwned>(&mut self) -> &mut Self {
+        let component_id = component_id::<T>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(ReplicationRegistry::default);
+        match registry.types.get(&component_id) {
+            Some(name) if *name == type_name::<T>() => return self,
+            Some(name) => panic!(
+                "{} and {} have the same replication id {}",
+                name,
+                type_name::<T>(),
+                component_id
+            ),
+            None => {
+                registry.types.insert(component_id, type_name::<T>());
+            }
+        }
+        self.init_resource::<CollectedComponents>();
+        self.init_resource::<ReceivedComponents>();
+        self.add_systems(
+            FixedPostUpdate,
+            collect_components_system::<T>
+                .in_set(ReplicationSet::Collect)
+                .run_if(resource_exists::<RenetServer>),
+        );
+        self.add_systems(
+            FixedPreUpdate,
+            apply_components_system::<T>
+                .in_set(ReplicationSet::Apply)
+                .run_if(resource_exists::<RenetClient>),
+        );
+        self
+    }
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReplicationSet {
+    /// on the server, before snapshots are put together
+    Collect,
+    /// on a client, after snapshots are applied and characters spawned
+    Apply,
+}
+
+/// what each id stands for, to catch two types ending up with the same one
+#[derive(Resource, Default)]
+struct ReplicationRegistry {
+    types: HashMap<u32, &'static str>,
+}
+
+/// the server and its clients run the same build, so the type's name is the same on both
+pub fn component_id<T>() -> u32 {
+    fnv1a(type_name::<T>().as_bytes()) as u32
+}
+
+/// this tick's components, taken by the next snapshot. only on the server
+#[derive(Resource, Default)]
+pub struct CollectedComponents {
+    pub entries: Vec<ComponentEntry>,
+}
+
+/// components from snapshots, waiting for their type's system to apply them. anything for an
+/// entity we haven't spawned yet waits for it, the server thinks we already have it and won't
+/// send it again. only on clients
+#[derive(Resource, Default)]
+pub struct ReceivedComponents {
+    pending: HashMap<(u64, u32), Option<Vec<u8>>>,
+}
+
+impl ReceivedComponents {
+    /// newer snapshots last
+    pub fn receive(&mut self, components: &ReplicatedComponents) {
+        for entry in components.iter() {
+            self.pending
+                .insert((entry.network_id, entry.component_id), entry.bytes.clone());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+fn collect_components_system<T: Component + Serialize>(
+    mut collected: ResMut<CollectedComponents>,
+    components: Query<(&NetworkId, &T)>,
+) {
+    let component_id = component_id::<T>();
+    for (network_id, component) in components.iter() {
+        match bincode::serialize(component) {
+            Ok(bytes) => collected.entries.push(ComponentEntry {
+                network_id: network_id.0,
+                component_id,
+                bytes: Some(bytes),
+            }),
+            Err(e) => eprintln!("Failed to serialize {}: {}", type_name::<T>(), e),
+        }
+    }
+}
+
+fn apply_components_system<T: Component + DeserializeOwned>(
+    mut commands: Commands,
+    mut received: ResMut<ReceivedComponents>,
+    entities: Query<(Entity, &NetworkId)>,
+) {
+    let component_id = component_id::<T>();
+    if !received.pending.keys().any(|(_, id)| *id == component_id) {
+        return;
+    }
+    let entities: HashMap<u64, Entity> = entities
+        .iter()
+        .map(|(entity, network_id)| (network_id.0, entity))
+        .collect();
+
+    received.pending.retain(|(network_id, id), bytes| {
+        if *id != component_id {
+            return true;
+        }
+        let entity = match entities.get(network_id) {
+            Some(entity) => *entity,
+            // nothing to take it off of
+            None => return bytes.is_some(),
+        };
+        match bytes {
+            Some(bytes) => match bincode::deserialize::<T>(bytes) {
+                Ok(component) => {
+                    commands.entity(entity).insert(component);
+                }
+                Err(e) => eprintln!("Failed to deserialize {}: {}", type_name::<T>(), e),
+            },
+            None => {
+                commands.entity(entity).remove::<T>();
+            }
+        }
+        false
+    });
+}
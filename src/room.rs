@@ -0,0 +1,313 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    game_mode::{MatchPhase, Matches},
+    localization::Localization,
+    palette::PlayerColor,
+    server::{self, ReliableOutbox},
+    SPAWN_POSITION,
+};
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+/// rooms 0 to one less than this, 0 being where everyone starts
+pub const MAX_ROOMS: u32 = 16;
+
+/// separate arenas in one server process. each room only has the characters of the clients in
+/// it, plays a match of its own and has its own spawn, and each client is only told about, sent
+/// snapshots of, hears from and can only hurt what's in its own room. the map and the clock are
+/// still shared. clients ask to move with `--room`, admins move them with
+/// `room <client id> <room>` and move a room's spawn with `roomspawn <room> <x> <y> <z>`
+pub struct RoomPlugin;
+impl Plugin for RoomPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                admin_room_system,
+                join_room_system,
+                forget_members_system.after(server::handle_connection_events_system),
+            )
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            host_room_visibility_system.run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            request_room_system
+                .run_if(resource_exists::<RenetClient>)
+                .run_if(resource_exists::<RoomRequest>),
+        );
+        app.init_resource::<Rooms>();
+        app.add_event::<JoinRoomEvent>();
+    }
+}
+
+/// which room each client is in, anyone not in here is in room 0
+#[derive(Resource, Default)]
+pub struct Rooms {
+    members: HashMap<ClientId, u32>,
+    /// where characters in each room start and come back to, the usual spawn if not set
+    spawns: HashMap<u32, Vec3>,
+}
+
+impl Rooms {
+    pub fn room_of(&self, client_id: ClientId) -> u32 {
+        self.members.get(&client_id).copied().unwrap_or(0)
+    }
+
+    pub fn same_room(&self, a: ClientId, b: ClientId) -> bool {
+        self.room_of(a) == self.room_of(b)
+    }
+
+    pub fn spawn_point(&self, room: u32) -> Vec3 {
+        self.spawns.get(&room).copied().unwrap_or(SPAWN_POSITION)
+    }
+
+    /// the spawn of the room the client is in
+    pub fn spawn_point_of(&self, client_id: ClientId) -> Vec3 {
+        self.spawn_point(self.room_of(client_id))
+    }
+}
+
+/// a client wants to be in another room, from the client itself or an admin
+#[derive(Event)]
+pub struct JoinRoomEvent {
+    pub client_id: ClientId,
+    pub room: u32,
+}
+
+/// the room this client asks for every time it connects
+#[derive(Resource)]
+pub struct RoomRequest(pub u32);
+
+fn admin_room_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut joins: EventWriter<JoinRoomEvent>,
+    mut rooms: ResMut<Rooms>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        match command {
+            AdminCommand::Room(client_id, room) => {
+                joins.send(JoinRoomEvent {
+                    client_id: *client_id,
+                    room: *room,
+                });
+            }
+            AdminCommand::RoomSpawn(room, spawn) if *room < MAX_ROOMS => {
+                rooms.spawns.insert(*room, *spawn);
+                println!("Room {} spawns at {}", room, spawn);
+            }
+            AdminCommand::RoomSpawn(room, _) => {
+                eprintln!("Room {} doesn't exist, there are {}", room, MAX_ROOMS);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// moves clients between rooms, for anything on the server that sends people elsewhere
+#[derive(SystemParam)]
+pub struct RoomTransfer<'w, 's> {
+    localization: Res<'w, Localization>,
+    rooms: ResMut<'w, Rooms>,
+    matches: ResMut<'w, Matches>,
+    outbox: ResMut<'w, ReliableOutbox>,
+    server: ResMut<'w, RenetServer>,
+    snapshot_history: ResMut<'w, SnapshotHistory>,
+    characters: Query<
+        'w,
+        's,
+        (
+            &'static mut Character,
+            &'static mut Transform,
+            &'static PlayerColor,
+            Option<&'static Cosmetics>,
+            Option<&'static PlayerName>,
+        ),
+    >,
+}
+
+impl RoomTransfer<'_, '_> {
+    /// the character leaves everyone in the old room and turns up at the spawn of the new one,
+    /// where its owner is told about everyone already there and how far along its match is.
+    /// returns the room it left
+    pub fn move_client(&mut self, client_id: ClientId, room: u32) -> Result<u32, String> {
+        if room >= MAX_ROOMS {
+            return Err(format!(
+                "room {} doesn't exist, there are {}",
+                room, MAX_ROOMS
+            ));
+        }
+        if !self.server.is_connected(client_id) {
+            return Err("not connected".to_string());
+        }
+        let old_room = self.rooms.room_of(client_id);
+        if old_room == room {
+            return Err(format!("already in room {}", room));
+        }
+
+        self.send_to_others(
+            old_room,
+            client_id,
+            &ReliableServerMessage::DespawnCharacter(client_id.raw()),
+        );
+        let old_owners: Vec<ClientId> = self
+            .characters
+            .iter()
+            .map(|(character, _, _, _, _)| character.owner_client_id)
+            .filter(|owner| *owner != client_id && self.rooms.room_of(*owner) == old_room)
+            .collect();
+        for owner in old_owners {
+            self.outbox.send(
+                &mut self.server,
+                client_id,
+                &ReliableServerMessage::DespawnCharacter(owner.raw()),
+            );
+        }
+
+        if room == 0 {
+            self.rooms.members.remove(&client_id);
+        } else {
+            self.rooms.members.insert(client_id, room);
+        }
+        // nothing it acked was of this room, the next snapshot is a full one
+        self.snapshot_history.client_views.remove(&client_id);
+
+        if let Some((mut character, mut transform, _, _, _)) = self
+            .characters
+            .iter_mut()
+            .find(|(character, _, _, _, _)| character.owner_client_id == client_id)
+        {
+            transform.translation = self.rooms.spawn_point(room);
+            character.velocity = Vec3::ZERO;
+            character.mantle = None;
+            character.teleport_count = character.teleport_count.wrapping_add(1);
+        }
+        let arrivals: Vec<(ClientId, ReliableServerMessage)> = self
+            .characters
+            .iter()
+            .filter(|(character, _, _, _, _)| self.rooms.room_of(character.owner_client_id) == room)
+            .map(|(character, transform, color, cosmetics, name)| {
+                (
+                    character.owner_client_id,
+                    ReliableServerMessage::SpawnCharacter(
+                        character.owner_client_id.raw(),
+                        color.0,
+                        transform.translation,
+                        character.velocity,
+                        cosmetics.copied().unwrap_or_default(),
+                        name.map_or(String::new(), |name| name.0.clone()),
+                        self.snapshot_history.next_id,
+                    ),
+                )
+            })
+            .collect();
+        for (owner, message) in arrivals {
+            if owner == client_id {
+                self.send_to_others(room, client_id, &message);
+            } else {
+                self.outbox.send(&mut self.server, client_id, &message);
+            }
+        }
+
+        // nothing of the old room's match carries over, the new one's could be over already
+        let match_state = self.matches.of(room);
+        let mut match_messages = vec![ReliableServerMessage::MatchStarted];
+        if let Some(result) = match_state
+            .last_result
+            .clone()
+            .filter(|_| !match_state.is_in_progress())
+        {
+            match_messages.push(ReliableServerMessage::MatchEnded(result));
+            if let MatchPhase::Results(_) = match_state.phase {
+                match_messages.push(ReliableServerMessage::ShowMatchResults);
+            }
+        }
+        for message in &match_messages {
+            self.outbox.send(&mut self.server, client_id, message);
+        }
+
+        let notice = self.localization.format("room.joined", &[&room]);
+        self.outbox.send(
+            &mut self.server,
+            client_id,
+            &ReliableServerMessage::Chat(None, notice),
+        );
+        Ok(old_room)
+    }
+
+    /// everyone connected in `room` but `client_id`
+    fn send_to_others(&mut self, room: u32, client_id: ClientId, message: &ReliableServerMessage) {
+        let members: Vec<ClientId> = self
+            .server
+            .clients_id()
+            .into_iter()
+            .filter(|member| *member != client_id && self.rooms.room_of(*member) == room)
+            .collect();
+        for member in members {
+            self.outbox.send(&mut self.server, member, message);
+        }
+    }
+}
+
+fn join_room_system(mut joins: EventReader<JoinRoomEvent>, mut transfer: RoomTransfer) {
+    for JoinRoomEvent { client_id, room } in joins.read() {
+        match transfer.move_client(*client_id, *room) {
+            Ok(old_room) => println!(
+                "Client {} moved from room {} to {}",
+                client_id, old_room, room
+            ),
+            Err(e) => eprintln!("Failed to move client {}: {}", client_id, e),
+        }
+    }
+}
+
+/// runs after the disconnect handling, which still needs to know whose room they were in
+fn forget_members_system(mut rooms: ResMut<Rooms>, server: Res<RenetServer>) {
+    rooms
+        .members
+        .retain(|client_id, _| server.is_connected(*client_id));
+}
+
+/// a listen server's host has every character in the world, so the ones in other rooms are
+/// only hidden
+fn host_room_visibility_system(
+    local_player: Res<LocalPlayer>,
+    rooms: Res<Rooms>,
+    mut visuals: Query<(&CharacterVisuals, &mut Visibility)>,
+) {
+    let host_room = rooms.room_of(local_player.client_id);
+    for (visuals, mut visibility) in visuals.iter_mut() {
+        let shown = if rooms.room_of(visuals.owner_client_id) == host_room {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != shown {
+            *visibility = shown;
+        }
+    }
+}
+
+/// asked for again after every reconnect, the server starts everyone off in room 0
+fn request_room_system(
+    room_request: Res<RoomRequest>,
+    mut requested: Local<bool>,
+    mut client: ResMut<RenetClient>,
+) {
+    if !client.is_connected() {
+        *requested = false;
+        return;
+    }
+    if *requested {
+        return;
+    }
+    *requested = true;
+    if let Ok(message) = bincode::serialize(&ReliableClientMessage::JoinRoom(room_request.0)) {
+        client.send_message(DefaultChannel::ReliableOrdered, message);
+    }
+}
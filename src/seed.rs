@@ -0,0 +1,55 @@
+use crate::{
+    core::*,
+    practice::Xorshift,
+    server::{reset_world_system, ReliableOutbox, ResetWorldEvent},
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer};
+
+/// a new `MatchSeed` for every world the server starts, sent out before the world is reset so
+/// clients have it before anything spawns in the new one
+pub struct MatchSeedPlugin;
+impl Plugin for MatchSeedPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            new_seed_system
+                .before(reset_world_system)
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            receive_seed_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.init_resource::<MatchSeed>();
+        app.add_event::<ReceivedMatchSeedEvent>();
+    }
+}
+
+/// the server told us the seed of the match
+#[derive(Event)]
+pub struct ReceivedMatchSeedEvent(pub u64);
+
+fn new_seed_system(
+    mut resets: EventReader<ResetWorldEvent>,
+    mut match_seed: ResMut<MatchSeed>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    // the first world starts with the server, without a reset
+    if resets.read().count() == 0 && !server.is_added() {
+        return;
+    }
+    match_seed.0 = Xorshift::default().next_u64();
+    println!("Match seed {}", match_seed.0);
+    outbox.broadcast(&mut server, &ReliableServerMessage::MatchSeed(match_seed.0));
+}
+
+fn receive_seed_system(
+    mut received: EventReader<ReceivedMatchSeedEvent>,
+    mut match_seed: ResMut<MatchSeed>,
+) {
+    if let Some(ReceivedMatchSeedEvent(seed)) = received.read().last() {
+        match_seed.0 = *seed;
+    }
+}
@@ -1,10 +1,37 @@
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     time::SystemTime,
 };
 
-use crate::core::*;
-use bevy::{prelude::*, utils::HashMap};
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    afk::{ClientActivityEvent, IncomingRejoinEvent},
+    anticheat::{self, InputsReceivedEvent, ViolationEvent, ViolationKind},
+    chat::IncomingChatEvent,
+    class::IncomingChooseClassEvent,
+    core::*,
+    cosmetics::{self, ClientCosmetics},
+    demo::DemoRecorder,
+    emote::IncomingEmoteEvent,
+    game_mode::Matches,
+    host_stats::HostStats,
+    inventory::IncomingInventoryActionEvent,
+    map::Map,
+    nameplate::{self, ClientNames, NameFilter},
+    palette::PlayerColor,
+    ping::IncomingPingEvent,
+    replication::{CollectedComponents, ReplicationSet},
+    room::{JoinRoomEvent, Rooms},
+    vote::IncomingVoteEvent,
+    weapon::{self, WeaponDefinitions},
+    KILL_Z, WORLD_HALF_EXTENT,
+};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_renet::renet::{
     transport::{NetcodeServerTransport, ServerAuthentication, ServerConfig},
     ClientId, ConnectionConfig, DefaultChannel, RenetServer, ServerEvent,
@@ -13,102 +40,612 @@ use bevy_renet::renet::{
 pub struct ServerPlugin;
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, start_server_system);
-        app.add_systems(FixedPreUpdate, handle_connection_events_system);
-        app.add_systems(FixedPreUpdate, receive_inputs_system);
-        app.add_systems(FixedUpdate, input_processing_system);
-        app.add_systems(FixedPostUpdate, snapshot_send_system);
+        // single player only gets `ServerSettings` once it's opened to LAN, so nothing here
+        // runs until the server exists
+        app.add_systems(
+            Update,
+            start_server_system.run_if(resource_added::<ServerSettings>),
+        );
+        app.add_systems(
+            FixedPreUpdate,
+            (handle_connection_events_system, receive_inputs_system)
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            (
+                receive_reliable_messages_system,
+                admin_status_system,
+                admin_interest_system,
+                admin_character_commands_system,
+                change_map_system,
+            )
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                reset_world_system,
+                host_inputs_system,
+                input_processing_system,
+                apply_impulses_system,
+                world_bounds_system,
+            )
+                .chain()
+                .after(crate::input::apply_inputs_system)
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            FixedPostUpdate,
+            (snapshot_send_system, flush_reliable_outbox_system)
+                .after(ReplicationSet::Collect)
+                .run_if(resource_exists::<RenetServer>),
+        );
         app.init_resource::<SnapshotHistory>();
+        app.init_resource::<CollectedComponents>();
         app.init_resource::<PlayerInputCache>();
+        app.init_resource::<ClientBandwidth>();
+        app.init_resource::<InterestReports>();
+        app.init_resource::<ReliableOutbox>();
+        app.init_resource::<ClientCosmetics>();
+        app.init_resource::<ClientNames>();
+        app.insert_resource(NameFilter::load_or_default());
+        app.init_resource::<WorldBounds>();
+        app.add_event::<ImpulseEvent>();
+        app.add_event::<LocalInputsEvent>();
+        app.add_event::<ResetWorldEvent>();
+        app.add_event::<ChangeMapEvent>();
     }
 }
 
+/// start the world over (next match, new map): everyone goes back to the spawn and clients
+/// rebuild their world from scratch
+#[derive(Event)]
+pub struct ResetWorldEvent;
+
+/// switch everyone to another map by name and start the world over on it
+#[derive(Event)]
+pub struct ChangeMapEvent(pub String);
+
+/// a tick of input from a player on this machine other than the host, e.g. split-screen
+#[derive(Event)]
+pub struct LocalInputsEvent {
+    pub client_id: ClientId,
+    pub input_group: Vec<PlayerInput>,
+}
+
+/// characters that leave this box are killed and put back at the spawn
+#[derive(Resource)]
+pub struct WorldBounds {
+    pub half_extent: f32,
+    pub kill_z: f32,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            half_extent: WORLD_HALF_EXTENT,
+            kill_z: KILL_Z,
+        }
+    }
+}
+
+impl WorldBounds {
+    fn contains(&self, translation: Vec3) -> bool {
+        translation.is_finite()
+            && translation.y >= self.kill_z
+            && translation.x.abs() <= self.half_extent
+            && translation.z.abs() <= self.half_extent
+    }
+}
+
+/// how many ticks worth of budget a quiet client can save up for a burst
+const BANDWIDTH_BURST_TICKS: f32 = 4.0;
+
 #[derive(Resource, Default)]
-struct PlayerInputCache {
+struct ClientBandwidth {
+    clients: HashMap<ClientId, BandwidthBudget>,
+}
+
+struct BandwidthBudget {
+    available_bytes: f32,
+    /// whether the last snapshot had to leave characters out
+    over_budget: bool,
+}
+
+/// reliable bytes each client can be sent per tick before the rest waits for the next one
+const RELIABLE_BYTES_PER_TICK: usize = 4096;
+
+/// every reliable message to clients goes through here rather than straight to renet. each
+/// client gets at most `RELIABLE_BYTES_PER_TICK` a tick and never more than its channel has room
+/// for, anything beyond that waits in order instead of getting the client disconnected
+#[derive(Resource, Default)]
+pub struct ReliableOutbox {
+    clients: HashMap<ClientId, ClientOutbox>,
+}
+
+#[derive(Default)]
+struct ClientOutbox {
+    queued: VecDeque<Vec<u8>>,
+    sent_this_tick: usize,
+}
+
+impl ReliableOutbox {
+    pub fn send(
+        &mut self,
+        server: &mut RenetServer,
+        client_id: ClientId,
+        message: &ReliableServerMessage,
+    ) {
+        // players on this machine aren't connected, there's nobody to send to
+        if !server.is_connected(client_id) {
+            return;
+        }
+        if let Some(bytes) = encode_reliable(message) {
+            self.push(server, client_id, bytes);
+        }
+    }
+
+    pub fn broadcast(&mut self, server: &mut RenetServer, message: &ReliableServerMessage) {
+        self.broadcast_filtered(server, message, |_| true);
+    }
+
+    /// serialized once for all the clients `filter` accepts
+    pub fn broadcast_filtered(
+        &mut self,
+        server: &mut RenetServer,
+        message: &ReliableServerMessage,
+        filter: impl Fn(ClientId) -> bool,
+    ) {
+        if let Some(bytes) = encode_reliable(message) {
+            for client_id in server.clients_id() {
+                if filter(client_id) {
+                    self.push(server, client_id, bytes.clone());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, server: &mut RenetServer, client_id: ClientId, bytes: Vec<u8>) {
+        let outbox = self.clients.entry(client_id).or_default();
+        outbox.queued.push_back(bytes);
+        outbox.flush(server, client_id);
+    }
+}
+
+impl ClientOutbox {
+    /// oldest first, stopping at the first one that doesn't fit so nothing overtakes it
+    fn flush(&mut self, server: &mut RenetServer, client_id: ClientId) {
+        while let Some(size) = self.queued.front().map(|bytes| bytes.len()) {
+            // a single message bigger than the whole budget still has to go out eventually
+            let within_budget =
+                self.sent_this_tick == 0 || self.sent_this_tick + size <= RELIABLE_BYTES_PER_TICK;
+            if !within_budget
+                || !server.can_send_message(client_id, DefaultChannel::ReliableOrdered, size)
+            {
+                break;
+            }
+            if let Some(bytes) = self.queued.pop_front() {
+                self.sent_this_tick += size;
+                server.send_message(client_id, DefaultChannel::ReliableOrdered, bytes);
+            }
+        }
+    }
+}
+
+fn encode_reliable(message: &ReliableServerMessage) -> Option<Vec<u8>> {
+    match bincode::serialize(message) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            eprintln!("Failed to serialize reliable message: {}", e);
+            None
+        }
+    }
+}
+
+/// spawns replicated entities on the server (or single player) in one go: the entity with its
+/// `NetworkId` here, and the reliable message that has every client spawn its own. snapshots
+/// cover every character there is, so nothing else needs telling about it
+#[derive(SystemParam)]
+pub struct NetSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    spawn_visuals: EventWriter<'w, SpawnCharacterVisualsEvent>,
+    outbox: ResMut<'w, ReliableOutbox>,
+    server: Option<ResMut<'w, RenetServer>>,
+    rooms: Res<'w, Rooms>,
+    snapshot_history: Res<'w, SnapshotHistory>,
+    pub cosmetics: ResMut<'w, ClientCosmetics>,
+    pub names: ResMut<'w, ClientNames>,
+}
+
+impl NetSpawner<'_, '_> {
+    pub fn spawn_character(
+        &mut self,
+        client_id: ClientId,
+        color: PlayerColor,
+        translation: Vec3,
+        velocity: Vec3,
+    ) -> Entity {
+        let entity = crate::spawn_character(
+            client_id,
+            color,
+            &mut self.spawn_visuals,
+            &mut self.commands,
+            translation,
+            velocity,
+        );
+        let cosmetics = self.cosmetics.get(client_id);
+        let name = self.names.get(client_id);
+        self.commands.entity(entity).insert((
+            SimulationLod::PLAYER,
+            cosmetics,
+            PlayerName(name.clone()),
+        ));
+        self.send_to_room(
+            self.rooms.room_of(client_id),
+            &ReliableServerMessage::SpawnCharacter(
+                client_id.raw(),
+                color.0,
+                translation,
+                velocity,
+                cosmetics,
+                name,
+                self.snapshot_history.next_id,
+            ),
+        );
+        entity
+    }
+
+    /// gone here and on every client in its room
+    pub fn despawn_character(&mut self, entity: Entity, client_id: ClientId) {
+        self.commands.entity(entity).despawn();
+        self.send_to_room(
+            self.rooms.room_of(client_id),
+            &ReliableServerMessage::DespawnCharacter(client_id.raw()),
+        );
+    }
+
+    /// tells one client about a character that's already there, for someone who just joined
+    pub fn send_character(
+        &mut self,
+        recipient: ClientId,
+        character: &Character,
+        transform: &Transform,
+        color: PlayerColor,
+        cosmetics: Cosmetics,
+        name: &str,
+    ) {
+        self.send(
+            recipient,
+            &ReliableServerMessage::SpawnCharacter(
+                character.owner_client_id.raw(),
+                color.0,
+                transform.translation,
+                character.velocity,
+                cosmetics,
+                name.to_string(),
+                self.snapshot_history.next_id,
+            ),
+        );
+    }
+
+    /// through the outbox like any reliable message, nothing without a server
+    pub fn send(&mut self, client_id: ClientId, message: &ReliableServerMessage) {
+        if let Some(server) = self.server.as_mut() {
+            self.outbox.send(server, client_id, message);
+        }
+    }
+
+    /// turns away a client that just connected, before anything was sent to it
+    pub fn reject(&mut self, client_id: ClientId) {
+        if let Some(server) = self.server.as_mut() {
+            server.disconnect(client_id);
+        }
+    }
+
+    pub fn send_to_room(&mut self, room: u32, message: &ReliableServerMessage) {
+        if let Some(server) = self.server.as_mut() {
+            for client_id in server.clients_id() {
+                if self.rooms.room_of(client_id) == room {
+                    self.outbox.send(server, client_id, message);
+                }
+            }
+        }
+    }
+}
+
+/// the last snapshot each client was sent, kept for the `interest` admin command
+#[derive(Resource, Default)]
+struct InterestReports {
+    clients: HashMap<ClientId, InterestReport>,
+}
+
+struct InterestReport {
+    snapshot_id: u32,
+    baseline_id: Option<u32>,
+    message_size: usize,
+    available_bytes: f32,
+    /// every character that exists, the ones that got as far as the budget in priority order
+    entries: Vec<InterestEntry>,
+}
+
+struct InterestEntry {
+    client_id: u64,
+    /// squared distance to the receiver, lower goes first. none if it never got to the budget
+    priority: Option<f32>,
+    size: u64,
+    decision: InterestDecision,
+}
+
+#[derive(PartialEq)]
+enum InterestDecision {
+    Included,
+    /// nothing changed since the baseline, so there was nothing to send
+    Unchanged,
+    /// didn't fit in what was left of the bandwidth budget after higher priorities
+    OverBudget,
+}
+
+#[derive(Resource, Default)]
+pub struct PlayerInputCache {
     inputs: HashMap<ClientId, PlayerInputCacheEntry>,
 }
 
+impl PlayerInputCache {
+    /// the most input groups any one client has waiting
+    pub fn longest_backlog(&self) -> usize {
+        self.inputs
+            .values()
+            .map(|entry| entry.input_groups.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// characters that are only repeating their owner's last input, because nothing newer came in,
+/// are stepped less often when nobody in their room is near enough to see the difference. inputs
+/// that did come in are always played tick by tick, like the client predicted them. added per
+/// kind of character when it's spawned, anything without it runs every tick
+#[derive(Component, Clone, Copy)]
+pub struct SimulationLod {
+    /// closer than this to anyone else's character in its room, every tick is simulated
+    pub full_rate_distance: f32,
+    /// ticks between steps further out, each step covering all of them
+    pub far_interval: u32,
+    pending_ticks: u32,
+}
+
+impl SimulationLod {
+    pub const PLAYER: Self = Self::new(40.0, 4);
+
+    pub const fn new(full_rate_distance: f32, far_interval: u32) -> Self {
+        Self {
+            full_rate_distance,
+            far_interval,
+            pending_ticks: 0,
+        }
+    }
+
+    /// how many ticks to step this one, none if it's skipped
+    pub fn step(&mut self, far: bool) -> Option<u32> {
+        self.pending_ticks += 1;
+        if far && self.pending_ticks < self.far_interval {
+            return None;
+        }
+        Some(self.take_pending())
+    }
+
+    /// the ticks skipped so far, so they can be caught up on before anything else
+    pub fn take_pending(&mut self) -> u32 {
+        std::mem::take(&mut self.pending_ticks)
+    }
+}
+
 #[derive(Resource, Default)]
 struct PlayerInputCacheEntry {
     input_groups: Vec<Vec<PlayerInput>>,
     latest_processed_input: Option<PlayerInput>,
     client_latest_processed_snapshot_id: Option<u32>,
+    /// how many ticks of inputs the client bundles into each message
+    groups_per_message: usize,
+    /// where the client says one of its inputs left it, until that input is processed
+    claimed_translation: Option<(u32, Vec3)>,
+    /// how far off that claim was, until the next snapshot tells the client
+    reconciliation: Option<(u32, Vec3)>,
 }
 
-fn start_server_system(mut commands: Commands, server_settings: Res<ServerSettings>) {
-    let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), server_settings.port);
-    if let Ok(socket) = UdpSocket::bind(server_addr) {
-        let server_config = ServerConfig {
-            current_time: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap(),
-            max_clients: 64,
-            protocol_id: 0,
-            public_addresses: vec![server_addr],
-            authentication: ServerAuthentication::Unsecure,
-        };
-
-        if let Ok(transport) = NetcodeServerTransport::new(server_config, socket) {
-            commands.insert_resource(LocalPlayer {
-                client_id: ClientId::from_raw(0),
-            });
-            commands.insert_resource(RenetServer::new(ConnectionConfig::default()));
-            commands.insert_resource(transport);
+fn start_server_system(
+    mut commands: Commands,
+    server_settings: Res<ServerSettings>,
+    server: Option<Res<RenetServer>>,
+) {
+    // practice mode brings its own, wired straight to the client
+    if server.is_some() {
+        return;
+    }
+    let server_addr = SocketAddr::new(server_settings.bind, server_settings.port);
+    let socket = match UdpSocket::bind(server_addr) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Failed to bind server socket {}: {}", server_addr, e);
+            return;
         }
+    };
+    let addresses = public_addresses(server_addr);
+
+    let server_config = ServerConfig {
+        current_time: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap(),
+        max_clients: 64,
+        protocol_id: 0,
+        public_addresses: addresses.clone(),
+        authentication: ServerAuthentication::Unsecure,
+    };
+
+    if let Ok(transport) = NetcodeServerTransport::new(server_config, socket) {
+        println!("Server listening on {:?}", addresses);
+        commands.insert_resource(RenetServer::new(ConnectionConfig::default()));
+        commands.insert_resource(transport);
     }
 }
 
-fn handle_connection_events_system(
-    characters: Query<(&Character, &Transform)>,
-    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
-    mut commands: Commands,
+/// what clients can reach us at. listening on every IPv6 address takes IPv4 clients too on a
+/// dual-stack socket, so both are given
+fn public_addresses(server_addr: SocketAddr) -> Vec<SocketAddr> {
+    if server_addr.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+        vec![
+            server_addr,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), server_addr.port()),
+        ]
+    } else {
+        vec![server_addr]
+    }
+}
+
+pub fn handle_connection_events_system(
+    map: Res<Map>,
+    rooms: Res<Rooms>,
+    mutators: Res<Mutators>,
+    match_seed: Res<MatchSeed>,
+    weapons: Res<WeaponDefinitions>,
+    transport: Option<Res<NetcodeServerTransport>>,
+    name_filter: Res<NameFilter>,
+    characters: Query<(
+        Entity,
+        &Character,
+        &Transform,
+        &PlayerColor,
+        Option<&Cosmetics>,
+        Option<&PlayerName>,
+    )>,
     mut server_events: EventReader<ServerEvent>,
     mut input_buffer: ResMut<PlayerInputCache>,
-    mut server: ResMut<RenetServer>,
+    mut duplicates: Local<HashSet<ClientId>>,
+    mut spawner: NetSpawner,
 ) {
+    // characters spawned this tick aren't queryable yet
+    let mut taken_colors: Vec<PlayerColor> = characters
+        .iter()
+        .map(|(_, _, _, color, _, _)| *color)
+        .collect();
+
     for event in server_events.read() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
-                let start_position = Vec3::new(0.0, 0.0, 0.0);
-                let start_velocity = Vec3::ZERO;
-
-                crate::spawn_character(
-                    *client_id,
-                    &mut spawn_visuals,
-                    &mut commands,
-                    start_position,
-                    start_velocity,
-                );
+                // the ids of players on this machine can't be taken from outside
+                if transport.is_some() && client_id.raw() < RESERVED_CLIENT_IDS {
+                    println!("Rejecting client {}: that id is reserved", client_id);
+                    duplicates.insert(*client_id);
+                    spawner.reject(*client_id);
+                    continue;
+                }
+                // netcode only turns away ids it has itself, not one already let in some other
+                // way or that still has a character here
+                if spawner.names.contains(*client_id)
+                    || characters
+                        .iter()
+                        .any(|(_, character, _, _, _, _)| character.owner_client_id == *client_id)
+                {
+                    println!("Rejecting client {}: that id is already taken", client_id);
+                    duplicates.insert(*client_id);
+                    spawner.reject(*client_id);
+                    continue;
+                }
+                // clients in the same process read the same file, only remote ones can differ
+                let user_data = transport
+                    .as_ref()
+                    .and_then(|transport| transport.user_data(*client_id));
+                let weapons_hash =
+                    user_data.map(|user_data| weapon::hash_from_user_data(&user_data));
+                if weapons_hash.is_some_and(|hash| hash != weapons.hash) {
+                    println!(
+                        "Rejecting client {}: their weapon definitions differ from ours",
+                        client_id
+                    );
+                    spawner.reject(*client_id);
+                    continue;
+                }
+                // nobody else hears of it before it's checked
+                let name = match user_data.map(|user_data| nameplate::from_user_data(&user_data)) {
+                    Some(Some(name)) => match name_filter.check(&name) {
+                        Ok(()) => spawner.names.unique(&name),
+                        Err(e) => {
+                            println!(
+                                "Rejecting client {}: {:?} can't be used, {}",
+                                client_id, name, e
+                            );
+                            spawner.reject(*client_id);
+                            continue;
+                        }
+                    },
+                    Some(None) => {
+                        println!("Rejecting client {}: their name isn't text", client_id);
+                        spawner.reject(*client_id);
+                        continue;
+                    }
+                    // players on this machine don't have one
+                    None => String::new(),
+                };
+
+                // the id everything about them goes by, then before anything that moves, so they
+                // predict on the right map with the right rules and the same random numbers from
+                // the start
+                spawner.send(*client_id, &ReliableServerMessage::Welcome(client_id.raw()));
+                spawner.send(*client_id, &ReliableServerMessage::Map(map.name.clone()));
+                spawner.send(*client_id, &ReliableServerMessage::Mutators(*mutators));
+                spawner.send(*client_id, &ReliableServerMessage::MatchSeed(match_seed.0));
 
-                // tell them to spawn it
-                if let Ok(message) = bincode::serialize(&ReliableServerMessage::SpawnCharacter(
-                    client_id.raw(),
-                    start_position,
-                    start_velocity,
-                )) {
-                    server.send_message(*client_id, DefaultChannel::ReliableUnordered, message);
-                }
-
-                // tell them to spawn all existing characters
-                for (character, transform) in characters.iter() {
-                    if let Ok(message) = bincode::serialize(&ReliableServerMessage::SpawnCharacter(
-                        character.owner_client_id.raw(),
-                        transform.translation,
-                        character.velocity,
-                    )) {
-                        server.send_message(*client_id, DefaultChannel::ReliableUnordered, message);
+                if let Some(user_data) = user_data {
+                    spawner
+                        .cosmetics
+                        .insert(*client_id, cosmetics::from_user_data(&user_data));
+                }
+                spawner.names.insert(*client_id, name);
+                let color = PlayerColor::first_free(&taken_colors);
+                taken_colors.push(color);
+                spawner.spawn_character(*client_id, color, rooms.spawn_point(0), Vec3::ZERO);
+
+                // tell them to spawn all existing characters in the room everyone starts in
+                for (_, character, transform, color, cosmetics, name) in characters.iter() {
+                    if rooms.room_of(character.owner_client_id) == 0 {
+                        spawner.send_character(
+                            *client_id,
+                            character,
+                            transform,
+                            *color,
+                            cosmetics.copied().unwrap_or_default(),
+                            name.map_or("", |name| name.0.as_str()),
+                        );
                     }
                 }
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 println!("Client disconnected: {:?} ({:?})", client_id, reason);
+                // turned away for having someone else's id, everything under it is theirs
+                if duplicates.remove(client_id) {
+                    continue;
+                }
                 input_buffer.inputs.remove(client_id);
+                spawner.cosmetics.remove(*client_id);
+                spawner.names.remove(*client_id);
+
+                // frees their color and stops them showing up in snapshots
+                for (entity, character, _, _, _, _) in characters.iter() {
+                    if character.owner_client_id == *client_id {
+                        spawner.despawn_character(entity, *client_id);
+                    }
+                }
             }
         }
     }
 }
 
 fn receive_inputs_system(
+    snapshot_history: Res<SnapshotHistory>,
+    mut activity: EventWriter<ClientActivityEvent>,
+    mut inputs_received: EventWriter<InputsReceivedEvent>,
+    mut violations: EventWriter<ViolationEvent>,
     mut input_buffer: ResMut<PlayerInputCache>,
     mut server: ResMut<RenetServer>,
 ) {
@@ -117,17 +654,158 @@ fn receive_inputs_system(
             if let Ok(message) = bincode::deserialize::<UnreliableClientMessage>(&message) {
                 match message {
                     UnreliableClientMessage::PlayerInputMessage(message) => {
+                        // one bad input spoils the message, the next one repeats most of it
+                        if !message
+                            .input_groups
+                            .iter()
+                            .flatten()
+                            .all(PlayerInput::is_finite)
+                            || !message
+                                .claimed_translation
+                                .is_none_or(|(_, translation)| translation.is_finite())
+                        {
+                            report_non_finite("input from client");
+                            violations.send(ViolationEvent {
+                                client_id,
+                                kind: ViolationKind::NonFinite,
+                            });
+                            continue;
+                        }
                         let player_inputs =
                             input_buffer.inputs.entry(client_id).or_insert_with(|| {
                                 PlayerInputCacheEntry {
                                     input_groups: Vec::new(),
                                     latest_processed_input: None,
                                     client_latest_processed_snapshot_id: None,
+                                    groups_per_message: 1,
+                                    claimed_translation: None,
+                                    reconciliation: None,
                                 }
                             });
+                        let mut previous = player_inputs
+                            .input_groups
+                            .last()
+                            .and_then(|inputs| inputs.last())
+                            .or(player_inputs.latest_processed_input.as_ref())
+                            .cloned()
+                            .unwrap_or_default();
+                        let mut active = false;
+                        let mut largest_turn: f32 = 0.0;
+                        for input in message.input_groups.iter().flatten() {
+                            active |= input.is_activity_since(&previous);
+                            largest_turn =
+                                largest_turn.max(anticheat::turn_between(&previous, input));
+                            previous = input.clone();
+                        }
+                        if active {
+                            activity.send(ClientActivityEvent(client_id));
+                        }
+                        inputs_received.send(InputsReceivedEvent {
+                            client_id,
+                            group_count: message.input_groups.len(),
+                            largest_group: message
+                                .input_groups
+                                .iter()
+                                .map(Vec::len)
+                                .max()
+                                .unwrap_or(0),
+                            largest_turn,
+                        });
+
                         player_inputs.client_latest_processed_snapshot_id =
                             message.latest_processed_snapshot_id;
-                        player_inputs.input_groups.push(message.inputs);
+                        player_inputs.groups_per_message = message.input_groups.len().max(1);
+                        if message.claimed_translation.is_some() {
+                            player_inputs.claimed_translation = message.claimed_translation;
+                        }
+                        player_inputs.input_groups.extend(message.input_groups);
+                        // a flood only ever costs the flooder its oldest inputs
+                        evict_oldest(
+                            &mut player_inputs.input_groups,
+                            MAX_INPUT_GROUPS,
+                            "cached input groups",
+                        );
+                    }
+                    // this runs as a tick starts, the one the next snapshot is taken on
+                    UnreliableClientMessage::TimeRequest(sent_at) => {
+                        if let Ok(message) =
+                            bincode::serialize(&UnreliableServerMessage::TimeResponse(
+                                sent_at,
+                                snapshot_history.next_id as f64,
+                            ))
+                        {
+                            server.send_message(client_id, DefaultChannel::Unreliable, message);
+                        }
+                    }
+                    // netcode keeps the connection open by itself, there's nothing more to it
+                    UnreliableClientMessage::Heartbeat => {}
+                }
+            }
+        }
+    }
+}
+
+fn receive_reliable_messages_system(
+    mut chat: EventWriter<IncomingChatEvent>,
+    mut emotes: EventWriter<IncomingEmoteEvent>,
+    mut inventory_actions: EventWriter<IncomingInventoryActionEvent>,
+    mut pings: EventWriter<IncomingPingEvent>,
+    mut rejoins: EventWriter<IncomingRejoinEvent>,
+    mut votes: EventWriter<IncomingVoteEvent>,
+    mut joins: EventWriter<JoinRoomEvent>,
+    mut classes: EventWriter<IncomingChooseClassEvent>,
+    mut server: ResMut<RenetServer>,
+) {
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, DefaultChannel::ReliableOrdered)
+        {
+            if let Ok(message) = bincode::deserialize::<ReliableClientMessage>(&message) {
+                match message {
+                    ReliableClientMessage::Chat(text) => {
+                        chat.send(IncomingChatEvent {
+                            sender: client_id,
+                            text,
+                        });
+                    }
+                    ReliableClientMessage::Action(action_id, action) => match action {
+                        PredictedAction::Emote(emote) => {
+                            emotes.send(IncomingEmoteEvent {
+                                sender: client_id,
+                                emote,
+                                action_id: Some(action_id),
+                            });
+                        }
+                        PredictedAction::Inventory(action) => {
+                            inventory_actions.send(IncomingInventoryActionEvent {
+                                sender: client_id,
+                                action,
+                                action_id: Some(action_id),
+                            });
+                        }
+                    },
+                    ReliableClientMessage::Ping(position) => {
+                        pings.send(IncomingPingEvent {
+                            sender: client_id,
+                            position,
+                        });
+                    }
+                    ReliableClientMessage::Rejoin => {
+                        rejoins.send(IncomingRejoinEvent { sender: client_id });
+                    }
+                    ReliableClientMessage::Vote(request) => {
+                        votes.send(IncomingVoteEvent {
+                            sender: client_id,
+                            request,
+                        });
+                    }
+                    ReliableClientMessage::JoinRoom(room) => {
+                        joins.send(JoinRoomEvent { client_id, room });
+                    }
+                    ReliableClientMessage::ChooseClass(class) => {
+                        classes.send(IncomingChooseClassEvent {
+                            sender: client_id,
+                            class,
+                        });
                     }
                 }
             }
@@ -135,110 +813,686 @@ fn receive_inputs_system(
     }
 }
 
+/// a new tick's budget, spent first on whatever has been waiting
+fn flush_reliable_outbox_system(
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    outbox
+        .clients
+        .retain(|client_id, _| server.is_connected(*client_id));
+    for (client_id, client_outbox) in outbox.clients.iter_mut() {
+        client_outbox.sent_this_tick = 0;
+        client_outbox.flush(&mut server, *client_id);
+    }
+}
+
 fn snapshot_send_system(
-    input_buffer: Res<PlayerInputCache>,
+    fixed_time: Res<Time<Fixed>>,
+    server_settings: Res<ServerSettings>,
+    rooms: Res<Rooms>,
+    mut input_buffer: ResMut<PlayerInputCache>,
     characters: Query<(&Character, &Transform)>,
+    mut collected: ResMut<CollectedComponents>,
+    mut bandwidth: ResMut<ClientBandwidth>,
+    mut interest_reports: ResMut<InterestReports>,
     mut server: ResMut<RenetServer>,
     mut snapshot_history: ResMut<SnapshotHistory>,
 ) {
-    let mut snapshot = Snapshot {
+    // collected every tick, only the latest go out
+    let components = std::mem::take(&mut collected.entries);
+    // ids keep counting through the ticks that are skipped, so they stay one per tick
+    if !snapshot_history
+        .next_id
+        .is_multiple_of(server_settings.snapshot_interval.max(1))
+    {
+        snapshot_history.next_id += 1;
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    let snapshot = Snapshot {
         id: snapshot_history.next_id,
-        timestamp: SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis(),
+        timestamp: now,
         latest_processed_input_id: None,
         character_snapshots: characters
             .iter()
             .map(|(character, transform)| CharacterSnapshot::from_character(character, transform))
             .collect(),
+        removed_client_ids: Vec::new(),
+        components: components.into_iter().collect(),
+        reconciliation: None,
     };
 
-    // retain snapshots up to a second ago
-    snapshot_history.snapshots.retain(|snapshot| {
-        snapshot.timestamp
-            > SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-                - 1000
-    });
+    let translations: HashMap<u64, Vec3> = characters
+        .iter()
+        .map(|(character, transform)| (character.owner_client_id.raw(), transform.translation))
+        .collect();
+    // forget clients that left
+    bandwidth
+        .clients
+        .retain(|client_id, _| server.is_connected(*client_id));
+    snapshot_history
+        .client_views
+        .retain(|client_id, _| server.is_connected(*client_id));
+    interest_reports
+        .clients
+        .retain(|client_id, _| server.is_connected(*client_id));
+
+    let budget_per_tick = server_settings.client_bandwidth as f32 * fixed_time.delta_seconds();
+    // every room is a stream of its own, made once for everyone in it
+    let mut room_snapshots: HashMap<u32, Snapshot> = HashMap::default();
 
     for client_id in server.clients_id() {
-        if let Some(player_inputs) = input_buffer.inputs.get(&client_id) {
-            snapshot.latest_processed_input_id =
-                if let Some(latest_processed_input) = &player_inputs.latest_processed_input {
-                    Some(latest_processed_input.id)
-                } else {
-                    None
-                };
+        if let Some(player_inputs) = input_buffer.inputs.get_mut(&client_id) {
+            let room = rooms.room_of(client_id);
+            let snapshot = &*room_snapshots.entry(room).or_insert_with(|| {
+                let mut room_snapshot = snapshot.clone();
+                room_snapshot
+                    .character_snapshots
+                    .retain(|client_id| rooms.room_of(ClientId::from_raw(client_id)) == room);
+                // networked entities are all characters so far, their network id is the owner's
+                room_snapshot
+                    .components
+                    .retain(|network_id| rooms.room_of(ClientId::from_raw(network_id)) == room);
+                room_snapshot
+            });
+            let views = snapshot_history.client_views.entry(client_id).or_default();
+
+            // retain snapshots up to a second ago
+            views.retain(|view| view.timestamp + 1000 > now);
+
+            // diff against what the client acked, or send everything if it never acked
+            // or its latest acked snapshot is too old
+            let baseline = player_inputs
+                .client_latest_processed_snapshot_id
+                .and_then(|acked_id| views.iter().find(|view| view.id == acked_id));
+            let mut outgoing = match baseline {
+                Some(baseline) => snapshot.diff(baseline),
+                None => snapshot.clone(),
+            };
+            outgoing.latest_processed_input_id = player_inputs
+                .latest_processed_input
+                .as_ref()
+                .map(|latest_processed_input| latest_processed_input.id);
+            outgoing.reconciliation = player_inputs.reconciliation.take();
 
-            if let Some(client_latest_processed_snapshot_id) =
-                player_inputs.client_latest_processed_snapshot_id
+            let budget = bandwidth
+                .clients
+                .entry(client_id)
+                .or_insert(BandwidthBudget {
+                    available_bytes: budget_per_tick,
+                    over_budget: false,
+                });
+            budget.available_bytes = (budget.available_bytes + budget_per_tick)
+                .min(budget_per_tick * BANDWIDTH_BURST_TICKS);
+            let available_bytes = budget.available_bytes;
+            let mut entries =
+                fit_to_budget(&mut outgoing, client_id, &translations, available_bytes);
+            budget.over_budget = entries
+                .iter()
+                .any(|entry| entry.decision == InterestDecision::OverBudget);
+            // whatever the diff dropped never made it to the budget
+            for client_id in snapshot.character_snapshots.client_ids() {
+                if !entries.iter().any(|entry| entry.client_id == *client_id) {
+                    entries.push(InterestEntry {
+                        client_id: *client_id,
+                        priority: None,
+                        size: 0,
+                        decision: InterestDecision::Unchanged,
+                    });
+                }
+            }
+
+            // characters left out are simply still at their baseline state for this client,
+            // so later diffs against this snapshot stay correct
+            let view = outgoing.applied_to(baseline);
+            let snapshot_id = outgoing.id;
+            let baseline_id = baseline.map(|baseline| baseline.id);
+            if let Ok(message) =
+                bincode::serialize(&UnreliableServerMessage::Snapshot(Box::new(outgoing)))
             {
-                // make a diff snapshot
-                if let Some(old_snapshot) = snapshot_history
-                    .snapshots
-                    .iter()
-                    .find(|old_snapshot| old_snapshot.id == client_latest_processed_snapshot_id)
+                budget.available_bytes -= message.len() as f32;
+                interest_reports.clients.insert(
+                    client_id,
+                    InterestReport {
+                        snapshot_id,
+                        baseline_id,
+                        message_size: message.len(),
+                        available_bytes,
+                        entries,
+                    },
+                );
+                server.send_message(client_id, DefaultChannel::Unreliable, message);
+            }
+            views.push(view);
+            evict_oldest(views, MAX_SNAPSHOT_VIEWS, "snapshot views");
+        }
+    }
+
+    snapshot_history.next_id += 1;
+}
+
+/// drops the lowest priority characters (furthest from the receiver) until the snapshot fits,
+/// the receiver's own character is always kept. returns whether anything was dropped
+/// keeps the highest priority characters that fit, returning what happened to each of them
+fn fit_to_budget(
+    snapshot: &mut Snapshot,
+    receiver: ClientId,
+    translations: &HashMap<u64, Vec3>,
+    available_bytes: f32,
+) -> Vec<InterestEntry> {
+    let mut character_snapshots: Vec<CharacterSnapshot> =
+        std::mem::take(&mut snapshot.character_snapshots)
+            .into_iter()
+            .collect();
+    let empty_size = bincode::serialized_size(&UnreliableServerMessage::Snapshot(Box::new(
+        snapshot.clone(),
+    )))
+    .unwrap_or(0) as f32;
+
+    let receiver_translation = translations
+        .get(&receiver.raw())
+        .copied()
+        .unwrap_or_default();
+    let priority = |character_snapshot: &CharacterSnapshot| {
+        if character_snapshot.client_id == receiver.raw() {
+            f32::NEG_INFINITY
+        } else {
+            translations
+                .get(&character_snapshot.client_id)
+                .map_or(f32::INFINITY, |translation| {
+                    translation.distance_squared(receiver_translation)
+                })
+        }
+    };
+    character_snapshots.sort_by(|a, b| priority(a).total_cmp(&priority(b)));
+
+    let mut remaining_bytes = available_bytes - empty_size;
+    let mut entries = Vec::new();
+    let mut included_snapshots = Vec::new();
+    for character_snapshot in character_snapshots {
+        let size = bincode::serialized_size(&character_snapshot).unwrap_or(0);
+        let included =
+            character_snapshot.client_id == receiver.raw() || size as f32 <= remaining_bytes;
+        entries.push(InterestEntry {
+            client_id: character_snapshot.client_id,
+            priority: Some(priority(&character_snapshot)),
+            size,
+            decision: if included {
+                InterestDecision::Included
+            } else {
+                InterestDecision::OverBudget
+            },
+        });
+        if included {
+            remaining_bytes -= size as f32;
+            included_snapshots.push(character_snapshot);
+        }
+    }
+    snapshot.character_snapshots = included_snapshots.into_iter().collect();
+    entries
+}
+
+fn admin_status_system(
+    bandwidth: Res<ClientBandwidth>,
+    host_stats: Option<Res<HostStats>>,
+    server: Res<RenetServer>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Status = command {
+            println!(
+                "{:<24}{:>10}{:>10}{:>12}{:>14}",
+                "Client", "RTT (ms)", "Loss", "Out (B/s)", "Over budget"
+            );
+            for client_id in server.clients_id() {
+                if let Ok(info) = server.network_info(client_id) {
+                    let over_budget = bandwidth
+                        .clients
+                        .get(&client_id)
+                        .is_some_and(|budget| budget.over_budget);
+                    println!(
+                        "{:<24}{:>10.0}{:>9.1}%{:>12.0}{:>14}",
+                        client_id.raw(),
+                        info.rtt * 1000.0,
+                        info.packet_loss * 100.0,
+                        info.bytes_sent_per_second,
+                        if over_budget { "yes" } else { "no" }
+                    );
+                }
+            }
+            println!("Non-finite values caught: {}", non_finite_count());
+            println!("History entries evicted: {}", evicted_count());
+            if let Some(host_stats) = host_stats.as_ref() {
+                let cpu = host_stats
+                    .cpu_percent
+                    .map_or("n/a".to_string(), |cpu| format!("{:.1}%", cpu));
+                let rss = host_stats.rss_bytes.map_or("n/a".to_string(), |rss| {
+                    format!("{:.1} MiB", rss as f64 / (1024.0 * 1024.0))
+                });
+                println!(
+                    "Host: CPU {}, RSS {}, tick jitter {:.2} ms (worst {:.2} ms)",
+                    cpu, rss, host_stats.tick_jitter_ms, host_stats.worst_tick_jitter_ms
+                );
+            }
+        }
+    }
+}
+
+/// the teleport count is bumped so the owning client snaps to the spawn instead of fighting it
+fn world_bounds_system(
+    world_bounds: Res<WorldBounds>,
+    rooms: Res<Rooms>,
+    mut matches: Option<ResMut<Matches>>,
+    mut characters: Query<(&mut Character, &mut Transform)>,
+) {
+    for (mut character, mut transform) in characters.iter_mut() {
+        if world_bounds.contains(transform.translation) {
+            continue;
+        }
+
+        println!(
+            "{} left the world at {}, respawning",
+            character.owner_client_id, transform.translation
+        );
+        let owner = character.owner_client_id;
+        transform.translation = rooms.spawn_point_of(owner);
+        character.velocity = Vec3::ZERO;
+        character.mantle = None;
+        character.teleport_count = character.teleport_count.wrapping_add(1);
+        if let Some(matches) = matches.as_mut() {
+            matches.of(rooms.room_of(owner)).record_death(owner, None);
+        }
+    }
+}
+
+/// applied after this tick's inputs, the owner is told which input that was so its replays
+/// push at the same point
+fn apply_impulses_system(
+    input_buffer: Res<PlayerInputCache>,
+    mut impulses: EventReader<ImpulseEvent>,
+    mut characters: Query<&mut Character>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in impulses.read() {
+        if !event.impulse.is_finite() {
+            continue;
+        }
+
+        if let Some(mut character) = characters
+            .iter_mut()
+            .find(|character| character.owner_client_id == event.client_id)
+        {
+            character.velocity += event.impulse;
+
+            // players on this machine have nobody to tell
+            if !server.is_connected(event.client_id) {
+                continue;
+            }
+
+            let after_input_id = input_buffer
+                .inputs
+                .get(&event.client_id)
+                .and_then(|cache_entry| cache_entry.latest_processed_input.as_ref())
+                .map(|input| input.id);
+            outbox.send(
+                &mut server,
+                event.client_id,
+                &ReliableServerMessage::Impulse(after_input_id, event.impulse),
+            );
+        }
+    }
+}
+
+fn admin_interest_system(
+    interest_reports: Res<InterestReports>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Interest(client_id) = command {
+            let report = match interest_reports.clients.get(client_id) {
+                Some(report) => report,
+                None => {
+                    println!("No snapshot sent to client {} yet", client_id);
+                    continue;
+                }
+            };
+
+            println!(
+                "Snapshot {} to client {}: baseline {}, {} bytes of {:.0} available",
+                report.snapshot_id,
+                client_id,
+                report
+                    .baseline_id
+                    .map_or_else(|| "none (full)".to_string(), |id| id.to_string()),
+                report.message_size,
+                report.available_bytes
+            );
+            println!(
+                "{:<24}{:>14}{:>10}  Result",
+                "Character", "Distance", "Bytes"
+            );
+            for entry in &report.entries {
+                let decision = match entry.decision {
+                    InterestDecision::Included => "included",
+                    InterestDecision::Unchanged => "excluded: unchanged since baseline",
+                    InterestDecision::OverBudget => "excluded: over bandwidth budget",
+                };
+                let distance = match entry.priority {
+                    _ if entry.client_id == client_id.raw() => "own".to_string(),
+                    Some(priority) if priority.is_finite() => format!("{:.1}", priority.sqrt()),
+                    _ => "-".to_string(),
+                };
+                println!(
+                    "{:<24}{:>14}{:>10}  {}",
+                    entry.client_id, distance, entry.size, decision
+                );
+            }
+        }
+    }
+}
+
+fn admin_character_commands_system(
+    mut commands: Commands,
+    mut impulses: EventWriter<ImpulseEvent>,
+    mut reset_world: EventWriter<ResetWorldEvent>,
+    mut change_map: EventWriter<ChangeMapEvent>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut characters: Query<(Entity, &mut Character, &mut Transform, Has<GodMode>)>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        match command {
+            AdminCommand::Teleport(client_id, translation) => {
+                if let Some((_, mut character, mut transform, _)) = characters
+                    .iter_mut()
+                    .find(|(_, character, _, _)| character.owner_client_id == *client_id)
                 {
-                    let diff = snapshot.diff(old_snapshot);
-                    if let Ok(message) =
-                        bincode::serialize(&UnreliableServerMessage::Snapshot(diff))
-                    {
-                        server.send_message(client_id, DefaultChannel::Unreliable, message);
-                    }
+                    transform.translation = *translation;
+                    character.velocity = Vec3::ZERO;
+                    character.mantle = None;
+                    character.teleport_count = character.teleport_count.wrapping_add(1);
+                    println!("Teleported {} to {}", client_id, translation);
+                } else {
+                    eprintln!("no character for client {}", client_id);
                 }
-                // can't make a diff, latest acked snapshot is too old, send the full latest snapshot
-                else {
-                    if let Ok(message) =
-                        bincode::serialize(&UnreliableServerMessage::Snapshot(snapshot.clone()))
-                    {
-                        server.send_message(client_id, DefaultChannel::Unreliable, message);
+            }
+            AdminCommand::ToggleGodMode(client_id) => {
+                if let Some((entity, _, _, god_mode)) = characters
+                    .iter()
+                    .find(|(_, character, _, _)| character.owner_client_id == *client_id)
+                {
+                    if god_mode {
+                        commands.entity(entity).remove::<GodMode>();
+                        println!("God mode off for {}", client_id);
+                    } else {
+                        commands.entity(entity).insert(GodMode);
+                        println!("God mode on for {}", client_id);
                     }
+                } else {
+                    eprintln!("no character for client {}", client_id);
                 }
-            // can't make a diff, client never acked a snapshot, send the full latest snapshot
-            } else {
-                if let Ok(message) =
-                    bincode::serialize(&UnreliableServerMessage::Snapshot(snapshot.clone()))
+            }
+            AdminCommand::Push(client_id, impulse) => {
+                impulses.send(ImpulseEvent {
+                    client_id: *client_id,
+                    impulse: *impulse,
+                });
+            }
+            AdminCommand::StatusEffect(client_id, kind, duration) => {
+                if let Some((_, mut character, _, _)) = characters
+                    .iter_mut()
+                    .find(|(_, character, _, _)| character.owner_client_id == *client_id)
                 {
-                    server.send_message(client_id, DefaultChannel::Unreliable, message);
+                    character.add_status_effect(*kind, *duration);
+                    println!("{:?} on {} for {}s", kind, client_id, duration);
+                } else {
+                    eprintln!("no character for client {}", client_id);
                 }
             }
+            AdminCommand::ResetWorld => {
+                reset_world.send(ResetWorldEvent);
+            }
+            AdminCommand::ChangeMap(name) => {
+                change_map.send(ChangeMapEvent(name.clone()));
+            }
+            _ => {}
         }
     }
+}
 
-    snapshot.latest_processed_input_id = None;
-    snapshot_history.snapshots.push(snapshot.clone());
-    snapshot_history.next_id += 1;
+/// clients are told before the reset, so the spawns that follow it land in the new map
+fn change_map_system(
+    mut changes: EventReader<ChangeMapEvent>,
+    mut map: ResMut<Map>,
+    mut reset_world: EventWriter<ResetWorldEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for ChangeMapEvent(name) in changes.read() {
+        match Map::load_named(name) {
+            Ok(new_map) => {
+                println!("Changing the map to {}", name);
+                *map = new_map;
+                outbox.broadcast(&mut server, &ReliableServerMessage::Map(name.clone()));
+                reset_world.send(ResetWorldEvent);
+            }
+            Err(e) => eprintln!("Failed to load map {}: {}", name, e),
+        }
+    }
 }
 
-fn input_processing_system(
+/// nothing from before the reset carries over: no baselines to diff against, no inputs still
+/// queued, no momentum. the entities themselves stay, everyone is back at their room's spawn and
+/// clients are told to spawn the characters in their room again
+pub fn reset_world_system(
+    mut resets: EventReader<ResetWorldEvent>,
+    rooms: Res<Rooms>,
+    mut input_buffer: ResMut<PlayerInputCache>,
+    mut snapshot_history: ResMut<SnapshotHistory>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+    mut characters: Query<(
+        &mut Character,
+        &mut Transform,
+        &PlayerColor,
+        Option<&Cosmetics>,
+        Option<&PlayerName>,
+    )>,
+) {
+    if resets.read().count() == 0 {
+        return;
+    }
+    println!("Resetting the world");
+
+    snapshot_history.client_views.clear();
+    for cache_entry in input_buffer.inputs.values_mut() {
+        cache_entry.input_groups.clear();
+        cache_entry.latest_processed_input = None;
+        cache_entry.client_latest_processed_snapshot_id = None;
+    }
+
+    outbox.broadcast(
+        &mut server,
+        &ReliableServerMessage::ResetWorld(snapshot_history.next_id),
+    );
+
+    for (mut character, mut transform, color, cosmetics, name) in characters.iter_mut() {
+        let owner = character.owner_client_id;
+        let spawn = rooms.spawn_point_of(owner);
+        transform.translation = spawn;
+        character.velocity = Vec3::ZERO;
+        character.mantle = None;
+        character.status_effects.clear();
+
+        outbox.broadcast_filtered(
+            &mut server,
+            &ReliableServerMessage::SpawnCharacter(
+                owner.raw(),
+                color.0,
+                spawn,
+                Vec3::ZERO,
+                cosmetics.copied().unwrap_or_default(),
+                name.map_or(String::new(), |name| name.0.clone()),
+                snapshot_history.next_id,
+            ),
+            |client_id| rooms.same_room(client_id, owner),
+        );
+    }
+}
+
+/// the inputs of everyone playing on this machine go into the cache the same tick they're made,
+/// as if they'd arrived from a client with no latency
+fn host_inputs_system(
+    local_player: Res<LocalPlayer>,
+    history: Option<ResMut<InputHistory>>,
+    mut local_inputs: EventReader<LocalInputsEvent>,
+    mut input_buffer: ResMut<PlayerInputCache>,
+) {
+    let new_cache_entry = || PlayerInputCacheEntry {
+        input_groups: Vec::new(),
+        latest_processed_input: None,
+        client_latest_processed_snapshot_id: None,
+        groups_per_message: 1,
+        claimed_translation: None,
+        reconciliation: None,
+    };
+
+    for LocalInputsEvent {
+        client_id,
+        input_group,
+    } in local_inputs.read()
+    {
+        let input_groups = &mut input_buffer
+            .inputs
+            .entry(*client_id)
+            .or_insert_with(new_cache_entry)
+            .input_groups;
+        input_groups.push(input_group.clone());
+        evict_oldest(input_groups, MAX_INPUT_GROUPS, "cached input groups");
+    }
+
+    if let Some(mut history) = history {
+        if local_player.is_authority() && !history.input_groups_for_next_send.is_empty() {
+            let input_groups = &mut input_buffer
+                .inputs
+                .entry(local_player.client_id)
+                .or_insert_with(new_cache_entry)
+                .input_groups;
+            input_groups.append(&mut history.input_groups_for_next_send);
+            evict_oldest(input_groups, MAX_INPUT_GROUPS, "cached input groups");
+        }
+    }
+}
+
+pub fn input_processing_system(
     fixed_time: Res<Time<Fixed>>,
+    map: Res<Map>,
+    mutators: Res<Mutators>,
+    matches: Res<Matches>,
+    rooms: Res<Rooms>,
     mut input_buffer: ResMut<PlayerInputCache>,
-    mut characters: Query<(&mut Character, &mut Transform)>,
+    mut demo_recorder: Option<ResMut<DemoRecorder>>,
+    mut characters: Query<(&mut Character, &mut Transform, Option<&mut SimulationLod>)>,
 ) {
-    for (mut character, mut transform) in characters.iter_mut() {
+    let positions: Vec<(ClientId, Vec3)> = characters
+        .iter()
+        .map(|(character, transform, _)| (character.owner_client_id, transform.translation))
+        .collect();
+
+    for (mut character, mut transform, mut lod) in characters.iter_mut() {
         if let Some(cache_entry) = input_buffer.inputs.get_mut(&character.owner_client_id) {
+            let owner = character.owner_client_id;
+            // inputs received while the room's match is frozen are dropped, not replayed later
+            if !matches.is_in_progress(rooms.room_of(owner)) {
+                cache_entry.input_groups.clear();
+                cache_entry.latest_processed_input = None;
+                continue;
+            }
             if cache_entry.input_groups.is_empty() {
+                let ticks = match lod.as_mut() {
+                    Some(lod) => {
+                        let far = positions.iter().all(|(client_id, translation)| {
+                            *client_id == owner
+                                || !rooms.same_room(owner, *client_id)
+                                || translation.distance(transform.translation)
+                                    > lod.full_rate_distance
+                        });
+                        match lod.step(far) {
+                            Some(ticks) => ticks,
+                            None => continue,
+                        }
+                    }
+                    None => 1,
+                };
                 for input in cache_entry.latest_processed_input.iter_mut() {
-                    character.process_input(input, &mut transform, fixed_time.delta_seconds());
+                    character.process_input(
+                        input,
+                        &mut transform,
+                        &map,
+                        &mutators,
+                        fixed_time.delta_seconds() * ticks as f32,
+                    );
                 }
                 continue;
             }
 
-            let chopped_delta = fixed_time.delta_seconds() / cache_entry.input_groups.len() as f32;
-            for input_group in cache_entry.input_groups.iter_mut() {
+            // whatever was skipped happened before these inputs
+            let skipped = lod.as_mut().map_or(0, |lod| lod.take_pending());
+            if skipped > 0 {
+                for input in cache_entry.latest_processed_input.iter_mut() {
+                    character.process_input(
+                        input,
+                        &mut transform,
+                        &map,
+                        &mutators,
+                        fixed_time.delta_seconds() * skipped as f32,
+                    );
+                }
+            }
+
+            // clients sending several ticks per message get one group played back per tick,
+            // anything queued beyond that is caught up on now
+            let backlog = cache_entry
+                .input_groups
+                .len()
+                .saturating_sub(cache_entry.groups_per_message);
+            let group_count = (backlog + 1).min(cache_entry.input_groups.len());
+            let chopped_delta = fixed_time.delta_seconds() / group_count as f32;
+            for mut input_group in cache_entry.input_groups.drain(..group_count) {
                 if input_group.is_empty() {
                     continue;
                 }
+                if let Some(demo_recorder) = demo_recorder.as_mut() {
+                    demo_recorder.record_inputs(character.owner_client_id, &input_group);
+                }
                 let even_more_chopped_delta = chopped_delta / input_group.len() as f32;
-                for mut input in input_group.iter_mut() {
-                    character.process_input(&mut input, &mut transform, even_more_chopped_delta);
+                for input in input_group.iter_mut() {
+                    character.process_input(
+                        input,
+                        &mut transform,
+                        &map,
+                        &mutators,
+                        even_more_chopped_delta,
+                    );
                     cache_entry.latest_processed_input = Some(input.clone());
+                    if let Some((input_id, claimed)) = cache_entry.claimed_translation {
+                        if input.id == input_id {
+                            cache_entry.reconciliation =
+                                Some((input_id, transform.translation - claimed));
+                        }
+                        // a claim for an input that was dropped can never be checked
+                        if input.id >= input_id {
+                            cache_entry.claimed_translation = None;
+                        }
+                    }
                 }
             }
-            cache_entry.input_groups.clear();
         }
     }
 }
@@ -0,0 +1,126 @@
+use crate::core::*;
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use std::collections::VecDeque;
+
+/// requests right after connecting, close together so there's an estimate quickly
+const BURST_REQUESTS: usize = 5;
+const BURST_INTERVAL: f64 = 0.1;
+/// seconds between requests after that
+const REQUEST_INTERVAL: f64 = 2.0;
+/// samples the estimate picks from
+const MAX_SAMPLES: usize = 8;
+
+/// the client asks the server which tick it's on every so often and times how long the answer
+/// takes. half of that round trip is how old the answer was on arrival, so from then on the
+/// server's tick can be worked out from our own clock. both count in virtual time, which the
+/// time scale and freezes affect on both sides alike
+pub struct ServerTimePlugin;
+impl Plugin for ServerTimePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (send_time_requests_system, receive_time_responses_system)
+                .run_if(resource_exists::<RenetClient>),
+        );
+        app.init_resource::<ServerTime>();
+        app.add_event::<ReceivedTimeResponseEvent>();
+    }
+}
+
+/// the server answered one of our requests: the time we sent it at, and the server's tick when
+/// it answered
+#[derive(Event)]
+pub struct ReceivedTimeResponseEvent {
+    pub sent_at: f64,
+    pub server_tick: f64,
+}
+
+/// where the server's clock is compared to ours
+#[derive(Resource, Default)]
+pub struct ServerTime {
+    /// the latest answers as (round trip, offset): the server's tick is our virtual seconds
+    /// times the tick rate plus the offset
+    samples: VecDeque<(f64, f64)>,
+    requests_sent: usize,
+    last_request: Option<f64>,
+}
+
+impl ServerTime {
+    /// the answer that took the shortest round trip spent the least time stuck anywhere, so
+    /// halving it is closest to the truth
+    fn best_sample(&self) -> Option<(f64, f64)> {
+        self.samples
+            .iter()
+            .copied()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    /// the server's tick at our virtual time `now`, with how far into it it is
+    pub fn tick(&self, now: f64, tick_rate: f64) -> Option<f64> {
+        let (_, offset) = self.best_sample()?;
+        Some(now * tick_rate + offset)
+    }
+
+    /// the tick of the snapshots arriving about now, half a round trip behind the server
+    pub fn arriving_tick(&self, now: f64, tick_rate: f64) -> Option<f64> {
+        let (round_trip, _) = self.best_sample()?;
+        Some(self.tick(now, tick_rate)? - round_trip / 2.0 * tick_rate)
+    }
+}
+
+fn send_time_requests_system(
+    time: Res<Time>,
+    mut server_time: ResMut<ServerTime>,
+    mut client: ResMut<RenetClient>,
+) {
+    if !client.is_connected() {
+        // a new connection could be to another server altogether
+        *server_time = default();
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    let interval = if server_time.requests_sent < BURST_REQUESTS {
+        BURST_INTERVAL
+    } else {
+        REQUEST_INTERVAL
+    };
+    if server_time
+        .last_request
+        .is_some_and(|last_request| now - last_request < interval)
+    {
+        return;
+    }
+
+    if let Ok(message) = bincode::serialize(&UnreliableClientMessage::TimeRequest(now)) {
+        client.send_message(DefaultChannel::Unreliable, message);
+        server_time.requests_sent += 1;
+        server_time.last_request = Some(now);
+    }
+}
+
+fn receive_time_responses_system(
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    mut responses: EventReader<ReceivedTimeResponseEvent>,
+    mut server_time: ResMut<ServerTime>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let tick_rate = 1.0 / fixed_time.timestep().as_secs_f64();
+    for ReceivedTimeResponseEvent {
+        sent_at,
+        server_tick,
+    } in responses.read()
+    {
+        // from before a reconnect, or made up
+        if !sent_at.is_finite() || *sent_at > now || !server_tick.is_finite() {
+            continue;
+        }
+        let round_trip = now - sent_at;
+        let offset = server_tick + round_trip / 2.0 * tick_rate - now * tick_rate;
+        if server_time.samples.len() >= MAX_SAMPLES {
+            server_time.samples.pop_front();
+        }
+        server_time.samples.push_back((round_trip, offset));
+    }
+}
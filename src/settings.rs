@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const SETTINGS_PATH: &str = "settings.ron";
+
+/// writes `settings.ron` when there isn't one yet so there's something to edit, and again
+/// whenever the settings change. only added where there's a player, a dedicated server never
+/// touches the file
+pub struct SettingsPlugin;
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            save_settings_system.run_if(resource_changed::<Settings>),
+        );
+    }
+}
+
+/// per-player preferences, read from `settings.ron` next to the executable if there is one
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub locale: String,
+    pub bindings: Bindings,
+    /// time constant of the mouse filter in seconds, higher is smoother but laggier
+    pub mouse_smoothing: f32,
+    /// mouse movement goes straight to the view, no filtering at all
+    pub raw_mouse_input: bool,
+    /// tell servers roughly what this machine is like when joining, see `TelemetryReport`
+    pub telemetry: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            locale: "en".into(),
+            bindings: Bindings::default(),
+            mouse_smoothing: 0.01,
+            raw_mouse_input: false,
+            telemetry: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ActionMode {
+    /// on while the key is down
+    Hold,
+    /// each press flips it
+    Toggle,
+}
+
+/// how each action is driven locally, the server only ever sees whether it's on
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Bindings {
+    pub keys: KeyBindings,
+    pub crouch: ActionMode,
+    pub sprint: ActionMode,
+    pub zoom: ActionMode,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: KeyBindings::default(),
+            crouch: ActionMode::Hold,
+            sprint: ActionMode::Hold,
+            zoom: ActionMode::Hold,
+        }
+    }
+}
+
+/// `KeyCode`s are physical positions named after the US QWERTY key in that spot, so the
+/// defaults land on ZQSD on AZERTY and ,OAE on Dvorak without anyone having to remap
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub forward: KeyCode,
+    pub backward: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub jump: KeyCode,
+    pub crouch: KeyCode,
+    pub sprint: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::KeyW,
+            backward: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            jump: KeyCode::Space,
+            crouch: KeyCode::ControlLeft,
+            sprint: KeyCode::ShiftLeft,
+        }
+    }
+}
+
+impl Settings {
+    /// a missing file means the defaults, a broken one is reported and ignored
+    pub fn load() -> Self {
+        let path = Path::new(SETTINGS_PATH);
+        if !path.exists() {
+            return Self::default();
+        }
+
+        match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| ron::from_str(&text).map_err(|e| e.to_string()))
+        {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Failed to load settings {}: {}", SETTINGS_PATH, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())
+            .and_then(|text| std::fs::write(SETTINGS_PATH, text).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!("Failed to save settings {}: {}", SETTINGS_PATH, e);
+        }
+    }
+}
+
+fn save_settings_system(settings: Res<Settings>) {
+    // just inserted, only worth writing if there's no file yet. a broken one is left for the
+    // player to fix
+    if settings.is_added() && Path::new(SETTINGS_PATH).exists() {
+        return;
+    }
+    settings.save();
+}
@@ -0,0 +1,405 @@
+use crate::{
+    action,
+    client::{self, PredictionError, ReconciliationError},
+    core::*,
+    input,
+    localization::Localization,
+    map::Map,
+    origin::RenderOrigin,
+    palette::ColorFilter,
+    practice::{self, Link, NetConditions, Xorshift},
+    server::PlayerInputCache,
+    server_time, telemetry, GameplayPlugins, DEFAULT_PORT, DEFAULT_TIMEOUT_SECONDS,
+    DEFAULT_UNSTABLE_AFTER, MAP_PATH,
+};
+use bevy::{audio::Pitch, prelude::*, window::ReceivedCharacter};
+use bevy_renet::{
+    renet::{ClientId, ConnectionConfig, RenetClient, RenetServer},
+    RenetClientPlugin,
+};
+use std::{
+    f32::consts::{FRAC_PI_4, TAU},
+    net::Ipv4Addr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const CLIENT_COUNT: u64 = 2;
+/// roughly a 60 Hz display
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+const REPORT_INTERVAL: f64 = 60.0;
+/// longest a scripted client holds the same keys, in seconds
+const MAX_HOLD: f32 = 1.5;
+/// every history is trimmed to about a second of fixed ticks, four is plenty of headroom
+const MAX_HISTORY_LENGTH: usize = 256;
+/// any more and the client would have hard snapped instead of replaying
+const MAX_PREDICTION_ERROR: f32 = 2.0;
+
+/// a server and a few scripted clients in one process, without a window, connected the same
+/// way practice mode is. runs for `minutes` and fails as soon as a history grows past what
+/// its trimming should allow or a prediction drifts too far. a panic anywhere fails it too
+pub fn run(locale: &str, minutes: f32, conditions: &NetConditions) -> Result<(), String> {
+    let mut random = Xorshift::default();
+    let client_ids: Vec<ClientId> = (1..=CLIENT_COUNT).map(ClientId::from_raw).collect();
+    let mut server_app = practice::build_server_app(locale, &client_ids)?;
+    let mut clients = Vec::new();
+    for client_id in client_ids {
+        clients.push(SoakClient {
+            app: build_client_app(locale, client_id, random.next_u64())?,
+            link: Link::default(),
+            client_id,
+        });
+    }
+
+    println!(
+        "Soaking {} clients for {} minutes at {} ms, {} ms jitter and {}% loss",
+        CLIENT_COUNT,
+        minutes,
+        conditions.latency_ms,
+        conditions.jitter_ms,
+        conditions.loss * 100.0
+    );
+    let start = Instant::now();
+    let duration = minutes as f64 * 60.0;
+    let mut next_report = REPORT_INTERVAL;
+    loop {
+        std::thread::sleep(FRAME_DURATION);
+        let now = start.elapsed().as_secs_f64();
+        if now >= duration {
+            break;
+        }
+
+        for client in clients.iter_mut() {
+            client.app.update();
+            client.link.carry_to_server(
+                conditions,
+                &mut random,
+                now,
+                &mut client.app.world.resource_mut::<RenetClient>(),
+                &mut server_app.world.resource_mut::<RenetServer>(),
+                client.client_id,
+            );
+        }
+        server_app.update();
+        for client in clients.iter_mut() {
+            client.link.carry_to_client(
+                conditions,
+                &mut random,
+                now,
+                &mut server_app.world.resource_mut::<RenetServer>(),
+                &mut client.app.world.resource_mut::<RenetClient>(),
+                client.client_id,
+            );
+        }
+
+        let usage = Usage::measure(&server_app, &clients);
+        usage.check()?;
+        if now >= next_report {
+            next_report += REPORT_INTERVAL;
+            println!("{:.0}s: {}", now, usage);
+        }
+    }
+    Ok(())
+}
+
+struct SoakClient {
+    app: App,
+    link: Link,
+    client_id: ClientId,
+}
+
+/// the biggest of everything that's supposed to stay small
+struct Usage {
+    snapshot_views: usize,
+    cached_input_groups: usize,
+    input_groups: usize,
+    unsent_input_groups: usize,
+    impulses: usize,
+    worst_prediction_error: f32,
+    worst_reconciliation_error: f32,
+}
+
+impl Usage {
+    fn measure(server_app: &App, clients: &[SoakClient]) -> Self {
+        let snapshot_history = server_app.world.resource::<SnapshotHistory>();
+        let mut usage = Self {
+            snapshot_views: snapshot_history
+                .client_views
+                .values()
+                .map(|views| views.len())
+                .max()
+                .unwrap_or(0),
+            cached_input_groups: server_app
+                .world
+                .resource::<PlayerInputCache>()
+                .longest_backlog(),
+            input_groups: 0,
+            unsent_input_groups: 0,
+            impulses: 0,
+            worst_prediction_error: 0.0,
+            worst_reconciliation_error: 0.0,
+        };
+        for client in clients {
+            let input_history = client.app.world.resource::<InputHistory>();
+            usage.input_groups = usage.input_groups.max(input_history.input_groups.len());
+            usage.unsent_input_groups = usage
+                .unsent_input_groups
+                .max(input_history.input_groups_for_next_send.len());
+            usage.impulses = usage.impulses.max(input_history.impulses.len());
+            usage.worst_prediction_error = usage
+                .worst_prediction_error
+                .max(client.app.world.resource::<PredictionError>().worst);
+            usage.worst_reconciliation_error = usage
+                .worst_reconciliation_error
+                .max(client.app.world.resource::<ReconciliationError>().worst);
+        }
+        usage
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (name, length) in [
+            ("server snapshot views", self.snapshot_views),
+            ("server input cache", self.cached_input_groups),
+            ("client input history", self.input_groups),
+            ("client unsent inputs", self.unsent_input_groups),
+            ("client impulses", self.impulses),
+        ] {
+            if length > MAX_HISTORY_LENGTH {
+                return Err(format!("{} grew to {}", name, length));
+            }
+        }
+        if self.worst_prediction_error > MAX_PREDICTION_ERROR {
+            return Err(format!(
+                "a prediction was off by {}",
+                self.worst_prediction_error
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "snapshot views {}, input cache {}, input history {}, unsent inputs {}, impulses {}, \
+             worst prediction error {:.3}, worst reconciliation error {:.3}",
+            self.snapshot_views,
+            self.cached_input_groups,
+            self.input_groups,
+            self.unsent_input_groups,
+            self.impulses,
+            self.worst_prediction_error,
+            self.worst_reconciliation_error
+        )
+    }
+}
+
+/// a client without anything to draw or listen to, moved by `scripted_input_system`
+fn build_client_app(locale: &str, client_id: ClientId, seed: u64) -> Result<App, String> {
+    let map = Map::load(Path::new(MAP_PATH))?;
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.insert_resource(Time::<Fixed>::from_hz(practice::FIXED_HZ));
+    app.insert_resource(Localization::load(locale));
+    app.insert_resource(map);
+    app.insert_resource(ClientSettings {
+        address: Ipv4Addr::LOCALHOST.into(),
+        port: DEFAULT_PORT,
+        input_send_interval: 1,
+        timeout_seconds: DEFAULT_TIMEOUT_SECONDS,
+        unstable_after: DEFAULT_UNSTABLE_AFTER,
+    });
+    let mut client = RenetClient::new(ConnectionConfig::default());
+    client.set_connected();
+    app.insert_resource(client);
+    app.insert_resource(LocalPlayer { client_id });
+    app.insert_resource(Script {
+        random: Xorshift::seeded(seed),
+        input: PlayerInput::default(),
+        next_change: 0.0,
+    });
+
+    app.init_resource::<ButtonInput<KeyCode>>();
+    app.init_resource::<ButtonInput<MouseButton>>();
+    app.init_resource::<Assets<Mesh>>();
+    app.init_resource::<Assets<StandardMaterial>>();
+    app.init_resource::<Assets<Pitch>>();
+    app.init_resource::<ColorFilter>();
+    app.init_resource::<RenderOrigin>();
+    app.init_resource::<InputHistory>();
+    app.add_event::<ReceivedCharacter>();
+    app.add_event::<SpawnCharacterVisualsEvent>();
+    app.add_event::<HardSnapEvent>();
+    app.add_systems(Update, scripted_input_system);
+    app.add_systems(FixedUpdate, input::apply_inputs_system);
+
+    app.add_plugins(client::ClientPlugin);
+    app.add_plugins(server_time::ServerTimePlugin);
+    app.add_plugins(action::ActionPlugin);
+    // nothing to report telemetry from, no settings to opt in with either
+    app.add_plugins(
+        GameplayPlugins
+            .build()
+            .disable::<telemetry::TelemetryPlugin>(),
+    );
+    app.add_plugins(RenetClientPlugin);
+    app.finish();
+    app.cleanup();
+    Ok(app)
+}
+
+#[derive(Resource)]
+struct Script {
+    random: Xorshift,
+    input: PlayerInput,
+    /// elapsed seconds when different keys get held
+    next_change: f32,
+}
+
+/// holds a random handful of keys for a random while, like someone who can't make up their mind
+fn scripted_input_system(
+    time: Res<Time>,
+    mut script: ResMut<Script>,
+    mut history: ResMut<InputHistory>,
+) {
+    let Script {
+        random,
+        input,
+        next_change,
+    } = &mut *script;
+    if time.elapsed_seconds() >= *next_change {
+        *next_change = time.elapsed_seconds() + random.next_f32() * MAX_HOLD;
+        *input = PlayerInput {
+            forward: random.next_f32() < 0.6,
+            backward: random.next_f32() < 0.2,
+            left: random.next_f32() < 0.3,
+            right: random.next_f32() < 0.3,
+            jump: random.next_f32() < 0.2,
+            crouch: random.next_f32() < 0.1,
+            sprint: random.next_f32() < 0.3,
+            pitch: (random.next_f32() - 0.5) * FRAC_PI_4,
+            yaw: random.next_f32() * TAU,
+            ..default()
+        };
+    }
+    history.pending_input = Some(input.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+    use serde::{Deserialize, Serialize};
+
+    /// 8 seconds, one fixed tick per frame
+    const GOLDEN_TICKS: usize = 512;
+    /// how far a position can be from the recording before movement counts as changed
+    const GOLDEN_TOLERANCE: f32 = 0.001;
+    const GOLDEN_PATH: &str = "assets/golden/loopback_movement.ron";
+    const GOLDEN_CLIENT_ID: u64 = 1;
+
+    /// where the scripted client's character was after one tick
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct GoldenTick {
+        predicted: Option<Vec3>,
+        server: Option<Vec3>,
+    }
+
+    fn character_translation(app: &mut App, client_id: ClientId) -> Option<Vec3> {
+        app.world
+            .query::<(&Character, &Transform)>()
+            .iter(&app.world)
+            .find(|(character, _)| character.owner_client_id == client_id)
+            .map(|(_, transform)| transform.translation)
+    }
+
+    fn close_enough(recorded: Option<Vec3>, replayed: Option<Vec3>) -> bool {
+        match (recorded, replayed) {
+            (Some(recorded), Some(replayed)) => recorded.distance(replayed) <= GOLDEN_TOLERANCE,
+            (recorded, replayed) => recorded.is_none() && replayed.is_none(),
+        }
+    }
+
+    /// one scripted client over a loopback with latency but no loss, the clock stepped by hand
+    /// so every run sees the same ticks. where the client predicted itself and where the
+    /// server had it are compared with the committed recording every tick. a change to how
+    /// movement feels that's on purpose is recorded again with `UPDATE_GOLDEN=1`
+    #[test]
+    fn loopback_movement_matches_the_recording() {
+        let tick = Duration::from_secs_f64(1.0 / practice::FIXED_HZ);
+        let client_id = ClientId::from_raw(GOLDEN_CLIENT_ID);
+        let conditions = NetConditions {
+            latency_ms: 100.0,
+            jitter_ms: 0.0,
+            loss: 0.0,
+            snapshot_interval: 1,
+        };
+        let mut random = Xorshift::seeded(1);
+        let mut link = Link::default();
+        let mut server_app = practice::build_server_app("en", &[client_id]).unwrap();
+        let mut client_app = build_client_app("en", client_id, 1).unwrap();
+        server_app.insert_resource(TimeUpdateStrategy::ManualDuration(tick));
+        client_app.insert_resource(TimeUpdateStrategy::ManualDuration(tick));
+
+        let mut replayed = Vec::new();
+        for frame in 0..GOLDEN_TICKS {
+            let now = frame as f64 * tick.as_secs_f64();
+            client_app.update();
+            link.carry_to_server(
+                &conditions,
+                &mut random,
+                now,
+                &mut client_app.world.resource_mut::<RenetClient>(),
+                &mut server_app.world.resource_mut::<RenetServer>(),
+                client_id,
+            );
+            server_app.update();
+            link.carry_to_client(
+                &conditions,
+                &mut random,
+                now,
+                &mut server_app.world.resource_mut::<RenetServer>(),
+                &mut client_app.world.resource_mut::<RenetClient>(),
+                client_id,
+            );
+            replayed.push(GoldenTick {
+                predicted: character_translation(&mut client_app, client_id),
+                server: character_translation(&mut server_app, client_id),
+            });
+        }
+
+        let path = Path::new(GOLDEN_PATH);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() || !path.exists() {
+            let text =
+                ron::ser::to_string_pretty(&replayed, ron::ser::PrettyConfig::default()).unwrap();
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+            assert!(
+                std::env::var_os("UPDATE_GOLDEN").is_some(),
+                "there was no recording, one was made at {}. check it in",
+                GOLDEN_PATH
+            );
+            return;
+        }
+
+        let recorded: Vec<GoldenTick> =
+            ron::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            recorded.len(),
+            replayed.len(),
+            "recorded a different length"
+        );
+        for (tick, (recorded, replayed)) in recorded.iter().zip(&replayed).enumerate() {
+            assert!(
+                close_enough(recorded.predicted, replayed.predicted)
+                    && close_enough(recorded.server, replayed.server),
+                "tick {} moved differently: recorded {:?}, now {:?}",
+                tick,
+                recorded,
+                replayed
+            );
+        }
+    }
+}
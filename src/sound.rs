@@ -0,0 +1,203 @@
+use crate::{
+    core::*, inventory::ShotFiredEvent, map::Map, origin::RenderOrigin, split_screen::GuestCamera,
+    weapon::WeaponDefinitions,
+};
+use bevy::{
+    audio::{Pitch, PitchBundle, SpatialListener, Volume},
+    prelude::*,
+    utils::HashMap,
+};
+use std::time::Duration;
+
+const STRIDE_LENGTH: f32 = 1.6;
+/// slower than this and the character is shuffling, not stepping
+const FOOTSTEP_MIN_SPEED: f32 = 1.0;
+const GROUNDED_TOLERANCE: f32 = 0.05;
+const LANDING_MIN_SPEED: f32 = 2.0;
+const EAR_GAP: f32 = 0.3;
+const SHOT_SOUND_DURATION: Duration = Duration::from_millis(30);
+
+/// footsteps and landings, derived on every machine from the character state it already has:
+/// the predicted state for our own character, the replicated state for everyone else. shots
+/// sound however their weapon's definition says
+pub struct SoundPlugin;
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_sounds_system);
+        app.add_systems(FixedPostUpdate, footstep_events_system);
+        app.add_systems(Update, (attach_listener_system, play_sounds_system));
+        // demos don't fire anything
+        app.add_systems(
+            Update,
+            play_shot_sounds_system.run_if(resource_exists::<Events<ShotFiredEvent>>),
+        );
+        app.add_event::<FootstepEvent>();
+        app.add_event::<LandingEvent>();
+    }
+}
+
+#[derive(Event)]
+pub struct FootstepEvent {
+    pub position: Vec3,
+}
+
+#[derive(Event)]
+pub struct LandingEvent {
+    pub position: Vec3,
+    pub speed: f32,
+}
+
+#[derive(Component)]
+struct Footsteps {
+    distance_since_step: f32,
+    grounded: bool,
+    /// how fast it was coming down last tick, landing stops it before we get to see it
+    fall_speed: f32,
+}
+
+/// generated tones until the example ships real sound files
+#[derive(Resource)]
+struct Sounds {
+    footstep: Handle<Pitch>,
+    landing: Handle<Pitch>,
+}
+
+fn load_sounds_system(mut commands: Commands, mut pitches: ResMut<Assets<Pitch>>) {
+    commands.insert_resource(Sounds {
+        footstep: pitches.add(Pitch::new(110.0, Duration::from_millis(40))),
+        landing: pitches.add(Pitch::new(70.0, Duration::from_millis(120))),
+    });
+}
+
+/// standing on the floor or the top of a wall, however the character got there
+fn is_grounded(map: &Map, translation: Vec3) -> bool {
+    translation.y
+        <= map.ground_height(translation, translation.y + GROUNDED_TOLERANCE) + GROUNDED_TOLERANCE
+}
+
+fn footstep_events_system(
+    fixed_time: Res<Time<Fixed>>,
+    map: Res<Map>,
+    mut commands: Commands,
+    mut characters: Query<(Entity, &Character, &Transform, Option<&mut Footsteps>)>,
+    mut footsteps: EventWriter<FootstepEvent>,
+    mut landings: EventWriter<LandingEvent>,
+) {
+    for (entity, character, transform, state) in characters.iter_mut() {
+        let grounded = is_grounded(&map, transform.translation);
+
+        let mut state = match state {
+            Some(state) => state,
+            None => {
+                commands.entity(entity).insert(Footsteps {
+                    distance_since_step: 0.0,
+                    grounded,
+                    fall_speed: 0.0,
+                });
+                continue;
+            }
+        };
+
+        if grounded && !state.grounded && state.fall_speed >= LANDING_MIN_SPEED {
+            landings.send(LandingEvent {
+                position: transform.translation,
+                speed: state.fall_speed,
+            });
+            state.distance_since_step = 0.0;
+        }
+        state.grounded = grounded;
+        state.fall_speed = -character.velocity.y;
+
+        // velocity rather than distance moved, so snapshot corrections don't count as walking
+        let speed = Vec2::new(character.velocity.x, character.velocity.z).length();
+        if !grounded || speed < FOOTSTEP_MIN_SPEED {
+            continue;
+        }
+
+        state.distance_since_step += speed * fixed_time.delta_seconds();
+        if state.distance_since_step >= STRIDE_LENGTH {
+            state.distance_since_step -= STRIDE_LENGTH;
+            footsteps.send(FootstepEvent {
+                position: transform.translation,
+            });
+        }
+    }
+}
+
+/// the camera is spawned by the level setup, it hears for the local player. a split-screen
+/// guest shares the speakers, so their camera doesn't get a pair of ears of its own
+fn attach_listener_system(
+    mut commands: Commands,
+    cameras: Query<
+        Entity,
+        (
+            With<Camera3d>,
+            Without<SpatialListener>,
+            Without<GuestCamera>,
+        ),
+    >,
+) {
+    for entity in cameras.iter() {
+        commands
+            .entity(entity)
+            .insert(SpatialListener::new(EAR_GAP));
+    }
+}
+
+fn play_sounds_system(
+    mut commands: Commands,
+    sounds: Res<Sounds>,
+    origin: Res<RenderOrigin>,
+    mut footsteps: EventReader<FootstepEvent>,
+    mut landings: EventReader<LandingEvent>,
+) {
+    for event in footsteps.read() {
+        spawn_sound(
+            &mut commands,
+            sounds.footstep.clone(),
+            origin.to_render(event.position),
+            0.3,
+        );
+    }
+    for event in landings.read() {
+        let volume = (event.speed / (LANDING_MIN_SPEED * 4.0)).clamp(0.3, 1.0);
+        spawn_sound(
+            &mut commands,
+            sounds.landing.clone(),
+            origin.to_render(event.position),
+            volume,
+        );
+    }
+}
+
+fn play_shot_sounds_system(
+    mut commands: Commands,
+    origin: Res<RenderOrigin>,
+    weapons: Res<WeaponDefinitions>,
+    mut shots: EventReader<ShotFiredEvent>,
+    mut pitches: ResMut<Assets<Pitch>>,
+    mut shot_sounds: Local<HashMap<Weapon, Handle<Pitch>>>,
+) {
+    for shot in shots.read() {
+        let source = shot_sounds
+            .entry(shot.weapon)
+            .or_insert_with(|| {
+                let definition = weapons.get(shot.weapon);
+                pitches.add(Pitch::new(definition.fire_pitch, SHOT_SOUND_DURATION))
+            })
+            .clone();
+        spawn_sound(&mut commands, source, origin.to_render(shot.origin), 0.5);
+    }
+}
+
+fn spawn_sound(commands: &mut Commands, source: Handle<Pitch>, position: Vec3, volume: f32) {
+    commands.spawn((
+        PitchBundle {
+            source,
+            settings: PlaybackSettings::DESPAWN
+                .with_spatial(true)
+                .with_volume(Volume::new(volume)),
+        },
+        TransformBundle::from_transform(Transform::from_translation(position)),
+    ));
+}
@@ -0,0 +1,306 @@
+use crate::{
+    core::*, game_mode, map::Map, palette::PlayerColor, server::LocalInputsEvent, CHARACTER_HEIGHT,
+    FIELD_OF_VIEW, SPAWN_POSITION, ZOOM_FIELD_OF_VIEW,
+};
+use bevy::{prelude::*, render::camera::Viewport, window::PrimaryWindow};
+use bevy_renet::renet::{ClientId, RenetServer};
+use std::{
+    f32::consts::{FRAC_PI_2, PI, TAU},
+    time::SystemTime,
+};
+
+/// the guest never goes over the network, so any id no netcode client can get will do, the
+/// server turns away remote clients with reserved ids
+const GUEST_CLIENT_ID: u64 = 1;
+/// radians per second at full stick
+const GAMEPAD_LOOK_SPEED: f32 = 3.0;
+const GAMEPAD_DEADZONE: f32 = 0.2;
+/// just short of straight up or down, where yaw stops meaning anything
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const GUEST_CAMERA_DISTANCE: f32 = 3.0;
+const ZOOM_SPEED: f32 = 15.0;
+
+/// a second player on the same machine with the first gamepad, drawn on the right half of the
+/// window. they're a character of their own with their own client id, fed to the server like
+/// the host is, so everything else only ever deals with one `LocalPlayer`
+pub struct SplitScreenPlugin;
+impl Plugin for SplitScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GuestPlayer {
+            client_id: ClientId::from_raw(GUEST_CLIENT_ID),
+        });
+        app.init_resource::<GuestInputs>();
+        app.add_systems(Startup, spawn_guest_system);
+        app.add_systems(
+            Update,
+            (
+                capture_guest_inputs_system,
+                split_viewports_system,
+                guest_camera_system,
+            )
+                .chain(),
+        );
+        app.add_systems(
+            FixedUpdate,
+            apply_guest_inputs_system.before(crate::input::apply_inputs_system),
+        );
+    }
+}
+
+#[derive(Resource)]
+pub struct GuestPlayer {
+    pub client_id: ClientId,
+}
+
+/// the guest's own camera, every other camera system leaves it alone
+#[derive(Component)]
+pub struct GuestCamera;
+
+#[derive(Resource, Default)]
+struct GuestInputs {
+    next_id: u32,
+    pending_input: Option<PlayerInput>,
+    last_input: Option<PlayerInput>,
+}
+
+fn spawn_guest_system(
+    guest: Res<GuestPlayer>,
+    mut spawn_visuals: EventWriter<SpawnCharacterVisualsEvent>,
+    mut commands: Commands,
+) {
+    crate::spawn_character(
+        guest.client_id,
+        PlayerColor(1),
+        &mut spawn_visuals,
+        &mut commands,
+        SPAWN_POSITION,
+        Vec3::ZERO,
+    );
+
+    // drawn first so its clear covers the whole window, the host's camera draws over its half
+    commands.spawn((
+        GuestCamera,
+        Camera3dBundle {
+            camera: Camera {
+                order: -1,
+                ..default()
+            },
+            projection: Projection::Perspective(PerspectiveProjection {
+                fov: FIELD_OF_VIEW,
+                ..default()
+            }),
+            ..default()
+        },
+    ));
+}
+
+fn stick(
+    axes: &Axis<GamepadAxis>,
+    gamepad: Gamepad,
+    x: GamepadAxisType,
+    y: GamepadAxisType,
+) -> Vec2 {
+    let value = Vec2::new(
+        axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+        axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+    );
+    if value.length() < GAMEPAD_DEADZONE {
+        Vec2::ZERO
+    } else {
+        value
+    }
+}
+
+/// like the host's capture: the view turns every frame and presses wait for the next tick
+fn capture_guest_inputs_system(
+    // real time, so turning feels the same in slow motion
+    time: Res<Time<Real>>,
+    virtual_time: Res<Time<Virtual>>,
+    guest: Res<GuestPlayer>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut guest_inputs: ResMut<GuestInputs>,
+    mut characters: Query<(&Character, &mut ViewAngles)>,
+) {
+    let gamepad = match gamepads.iter().next() {
+        Some(gamepad) => gamepad,
+        None => return,
+    };
+    if virtual_time.is_paused() {
+        return;
+    }
+    let pressed = |button_type| buttons.pressed(GamepadButton::new(gamepad, button_type));
+
+    let movement = stick(
+        &axes,
+        gamepad,
+        GamepadAxisType::LeftStickX,
+        GamepadAxisType::LeftStickY,
+    );
+    let look = stick(
+        &axes,
+        gamepad,
+        GamepadAxisType::RightStickX,
+        GamepadAxisType::RightStickY,
+    ) * GAMEPAD_LOOK_SPEED
+        * time.delta_seconds();
+
+    if let Some((_, mut view)) = characters
+        .iter_mut()
+        .find(|(character, _)| character.owner_client_id == guest.client_id)
+    {
+        view.pitch = (view.pitch + look.y).clamp(-MAX_PITCH, MAX_PITCH);
+        view.yaw -= look.x;
+        if view.yaw.abs() > PI {
+            view.yaw = view.yaw.rem_euclid(TAU);
+        }
+
+        let pending = guest_inputs
+            .pending_input
+            .get_or_insert_with(PlayerInput::default);
+        pending.forward |= movement.y > 0.0;
+        pending.backward |= movement.y < 0.0;
+        pending.left |= movement.x < 0.0;
+        pending.right |= movement.x > 0.0;
+        pending.jump |= pressed(GamepadButtonType::South);
+        pending.crouch |= pressed(GamepadButtonType::East);
+        pending.sprint |= pressed(GamepadButtonType::LeftThumb);
+        pending.zoom |= pressed(GamepadButtonType::LeftTrigger2);
+        pending.pitch = view.pitch;
+        pending.yaw = view.yaw;
+    }
+}
+
+/// goes to the server with the host's inputs once there is one, until then it moves right away
+fn apply_guest_inputs_system(
+    guest: Res<GuestPlayer>,
+    server: Option<Res<RenetServer>>,
+    match_state: Option<Res<game_mode::MatchState>>,
+    fixed_time: Res<Time<Fixed>>,
+    map: Res<Map>,
+    mutators: Res<Mutators>,
+    mut guest_inputs: ResMut<GuestInputs>,
+    mut local_inputs: EventWriter<LocalInputsEvent>,
+    mut characters: Query<(&mut Character, &mut Transform), Without<CharacterVisuals>>,
+) {
+    if game_mode::inputs_frozen(match_state) {
+        guest_inputs.pending_input = None;
+        return;
+    }
+
+    let mut input = match guest_inputs
+        .pending_input
+        .take()
+        .or_else(|| guest_inputs.last_input.clone())
+    {
+        Some(input) => input,
+        None => return,
+    };
+    input.id = guest_inputs.next_id;
+    input.timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    guest_inputs.next_id += 1;
+    guest_inputs.last_input = Some(input.clone());
+
+    if server.is_some() {
+        local_inputs.send(LocalInputsEvent {
+            client_id: guest.client_id,
+            input_group: vec![input],
+        });
+    } else if let Some((mut character, mut transform)) = characters
+        .iter_mut()
+        .find(|(character, _)| character.owner_client_id == guest.client_id)
+    {
+        character.process_input(
+            &mut input,
+            &mut transform,
+            &map,
+            &mutators,
+            fixed_time.delta_seconds(),
+        );
+    }
+}
+
+/// host on the left, guest on the right, kept in step with the window size
+fn split_viewports_system(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Camera, Has<GuestCamera>), With<Camera3d>>,
+) {
+    let window = match windows.get_single() {
+        Ok(window) => window,
+        Err(_) => return,
+    };
+    let half_size = UVec2::new(
+        (window.physical_width() / 2).max(1),
+        window.physical_height().max(1),
+    );
+
+    for (mut camera, is_guest) in cameras.iter_mut() {
+        let position = if is_guest {
+            UVec2::new(half_size.x, 0)
+        } else {
+            UVec2::ZERO
+        };
+        let up_to_date = camera.viewport.as_ref().is_some_and(|viewport| {
+            viewport.physical_position == position && viewport.physical_size == half_size
+        });
+        if up_to_date {
+            continue;
+        }
+        camera.viewport = Some(Viewport {
+            physical_position: position,
+            physical_size: half_size,
+            ..default()
+        });
+        if !is_guest {
+            camera.clear_color = ClearColorConfig::None;
+        }
+    }
+}
+
+/// always third-person, so the guest never has to see the inside of their own body
+fn guest_camera_system(
+    time: Res<Time>,
+    guest: Res<GuestPlayer>,
+    characters: Query<(&Character, &ViewAngles)>,
+    visuals: Query<(&CharacterVisuals, &Transform)>,
+    mut cameras: Query<
+        (&mut Transform, &mut Projection),
+        (With<GuestCamera>, Without<CharacterVisuals>),
+    >,
+) {
+    let (character, view) = match characters
+        .iter()
+        .find(|(character, _)| character.owner_client_id == guest.client_id)
+    {
+        Some(found) => found,
+        None => return,
+    };
+    let visuals_transform = match visuals
+        .iter()
+        .find(|(visuals, _)| visuals.owner_client_id == guest.client_id)
+    {
+        Some((_, transform)) => transform,
+        None => return,
+    };
+    let target_fov = if character.aiming {
+        ZOOM_FIELD_OF_VIEW
+    } else {
+        FIELD_OF_VIEW
+    };
+
+    for (mut camera_transform, mut projection) in cameras.iter_mut() {
+        camera_transform.rotation = Quat::from_euler(EulerRot::YXZ, view.yaw, view.pitch, 0.0);
+        camera_transform.translation = visuals_transform.translation
+            + Vec3::new(0.0, CHARACTER_HEIGHT, 0.0)
+            + camera_transform.rotation * Vec3::Z * GUEST_CAMERA_DISTANCE;
+
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov +=
+                (target_fov - perspective.fov) * (time.delta_seconds() * ZOOM_SPEED).min(1.0);
+        }
+    }
+}
@@ -4,9 +4,17 @@ use bevy::diagnostic::DiagnosticsStore;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
 use bevy::prelude::*;
 
+use bevy_renet::renet::RenetClient;
+
+use crate::client::{PredictionError, SnapshotsReceived};
+use crate::localization::Localization;
 use crate::Character;
 use crate::LocalPlayer;
 
+const NET_STATS_KEY: KeyCode = KeyCode::F2;
+/// seconds the rates are counted over before the overlay changes
+const NET_STATS_INTERVAL: f32 = 1.0;
+
 pub struct FpsCounterPlugin;
 impl Plugin for FpsCounterPlugin {
     fn build(&self, app: &mut App) {
@@ -15,19 +23,32 @@ impl Plugin for FpsCounterPlugin {
     }
 }
 
+/// how the connection is doing, toggled with F2: renet's own numbers for round trip, loss and
+/// bandwidth, plus how many snapshots come in a second and how far off our prediction was
+pub struct NetStatsPlugin;
+impl Plugin for NetStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_net_stats_text);
+        app.add_systems(
+            Update,
+            net_stats_text_system.run_if(resource_exists::<RenetClient>),
+        );
+    }
+}
+
 #[derive(Component)]
 struct FpsText;
 
 #[derive(Component)]
 struct SpeedText;
 
-fn setup_fps_counter(mut commands: Commands) {
+fn setup_fps_counter(mut commands: Commands, localization: Res<Localization>) {
     commands.spawn((
         FpsText,
         TextBundle {
             text: Text::from_sections([
                 TextSection {
-                    value: "FPS: ".into(),
+                    value: localization.get("hud.fps").into(),
                     style: TextStyle {
                         font_size: 16.0,
                         color: Color::WHITE,
@@ -35,7 +56,7 @@ fn setup_fps_counter(mut commands: Commands) {
                     },
                 },
                 TextSection {
-                    value: " N/A".into(),
+                    value: format!("{:>4}", localization.get("hud.not_available")),
                     style: TextStyle {
                         font_size: 16.0,
                         color: Color::WHITE,
@@ -52,7 +73,7 @@ fn setup_fps_counter(mut commands: Commands) {
         TextBundle {
             text: Text::from_sections([
                 TextSection {
-                    value: "Speed: ".into(),
+                    value: localization.get("hud.speed").into(),
                     style: TextStyle {
                         font_size: 16.0,
                         color: Color::WHITE,
@@ -60,7 +81,7 @@ fn setup_fps_counter(mut commands: Commands) {
                     },
                 },
                 TextSection {
-                    value: " N/A".into(),
+                    value: format!("{:>4}", localization.get("hud.not_available")),
                     style: TextStyle {
                         font_size: 16.0,
                         color: Color::WHITE,
@@ -75,6 +96,7 @@ fn setup_fps_counter(mut commands: Commands) {
 
 fn fps_text_update_system(
     diagnostics: Res<DiagnosticsStore>,
+    localization: Res<Localization>,
     mut query: Query<&mut Text, With<FpsText>>,
 ) {
     for mut text in &mut query {
@@ -105,8 +127,8 @@ fn fps_text_update_system(
             }
         } else {
             // display "N/A" if we can't get a FPS measurement
-            // add an extra space to preserve alignment
-            text.sections[1].value = " N/A".into();
+            // padded to preserve alignment
+            text.sections[1].value = format!("{:>4}", localization.get("hud.not_available"));
             text.sections[1].style.color = Color::WHITE;
         }
     }
@@ -126,3 +148,84 @@ fn speed_text_update_system(
         }
     }
 }
+
+#[derive(Component)]
+struct NetStatsText;
+
+fn setup_net_stats_text(mut commands: Commands) {
+    commands.spawn((
+        NetStatsText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(128.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+/// the snapshot count when the rate was last worked out, and when that was
+#[derive(Default)]
+struct SnapshotRate {
+    counted: u64,
+    since: f32,
+}
+
+fn net_stats_text_system(
+    time: Res<Time<Real>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    localization: Res<Localization>,
+    client: Res<RenetClient>,
+    snapshots_received: Res<SnapshotsReceived>,
+    prediction_error: Res<PredictionError>,
+    mut rate: Local<SnapshotRate>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<NetStatsText>>,
+) {
+    for (_, mut visibility) in texts.iter_mut() {
+        if keyboard.just_pressed(NET_STATS_KEY) {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+
+    let now = time.elapsed_seconds();
+    let elapsed = now - rate.since;
+    if elapsed < NET_STATS_INTERVAL {
+        return;
+    }
+    let snapshots_per_second = (snapshots_received.0 - rate.counted) as f32 / elapsed;
+    *rate = SnapshotRate {
+        counted: snapshots_received.0,
+        since: now,
+    };
+
+    let info = client.network_info();
+    let value = localization.format(
+        "net.stats",
+        &[
+            &format!("{:.0}", info.rtt * 1000.0),
+            &format!("{:.1}", info.packet_loss * 100.0),
+            &format!("{:.1}", info.bytes_sent_per_second / 1024.0),
+            &format!("{:.1}", info.bytes_received_per_second / 1024.0),
+            &format!("{:.0}", snapshots_per_second),
+            &format!("{:.3}", prediction_error.latest),
+        ],
+    );
+    for (mut text, _) in texts.iter_mut() {
+        text.sections[0].value.clone_from(&value);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{core::*, localization::Localization};
+use bevy::prelude::*;
+
+/// shows the local character's status effects along the bottom of the screen, one icon per kind
+pub struct StatusEffectPlugin;
+impl Plugin for StatusEffectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_status_effect_hud_system);
+        app.add_systems(Update, status_effect_hud_system);
+    }
+}
+
+#[derive(Component)]
+struct StatusEffectIcon(StatusEffectKind);
+
+#[derive(Component)]
+struct StatusEffectRemainingText(StatusEffectKind);
+
+fn icon_color(kind: StatusEffectKind) -> Color {
+    match kind {
+        StatusEffectKind::SpeedBoost => Color::rgb(0.2, 0.7, 0.3),
+        StatusEffectKind::Slow => Color::rgb(0.2, 0.4, 0.8),
+        StatusEffectKind::Burn => Color::rgb(0.9, 0.4, 0.1),
+    }
+}
+
+fn name_key(kind: StatusEffectKind) -> &'static str {
+    match kind {
+        StatusEffectKind::SpeedBoost => "status.speed_boost",
+        StatusEffectKind::Slow => "status.slow",
+        StatusEffectKind::Burn => "status.burn",
+    }
+}
+
+fn setup_status_effect_hud_system(mut commands: Commands, localization: Res<Localization>) {
+    let text_style = TextStyle {
+        font_size: 14.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                column_gap: Val::Px(6.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for kind in StatusEffectKind::ALL {
+                parent
+                    .spawn((
+                        StatusEffectIcon(kind),
+                        NodeBundle {
+                            style: Style {
+                                display: Display::None,
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                padding: UiRect::all(Val::Px(4.0)),
+                                min_width: Val::Px(64.0),
+                                ..default()
+                            },
+                            background_color: icon_color(kind).into(),
+                            ..default()
+                        },
+                    ))
+                    .with_children(|icon| {
+                        icon.spawn(TextBundle::from_section(
+                            localization.get(name_key(kind)),
+                            text_style.clone(),
+                        ));
+                        icon.spawn((
+                            StatusEffectRemainingText(kind),
+                            TextBundle::from_section("", text_style.clone()),
+                        ));
+                    });
+            }
+        });
+}
+
+/// reads the character itself, so on a client it's the predicted effects ticking down
+fn status_effect_hud_system(
+    localization: Res<Localization>,
+    local_player: Option<Res<LocalPlayer>>,
+    characters: Query<&Character>,
+    mut icons: Query<(&StatusEffectIcon, &mut Style)>,
+    mut texts: Query<(&StatusEffectRemainingText, &mut Text)>,
+) {
+    let status_effects = local_player
+        .and_then(|local_player| {
+            characters
+                .iter()
+                .find(|character| character.owner_client_id == local_player.client_id)
+        })
+        .map(|character| character.status_effects.as_slice())
+        .unwrap_or_default();
+    let find = |kind: StatusEffectKind| {
+        status_effects
+            .iter()
+            .find(|status_effect| status_effect.kind == kind)
+    };
+
+    for (StatusEffectIcon(kind), mut style) in icons.iter_mut() {
+        let display = if find(*kind).is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        if style.display != display {
+            style.display = display;
+        }
+    }
+    for (StatusEffectRemainingText(kind), mut text) in texts.iter_mut() {
+        if let Some(status_effect) = find(*kind) {
+            let value = localization.format(
+                "status.remaining",
+                &[&status_effect.remaining.ceil().max(0.0)],
+            );
+            if text.sections[0].value != value {
+                text.sections[0].value = value;
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    settings::Settings,
+};
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    utils::{HashMap, HashSet},
+    window::PrimaryWindow,
+};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer, ServerEvent};
+
+/// seconds after connecting before the frame rate is read, so loading doesn't drag it down
+const SETTLE_TIME: f32 = 5.0;
+/// past this many different resolutions the rest are only counted, not told apart
+const MAX_RESOLUTIONS: usize = 32;
+/// bigger than any real screen
+const MAX_RESOLUTION: u32 = 16384;
+
+/// clients that opted in with `telemetry` in their settings describe their setup once per
+/// connection. it travels on the reliable unordered channel, which nothing else uses, and the
+/// server only keeps totals, not who sent what
+pub struct TelemetryPlugin;
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            send_telemetry_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.add_systems(
+            Update,
+            (receive_telemetry_system, admin_telemetry_system)
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.init_resource::<TelemetryTotals>();
+    }
+}
+
+const TELEMETRY_CHANNEL: DefaultChannel = DefaultChannel::ReliableUnordered;
+
+fn send_telemetry_system(
+    time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    diagnostics: Option<Res<DiagnosticsStore>>,
+    gamepads: Option<Res<Gamepads>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut connected_at: Local<Option<f32>>,
+    mut sent: Local<bool>,
+    mut client: ResMut<RenetClient>,
+) {
+    if !client.is_connected() {
+        *connected_at = None;
+        *sent = false;
+        return;
+    }
+    if !settings.telemetry || *sent {
+        return;
+    }
+    let connected_at = *connected_at.get_or_insert(time.elapsed_seconds());
+    if time.elapsed_seconds() - connected_at < SETTLE_TIME {
+        return;
+    }
+
+    let fps = diagnostics
+        .as_ref()
+        .and_then(|diagnostics| diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS))
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or(0.0);
+    let resolution = windows
+        .get_single()
+        .map(|window| {
+            (
+                window.resolution.physical_width(),
+                window.resolution.physical_height(),
+            )
+        })
+        .unwrap_or_default();
+    let input_device = if gamepads.is_some_and(|gamepads| gamepads.iter().next().is_some()) {
+        InputDevice::Gamepad
+    } else {
+        InputDevice::KeyboardMouse
+    };
+    let report = TelemetryReport {
+        frame_rate: FrameRateBand::from_fps(fps),
+        resolution,
+        input_device,
+    };
+    if let Ok(message) = bincode::serialize(&report) {
+        client.send_message(TELEMETRY_CHANNEL, message);
+    }
+    *sent = true;
+}
+
+#[derive(Resource, Default)]
+struct TelemetryTotals {
+    /// connected clients that already reported, a second report from them is ignored
+    reported: HashSet<ClientId>,
+    frame_rates: HashMap<FrameRateBand, u32>,
+    resolutions: HashMap<(u32, u32), u32>,
+    other_resolutions: u32,
+    input_devices: HashMap<InputDevice, u32>,
+}
+
+impl TelemetryTotals {
+    fn add(&mut self, report: &TelemetryReport) {
+        *self.frame_rates.entry(report.frame_rate).or_default() += 1;
+        *self.input_devices.entry(report.input_device).or_default() += 1;
+
+        let (width, height) = report.resolution;
+        let plausible = width <= MAX_RESOLUTION && height <= MAX_RESOLUTION;
+        let has_room = self.resolutions.len() < MAX_RESOLUTIONS;
+        match self.resolutions.get_mut(&report.resolution) {
+            Some(count) => *count += 1,
+            None if plausible && has_room => {
+                self.resolutions.insert(report.resolution, 1);
+            }
+            None => self.other_resolutions += 1,
+        }
+    }
+}
+
+fn receive_telemetry_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut totals: ResMut<TelemetryTotals>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = event {
+            totals.reported.remove(client_id);
+        }
+    }
+
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, TELEMETRY_CHANNEL) {
+            match bincode::deserialize::<TelemetryReport>(&message) {
+                Ok(report) => {
+                    if totals.reported.insert(client_id) {
+                        totals.add(&report);
+                    }
+                }
+                Err(e) => eprintln!("Bad telemetry from {}: {}", client_id, e),
+            }
+        }
+    }
+}
+
+fn admin_telemetry_system(
+    totals: Res<TelemetryTotals>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::Telemetry = command {
+            println!("Frame rates:");
+            for band in FrameRateBand::ALL {
+                println!(
+                    "  {:<16}{:>8}",
+                    format!("{:?}", band),
+                    totals.frame_rates.get(&band).copied().unwrap_or(0)
+                );
+            }
+            println!("Resolutions:");
+            let mut resolutions: Vec<_> = totals.resolutions.iter().collect();
+            resolutions.sort_by(|a, b| b.1.cmp(a.1));
+            for ((width, height), count) in resolutions {
+                println!("  {:<16}{:>8}", format!("{}x{}", width, height), count);
+            }
+            if totals.other_resolutions > 0 {
+                println!("  {:<16}{:>8}", "other", totals.other_resolutions);
+            }
+            println!("Input devices:");
+            for device in [InputDevice::KeyboardMouse, InputDevice::Gamepad] {
+                println!(
+                    "  {:<16}{:>8}",
+                    format!("{:?}", device),
+                    totals.input_devices.get(&device).copied().unwrap_or(0)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{core::*, map::Map, server::input_processing_system};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, RenetClient};
+
+/// the map's teleporters. only whoever runs the simulation moves anyone through one, clients
+/// don't predict it: the teleport count in the next snapshot tells the owner to snap to the
+/// other side instead of smoothing or replaying its way there through the map
+pub struct TeleporterPlugin;
+impl Plugin for TeleporterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            teleport_system
+                .after(input_processing_system)
+                .run_if(not(resource_exists::<RenetClient>)),
+        );
+    }
+}
+
+/// a character arrives standing on the other pad, which only sends it back once it has
+/// stepped off and on again
+fn teleport_system(
+    map: Res<Map>,
+    mut arrivals: Local<HashMap<ClientId, (usize, usize)>>,
+    mut characters: Query<(&mut Character, &mut Transform)>,
+) {
+    if map.is_changed() {
+        arrivals.clear();
+    }
+
+    for (mut character, mut transform) in characters.iter_mut() {
+        let client_id = character.owner_client_id;
+        let (index, pad) = match map.teleporter_pad_at(transform.translation) {
+            Some(found) => found,
+            None => {
+                arrivals.remove(&client_id);
+                continue;
+            }
+        };
+        if arrivals.get(&client_id) == Some(&(index, pad)) {
+            continue;
+        }
+
+        // same spot on the other pad and the same velocity, so it carries on walking
+        let teleporter = &map.teleporters[index];
+        let exit = 1 - pad;
+        transform.translation += teleporter.pads[exit] - teleporter.pads[pad];
+        character.mantle = None;
+        character.teleport_count = character.teleport_count.wrapping_add(1);
+        arrivals.insert(client_id, (index, exit));
+    }
+}
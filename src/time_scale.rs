@@ -0,0 +1,102 @@
+use crate::{
+    admin::{AdminCommand, AdminCommandEvent},
+    core::*,
+    server::ReliableOutbox,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer, ServerEvent};
+
+/// any slower and a tick takes long enough for clients to notice
+const MIN_TIME_SCALE: f32 = 0.1;
+/// any faster and the histories sized for about two seconds of ticks cover less than one
+const MAX_TIME_SCALE: f32 = 2.0;
+
+/// slow motion, or fast forward. fixed ticks follow virtual time, so scaling it on the server
+/// and every client alike changes how many ticks happen in a real second while each tick stays
+/// the same. prediction, inputs and snapshots all count in ticks and carry on as they were
+pub struct TimeScalePlugin;
+impl Plugin for TimeScalePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                admin_time_scale_system,
+                set_time_scale_system,
+                send_time_scale_system,
+            )
+                .chain()
+                .run_if(resource_exists::<RenetServer>),
+        );
+        app.add_systems(
+            Update,
+            receive_time_scale_system.run_if(resource_exists::<RenetClient>),
+        );
+        app.add_event::<TimeScaleEvent>();
+        app.add_event::<ReceivedTimeScaleEvent>();
+    }
+}
+
+/// run the simulation at this speed from now on, for the game mode or an admin to send
+#[derive(Event)]
+pub struct TimeScaleEvent(pub f32);
+
+/// the server told us how fast the simulation runs
+#[derive(Event)]
+pub struct ReceivedTimeScaleEvent(pub f32);
+
+fn admin_time_scale_system(
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut time_scales: EventWriter<TimeScaleEvent>,
+) {
+    for AdminCommandEvent(command) in admin_commands.read() {
+        if let AdminCommand::TimeScale(scale) = command {
+            time_scales.send(TimeScaleEvent(*scale));
+        }
+    }
+}
+
+fn set_time_scale_system(
+    mut time_scales: EventReader<TimeScaleEvent>,
+    mut time: ResMut<Time<Virtual>>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for TimeScaleEvent(scale) in time_scales.read() {
+        if !scale.is_finite() {
+            report_non_finite("time scale");
+            continue;
+        }
+        let scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+        println!("Time scale set to {}", scale);
+        time.set_relative_speed(scale);
+        outbox.broadcast(&mut server, &ReliableServerMessage::TimeScale(scale));
+    }
+}
+
+fn send_time_scale_system(
+    time: Res<Time<Virtual>>,
+    mut server_events: EventReader<ServerEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientConnected { client_id } = event {
+            outbox.send(
+                &mut server,
+                *client_id,
+                &ReliableServerMessage::TimeScale(time.relative_speed()),
+            );
+        }
+    }
+}
+
+fn receive_time_scale_system(
+    mut received: EventReader<ReceivedTimeScaleEvent>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    for ReceivedTimeScaleEvent(scale) in received.read() {
+        if scale.is_finite() {
+            time.set_relative_speed(scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE));
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{inventory::ShotFiredEvent, map::Map, origin::RenderOrigin};
+use bevy::prelude::*;
+
+/// seconds a tracer stays up
+const TRACER_DURATION: f32 = 0.15;
+
+/// a line along every shot we know of, our own as soon as we fire, through and off walls the
+/// way the server has it go
+pub struct TracerPlugin;
+impl Plugin for TracerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, tracer_system);
+    }
+}
+
+fn tracer_system(
+    time: Res<Time>,
+    map: Res<Map>,
+    origin: Res<RenderOrigin>,
+    mut shots: EventReader<ShotFiredEvent>,
+    mut tracers: Local<Vec<(Vec3, Vec3, f32)>>,
+    mut gizmos: Gizmos,
+) {
+    let now = time.elapsed_seconds();
+    for shot in shots.read() {
+        for leg in map.shot_path(shot.origin, shot.direction) {
+            tracers.push((leg.start, leg.end, now + TRACER_DURATION));
+        }
+    }
+    tracers.retain(|(_, _, until)| *until > now);
+    for (start, end, _) in tracers.iter() {
+        gizmos.line(
+            origin.to_render(*start),
+            origin.to_render(*end),
+            Color::YELLOW,
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use crate::{
+    chat::ChatInput,
+    core::*,
+    interpolation::{InterpolationClock, SnapshotBuffer},
+    origin::RenderOrigin,
+    palette::ColorFilter,
+    settings::Settings,
+    split_screen::GuestCamera,
+    CHARACTER_HEIGHT, CHARACTER_SPEED, FIELD_OF_VIEW, MOUSE_SENSITIVITY, ZOOM_FIELD_OF_VIEW,
+};
+use bevy::{input::mouse::MouseMotion, prelude::*, render::view::RenderLayers};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+/// our own body lives on this layer, which only the third-person camera renders. shadows
+/// ignore render layers, so it still casts one in first-person
+const OWN_BODY_LAYER: u8 = 1;
+const CAMERA_MODE_KEY: KeyCode = KeyCode::KeyV;
+const FREE_FLY_KEY: KeyCode = KeyCode::F3;
+const FREE_FLY_SPEED: f32 = 8.0;
+const FREE_FLY_FAST_MULTIPLIER: f32 = 4.0;
+const THIRD_PERSON_DISTANCE: f32 = 3.0;
+
+/// where the view model sits relative to the camera
+const VIEW_MODEL_OFFSET: Vec3 = Vec3::new(0.25, -0.2, -0.45);
+const VIEW_MODEL_SWAY_SCALE: f32 = 0.01;
+const VIEW_MODEL_SWAY_MAX: f32 = 0.05;
+const VIEW_MODEL_SWAY_SPEED: f32 = 10.0;
+const VIEW_MODEL_BOB_FREQUENCY: f32 = 2.0;
+const VIEW_MODEL_BOB_AMOUNT: f32 = 0.015;
+
+/// how quickly the field of view follows zooming in and out
+const ZOOM_SPEED: f32 = 15.0;
+/// aiming characters hunch down a bit so others can tell
+const AIM_POSE_SCALE: Vec3 = Vec3::new(1.0, 0.9, 1.0);
+
+/// where the camera is put and how each character looks, on anything with a window.
+/// our own character additionally gets a first-person view model nobody else sees
+pub struct VisualsPlugin;
+impl Plugin for VisualsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_view_model_system);
+        app.add_systems(
+            Update,
+            (
+                spawn_character_visuals_system,
+                despawn_orphaned_visuals_system,
+                own_body_layer_system,
+            )
+                .chain(),
+        );
+        app.add_systems(
+            Update,
+            (
+                camera_mode_system,
+                extrapolate_player_visuals_system,
+                camera_system,
+                free_fly_camera_system,
+                zoom_system,
+                aim_pose_system,
+                view_model_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<CameraMode>();
+        app.init_resource::<CorrectionTuning>();
+        app.add_systems(FixedPostUpdate, post_fixed_player_visuals_system);
+    }
+}
+
+fn spawn_character_visuals_system(
+    color_filter: Res<ColorFilter>,
+    origin: Res<RenderOrigin>,
+    mut spawn_visuals: EventReader<SpawnCharacterVisualsEvent>,
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for event in spawn_visuals.read() {
+        if commands.get_entity(event.entity).is_some() {
+            commands.spawn((
+                CharacterVisuals {
+                    owner_client_id: event.owner_client_id,
+                    character_entity: event.entity,
+                    last_physics_translation: event.translation,
+                },
+                PbrBundle {
+                    mesh: meshes.add(Cuboid::new(0.465, CHARACTER_HEIGHT * 2.0, 0.465)),
+                    material: materials.add(color_filter.color(event.color)),
+                    transform: Transform::from_translation(origin.to_render(event.translation)),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// the character left or was despawned, its body goes with it
+fn despawn_orphaned_visuals_system(
+    mut commands: Commands,
+    characters: Query<(), With<Character>>,
+    visuals: Query<(Entity, &CharacterVisuals)>,
+) {
+    for (entity, visuals) in visuals.iter() {
+        if !characters.contains(visuals.character_entity) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// how our own body catches up with where prediction says it is after a correction. each physics
+/// tick it closes a fraction (the gain) of the gap, a larger fraction the bigger the gap
+#[derive(Resource, Clone, Copy)]
+pub struct CorrectionTuning {
+    /// gaps smaller than this are closed right away, there's nothing visible to smooth
+    pub deadzone: f32,
+    /// fraction of the gap closed per tick for tiny gaps
+    pub min_gain: f32,
+    /// fraction of the gap closed per tick from `full_gain_distance` on
+    pub max_gain: f32,
+    pub full_gain_distance: f32,
+    /// gaps bigger than this are snapped, smoothing them would just look like sliding
+    pub max_smooth_distance: f32,
+}
+
+impl Default for CorrectionTuning {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.001,
+            min_gain: 0.25,
+            max_gain: 0.75,
+            full_gain_distance: 1.0,
+            max_smooth_distance: 4.0,
+        }
+    }
+}
+
+impl CorrectionTuning {
+    /// the fraction of a `distance` gap to close this tick
+    pub fn gain(&self, distance: f32) -> f32 {
+        if distance <= self.deadzone || distance > self.max_smooth_distance {
+            return 1.0;
+        }
+        let t = if self.full_gain_distance > 0.0 {
+            (distance / self.full_gain_distance).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.min_gain + (self.max_gain - self.min_gain) * t
+    }
+}
+
+#[derive(Resource, Default, PartialEq, Clone, Copy)]
+pub enum CameraMode {
+    #[default]
+    FirstPerson,
+    ThirdPerson,
+    /// detached from the character for debugging, which keeps being simulated without input
+    FreeFly,
+}
+
+/// the arms/weapon held in front of the camera, never replicated
+#[derive(Component, Default)]
+struct ViewModel {
+    sway: Vec2,
+    bob_phase: f32,
+    last_view: Option<ViewAngles>,
+}
+
+/// how far into the next fixed tick the frame is, from the time the fixed loop has left over
+/// rather than the wall clock, so it's the same however many ticks ran this frame
+pub fn compute_physics_interpolation_fraction(fixed_time: &Time<Fixed>) -> f32 {
+    fixed_time.overstep_fraction().clamp(0.0, 1.0)
+}
+
+/// characters with snapshots to draw them from are drawn in the past instead
+fn extrapolate_player_visuals_system(
+    fixed_time: Res<Time<Fixed>>,
+    origin: Res<RenderOrigin>,
+    interpolation_clock: Option<Res<InterpolationClock>>,
+    mut visuals: Query<(&CharacterVisuals, &mut Transform)>,
+    characters: Query<(&Character, Option<&SnapshotBuffer>)>,
+) {
+    let fraction = compute_physics_interpolation_fraction(&fixed_time);
+    let tick = interpolation_clock.and_then(|clock| clock.tick());
+    for (visuals, mut visuals_transform) in visuals.iter_mut() {
+        if let Ok((character, buffer)) = characters.get(visuals.character_entity) {
+            let interpolated = buffer
+                .zip(tick)
+                .and_then(|(buffer, tick)| buffer.sample(tick));
+            if let Some(translation) = interpolated {
+                visuals_transform.translation = origin.to_render(translation);
+            } else if character.velocity.is_finite() {
+                visuals_transform.translation = origin.to_render(
+                    visuals.last_physics_translation
+                        + character.velocity * fixed_time.delta_seconds() * fraction,
+                );
+            }
+        }
+    }
+}
+
+fn post_fixed_player_visuals_system(
+    local_player: Res<LocalPlayer>,
+    correction: Res<CorrectionTuning>,
+    origin: Res<RenderOrigin>,
+    mut hard_snaps: EventReader<HardSnapEvent>,
+    characters: Query<(&Character, &Transform)>,
+    mut visuals: Query<(&mut CharacterVisuals, &mut Transform), Without<Character>>,
+) {
+    let hard_snapped = hard_snaps.read().count() > 0;
+
+    for (mut visuals, mut visuals_transform) in visuals.iter_mut() {
+        if let Ok((character, character_transform)) = characters.get(visuals.character_entity) {
+            if character.owner_client_id == local_player.client_id {
+                update_own_visuals(
+                    &mut visuals,
+                    &mut visuals_transform,
+                    *origin,
+                    character_transform,
+                    &correction,
+                    local_player.is_authority(),
+                    hard_snapped,
+                );
+            } else {
+                update_observed_visuals(&mut visuals, character_transform);
+            }
+        }
+    }
+}
+
+/// simulated characters ("we" aren't controlling these, just observing)
+fn update_observed_visuals(visuals: &mut CharacterVisuals, character_transform: &Transform) {
+    visuals.last_physics_translation = character_transform.translation;
+}
+
+/// owned characters ("we" are controlling these)
+fn update_own_visuals(
+    visuals: &mut CharacterVisuals,
+    visuals_transform: &mut Transform,
+    origin: RenderOrigin,
+    character_transform: &Transform,
+    correction: &CorrectionTuning,
+    is_authority: bool,
+    hard_snapped: bool,
+) {
+    // if we're the server player, we can just use the physics translation
+    if is_authority {
+        visuals.last_physics_translation = character_transform.translation;
+    }
+    // teleported, smoothing over that distance would just look broken
+    else if hard_snapped {
+        visuals_transform.translation = origin.to_render(character_transform.translation);
+        visuals.last_physics_translation = character_transform.translation;
+    }
+    // if we're a client and this is our character, close part of the gap to where prediction
+    // has us. the further off we are, the faster we catch up
+    else {
+        // where we're drawn, in the world coordinates prediction uses
+        let shown = origin.to_world(visuals_transform.translation);
+        let diff = shown.distance(character_transform.translation);
+        visuals.last_physics_translation =
+            shown.lerp(character_transform.translation, correction.gain(diff));
+    }
+}
+
+/// only our own body moves to its layer, the player being followed can change (demos). layers
+/// aren't inherited, anything worn on the body moves along with it
+fn own_body_layer_system(
+    mut commands: Commands,
+    local_player: Res<LocalPlayer>,
+    visuals: Query<(Entity, &CharacterVisuals, Option<&Children>)>,
+    layers: Query<Has<RenderLayers>>,
+) {
+    for (entity, visuals, children) in visuals.iter() {
+        let is_own = visuals.owner_client_id == local_player.client_id;
+        for part in std::iter::once(entity).chain(children.into_iter().flatten().copied()) {
+            let has_layers = layers.get(part).unwrap_or(false);
+            if is_own && !has_layers {
+                commands
+                    .entity(part)
+                    .insert(RenderLayers::layer(OWN_BODY_LAYER));
+            } else if !is_own && has_layers {
+                commands.entity(part).remove::<RenderLayers>();
+            }
+        }
+    }
+}
+
+fn camera_mode_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    mut camera_mode: ResMut<CameraMode>,
+    cameras: Query<Entity, (With<Camera3d>, Without<GuestCamera>)>,
+) {
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active);
+    if !typing && keyboard.just_pressed(CAMERA_MODE_KEY) {
+        *camera_mode = match *camera_mode {
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::FirstPerson,
+            CameraMode::FreeFly => CameraMode::FreeFly,
+        };
+    }
+    if !typing && keyboard.just_pressed(FREE_FLY_KEY) {
+        *camera_mode = match *camera_mode {
+            CameraMode::FreeFly => CameraMode::FirstPerson,
+            _ => CameraMode::FreeFly,
+        };
+    }
+
+    if !camera_mode.is_changed() {
+        return;
+    }
+    let layers = match *camera_mode {
+        CameraMode::FirstPerson => RenderLayers::layer(0),
+        CameraMode::ThirdPerson | CameraMode::FreeFly => {
+            RenderLayers::from_layers(&[0, OWN_BODY_LAYER])
+        }
+    };
+    for entity in cameras.iter() {
+        commands.entity(entity).insert(layers);
+    }
+}
+
+fn camera_system(
+    camera_mode: Res<CameraMode>,
+    local_player: Res<LocalPlayer>,
+    views: Query<&ViewAngles>,
+    visuals: Query<(&CharacterVisuals, &Transform)>,
+    mut camera: Query<
+        &mut Transform,
+        (
+            With<Camera>,
+            Without<CharacterVisuals>,
+            Without<GuestCamera>,
+        ),
+    >,
+) {
+    if *camera_mode == CameraMode::FreeFly {
+        return;
+    }
+
+    for mut camera_transform in camera.iter_mut() {
+        for (visuals, visuals_transform) in visuals.iter() {
+            if visuals.owner_client_id == local_player.client_id {
+                if let Ok(view) = views.get(visuals.character_entity) {
+                    camera_transform.rotation =
+                        Quat::from_euler(EulerRot::YXZ, view.yaw, view.pitch, 0.0);
+                    camera_transform.translation =
+                        visuals_transform.translation + Vec3::new(0.0, CHARACTER_HEIGHT, 0.0);
+                    if *camera_mode == CameraMode::ThirdPerson {
+                        let rotation = camera_transform.rotation;
+                        camera_transform.translation += rotation * Vec3::Z * THIRD_PERSON_DISTANCE;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the movement keys fly, jump/crouch for up/down, sprint to go faster
+fn free_fly_camera_system(
+    time: Res<Time>,
+    camera_mode: Res<CameraMode>,
+    settings: Res<Settings>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_input: Option<Res<ChatInput>>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut look: Local<Option<Vec2>>,
+    mut cameras: Query<&mut Transform, (With<Camera3d>, Without<GuestCamera>)>,
+) {
+    if *camera_mode != CameraMode::FreeFly {
+        *look = None;
+        mouse_motion_events.clear();
+        return;
+    }
+
+    let mouse_delta: Vec2 = mouse_motion_events.read().map(|event| event.delta).sum();
+    let typing = chat_input.is_some_and(|chat_input| chat_input.active);
+    let pressed = |key| !typing && keyboard.pressed(key);
+    let keys = settings.bindings.keys;
+
+    for mut transform in cameras.iter_mut() {
+        // picks up from wherever the camera was looking when it was detached
+        let (yaw, pitch) = look.map(|look| (look.x, look.y)).unwrap_or_else(|| {
+            let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+            (yaw, pitch)
+        });
+        let yaw = yaw - mouse_delta.x * MOUSE_SENSITIVITY;
+        let pitch = (pitch - mouse_delta.y * MOUSE_SENSITIVITY).clamp(-FRAC_PI_2, FRAC_PI_2);
+        *look = Some(Vec2::new(yaw, pitch));
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+
+        let mut direction = Vec3::ZERO;
+        if pressed(keys.forward) {
+            direction += *transform.forward();
+        }
+        if pressed(keys.backward) {
+            direction -= *transform.forward();
+        }
+        if pressed(keys.right) {
+            direction += *transform.right();
+        }
+        if pressed(keys.left) {
+            direction -= *transform.right();
+        }
+        if pressed(keys.jump) {
+            direction += Vec3::Y;
+        }
+        if pressed(keys.crouch) {
+            direction -= Vec3::Y;
+        }
+
+        let speed = if pressed(keys.sprint) {
+            FREE_FLY_SPEED * FREE_FLY_FAST_MULTIPLIER
+        } else {
+            FREE_FLY_SPEED
+        };
+        transform.translation += direction.normalize_or_zero() * speed * time.delta_seconds();
+    }
+}
+
+fn setup_view_model_system(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    commands.spawn((
+        ViewModel::default(),
+        PbrBundle {
+            mesh: meshes.add(Cuboid::new(0.06, 0.06, 0.35)),
+            material: materials.add(Color::rgb(0.2, 0.2, 0.2)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+/// follows the camera, lagging behind turns (sway) and bobbing while walking
+fn view_model_system(
+    time: Res<Time>,
+    camera_mode: Res<CameraMode>,
+    local_player: Res<LocalPlayer>,
+    characters: Query<(&Character, &ViewAngles)>,
+    cameras: Query<&Transform, (With<Camera>, Without<ViewModel>, Without<GuestCamera>)>,
+    mut view_models: Query<(&mut ViewModel, &mut Transform, &mut Visibility)>,
+) {
+    let own_character = characters
+        .iter()
+        .find(|(character, _)| character.owner_client_id == local_player.client_id);
+    // the body is what's shown in third-person
+    let camera_transform = cameras
+        .iter()
+        .next()
+        .filter(|_| *camera_mode == CameraMode::FirstPerson);
+    let delta_seconds = time.delta_seconds();
+
+    for (mut view_model, mut transform, mut visibility) in view_models.iter_mut() {
+        let (character, view, camera_transform) = match (own_character, camera_transform) {
+            (Some((character, view)), Some(camera_transform)) => {
+                (character, view, camera_transform)
+            }
+            _ => {
+                *visibility = Visibility::Hidden;
+                view_model.last_view = None;
+                continue;
+            }
+        };
+        *visibility = Visibility::Visible;
+
+        let turn_rate = match view_model.last_view {
+            Some(last_view) if delta_seconds > 0.0 => {
+                let yaw_delta = (view.yaw - last_view.yaw + PI).rem_euclid(TAU) - PI;
+                Vec2::new(yaw_delta, view.pitch - last_view.pitch) / delta_seconds
+            }
+            _ => Vec2::ZERO,
+        };
+        view_model.last_view = Some(*view);
+
+        let target_sway = (-turn_rate * VIEW_MODEL_SWAY_SCALE).clamp(
+            Vec2::splat(-VIEW_MODEL_SWAY_MAX),
+            Vec2::splat(VIEW_MODEL_SWAY_MAX),
+        );
+        view_model.sway = view_model.sway.lerp(
+            target_sway,
+            (delta_seconds * VIEW_MODEL_SWAY_SPEED).min(1.0),
+        );
+
+        let speed = Vec2::new(character.velocity.x, character.velocity.z).length();
+        let bob_strength = (speed / CHARACTER_SPEED).clamp(0.0, 1.0);
+        view_model.bob_phase = (view_model.bob_phase
+            + speed * delta_seconds * VIEW_MODEL_BOB_FREQUENCY)
+            .rem_euclid(TAU);
+        let bob = Vec3::new(
+            view_model.bob_phase.sin(),
+            (view_model.bob_phase * 2.0).sin().abs(),
+            0.0,
+        ) * VIEW_MODEL_BOB_AMOUNT
+            * bob_strength;
+
+        let offset = VIEW_MODEL_OFFSET + Vec3::new(view_model.sway.x, view_model.sway.y, 0.0) + bob;
+        transform.translation = camera_transform.translation + camera_transform.rotation * offset;
+        transform.rotation = camera_transform.rotation;
+    }
+}
+
+/// how much to scale look sensitivity at this field of view, so a zoomed view turns as much
+/// on screen per mouse movement as an unzoomed one
+pub fn zoom_sensitivity_scale(fov: f32) -> f32 {
+    (fov * 0.5).tan() / (FIELD_OF_VIEW * 0.5).tan()
+}
+
+/// narrows the field of view while our character aims, purely local
+fn zoom_system(
+    time: Res<Time>,
+    camera_mode: Res<CameraMode>,
+    local_player: Res<LocalPlayer>,
+    characters: Query<&Character>,
+    mut projections: Query<&mut Projection, (With<Camera3d>, Without<GuestCamera>)>,
+) {
+    let aiming = *camera_mode != CameraMode::FreeFly
+        && characters.iter().any(|character| {
+            character.owner_client_id == local_player.client_id && character.aiming
+        });
+    let target_fov = if aiming {
+        ZOOM_FIELD_OF_VIEW
+    } else {
+        FIELD_OF_VIEW
+    };
+
+    for mut projection in projections.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov +=
+                (target_fov - perspective.fov) * (time.delta_seconds() * ZOOM_SPEED).min(1.0);
+        }
+    }
+}
+
+fn aim_pose_system(
+    characters: Query<&Character>,
+    mut visuals: Query<(&CharacterVisuals, &mut Transform)>,
+) {
+    for (visuals, mut transform) in visuals.iter_mut() {
+        if let Ok(character) = characters.get(visuals.character_entity) {
+            transform.scale = if character.aiming {
+                AIM_POSE_SCALE
+            } else {
+                Vec3::ONE
+            };
+        }
+    }
+}
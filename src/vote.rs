@@ -0,0 +1,454 @@
+use crate::{
+    chat::ChatMessageEvent,
+    core::*,
+    localization::Localization,
+    map::Map,
+    mutators::{self, SetMutatorsEvent},
+    room::Rooms,
+    server::{ChangeMapEvent, ReliableOutbox},
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::{ClientId, DefaultChannel, RenetClient, RenetServer};
+
+/// how long everyone has to make up their mind, in seconds
+const VOTE_DURATION: f32 = 30.0;
+/// how long after starting a vote the same player has to wait to start another
+const VOTE_COOLDOWN: f32 = 60.0;
+/// the share of everyone who can vote that has to, for a vote that runs out to count
+const QUORUM: f32 = 0.5;
+
+/// players vote to kick someone, change the map or turn a mutator on or off. only the room the
+/// vote was started in gets a say and hears about it. the server keeps the tally and tells them
+/// how it stands, a majority of everyone who can vote passes it right away, otherwise it comes
+/// down to yes against no once time runs out
+pub struct VotePlugin;
+impl Plugin for VotePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_vote_hud_system);
+        app.add_systems(
+            Update,
+            (
+                send_vote_commands_system,
+                (vote_requests_system, tally_votes_system)
+                    .chain()
+                    .run_if(resource_exists::<RenetServer>),
+                receive_vote_system.run_if(resource_exists::<RenetClient>),
+                vote_hud_system,
+            )
+                .chain(),
+        );
+        app.init_resource::<Votes>();
+        app.init_resource::<VoteDisplay>();
+        app.add_event::<VoteCommandEvent>();
+        app.add_event::<IncomingVoteEvent>();
+        app.add_event::<ReceivedVoteEvent>();
+    }
+}
+
+/// a vote command the local player typed into chat
+#[derive(Event)]
+pub struct VoteCommandEvent(pub VoteRequest);
+
+/// a vote command from a player, for the server to deal with
+#[derive(Event)]
+pub struct IncomingVoteEvent {
+    pub sender: ClientId,
+    pub request: VoteRequest,
+}
+
+/// the server told us how the vote stands
+#[derive(Event)]
+pub struct ReceivedVoteEvent(pub Option<VoteStatus>);
+
+/// `/votekick <client id>`, `/votemap <map name>`, `/votemutator <mutator> on|off`, `/yes`
+/// and `/no`
+pub fn parse_vote_command(text: &str) -> Option<VoteRequest> {
+    let mut words = text.split_whitespace();
+    match (words.next()?, words.next()) {
+        ("/votemutator", Some(name)) => {
+            let on = match words.next()? {
+                "on" => true,
+                "off" => false,
+                _ => return None,
+            };
+            Mutator::from_name(name)
+                .map(|mutator| VoteRequest::Start(VoteKind::Mutator(mutator, on)))
+        }
+        ("/votekick", Some(client_id)) => client_id
+            .parse()
+            .ok()
+            .map(|client_id| VoteRequest::Start(VoteKind::Kick(client_id))),
+        ("/votemap", Some(name)) => Some(VoteRequest::Start(VoteKind::ChangeMap(name.into()))),
+        ("/yes", _) => Some(VoteRequest::Cast(true)),
+        ("/no", _) => Some(VoteRequest::Cast(false)),
+        _ => None,
+    }
+}
+
+/// only filled in on the server
+#[derive(Resource, Default)]
+struct Votes {
+    current: Option<Vote>,
+    /// the room the current vote is in, or the last one was
+    room: u32,
+    /// elapsed seconds until each player may start another vote
+    cooldowns: HashMap<ClientId, f32>,
+    /// what everyone was last told
+    last_status: Option<VoteStatus>,
+}
+
+struct Vote {
+    kind: VoteKind,
+    ends_at: f32,
+    ballots: HashMap<ClientId, bool>,
+}
+
+/// what the HUD shows, the server's own tally or the one it sent us
+#[derive(Resource, Default)]
+struct VoteDisplay(Option<VoteStatus>);
+
+fn describe(localization: &Localization, kind: &VoteKind) -> String {
+    match kind {
+        VoteKind::Kick(client_id) => localization.format("vote.kick", &[client_id]),
+        VoteKind::ChangeMap(name) => localization.format("vote.change_map", &[name]),
+        VoteKind::Mutator(mutator, on) => localization.format(
+            if *on {
+                "vote.mutator_on"
+            } else {
+                "vote.mutator_off"
+            },
+            &[&mutators::describe(localization, *mutator)],
+        ),
+    }
+}
+
+/// everyone connected in the room and the host if they're playing there, apart from whoever is
+/// being kicked
+fn voters(
+    kind: &VoteKind,
+    room: u32,
+    rooms: &Rooms,
+    local_player: Option<&LocalPlayer>,
+    server: &RenetServer,
+    characters: &Query<&Character>,
+) -> Vec<ClientId> {
+    let host = local_player
+        .filter(|local_player| local_player.is_authority())
+        .map(|local_player| local_player.client_id)
+        .filter(|host| {
+            characters
+                .iter()
+                .any(|character| character.owner_client_id == *host)
+        });
+    server
+        .clients_id()
+        .into_iter()
+        .chain(host)
+        .filter(|client_id| {
+            rooms.room_of(*client_id) == room && *kind != VoteKind::Kick(client_id.raw())
+        })
+        .collect()
+}
+
+/// a line in one player's chat log
+fn tell(
+    recipient: ClientId,
+    text: String,
+    local_player: Option<&LocalPlayer>,
+    messages: &mut EventWriter<ChatMessageEvent>,
+    outbox: &mut ReliableOutbox,
+    server: &mut RenetServer,
+) {
+    if local_player.is_some_and(|local_player| local_player.client_id == recipient) {
+        messages.send(ChatMessageEvent { sender: None, text });
+    } else {
+        outbox.send(server, recipient, &ReliableServerMessage::Chat(None, text));
+    }
+}
+
+/// a line in the chat log of everyone in the room, the host's included if they're in it
+fn notify(
+    text: String,
+    room: u32,
+    rooms: &Rooms,
+    local_player: Option<&LocalPlayer>,
+    messages: &mut EventWriter<ChatMessageEvent>,
+    outbox: &mut ReliableOutbox,
+    server: &mut RenetServer,
+) {
+    outbox.broadcast_filtered(
+        server,
+        &ReliableServerMessage::Chat(None, text.clone()),
+        |client_id| rooms.room_of(client_id) == room,
+    );
+    if local_player.is_none_or(|local_player| rooms.room_of(local_player.client_id) == room) {
+        messages.send(ChatMessageEvent { sender: None, text });
+    }
+}
+
+fn send_vote_commands_system(
+    local_player: Option<Res<LocalPlayer>>,
+    mut vote_commands: EventReader<VoteCommandEvent>,
+    mut incoming: EventWriter<IncomingVoteEvent>,
+    mut client: Option<ResMut<RenetClient>>,
+) {
+    for VoteCommandEvent(request) in vote_commands.read() {
+        if let Some(client) = client.as_mut() {
+            if let Ok(message) = bincode::serialize(&ReliableClientMessage::Vote(request.clone())) {
+                client.send_message(DefaultChannel::ReliableOrdered, message);
+            }
+        } else if let Some(local_player) = &local_player {
+            incoming.send(IncomingVoteEvent {
+                sender: local_player.client_id,
+                request: request.clone(),
+            });
+        }
+    }
+}
+
+fn vote_requests_system(
+    time: Res<Time>,
+    localization: Res<Localization>,
+    local_player: Option<Res<LocalPlayer>>,
+    rooms: Res<Rooms>,
+    mutators: Res<Mutators>,
+    mut incoming: EventReader<IncomingVoteEvent>,
+    mut votes: ResMut<Votes>,
+    mut messages: EventWriter<ChatMessageEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    let now = time.elapsed_seconds();
+    let local_player = local_player.as_deref();
+
+    for IncomingVoteEvent { sender, request } in incoming.read() {
+        match request {
+            VoteRequest::Start(kind) => {
+                votes.cooldowns.retain(|_, until| *until > now);
+                let refusal = if votes.current.is_some() {
+                    Some(localization.get("vote.already_running").to_string())
+                } else if let Some(until) = votes.cooldowns.get(sender) {
+                    Some(localization.format("vote.cooldown", &[&(until - now).ceil()]))
+                } else {
+                    match kind {
+                        // the host can't be kicked, they are the server. nor can anyone the
+                        // room doesn't know
+                        VoteKind::Kick(client_id)
+                            if *client_id == sender.raw()
+                                || !server.is_connected(ClientId::from_raw(*client_id))
+                                || !rooms.same_room(*sender, ClientId::from_raw(*client_id)) =>
+                        {
+                            Some(localization.format("vote.invalid_target", &[client_id]))
+                        }
+                        VoteKind::ChangeMap(name) if Map::load_named(name).is_err() => {
+                            Some(localization.format("vote.unknown_map", &[name]))
+                        }
+                        VoteKind::Mutator(mutator, on) if mutators.contains(*mutator) == *on => {
+                            Some(localization.get("vote.mutator_unchanged").to_string())
+                        }
+                        _ => None,
+                    }
+                };
+                if let Some(refusal) = refusal {
+                    tell(
+                        *sender,
+                        refusal,
+                        local_player,
+                        &mut messages,
+                        &mut outbox,
+                        &mut server,
+                    );
+                    continue;
+                }
+
+                println!("{} started a vote: {:?}", sender, kind);
+                votes.cooldowns.insert(*sender, now + VOTE_COOLDOWN);
+                let mut ballots = HashMap::default();
+                ballots.insert(*sender, true);
+                votes.current = Some(Vote {
+                    kind: kind.clone(),
+                    ends_at: now + VOTE_DURATION,
+                    ballots,
+                });
+                votes.room = rooms.room_of(*sender);
+                notify(
+                    localization.format("vote.started", &[sender, &describe(&localization, kind)]),
+                    votes.room,
+                    &rooms,
+                    local_player,
+                    &mut messages,
+                    &mut outbox,
+                    &mut server,
+                );
+            }
+            VoteRequest::Cast(yes) => {
+                // whoever is being kicked doesn't get a say, the tally leaves them out
+                if let Some(vote) = votes.current.as_mut() {
+                    vote.ballots.insert(*sender, *yes);
+                }
+            }
+        }
+    }
+}
+
+/// settles the vote once the outcome can't change or time is up, and keeps everyone's view of
+/// it current
+fn tally_votes_system(
+    time: Res<Time>,
+    localization: Res<Localization>,
+    local_player: Option<Res<LocalPlayer>>,
+    rooms: Res<Rooms>,
+    characters: Query<&Character>,
+    mutators: Res<Mutators>,
+    mut votes: ResMut<Votes>,
+    mut display: ResMut<VoteDisplay>,
+    mut messages: EventWriter<ChatMessageEvent>,
+    mut change_map: EventWriter<ChangeMapEvent>,
+    mut set_mutators: EventWriter<SetMutatorsEvent>,
+    mut outbox: ResMut<ReliableOutbox>,
+    mut server: ResMut<RenetServer>,
+) {
+    let now = time.elapsed_seconds();
+    let local_player = local_player.as_deref();
+    let room = votes.room;
+    let (status, passed) = match votes.current.as_mut() {
+        Some(vote) => {
+            let voters = voters(&vote.kind, room, &rooms, local_player, &server, &characters);
+            vote.ballots
+                .retain(|client_id, _| voters.contains(client_id));
+            let yes = vote.ballots.values().filter(|yes| **yes).count();
+            let no = vote.ballots.len() - yes;
+            let eligible = voters.len();
+            let needed = eligible / 2 + 1;
+
+            let passed = if yes >= needed {
+                Some(true)
+            } else if no * 2 >= eligible {
+                // yes can't outnumber no anymore
+                Some(false)
+            } else if now >= vote.ends_at {
+                Some(yes > no && (yes + no) as f32 >= eligible as f32 * QUORUM)
+            } else {
+                None
+            };
+            let status = VoteStatus {
+                kind: vote.kind.clone(),
+                yes: yes as u8,
+                no: no as u8,
+                needed: needed as u8,
+                remaining: (vote.ends_at - now).max(0.0).ceil() as u16,
+            };
+            (passed.is_none().then_some(status), passed)
+        }
+        None => (None, None),
+    };
+
+    if let Some(passed) = passed {
+        if let Some(vote) = votes.current.take() {
+            let description = describe(&localization, &vote.kind);
+            let key = if passed { "vote.passed" } else { "vote.failed" };
+            println!(
+                "Vote {}: {:?}",
+                if passed { "passed" } else { "failed" },
+                vote.kind
+            );
+            notify(
+                localization.format(key, &[&description]),
+                room,
+                &rooms,
+                local_player,
+                &mut messages,
+                &mut outbox,
+                &mut server,
+            );
+            if passed {
+                match vote.kind {
+                    VoteKind::Kick(client_id) => {
+                        server.disconnect(ClientId::from_raw(client_id));
+                    }
+                    VoteKind::ChangeMap(name) => {
+                        change_map.send(ChangeMapEvent(name));
+                    }
+                    VoteKind::Mutator(mutator, on) => {
+                        let mut new_mutators = *mutators;
+                        new_mutators.set(mutator, on);
+                        set_mutators.send(SetMutatorsEvent(new_mutators));
+                    }
+                }
+            }
+        }
+    }
+
+    if status != votes.last_status {
+        outbox.broadcast_filtered(
+            &mut server,
+            &ReliableServerMessage::Vote(status.clone()),
+            |client_id| rooms.room_of(client_id) == room,
+        );
+        if local_player.is_none_or(|local_player| rooms.room_of(local_player.client_id) == room) {
+            display.0.clone_from(&status);
+        }
+        votes.last_status = status;
+    }
+}
+
+fn receive_vote_system(
+    mut received: EventReader<ReceivedVoteEvent>,
+    mut display: ResMut<VoteDisplay>,
+) {
+    for ReceivedVoteEvent(status) in received.read() {
+        display.0.clone_from(status);
+    }
+}
+
+#[derive(Component)]
+struct VoteText;
+
+fn setup_vote_hud_system(mut commands: Commands) {
+    commands.spawn((
+        VoteText,
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        },
+    ));
+}
+
+fn vote_hud_system(
+    localization: Res<Localization>,
+    display: Res<VoteDisplay>,
+    mut texts: Query<&mut Text, With<VoteText>>,
+) {
+    if !display.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let value = match &display.0 {
+        Some(status) => localization.format(
+            "vote.status",
+            &[
+                &describe(&localization, &status.kind),
+                &status.yes,
+                &status.no,
+                &status.needed,
+                &status.remaining,
+            ],
+        ),
+        None => String::new(),
+    };
+    for mut text in texts.iter_mut() {
+        text.sections[0].value.clone_from(&value);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::core::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_renet::renet::transport::NETCODE_USER_DATA_BYTES;
+use serde::Deserialize;
+use std::path::Path;
+
+pub const WEAPONS_PATH: &str = "assets/weapons.ron";
+
+/// what every weapon does, read from `assets/weapons.ron` once at startup. the server and every
+/// client have to agree on it for predictions to come out the same, so clients send a hash of
+/// the file when connecting and the server turns away any that differ. one that can't be read
+/// stops the game, anything made up in its place would only be turned away
+pub struct WeaponPlugin;
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        match WeaponDefinitions::load(Path::new(WEAPONS_PATH)) {
+            Ok(definitions) => {
+                app.insert_resource(definitions);
+            }
+            Err(e) => {
+                eprintln!("Failed to load weapons {}: {}", WEAPONS_PATH, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct WeaponDefinitions {
+    weapons: HashMap<Weapon, WeaponDefinition>,
+    /// of the file as it was read
+    pub hash: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WeaponDefinition {
+    pub fire_mode: FireMode,
+    pub max_ammo: u32,
+    /// radians a shot can stray from the aim once the weapon has cooled down
+    pub spread: f32,
+    /// radians every shot kicks the view up by, and adds to the spread until it cools down
+    pub recoil: f32,
+    /// heat lost per input, one a tick
+    pub cooling: f32,
+    /// inputs from one shot to the next at the fastest, 1 is every input
+    pub fire_interval: u32,
+    /// health a hit takes
+    pub damage: f32,
+    /// damage is multiplied by this for a hit to the head
+    pub headshot: f32,
+    /// full damage up to this many meters, less and less from there
+    pub falloff_start: f32,
+    /// from this many meters on, only `falloff_min` of it
+    pub falloff_end: f32,
+    pub falloff_min: f32,
+    /// the tone a shot makes, in hertz
+    pub fire_pitch: f32,
+}
+
+/// how a shot gets to what it hits
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FireMode {
+    /// there the moment it's fired
+    Hitscan,
+    /// flies down the same path at this many meters a second, hitting whoever is in the way
+    /// when it gets there
+    Projectile { speed: f32 },
+}
+
+impl WeaponDefinitions {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let weapons: HashMap<Weapon, WeaponDefinition> =
+            ron::from_str(&text).map_err(|e| e.to_string())?;
+        if let Some(missing) = Weapon::ALL
+            .iter()
+            .find(|weapon| !weapons.contains_key(*weapon))
+        {
+            return Err(format!("{:?} isn't defined", missing));
+        }
+        Ok(Self {
+            weapons,
+            hash: fnv1a(text.as_bytes()),
+        })
+    }
+
+    /// every weapon is in there, `load` makes sure of it
+    pub fn get(&self, weapon: Weapon) -> &WeaponDefinition {
+        &self.weapons[&weapon]
+    }
+
+    /// what a hit's damage is multiplied by, for where it hit and how far the shot went
+    pub fn multiplier(&self, weapon: Weapon, part: BodyPart, distance: f32) -> f32 {
+        let definition = self.get(weapon);
+        let part = match part {
+            BodyPart::Head => definition.headshot,
+            BodyPart::Body => 1.0,
+        };
+        let span = (definition.falloff_end - definition.falloff_start).max(f32::EPSILON);
+        let t = ((distance - definition.falloff_start) / span).clamp(0.0, 1.0);
+        part * (1.0 + (definition.falloff_min - 1.0) * t)
+    }
+}
+
+/// what clients connect with, the hash of their definitions up front
+pub fn handshake_user_data(definitions: &WeaponDefinitions) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+    user_data[..8].copy_from_slice(&definitions.hash.to_le_bytes());
+    user_data
+}
+
+pub fn hash_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> u64 {
+    let mut hash = [0; 8];
+    hash.copy_from_slice(&user_data[..8]);
+    u64::from_le_bytes(hash)
+}
+
+/// the same on every machine and every build, unlike the std hasher
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}